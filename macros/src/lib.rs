@@ -0,0 +1,141 @@
+/*!
+Attribute macros for [actix-4-jwt-auth](https://crates.io/crates/actix-4-jwt-auth), enabled with
+its `macros` feature.
+*/
+#![warn(missing_docs)]
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use syn::{
+    parse::{Parse, ParseStream},
+    parse_macro_input,
+    punctuated::Punctuated,
+    spanned::Spanned,
+    FnArg, Ident, ItemFn, LitStr, ReturnType, Token,
+};
+
+/// The requirements of `#[protect(scopes(...), roles(...))]`
+struct Protection {
+    scopes: Vec<LitStr>,
+    roles: Vec<LitStr>,
+}
+
+impl Parse for Protection {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut protection = Protection {
+            scopes: vec![],
+            roles: vec![],
+        };
+        while !input.is_empty() {
+            let kind: Ident = input.parse()?;
+            let content;
+            syn::parenthesized!(content in input);
+            let values = Punctuated::<LitStr, Token![,]>::parse_terminated(&content)?;
+            match kind.to_string().as_str() {
+                "scopes" => protection.scopes.extend(values),
+                "roles" => protection.roles.extend(values),
+                _ => {
+                    return Err(syn::Error::new(
+                        kind.span(),
+                        "expected `scopes(...)` or `roles(...)`",
+                    ))
+                }
+            }
+            if !input.is_empty() {
+                input.parse::<Token![,]>()?;
+            }
+        }
+        Ok(protection)
+    }
+}
+
+/// Protects a handler with the scopes and roles the token must have
+///
+/// The handler gets an [`AuthContext`](https://docs.rs/actix-4-jwt-auth/latest/actix_4_jwt_auth/struct.AuthContext.html)
+/// argument, a token without one of the scopes (or permissions they map to) or roles is rejected
+/// as forbidden before the handler runs. Place it below the route attribute:
+///
+/// ```ignore
+/// #[get("/items")]
+/// #[protect(scopes("read:items"), roles("admin"))]
+/// async fn items() -> String {
+///     "items".to_string()
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn protect(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let protection = parse_macro_input!(attr as Protection);
+    let handler = parse_macro_input!(item as ItemFn);
+    match expand(protection, handler) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn expand(protection: Protection, handler: ItemFn) -> syn::Result<proc_macro2::TokenStream> {
+    if handler.sig.asyncness.is_none() {
+        return Err(syn::Error::new(
+            handler.sig.span(),
+            "#[protect] requires an async handler",
+        ));
+    }
+    let ItemFn {
+        attrs,
+        vis,
+        sig,
+        block,
+    } = handler;
+
+    let mut inner_sig = sig.clone();
+    inner_sig.ident = Ident::new("__protected", Span::call_site());
+
+    let mut outer_sig = sig;
+    let mut forwarded = vec![];
+    for (index, arg) in outer_sig.inputs.iter_mut().enumerate() {
+        match arg {
+            FnArg::Typed(arg) => {
+                let ident = Ident::new(&format!("__arg{}", index), Span::call_site());
+                *arg.pat = syn::parse_quote!(#ident);
+                forwarded.push(ident);
+            }
+            FnArg::Receiver(receiver) => {
+                return Err(syn::Error::new(
+                    receiver.span(),
+                    "#[protect] does not support methods",
+                ))
+            }
+        }
+    }
+    outer_sig.inputs.insert(
+        0,
+        syn::parse_quote!(__protect_req: ::actix_4_jwt_auth::__private::HttpRequest),
+    );
+    outer_sig.inputs.insert(
+        1,
+        syn::parse_quote!(__protect_context: ::actix_4_jwt_auth::AuthContext),
+    );
+    let output = match &outer_sig.output {
+        ReturnType::Default => quote!(()),
+        ReturnType::Type(_, ty) => quote!(#ty),
+    };
+    outer_sig.output = syn::parse_quote!(
+        -> ::std::result::Result<#output, ::actix_4_jwt_auth::__private::Error>
+    );
+
+    let Protection { scopes, roles } = protection;
+    Ok(quote! {
+        #(#attrs)*
+        #vis #outer_sig {
+            #inner_sig #block
+
+            ::actix_4_jwt_auth::__private::protect(
+                &__protect_req,
+                &__protect_context,
+                &[#(#scopes),*],
+                &[#(#roles),*],
+            )?;
+            ::std::result::Result::Ok(__protected(#(#forwarded),*).await)
+        }
+    })
+}
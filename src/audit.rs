@@ -0,0 +1,279 @@
+use std::{
+    cell::Cell,
+    collections::BTreeMap,
+    future::{ready, Ready},
+    rc::Rc,
+    sync::{Arc, Mutex},
+};
+
+use actix_web::{
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    web, Error, HttpMessage, HttpResponse, Resource,
+};
+use futures_util::future::LocalBoxFuture;
+use serde::Serialize;
+
+/// How a request was covered by the authentication of this crate, from weak to strong
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RouteCoverage {
+    /// Neither the middleware nor an extractor looked at the token
+    Unprotected,
+    /// The middleware let the request pass as allowlisted public path
+    Public,
+    /// An extractor of the handler validated the token
+    Extractor,
+    /// The [`OidcBiscuitValidator`](crate::OidcBiscuitValidator) middleware validated the token
+    Middleware,
+}
+
+/// Marks the coverage of the request, a stronger coverage is never downgraded
+pub(crate) fn cover(req: &impl HttpMessage, coverage: RouteCoverage) {
+    let mut extensions = req.extensions_mut();
+    if extensions
+        .get::<RouteCoverage>()
+        .is_none_or(|covered| *covered < coverage)
+    {
+        extensions.insert(coverage);
+        if let Some(AuditedCoverage(audited)) = extensions.get::<AuditedCoverage>() {
+            audited.set(coverage);
+        }
+    }
+}
+
+/// The coverage of a request audited by the [`RouteAudit`], still known when the request fails
+/// with an error instead of a response
+#[derive(Clone)]
+struct AuditedCoverage(Rc<Cell<RouteCoverage>>);
+
+/// The audited coverage of a route
+#[derive(Debug, PartialEq, Eq, Clone, Serialize)]
+pub struct AuditedRoute {
+    /// The route pattern, e.g. `/items/{id}`
+    pub route: String,
+    /// The weakest coverage seen for the route, `None` when a declared route was not requested yet
+    pub coverage: Option<RouteCoverage>,
+    /// The number of audited requests
+    pub requests: u64,
+}
+
+/// Audit mode that records for every route whether it is covered by the middleware or an
+/// extractor, to catch accidentally unprotected endpoints during security reviews
+///
+/// Wrap it around the whole `App`, outside the [`OidcBiscuitValidator`](crate::OidcBiscuitValidator).
+/// Actix does not expose the registered routes, so routes are recorded as they are requested;
+/// routes declared with [`Self::declare`] are reported even before their first request.
+///
+/// ```ignore
+/// let audit = RouteAudit::new().declare("/items/{id}").declare("/admin");
+/// App::new()
+///     .wrap(OidcBiscuitValidator::default().public_path("/_audit"))
+///     .wrap(audit.clone())
+///     .service(audit.resource("/_audit"))
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct RouteAudit {
+    routes: Arc<Mutex<BTreeMap<String, AuditedRoute>>>,
+}
+
+impl RouteAudit {
+    /// Creates an audit without declared routes
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Declares a registered route pattern, so it is reported before its first request
+    pub fn declare(self, route: impl Into<String>) -> Self {
+        let route = route.into();
+        self.routes
+            .lock()
+            .unwrap()
+            .entry(route.clone())
+            .or_insert(AuditedRoute {
+                route,
+                coverage: None,
+                requests: 0,
+            });
+        self
+    }
+
+    /// The audited routes ordered by route pattern
+    pub fn report(&self) -> Vec<AuditedRoute> {
+        self.routes.lock().unwrap().values().cloned().collect()
+    }
+
+    /// The audited routes that were requested without any protection
+    pub fn unprotected(&self) -> Vec<AuditedRoute> {
+        self.report()
+            .into_iter()
+            .filter(|route| route.coverage == Some(RouteCoverage::Unprotected))
+            .collect()
+    }
+
+    /// A resource serving the report as JSON, it must be protected or allowlisted like any other route
+    pub fn resource(&self, path: &str) -> Resource {
+        let audit = self.clone();
+        web::resource(path).route(web::get().to(move || {
+            let report = audit.report();
+            async move { HttpResponse::Ok().json(report) }
+        }))
+    }
+
+    fn record(&self, route: String, coverage: RouteCoverage) {
+        let mut routes = self.routes.lock().unwrap();
+        let audited = routes.entry(route.clone()).or_insert(AuditedRoute {
+            route,
+            coverage: None,
+            requests: 0,
+        });
+        audited.coverage = Some(audited.coverage.map_or(coverage, |seen| seen.min(coverage)));
+        audited.requests += 1;
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RouteAudit
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RouteAuditMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RouteAuditMiddleware {
+            service: Rc::new(service),
+            audit: self.clone(),
+        }))
+    }
+}
+
+pub struct RouteAuditMiddleware<S> {
+    service: Rc<S>,
+    audit: RouteAudit,
+}
+
+impl<S, B> Service<ServiceRequest> for RouteAuditMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let svc = self.service.clone();
+        let audit = self.audit.clone();
+        // a request rejected with an error by a middleware is not routed yet
+        let requested = req.resource_map().match_pattern(req.path());
+        let covered = Rc::new(Cell::new(RouteCoverage::Unprotected));
+        req.extensions_mut()
+            .insert(AuditedCoverage(covered.clone()));
+        Box::pin(async move {
+            let res = svc.call(req).await;
+            // unmatched requests are answered by the default service and are no route
+            let route = match &res {
+                Ok(res) => res.request().match_pattern(),
+                Err(_) => requested,
+            };
+            if let Some(route) = route {
+                audit.record(route, covered.get());
+            }
+            res
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        tests::{create_get_jwt_request, create_jwt_token, create_oidc},
+        AuditedRoute, DecodedInfo, OidcBiscuitValidator, RouteAudit, RouteCoverage,
+    };
+    use actix_web::{dev::Service, http::StatusCode, test, web, App, Error, HttpResponse};
+
+    async fn hello() -> HttpResponse {
+        HttpResponse::Ok().finish()
+    }
+
+    async fn decoder(claims: DecodedInfo) -> HttpResponse {
+        HttpResponse::Ok().body(claims.jwt)
+    }
+
+    ///Test for auditing the coverage of the routes
+    #[actix_rt::test]
+    async fn test_route_audit() -> Result<(), Error> {
+        let oidc = create_oidc().await;
+        let audit = RouteAudit::new().declare("/never");
+        let app = test::init_service(
+            App::new()
+                .app_data(oidc.clone())
+                .wrap(audit.clone())
+                .service(web::resource("/decoder").to(decoder))
+                .service(web::resource("/open").to(hello))
+                .service(
+                    web::scope("/api")
+                        .wrap(OidcBiscuitValidator::default().public_path("/api/status"))
+                        .route("/items/{id}", web::get().to(hello))
+                        .route("/status", web::get().to(hello)),
+                ),
+        )
+        .await;
+
+        for path in [
+            "/decoder",
+            "/open",
+            "/api/items/1",
+            "/api/status",
+            "/missing",
+        ] {
+            let req = create_get_jwt_request(path, &create_jwt_token()).to_request();
+            test::call_service(&app, req).await;
+        }
+        // the middleware fails the request with an error
+        let req = create_get_jwt_request("/api/items/2", "invalid").to_request();
+        assert!(app.call(req).await.is_err());
+
+        let route = |route: &str, coverage, requests| AuditedRoute {
+            route: route.to_string(),
+            coverage,
+            requests,
+        };
+        assert_eq!(
+            audit.report(),
+            vec![
+                route("/api/items/{id}", Some(RouteCoverage::Middleware), 2),
+                route("/api/status", Some(RouteCoverage::Public), 1),
+                route("/decoder", Some(RouteCoverage::Extractor), 1),
+                route("/never", None, 0),
+                route("/open", Some(RouteCoverage::Unprotected), 1),
+            ]
+        );
+        assert_eq!(
+            audit.unprotected(),
+            vec![route("/open", Some(RouteCoverage::Unprotected), 1)]
+        );
+        Ok(())
+    }
+
+    ///Test for serving the audit report
+    #[actix_rt::test]
+    async fn test_route_audit_resource() -> Result<(), Error> {
+        let audit = RouteAudit::new().declare("/items");
+        let app = test::init_service(App::new().service(audit.resource("/_audit"))).await;
+
+        let req = test::TestRequest::get().uri("/_audit").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = test::read_body(res).await;
+        assert_eq!(body, r#"[{"route":"/items","coverage":null,"requests":0}]"#);
+        Ok(())
+    }
+}
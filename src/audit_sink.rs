@@ -0,0 +1,196 @@
+use std::time::SystemTime;
+
+use actix_web::HttpRequest;
+use futures_util::future::LocalBoxFuture;
+use serde::Serialize;
+
+use crate::{DecodedInfo, ErrorCode, OIDCValidationError, Oidc};
+
+/// The authentication decision on a request
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditDecision {
+    /// The token is valid and meets the requirements of the route
+    Allowed,
+    /// The request is rejected
+    Denied,
+    /// The middleware let the request without token through, see
+    /// [`OidcBiscuitValidator::allow_anonymous`](crate::OidcBiscuitValidator::allow_anonymous)
+    Anonymous,
+}
+
+/// A structured record of an authentication decision, for the [`AuditSink`]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize)]
+pub struct AuditRecord {
+    /// When the decision was made
+    pub timestamp: SystemTime,
+    /// The route pattern of the request, e.g. `/items/{id}`, or its path for unknown routes
+    pub route: String,
+    /// The `sub` of the token, when it is validated
+    pub subject: Option<String>,
+    /// The decision
+    pub decision: AuditDecision,
+    /// The code of the failure of a denied request
+    pub reason: Option<ErrorCode>,
+}
+
+/// Receives a record of every authentication decision, e.g. to ship the authentication events to
+/// a SIEM, configured with [`Oidc::with_audit_sink`]
+///
+/// The decisions are made by the middleware, and by the extractors for the routes without
+/// middleware. The returned future is spawned, the request does not wait for it.
+pub trait AuditSink: Send + Sync {
+    /// Records the decision
+    fn record(&self, record: AuditRecord) -> LocalBoxFuture<'static, ()>;
+}
+
+/// Emits the records as `info` events of the `actix_4_jwt_auth::audit` target with the `tracing`
+/// crate, the subscriber of the application ships them
+#[cfg(feature = "tracing")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TracingAuditSink;
+
+#[cfg(feature = "tracing")]
+impl AuditSink for TracingAuditSink {
+    fn record(&self, record: AuditRecord) -> LocalBoxFuture<'static, ()> {
+        Box::pin(async move {
+            let timestamp = record
+                .timestamp
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            tracing::info!(
+                target: "actix_4_jwt_auth::audit",
+                timestamp,
+                route = %record.route,
+                subject = record.subject.as_deref(),
+                decision = ?record.decision,
+                reason = record.reason.map(|reason| reason.as_str()),
+                "authentication decision"
+            );
+        })
+    }
+}
+
+/// Hands the decision on the request to the audit sink and the auth metrics of the Oidc, when
+/// configured
+pub(crate) fn audit(
+    oidc: &Oidc,
+    req: &HttpRequest,
+    decision: AuditDecision,
+    info: Option<&DecodedInfo>,
+    failure: Option<&OIDCValidationError>,
+) {
+    if let Some(metrics) = &oidc.auth_metrics {
+        metrics.record_decision(decision, failure);
+    }
+    let Some(sink) = &oidc.audit_sink else {
+        return;
+    };
+    let record = AuditRecord {
+        timestamp: SystemTime::now(),
+        route: req
+            .match_pattern()
+            .unwrap_or_else(|| req.path().to_string()),
+        subject: info.and_then(|info| info.payload.registered.subject.clone()),
+        decision,
+        reason: failure.map(OIDCValidationError::code),
+    };
+    actix_web::rt::spawn(sink.record(record));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use actix_web::{get, http::StatusCode, test, App};
+    use futures_util::future::LocalBoxFuture;
+    use serde_json::json;
+
+    use super::{AuditDecision, AuditRecord, AuditSink};
+    use crate::{
+        tests::{create_get_jwt_request, create_oidc, create_token},
+        DecodedInfo, ErrorCode, OidcBiscuitValidator,
+    };
+
+    #[derive(Default)]
+    struct Recorded(Arc<Mutex<Vec<AuditRecord>>>);
+
+    impl AuditSink for Recorded {
+        fn record(&self, record: AuditRecord) -> LocalBoxFuture<'static, ()> {
+            let records = self.0.clone();
+            Box::pin(async move { records.lock().unwrap().push(record) })
+        }
+    }
+
+    #[get("/items/{id}")]
+    async fn item(claims: DecodedInfo) -> String {
+        claims.jwt
+    }
+
+    ///Test that the decisions of the middleware and the extractors are recorded once per request
+    #[actix_rt::test]
+    async fn test_audit_sink() {
+        let sink = Recorded::default();
+        let records = sink.0.clone();
+        let oidc = create_oidc().await.with_audit_sink(sink);
+        let token = create_token(json!({ "sub": "admin", "scope": "items.read" }));
+
+        let app = test::init_service(App::new().app_data(oidc.clone()).service(item)).await;
+        test::call_service(
+            &app,
+            create_get_jwt_request("/items/1", &token).to_request(),
+        )
+        .await;
+        let req = test::TestRequest::get().uri("/items/2").to_request();
+        assert_eq!(
+            test::call_service(&app, req).await.status(),
+            StatusCode::UNAUTHORIZED
+        );
+
+        let app = test::init_service(
+            App::new()
+                .app_data(oidc.clone())
+                .wrap(OidcBiscuitValidator::default().require_scope("items.write"))
+                .service(item),
+        )
+        .await;
+        test::call_service(
+            &app,
+            create_get_jwt_request("/items/3", &token).to_request(),
+        )
+        .await;
+        actix_rt::task::yield_now().await;
+
+        let records = records.lock().unwrap();
+        let decisions: Vec<_> = records
+            .iter()
+            .map(|record| {
+                (
+                    record.route.as_str(),
+                    record.subject.as_deref(),
+                    record.decision,
+                    record.reason,
+                )
+            })
+            .collect();
+        assert_eq!(
+            decisions,
+            vec![
+                ("/items/{id}", Some("admin"), AuditDecision::Allowed, None),
+                (
+                    "/items/{id}",
+                    None,
+                    AuditDecision::Denied,
+                    Some(ErrorCode::TokenMissing)
+                ),
+                (
+                    "/items/{id}",
+                    Some("admin"),
+                    AuditDecision::Denied,
+                    Some(ErrorCode::InsufficientRights)
+                ),
+            ]
+        );
+    }
+}
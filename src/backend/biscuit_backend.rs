@@ -0,0 +1,96 @@
+use biscuit::{
+    errors::{Error as BiscuitError, ValidationError},
+    jwa::{Algorithm, SignatureAlgorithm},
+    jwk::{AlgorithmParameters, EllipticCurve, JWKSet},
+    jws, ClaimsSet, Compact, Empty, JWT,
+};
+use ring::signature::{UnparsedPublicKey, ED25519};
+use serde_json::Value;
+
+use super::VerificationBackend;
+use crate::{unverified::RawHeader, OIDCValidationError};
+
+/// Default backend, verifies tokens with biscuit.
+#[derive(Clone, Copy)]
+pub(crate) struct BiscuitBackend;
+
+impl VerificationBackend for BiscuitBackend {
+    fn verify(
+        &self,
+        jwks: &JWKSet<Empty>,
+        token: &str,
+    ) -> Result<ClaimsSet<Value>, OIDCValidationError> {
+        let header = RawHeader::peek(token);
+        if let Some(header) = header.as_ref().filter(|header| header.alg == "EdDSA") {
+            return verify_eddsa(jwks, token, header);
+        }
+        let algorithm = header
+            .and_then(|header| expected_algorithm(jwks, &header))
+            .unwrap_or(SignatureAlgorithm::RS256);
+        let token: jws::Compact<ClaimsSet<Value>, Empty> = JWT::new_encoded(token);
+        let decoded_token = token.decode_with_jwks(jwks, Some(algorithm))?;
+        decoded_token
+            .payload()
+            .map(|payload| payload.to_owned())
+            .map_err(|_| OIDCValidationError::Unauthorized)
+    }
+}
+
+/// The `alg` of the key of its `kid`, or of the header when the key has none, when it suits the
+/// key type, so a token can never pick HMAC or an algorithm of another key type
+///
+/// Biscuit rejects the token when the header does not agree with the `alg` of the key.
+///
+/// ES512 is not supported, ring cannot verify P-521 signatures.
+fn expected_algorithm(jwks: &JWKSet<Empty>, header: &RawHeader) -> Option<SignatureAlgorithm> {
+    use SignatureAlgorithm::{ES256, ES384, PS256, PS384, PS512, RS256, RS384, RS512};
+
+    let jwk = jwks.find(header.kid.as_deref()?)?;
+    let algorithm = match jwk.common.algorithm {
+        Some(Algorithm::Signature(algorithm)) => algorithm,
+        _ => serde_json::from_value(Value::from(header.alg.as_str())).ok()?,
+    };
+    match (&jwk.algorithm, algorithm) {
+        (AlgorithmParameters::RSA(_), RS256 | RS384 | RS512 | PS256 | PS384 | PS512) => {
+            Some(algorithm)
+        }
+        (AlgorithmParameters::EllipticCurve(ec), ES256) if ec.curve == EllipticCurve::P256 => {
+            Some(algorithm)
+        }
+        (AlgorithmParameters::EllipticCurve(ec), ES384) if ec.curve == EllipticCurve::P384 => {
+            Some(algorithm)
+        }
+        _ => None,
+    }
+}
+
+/// Verifies an EdDSA token with the Ed25519 key of its `kid`, biscuit has no EdDSA support
+fn verify_eddsa(
+    jwks: &JWKSet<Empty>,
+    token: &str,
+    header: &RawHeader,
+) -> Result<ClaimsSet<Value>, OIDCValidationError> {
+    let kid = header
+        .kid
+        .as_deref()
+        .ok_or(BiscuitError::ValidationError(ValidationError::KidMissing))?;
+    let jwk = jwks
+        .find(kid)
+        .ok_or(BiscuitError::ValidationError(ValidationError::KeyNotFound))?;
+    let public_key = match &jwk.algorithm {
+        AlgorithmParameters::OctetKeyPair(okp) if okp.curve == EllipticCurve::Curve25519 => &okp.x,
+        _ => {
+            return Err(
+                BiscuitError::ValidationError(ValidationError::UnsupportedKeyAlgorithm).into(),
+            )
+        }
+    };
+
+    let compact = Compact::decode(token);
+    let signature: Vec<u8> = compact.part(2)?;
+    let (signing_input, _) = token.rsplit_once('.').unwrap_or_default();
+    UnparsedPublicKey::new(&ED25519, public_key)
+        .verify(signing_input.as_bytes(), &signature)
+        .map_err(|_| BiscuitError::ValidationError(ValidationError::InvalidSignature))?;
+    Ok(compact.part(1)?)
+}
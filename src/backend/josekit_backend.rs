@@ -0,0 +1,79 @@
+use biscuit::{
+    errors::{Error as BiscuitError, ValidationError},
+    jwk::JWKSet,
+    ClaimsSet, Empty,
+};
+use josekit::{
+    jwk::Jwk,
+    jws::{EdDSA, JwsVerifier, ES256, ES384, ES512, PS256, PS384, PS512, RS256, RS384, RS512},
+    jwt,
+};
+use serde_json::Value;
+
+use super::{backend_error, VerificationBackend};
+use crate::OIDCValidationError;
+
+/// Backend verifying tokens with the `josekit` crate.
+#[derive(Clone, Copy)]
+pub(crate) struct JosekitBackend;
+
+impl VerificationBackend for JosekitBackend {
+    fn verify(
+        &self,
+        jwks: &JWKSet<Empty>,
+        token: &str,
+    ) -> Result<ClaimsSet<Value>, OIDCValidationError> {
+        let header = jwt::decode_header(token).map_err(backend_error)?;
+        let key_id = header
+            .claim("kid")
+            .and_then(Value::as_str)
+            .ok_or(BiscuitError::ValidationError(ValidationError::KidMissing))?;
+        let jwk = jwks
+            .find(key_id)
+            .ok_or(BiscuitError::ValidationError(ValidationError::KeyNotFound))?;
+        let jwk = Jwk::from_bytes(serde_json::to_vec(jwk).map_err(backend_error)?)
+            .map_err(backend_error)?;
+        // The `alg` of the key takes precedence over the header, HMAC is never accepted
+        let algorithm = jwk
+            .algorithm()
+            .or_else(|| header.claim("alg").and_then(Value::as_str));
+        let verifier: Box<dyn JwsVerifier> = match algorithm {
+            Some("RS384") => Box::new(RS384.verifier_from_jwk(&jwk).map_err(backend_error)?),
+            Some("RS512") => Box::new(RS512.verifier_from_jwk(&jwk).map_err(backend_error)?),
+            Some("PS256") => Box::new(PS256.verifier_from_jwk(&jwk).map_err(backend_error)?),
+            Some("PS384") => Box::new(PS384.verifier_from_jwk(&jwk).map_err(backend_error)?),
+            Some("PS512") => Box::new(PS512.verifier_from_jwk(&jwk).map_err(backend_error)?),
+            Some("ES256") => Box::new(ES256.verifier_from_jwk(&jwk).map_err(backend_error)?),
+            Some("ES384") => Box::new(ES384.verifier_from_jwk(&jwk).map_err(backend_error)?),
+            Some("ES512") => Box::new(ES512.verifier_from_jwk(&jwk).map_err(backend_error)?),
+            Some("EdDSA") => Box::new(EdDSA.verifier_from_jwk(&jwk).map_err(backend_error)?),
+            _ => Box::new(RS256.verifier_from_jwk(&jwk).map_err(backend_error)?),
+        };
+
+        let (payload, _header) =
+            jwt::decode_with_verifier(token, verifier.as_ref()).map_err(backend_error)?;
+        let claims: serde_json::Map<String, Value> = payload.into();
+        serde_json::from_value(Value::Object(claims)).map_err(backend_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{create_jwk_set, create_jwt_token};
+
+    #[test]
+    fn test_josekit_backend_verifies_token() {
+        let claims = JosekitBackend
+            .verify(&create_jwk_set(), &create_jwt_token())
+            .unwrap();
+        assert_eq!(claims.private["name"], "admin");
+    }
+
+    #[test]
+    fn test_josekit_backend_rejects_bad_token() {
+        assert!(JosekitBackend
+            .verify(&create_jwk_set(), "bad_token")
+            .is_err());
+    }
+}
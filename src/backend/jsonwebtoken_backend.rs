@@ -0,0 +1,115 @@
+use biscuit::{
+    errors::{Error as BiscuitError, ValidationError},
+    jwk::JWKSet,
+    ClaimsSet, Empty,
+};
+use jsonwebtoken::{jwk::Jwk, Algorithm, DecodingKey, Validation};
+use serde_json::Value;
+
+use super::{backend_error, VerificationBackend};
+use crate::OIDCValidationError;
+
+/// Backend verifying tokens with the `jsonwebtoken` crate.
+#[derive(Clone, Copy)]
+pub(crate) struct JsonWebTokenBackend;
+
+impl VerificationBackend for JsonWebTokenBackend {
+    fn verify(
+        &self,
+        jwks: &JWKSet<Empty>,
+        token: &str,
+    ) -> Result<ClaimsSet<Value>, OIDCValidationError> {
+        #[cfg(feature = "fips")]
+        fips::ensure_provider()?;
+
+        let header = jsonwebtoken::decode_header(token).map_err(backend_error)?;
+        let key_id = header
+            .kid
+            .ok_or(BiscuitError::ValidationError(ValidationError::KidMissing))?;
+        let jwk = jwks
+            .find(&key_id)
+            .ok_or(BiscuitError::ValidationError(ValidationError::KeyNotFound))?;
+        // Both crates follow RFC 7517, so the key is converted through its JSON representation
+        let jwk: Jwk = serde_json::from_value(serde_json::to_value(jwk).map_err(backend_error)?)
+            .map_err(backend_error)?;
+        let key = DecodingKey::from_jwk(&jwk).map_err(backend_error)?;
+
+        // The `alg` of the key takes precedence over the header, HMAC is never accepted
+        let algorithm = match jwk.common.key_algorithm {
+            Some(key_algorithm) => key_algorithm.to_string().parse().map_err(backend_error)?,
+            None => header.alg,
+        };
+        let algorithm = match algorithm {
+            Algorithm::RS384
+            | Algorithm::RS512
+            | Algorithm::PS256
+            | Algorithm::PS384
+            | Algorithm::PS512
+            | Algorithm::ES256
+            | Algorithm::ES384
+            | Algorithm::EdDSA => algorithm,
+            _ => Algorithm::RS256,
+        };
+
+        // Only the signature is verified here, registered claims are validated by the middleware
+        let mut validation = Validation::new(algorithm);
+        validation.required_spec_claims.clear();
+        validation.validate_exp = false;
+        validation.validate_aud = false;
+
+        let token_data = jsonwebtoken::decode::<ClaimsSet<Value>>(token, &key, &validation)
+            .map_err(backend_error)?;
+        Ok(token_data.claims)
+    }
+}
+
+#[cfg(feature = "fips")]
+mod fips {
+    use std::sync::OnceLock;
+
+    use jsonwebtoken::crypto::aws_lc::DEFAULT_PROVIDER;
+
+    use super::backend_error;
+    use crate::OIDCValidationError;
+
+    static FIPS_PROVIDER: OnceLock<Result<(), String>> = OnceLock::new();
+
+    /// Makes sure every signature is verified by aws-lc running in FIPS mode.
+    ///
+    /// Fails when the module is not in FIPS mode or when the application already installed
+    /// another jsonwebtoken crypto provider for the process.
+    pub(super) fn ensure_provider() -> Result<(), OIDCValidationError> {
+        FIPS_PROVIDER
+            .get_or_init(|| {
+                aws_lc_rs::try_fips_mode().map_err(|e| e.to_string())?;
+                match DEFAULT_PROVIDER.install_default() {
+                    Ok(()) => Ok(()),
+                    Err(installed) if std::ptr::eq(installed, &DEFAULT_PROVIDER) => Ok(()),
+                    Err(_) => Err("a non FIPS crypto provider is already installed".to_string()),
+                }
+            })
+            .clone()
+            .map_err(backend_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{create_jwk_set, create_jwt_token};
+
+    #[test]
+    fn test_jsonwebtoken_backend_verifies_token() {
+        let claims = JsonWebTokenBackend
+            .verify(&create_jwk_set(), &create_jwt_token())
+            .unwrap();
+        assert_eq!(claims.private["name"], "admin");
+    }
+
+    #[test]
+    fn test_jsonwebtoken_backend_rejects_bad_token() {
+        assert!(JsonWebTokenBackend
+            .verify(&create_jwk_set(), "bad_token")
+            .is_err());
+    }
+}
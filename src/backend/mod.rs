@@ -0,0 +1,46 @@
+//! Signature verification engines.
+//!
+//! The engine is selected at compile time through cargo features. Biscuit is used unless one of
+//! the alternative backend features is enabled; the public API is the same for every backend.
+//! When several backend features are enabled, `fips` takes precedence over `josekit`, which takes
+//! precedence over `jsonwebtoken`. The `fips` feature uses the `jsonwebtoken` backend on top of
+//! the FIPS validated aws-lc module.
+use biscuit::{jwk::JWKSet, ClaimsSet, Empty};
+use serde_json::Value;
+
+use crate::OIDCValidationError;
+
+#[cfg(not(any(feature = "jsonwebtoken", feature = "josekit", feature = "fips")))]
+mod biscuit_backend;
+#[cfg(all(feature = "josekit", not(feature = "fips")))]
+mod josekit_backend;
+#[cfg(any(
+    feature = "fips",
+    all(feature = "jsonwebtoken", not(feature = "josekit"))
+))]
+mod jsonwebtoken_backend;
+
+/// Verifies the signature of a compact JWS token against a key set and returns its claims.
+pub(crate) trait VerificationBackend {
+    fn verify(
+        &self,
+        jwks: &JWKSet<Empty>,
+        token: &str,
+    ) -> Result<ClaimsSet<Value>, OIDCValidationError>;
+}
+
+#[cfg(not(any(feature = "jsonwebtoken", feature = "josekit", feature = "fips")))]
+pub(crate) use biscuit_backend::BiscuitBackend as SelectedBackend;
+#[cfg(all(feature = "josekit", not(feature = "fips")))]
+pub(crate) use josekit_backend::JosekitBackend as SelectedBackend;
+#[cfg(any(
+    feature = "fips",
+    all(feature = "jsonwebtoken", not(feature = "josekit"))
+))]
+pub(crate) use jsonwebtoken_backend::JsonWebTokenBackend as SelectedBackend;
+
+/// Maps an error of a non-biscuit backend onto the crypto error exposed by the crate.
+#[cfg(any(feature = "jsonwebtoken", feature = "josekit", feature = "fips"))]
+pub(crate) fn backend_error(e: impl std::fmt::Display) -> OIDCValidationError {
+    OIDCValidationError::CryptoError(biscuit::errors::Error::GenericError(e.to_string()))
+}
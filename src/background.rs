@@ -0,0 +1,151 @@
+use std::{future::Future, sync::Arc, time::Duration};
+
+use actix_web::rt::{self, task::JoinHandle};
+use futures_util::future::{select, FutureExt, LocalBoxFuture};
+use tokio::sync::Notify;
+
+type Job = Arc<dyn Fn() -> LocalBoxFuture<'static, bool> + Send + Sync>;
+
+/// Handle of background work that runs periodically, e.g. the key refresh of
+/// [`crate::Oidc::spawn_key_refresh`]
+///
+/// The work stops gracefully with [`Self::shutdown`], restarts with a new interval with
+/// [`Self::restart`] and is aborted when the handle is dropped, so tests and hot-reloading
+/// servers don't leak tasks. The work runs on the actix runtime of the thread that spawned it.
+pub struct BackgroundTask {
+    job: Job,
+    interval: Duration,
+    stop: Arc<Notify>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl BackgroundTask {
+    /// Runs the job every interval, until it returns `false` or the task is stopped
+    pub fn spawn<F, Fut>(interval: Duration, job: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = bool> + 'static,
+    {
+        let job: Job = Arc::new(move || job().boxed_local());
+        let stop = Arc::new(Notify::new());
+        BackgroundTask {
+            handle: Some(run(job.clone(), interval, stop.clone())),
+            job,
+            interval,
+            stop,
+        }
+    }
+
+    /// The interval the job runs at
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Returns whether the job still runs
+    pub fn is_running(&self) -> bool {
+        self.handle
+            .as_ref()
+            .is_some_and(|handle| !handle.is_finished())
+    }
+
+    /// Stops the task after the current run of the job finishes
+    pub async fn shutdown(mut self) {
+        self.stop_and_wait().await;
+    }
+
+    /// Stops the task gracefully and starts it again with the new interval
+    pub async fn restart(&mut self, interval: Duration) {
+        self.stop_and_wait().await;
+        self.interval = interval;
+        self.stop = Arc::new(Notify::new());
+        self.handle = Some(run(self.job.clone(), interval, self.stop.clone()));
+    }
+
+    async fn stop_and_wait(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            self.stop.notify_one();
+            // A panic of the job has ended the task already
+            let _ = handle.await;
+        }
+    }
+}
+
+impl Drop for BackgroundTask {
+    fn drop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            handle.abort();
+        }
+    }
+}
+
+fn run(job: Job, interval: Duration, stop: Arc<Notify>) -> JoinHandle<()> {
+    rt::spawn(async move {
+        loop {
+            let stopped = Box::pin(stop.notified());
+            let tick = Box::pin(rt::time::sleep(interval));
+            if matches!(
+                select(stopped, tick).await,
+                futures_util::future::Either::Left(_)
+            ) {
+                break;
+            }
+            if !job().await {
+                break;
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use actix_web::rt::time::sleep;
+
+    use super::BackgroundTask;
+
+    fn counting(runs: &Arc<AtomicUsize>) -> BackgroundTask {
+        let runs = runs.clone();
+        BackgroundTask::spawn(Duration::from_millis(10), move || {
+            let runs = runs.clone();
+            async move {
+                runs.fetch_add(1, Ordering::SeqCst);
+                true
+            }
+        })
+    }
+
+    ///Test the shutdown and restart of a task
+    #[actix_rt::test]
+    async fn test_background_task_lifecycle() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let mut task = counting(&runs);
+        sleep(Duration::from_millis(100)).await;
+        assert!(task.is_running());
+        assert!(runs.load(Ordering::SeqCst) > 0);
+
+        task.restart(Duration::from_secs(3600)).await;
+        assert_eq!(task.interval(), Duration::from_secs(3600));
+        let after_restart = runs.load(Ordering::SeqCst);
+        sleep(Duration::from_millis(50)).await;
+        assert_eq!(runs.load(Ordering::SeqCst), after_restart);
+        assert!(task.is_running());
+
+        task.shutdown().await;
+    }
+
+    ///Test that dropping the handle stops the task
+    #[actix_rt::test]
+    async fn test_background_task_dropped() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        drop(counting(&runs));
+        sleep(Duration::from_millis(50)).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 0);
+    }
+}
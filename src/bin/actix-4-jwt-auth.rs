@@ -0,0 +1,127 @@
+//! Generates dev keys, mints and inspects tokens and queries the keys of an issuer, with the
+//! plumbing of the crate
+
+use std::{
+    fs,
+    io::Read,
+    process::ExitCode,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use actix_4_jwt_auth::{dangerous_peek_claims, DevKeyPair, Oidc, OidcConfig};
+use serde_json::{json, Value};
+
+const USAGE: &str = "\
+Usage: actix-4-jwt-auth <command> [options]
+
+Commands:
+  keygen <key.der> [--kid <kid>]         Generates a dev key pair, writes the private key and prints the JWKS
+  jwks <key.der> [--kid <kid>]           Prints the JWKS of a dev key
+  mint <key.der> <claims> [--kid <kid>] [--expires-in <seconds>]
+                                         Signs the claims (JSON, or - for stdin) as RS256 token
+  inspect <token>                        Prints the header and claims of a token WITHOUT verifying it
+  issuer-jwks <issuer>                   Discovers and prints the keys of an issuer";
+
+const DEFAULT_KID: &str = "dev";
+const DEFAULT_EXPIRES_IN: u64 = 3600;
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(&args) {
+        Ok(output) => {
+            println!("{}", output);
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: &[String]) -> Result<String, String> {
+    let (positional, kid, expires_in) = parse(args)?;
+    let positional: Vec<&str> = positional.iter().map(String::as_str).collect();
+    match positional.as_slice() {
+        ["keygen", path] => {
+            let key = DevKeyPair::generate(kid).map_err(|e| e.to_string())?;
+            fs::write(path, key.to_der()).map_err(|e| format!("cannot write {}: {}", path, e))?;
+            pretty(&key.jwks())
+        }
+        ["jwks", path] => pretty(&load(path, kid)?.jwks()),
+        ["mint", path, claims] => {
+            let key = load(path, kid)?;
+            let claims = match *claims {
+                "-" => {
+                    let mut claims = String::new();
+                    std::io::stdin()
+                        .read_to_string(&mut claims)
+                        .map_err(|e| format!("cannot read claims: {}", e))?;
+                    claims
+                }
+                claims => claims.to_string(),
+            };
+            let claims = with_lifetime(
+                serde_json::from_str(&claims).map_err(|e| format!("invalid claims: {}", e))?,
+                expires_in,
+            );
+            let claims = serde_json::from_value(claims).map_err(|e| e.to_string())?;
+            key.mint(claims).map_err(|e| e.to_string())
+        }
+        ["inspect", token] => {
+            let token = dangerous_peek_claims(token).map_err(|e| e.to_string())?;
+            pretty(&json!({ "header": token.header, "claims": token.payload }))
+        }
+        ["issuer-jwks", issuer] => actix_web::rt::System::new().block_on(async {
+            let oidc = Oidc::new(OidcConfig::Issuer(issuer.to_string().into()))
+                .await
+                .map_err(|e| e.to_string())?;
+            pretty(&oidc.key_set().as_deref())
+        }),
+        _ => Err(USAGE.to_string()),
+    }
+}
+
+/// Splits the arguments in the positional ones and the `--kid` and `--expires-in` options
+fn parse(args: &[String]) -> Result<(Vec<String>, String, u64), String> {
+    let mut positional = vec![];
+    let mut kid = DEFAULT_KID.to_string();
+    let mut expires_in = DEFAULT_EXPIRES_IN;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--kid" => kid = args.next().ok_or(USAGE)?.clone(),
+            "--expires-in" => {
+                expires_in = args
+                    .next()
+                    .and_then(|seconds| seconds.parse().ok())
+                    .ok_or(USAGE)?
+            }
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            _ => positional.push(arg.clone()),
+        }
+    }
+    Ok((positional, kid, expires_in))
+}
+
+fn load(path: &str, kid: String) -> Result<DevKeyPair, String> {
+    let der = fs::read(path).map_err(|e| format!("cannot read {}: {}", path, e))?;
+    DevKeyPair::from_der(kid, der).map_err(|e| e.to_string())
+}
+
+/// Adds `iat` and `exp` to the claims, unless they are given
+fn with_lifetime(mut claims: Value, expires_in: u64) -> Value {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    if let Some(claims) = claims.as_object_mut() {
+        claims.entry("iat").or_insert(json!(now));
+        claims.entry("exp").or_insert(json!(now + expires_in));
+    }
+    claims
+}
+
+fn pretty(value: &impl serde::Serialize) -> Result<String, String> {
+    serde_json::to_string_pretty(value).map_err(|e| e.to_string())
+}
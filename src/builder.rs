@@ -0,0 +1,334 @@
+use std::{borrow::Cow, sync::Arc, time::Duration};
+
+use biscuit::jwa;
+
+use crate::{
+    AudienceValidator, HttpLimits, IssuerValidator, OIDCValidationError, Oidc, OidcConfig,
+    TokenLookup,
+};
+
+/// Configures an [`Oidc`] step by step, see [`Oidc::builder`]
+///
+/// The combination of the settings is checked by [`Self::build`], before the identity provider is
+/// contacted, and fails with [`OIDCValidationError::InvalidConfiguration`].
+#[derive(Default)]
+pub struct OidcBuilder {
+    issuer: Option<String>,
+    config: Option<OidcConfig>,
+    audiences: Vec<String>,
+    leeway: Option<Duration>,
+    algorithms: Vec<jwa::SignatureAlgorithm>,
+    token_lookup: Option<TokenLookup>,
+    query_parameter: Option<Cow<'static, str>>,
+    key_refresh: Option<Duration>,
+    http_limits: HttpLimits,
+}
+
+impl Oidc {
+    /// Configures an Oidc with fluent methods, as alternative of [`Oidc::new`] followed by the
+    /// `with_` methods
+    /// ```no_run
+    /// # async fn example() -> Result<(), actix_4_jwt_auth::OIDCValidationError> {
+    /// use std::time::Duration;
+    /// use actix_4_jwt_auth::{biscuit::jwa::SignatureAlgorithm, Oidc};
+    ///
+    /// let oidc = Oidc::builder()
+    ///     .issuer("https://accounts.google.com")
+    ///     .audience("my-api")
+    ///     .leeway(Duration::from_secs(30))
+    ///     .algorithms([SignatureAlgorithm::RS256])
+    ///     .key_refresh(Duration::from_secs(300))
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn builder() -> OidcBuilder {
+        OidcBuilder::default()
+    }
+}
+
+impl OidcBuilder {
+    /// Discovers the keys of the issuer, and accepts only tokens with the issuer as `iss`
+    ///
+    /// A trailing slash of the issuer or the `iss` claim is ignored, like in the discovery.
+    ///
+    /// Use [`Self::config`] with [`OidcConfig::Issuer`] when the identity provider puts another
+    /// `iss` in its tokens than its base URL.
+    pub fn issuer(mut self, issuer: impl Into<String>) -> Self {
+        self.issuer = Some(issuer.into());
+        self
+    }
+
+    /// Takes the keys from the configuration, e.g. key URLs or a fixed key set, instead of an
+    /// issuer
+    pub fn config(mut self, config: OidcConfig) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Accepts the tokens for the audience, can be called for several audiences
+    pub fn audience(mut self, audience: impl Into<String>) -> Self {
+        self.audiences.push(audience.into());
+        self
+    }
+
+    /// Accepts the tokens for one of the audiences
+    pub fn audiences<I>(mut self, audiences: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        self.audiences.extend(audiences.into_iter().map(Into::into));
+        self
+    }
+
+    /// Accepts `exp`, `nbf` and `iat` claims that are off by no more than the leeway, for the
+    /// clock skew with the identity provider, see [`Oidc::with_leeway`]
+    ///
+    /// The claims are checked by the [`OidcBiscuitValidator`](crate::OidcBiscuitValidator), which
+    /// uses this leeway unless it is configured with its own.
+    pub fn leeway(mut self, leeway: Duration) -> Self {
+        self.leeway = Some(leeway);
+        self
+    }
+
+    /// Accepts only tokens signed with one of the algorithms, see
+    /// [`Oidc::with_allowed_algorithms`]
+    pub fn algorithms(
+        mut self,
+        algorithms: impl IntoIterator<Item = jwa::SignatureAlgorithm>,
+    ) -> Self {
+        self.algorithms.extend(algorithms);
+        self
+    }
+
+    /// Reads the token from another header or a cookie instead of the `Authorization` header
+    pub fn token_lookup(mut self, token_lookup: TokenLookup) -> Self {
+        self.token_lookup = Some(token_lookup);
+        self
+    }
+
+    /// Accepts the token in the query parameter when the token lookup finds no token, see
+    /// [`Oidc::with_query_parameter`]
+    pub fn query_parameter(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.query_parameter = Some(name.into());
+        self
+    }
+
+    /// Refreshes the keys every interval for as long as the Oidc or one of its clones lives, see
+    /// [`Oidc::spawn_key_refresh`]
+    ///
+    /// Needs keys fetched from the identity provider or a file.
+    pub fn key_refresh(mut self, interval: Duration) -> Self {
+        self.key_refresh = Some(interval);
+        self
+    }
+
+    /// Limits the calls to the identity provider, including the discovery and the first key fetch
+    /// of [`Self::build`]
+    pub fn http_limits(mut self, limits: HttpLimits) -> Self {
+        self.http_limits = limits;
+        self
+    }
+
+    /// Checks the combination of the settings and creates the Oidc, the keys are fetched
+    pub async fn build(self) -> Result<Oidc, OIDCValidationError> {
+        self.validate()?;
+        let OidcBuilder {
+            issuer,
+            config,
+            audiences,
+            leeway,
+            algorithms,
+            token_lookup,
+            query_parameter,
+            key_refresh,
+            http_limits,
+        } = self;
+        let config = match (&issuer, config) {
+            (Some(issuer), _) => OidcConfig::Issuer(issuer.clone().into()),
+            (None, Some(config)) => config,
+            (None, None) => unreachable!("validated to have a source of the keys"),
+        };
+        let token_lookup = token_lookup.unwrap_or(TokenLookup::Header(
+            actix_web::http::header::AUTHORIZATION.as_str().into(),
+        ));
+
+        let mut oidc = Oidc::new_with_limits(config, token_lookup, http_limits).await?;
+        if let Some(name) = query_parameter {
+            oidc = oidc.with_query_parameter(name);
+        }
+        if !algorithms.is_empty() {
+            oidc = oidc.with_allowed_algorithms(algorithms);
+        }
+        if let Some(leeway) = leeway {
+            oidc = oidc.with_leeway(leeway);
+        }
+        if let Some(issuer) = issuer {
+            oidc = oidc.with_claims_validator(IssuerValidator::new(issuer));
+        }
+        if !audiences.is_empty() {
+            oidc = oidc.with_claims_validator(AudienceValidator::new(audiences));
+        }
+        if let Some(interval) = key_refresh {
+            oidc.key_refresh = Some(Arc::new(oidc.spawn_key_refresh(interval)));
+        }
+        Ok(oidc)
+    }
+
+    fn validate(&self) -> Result<(), OIDCValidationError> {
+        let invalid =
+            |message: &str| Err(OIDCValidationError::InvalidConfiguration(message.into()));
+        let config = match (&self.issuer, &self.config) {
+            (None, None) => return invalid("an issuer or a configuration is required"),
+            (Some(_), Some(_)) => return invalid("set either an issuer or a configuration"),
+            (Some(_), None) => None,
+            (None, Some(config)) => Some(config),
+        };
+        if self.audiences.iter().any(String::is_empty) {
+            return invalid("audiences must not be empty");
+        }
+        if self.http_limits.timeout.is_zero() {
+            return invalid("HTTP timeout must be positive");
+        }
+        match (self.key_refresh, config) {
+            (Some(interval), _) if interval.is_zero() => {
+                invalid("key refresh interval must be positive")
+            }
+            (
+                Some(_),
+                Some(
+                    OidcConfig::Jwks(_)
+                    | OidcConfig::JwksJson(_)
+                    | OidcConfig::Issuers(_)
+                    | OidcConfig::IssuerPatterns(_)
+                    | OidcConfig::Validator(_),
+                ),
+            ) => invalid("key refresh needs keys fetched from the identity provider or a file"),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use actix_web::{dev::Service, get, http::StatusCode, test, App};
+    use biscuit::jwa::SignatureAlgorithm;
+    use serde_json::json;
+
+    use crate::{
+        tests::{create_get_jwt_request, create_jwk_set, create_token, start_test_idp},
+        DecodedInfo, OIDCValidationError, Oidc, OidcBiscuitValidator, OidcConfig,
+    };
+
+    #[get("/decoder")]
+    async fn decoder(_: DecodedInfo) -> String {
+        String::new()
+    }
+
+    ///Test that the builder validates the tokens with the configured issuer and audience
+    #[actix_rt::test]
+    async fn test_builder() {
+        let issuer = start_test_idp().await;
+        let oidc = Oidc::builder()
+            .issuer(issuer.clone())
+            .audience("api")
+            .leeway(Duration::from_secs(30))
+            .algorithms([SignatureAlgorithm::RS256])
+            .key_refresh(Duration::from_secs(300))
+            .build()
+            .await
+            .unwrap();
+        assert!(oidc.key_refresh.as_ref().unwrap().is_running());
+
+        let exp = chrono::Utc::now().timestamp() + 300;
+        let token = create_token(json!({ "iss": issuer, "aud": "api", "exp": exp }));
+        assert!(oidc.validate_token(&token).await.is_ok());
+        let token = create_token(json!({ "iss": issuer, "aud": "other", "exp": exp }));
+        assert!(oidc.validate_token(&token).await.is_err());
+        let token = create_token(json!({ "iss": "http://other", "aud": "api", "exp": exp }));
+        assert!(oidc.validate_token(&token).await.is_err());
+        // the leeway does not make exp required
+        let token = create_token(json!({ "iss": issuer, "aud": "api" }));
+        assert!(oidc.validate_token(&token).await.is_ok());
+    }
+
+    ///Test that the leeway of the builder is the one of the middleware, and that a trailing slash
+    ///of the issuer is ignored
+    #[actix_rt::test]
+    async fn test_builder_leeway() {
+        let issuer = start_test_idp().await;
+        let oidc = Oidc::builder()
+            .issuer(format!("{}/", issuer))
+            .leeway(Duration::from_secs(60))
+            .build()
+            .await
+            .unwrap();
+        let app = |validator: OidcBiscuitValidator| {
+            test::init_service(
+                App::new()
+                    .app_data(oidc.clone())
+                    .wrap(validator)
+                    .service(decoder),
+            )
+        };
+        let shared = app(OidcBiscuitValidator::default()).await;
+        let strict = app(OidcBiscuitValidator::default().with_leeway(Duration::ZERO)).await;
+        let now = chrono::Utc::now().timestamp();
+
+        for (claims, shared_status) in [
+            (json!({ "iss": issuer, "exp": now - 30 }), StatusCode::OK),
+            (json!({ "iss": issuer, "nbf": now + 30 }), StatusCode::OK),
+            (json!({ "iss": issuer, "iat": now + 30 }), StatusCode::OK),
+            (
+                json!({ "iss": issuer, "exp": now - 120 }),
+                StatusCode::UNAUTHORIZED,
+            ),
+        ] {
+            let token = create_token(claims);
+            let req = create_get_jwt_request("/decoder", &token).to_request();
+            assert_eq!(shared.call(req).await.unwrap().status(), shared_status);
+            let req = create_get_jwt_request("/decoder", &token).to_request();
+            assert_eq!(
+                strict.call(req).await.unwrap().status(),
+                StatusCode::UNAUTHORIZED
+            );
+        }
+    }
+
+    ///Test that the builder rejects invalid combinations before contacting the identity provider
+    #[actix_rt::test]
+    async fn test_builder_invalid() {
+        let rejection = |built: Result<Oidc, OIDCValidationError>| match built {
+            Err(OIDCValidationError::InvalidConfiguration(message)) => message,
+            _ => panic!("the configuration is accepted"),
+        };
+        assert_eq!(
+            rejection(Oidc::builder().audience("api").build().await),
+            "an issuer or a configuration is required"
+        );
+        assert_eq!(
+            rejection(
+                Oidc::builder()
+                    .config(OidcConfig::Jwks(create_jwk_set()))
+                    .key_refresh(Duration::from_secs(300))
+                    .build()
+                    .await
+            ),
+            "key refresh needs keys fetched from the identity provider or a file"
+        );
+        assert_eq!(
+            rejection(
+                Oidc::builder()
+                    .issuer("http://127.0.0.1:1")
+                    .key_refresh(Duration::ZERO)
+                    .build()
+                    .await
+            ),
+            "key refresh interval must be positive"
+        );
+    }
+}
@@ -0,0 +1,206 @@
+use std::{
+    future::Future,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::OIDCValidationError;
+
+/// What to do with a call to the identity provider while the circuit is open
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum FallbackPolicy {
+    /// Fail the call with [`OIDCValidationError::CircuitOpen`]
+    #[default]
+    Reject,
+    /// Continue with the data that was fetched before (e.g. the cached keys)
+    AcceptCached,
+}
+
+/// Thresholds of the circuit breaker around outbound calls to the identity provider
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct CircuitBreakerConfig {
+    /// Number of consecutive failures that opens the circuit
+    pub failure_threshold: u32,
+    /// How long the circuit stays open before a single trial call is let through
+    pub open_duration: Duration,
+    /// What to do with calls while the circuit is open
+    pub fallback: FallbackPolicy,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        CircuitBreakerConfig {
+            failure_threshold: 5,
+            open_duration: Duration::from_secs(30),
+            fallback: FallbackPolicy::default(),
+        }
+    }
+}
+
+/// State of the circuit breaker
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum CircuitState {
+    /// Calls are passed through, with the number of consecutive failures so far
+    Closed(u32),
+    /// Calls are short-circuited until the given instant
+    Open(Instant),
+    /// A single trial call is in progress
+    HalfOpen,
+}
+
+/// Stops calling the identity provider after repeated failures, so an IdP brownout does not
+/// pile up requests waiting for it.
+pub(crate) struct CircuitBreaker {
+    pub(crate) config: CircuitBreakerConfig,
+    state: Mutex<CircuitState>,
+}
+
+impl CircuitBreaker {
+    pub(crate) fn new(config: CircuitBreakerConfig) -> Self {
+        CircuitBreaker {
+            config,
+            state: Mutex::new(CircuitState::Closed(0)),
+        }
+    }
+
+    pub(crate) fn state(&self) -> CircuitState {
+        *self.state.lock().unwrap()
+    }
+
+    /// Runs the call unless the circuit is open and records its outcome
+    ///
+    /// A trial call that is dropped before it completes, e.g. because the request was cancelled,
+    /// counts as failed, so the circuit does not stay half-open.
+    pub(crate) async fn call<T, F>(&self, call: F) -> Result<T, OIDCValidationError>
+    where
+        F: Future<Output = Result<T, OIDCValidationError>>,
+    {
+        let trial = self.acquire()?;
+        let mut outcome = Outcome {
+            breaker: self,
+            trial,
+            recorded: false,
+        };
+        let result = call.await;
+        outcome.recorded = true;
+        self.record(result.is_ok());
+        result
+    }
+
+    /// Returns whether the call is the trial call of a half-open circuit
+    fn acquire(&self) -> Result<bool, OIDCValidationError> {
+        let mut state = self.state.lock().unwrap();
+        match *state {
+            CircuitState::Closed(_) => Ok(false),
+            CircuitState::Open(until) if Instant::now() >= until => {
+                *state = CircuitState::HalfOpen;
+                Ok(true)
+            }
+            CircuitState::Open(_) | CircuitState::HalfOpen => Err(OIDCValidationError::CircuitOpen),
+        }
+    }
+
+    fn record(&self, success: bool) {
+        let mut state = self.state.lock().unwrap();
+        *state = match (*state, success) {
+            (_, true) => CircuitState::Closed(0),
+            (CircuitState::Closed(failures), false)
+                if failures + 1 < self.config.failure_threshold =>
+            {
+                CircuitState::Closed(failures + 1)
+            }
+            (_, false) => CircuitState::Open(Instant::now() + self.config.open_duration),
+        };
+    }
+}
+
+/// Records a failure when a trial call is dropped before its outcome is recorded
+struct Outcome<'a> {
+    breaker: &'a CircuitBreaker,
+    trial: bool,
+    recorded: bool,
+}
+
+impl Drop for Outcome<'_> {
+    fn drop(&mut self) {
+        if self.trial && !self.recorded {
+            self.breaker.record(false);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{CircuitBreaker, CircuitBreakerConfig, CircuitState};
+    use crate::OIDCValidationError;
+
+    fn breaker() -> CircuitBreaker {
+        CircuitBreaker::new(CircuitBreakerConfig {
+            failure_threshold: 2,
+            open_duration: Duration::from_millis(50),
+            ..CircuitBreakerConfig::default()
+        })
+    }
+
+    async fn failing() -> Result<(), OIDCValidationError> {
+        Err(OIDCValidationError::Unauthorized)
+    }
+
+    async fn succeeding() -> Result<(), OIDCValidationError> {
+        Ok(())
+    }
+
+    #[actix_rt::test]
+    async fn test_circuit_opens_after_threshold() {
+        let breaker = breaker();
+        assert!(breaker.call(failing()).await.is_err());
+        assert_eq!(breaker.state(), CircuitState::Closed(1));
+        assert!(breaker.call(failing()).await.is_err());
+        assert!(matches!(breaker.state(), CircuitState::Open(_)));
+
+        let res = breaker.call(succeeding()).await;
+        assert!(matches!(res, Err(OIDCValidationError::CircuitOpen)));
+    }
+
+    #[actix_rt::test]
+    async fn test_circuit_closes_after_successful_trial() {
+        let breaker = breaker();
+        let _ = breaker.call(failing()).await;
+        let _ = breaker.call(failing()).await;
+
+        actix_rt::time::sleep(Duration::from_millis(60)).await;
+        assert!(breaker.call(succeeding()).await.is_ok());
+        assert_eq!(breaker.state(), CircuitState::Closed(0));
+    }
+
+    #[actix_rt::test]
+    async fn test_circuit_reopens_after_failed_trial() {
+        let breaker = breaker();
+        let _ = breaker.call(failing()).await;
+        let _ = breaker.call(failing()).await;
+
+        actix_rt::time::sleep(Duration::from_millis(60)).await;
+        assert!(breaker.call(failing()).await.is_err());
+        assert!(matches!(breaker.state(), CircuitState::Open(_)));
+    }
+
+    ///Test that a cancelled trial call reopens the circuit instead of leaving it half-open
+    #[actix_rt::test]
+    async fn test_circuit_reopens_after_cancelled_trial() {
+        let breaker = breaker();
+        let _ = breaker.call(failing()).await;
+        let _ = breaker.call(failing()).await;
+
+        actix_rt::time::sleep(Duration::from_millis(60)).await;
+        let trial = breaker.call(std::future::pending::<Result<(), OIDCValidationError>>());
+        let cancelled = actix_rt::time::timeout(Duration::from_millis(10), trial).await;
+        assert!(cancelled.is_err());
+        assert!(matches!(breaker.state(), CircuitState::Open(_)));
+
+        actix_rt::time::sleep(Duration::from_millis(60)).await;
+        assert!(breaker.call(succeeding()).await.is_ok());
+        assert_eq!(breaker.state(), CircuitState::Closed(0));
+    }
+}
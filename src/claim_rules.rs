@@ -0,0 +1,353 @@
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{DecodedInfo, OIDCValidationError};
+
+/// Comparison of a claim rule
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ClaimOperator {
+    /// The claim must have the value
+    Equals,
+    /// The claim must be absent or have another value
+    NotEquals,
+}
+
+/// Declarative rule on a claim, for policy tweaks that shouldn't need code changes
+///
+/// Rules are written as `claims.<path> == <json>` or `claims.<path> != <json>`, e.g.
+/// `claims.email_verified == true` or `claims.env != "staging"`. The path is dotted, e.g.
+/// `claims.realm_access.active`, or a JSON pointer like `/realm_access/active`. Rules are
+/// deserialized from their string form, so they can be configured.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ClaimRule {
+    pointer: String,
+    operator: ClaimOperator,
+    value: Value,
+}
+
+impl ClaimRule {
+    /// Requires the claim at the JSON pointer to have the value
+    pub fn equals(pointer: impl Into<String>, value: Value) -> Self {
+        ClaimRule {
+            pointer: pointer.into(),
+            operator: ClaimOperator::Equals,
+            value,
+        }
+    }
+
+    /// Forbids the claim at the JSON pointer to have the value
+    pub fn not_equals(pointer: impl Into<String>, value: Value) -> Self {
+        ClaimRule {
+            pointer: pointer.into(),
+            operator: ClaimOperator::NotEquals,
+            value,
+        }
+    }
+
+    /// Returns whether the claims of the token meet the rule
+    pub fn holds(&self, info: &DecodedInfo) -> bool {
+        let claim = info.claim_value(&self.pointer);
+        match self.operator {
+            ClaimOperator::Equals => claim.as_ref() == Some(&self.value),
+            ClaimOperator::NotEquals => claim.as_ref() != Some(&self.value),
+        }
+    }
+}
+
+/// Converts `claims.a.b` to the JSON pointer `/a/b`
+fn pointer_of(path: &str) -> Option<String> {
+    if path.starts_with('/') {
+        return Some(path.to_string());
+    }
+    let path = path.strip_prefix("claims.")?;
+    if path.split('.').any(str::is_empty) {
+        return None;
+    }
+    Some(
+        path.split('.')
+            .map(|segment| format!("/{}", segment.replace('~', "~0").replace('/', "~1")))
+            .collect(),
+    )
+}
+
+impl FromStr for ClaimRule {
+    type Err = OIDCValidationError;
+
+    fn from_str(rule: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            OIDCValidationError::InvalidConfiguration(format!(
+                "claim rule {:?} must be `claims.<path> == <json>` or `claims.<path> != <json>`",
+                rule
+            ))
+        };
+        // the first operator ends the path, the value may contain operators itself
+        let (index, operator) = [
+            ("==", ClaimOperator::Equals),
+            ("!=", ClaimOperator::NotEquals),
+        ]
+        .into_iter()
+        .filter_map(|(token, operator)| Some((rule.find(token)?, operator)))
+        .min_by_key(|(index, _)| *index)
+        .ok_or_else(invalid)?;
+        let (path, value) = (&rule[..index], &rule[index + 2..]);
+        Ok(ClaimRule {
+            pointer: pointer_of(path.trim()).ok_or_else(invalid)?,
+            operator,
+            value: serde_json::from_str(value.trim()).map_err(|_| invalid())?,
+        })
+    }
+}
+
+impl TryFrom<String> for ClaimRule {
+    type Error = OIDCValidationError;
+
+    fn try_from(rule: String) -> Result<Self, Self::Error> {
+        rule.parse()
+    }
+}
+
+impl From<ClaimRule> for String {
+    fn from(rule: ClaimRule) -> Self {
+        rule.to_string()
+    }
+}
+
+impl fmt::Display for ClaimRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let operator = match self.operator {
+            ClaimOperator::Equals => "==",
+            ClaimOperator::NotEquals => "!=",
+        };
+        write!(f, "{} {} {}", self.pointer, operator, self.value)
+    }
+}
+
+/// A claim every validated token must carry, optionally with an expected value
+///
+/// The claim is a JSON pointer, e.g. `/tenant/id`, or the name of a top-level claim, e.g.
+/// `tenant_id`. A string converts into a claim that must be present and not null:
+///
+/// ```ignore
+/// let oidc = oidc.with_required_claims([
+///     RequiredClaim::equals("email_verified", true),
+///     RequiredClaim::present("tenant_id"),
+/// ]);
+/// ```
+#[derive(Debug, PartialEq, Clone)]
+pub struct RequiredClaim {
+    pointer: String,
+    value: Option<Value>,
+}
+
+impl RequiredClaim {
+    /// The claim must be present and not null
+    pub fn present(claim: impl Into<String>) -> Self {
+        let claim = claim.into();
+        let pointer = match claim.starts_with('/') {
+            true => claim,
+            false => format!("/{}", claim),
+        };
+        RequiredClaim {
+            pointer,
+            value: None,
+        }
+    }
+
+    /// The claim must have the value
+    pub fn equals(claim: impl Into<String>, value: impl Into<Value>) -> Self {
+        RequiredClaim {
+            value: Some(value.into()),
+            ..RequiredClaim::present(claim)
+        }
+    }
+
+    /// The JSON pointer of the claim
+    pub fn pointer(&self) -> &str {
+        &self.pointer
+    }
+
+    fn check(&self, info: &DecodedInfo) -> Result<(), OIDCValidationError> {
+        match (info.claim_value(&self.pointer), &self.value) {
+            (None | Some(Value::Null), _) => {
+                Err(OIDCValidationError::MissingClaim(self.pointer.clone()))
+            }
+            (Some(actual), Some(expected)) if actual != *expected => Err(
+                OIDCValidationError::ClaimRuleFailed(format!("{} == {}", self.pointer, expected)),
+            ),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl From<&str> for RequiredClaim {
+    fn from(claim: &str) -> Self {
+        RequiredClaim::present(claim)
+    }
+}
+
+impl From<String> for RequiredClaim {
+    fn from(claim: String) -> Self {
+        RequiredClaim::present(claim)
+    }
+}
+
+/// Checks that the required claims are present and have their expected values
+pub(crate) fn check_required(
+    required: &[RequiredClaim],
+    info: &DecodedInfo,
+) -> Result<(), OIDCValidationError> {
+    required.iter().try_for_each(|claim| claim.check(info))
+}
+
+/// Checks all rules, the first rule that doesn't hold rejects the token
+pub(crate) fn check(rules: &[ClaimRule], info: &DecodedInfo) -> Result<(), OIDCValidationError> {
+    match rules.iter().find(|rule| !rule.holds(info)) {
+        Some(rule) => Err(OIDCValidationError::ClaimRuleFailed(rule.to_string())),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{get, http::StatusCode, test, App, Error};
+    use serde_json::{json, Value};
+
+    use super::{ClaimRule, RequiredClaim};
+    use crate::{
+        tests::{create_get_jwt_request, create_oidc, create_token},
+        DecodedInfo,
+    };
+
+    #[get("/decoder")]
+    async fn decoder(claims: DecodedInfo) -> String {
+        claims.jwt
+    }
+
+    ///Test parsing and evaluating the rules
+    #[actix_rt::test]
+    async fn test_claim_rules() {
+        let rule: ClaimRule = "claims.email_verified == true".parse().unwrap();
+        assert_eq!(rule, ClaimRule::equals("/email_verified", json!(true)));
+        let rule: ClaimRule =
+            serde_json::from_value(json!("claims.tenant.env != \"staging\"")).unwrap();
+        assert_eq!(rule, ClaimRule::not_equals("/tenant/env", json!("staging")));
+        assert_eq!(rule.to_string(), "/tenant/env != \"staging\"");
+        // the value may contain the other operator
+        assert_eq!(
+            "claims.x != \"a==b\"".parse::<ClaimRule>().unwrap(),
+            ClaimRule::not_equals("/x", json!("a==b"))
+        );
+        assert_eq!(
+            "claims.x == \"a!=b\"".parse::<ClaimRule>().unwrap(),
+            ClaimRule::equals("/x", json!("a!=b"))
+        );
+
+        for rule in [
+            "email_verified == true",
+            "claims.email_verified = true",
+            "claims.env != staging",
+        ] {
+            assert!(rule.parse::<ClaimRule>().is_err());
+        }
+
+        let info = |claims: Value| DecodedInfo {
+            jwt: String::new(),
+            payload: serde_json::from_value(claims).unwrap(),
+        };
+        assert!(rule.holds(&info(json!({ "tenant": { "env": "production" } }))));
+        assert!(rule.holds(&info(json!({}))));
+        assert!(!rule.holds(&info(json!({ "tenant": { "env": "staging" } }))));
+    }
+
+    ///Test that the extractors reject tokens that break a rule
+    #[actix_rt::test]
+    async fn test_claim_rules_extractor() -> Result<(), Error> {
+        let oidc = create_oidc().await.with_claim_rules([
+            "claims.email_verified == true".parse().unwrap(),
+            "claims.env != \"staging\"".parse().unwrap(),
+        ]);
+        let app = test::init_service(App::new().app_data(oidc.clone()).service(decoder)).await;
+
+        for (claims, expected) in [
+            (
+                json!({ "email_verified": true, "env": "production" }),
+                StatusCode::OK,
+            ),
+            (json!({ "email_verified": false }), StatusCode::FORBIDDEN),
+            (
+                json!({ "email_verified": true, "env": "staging" }),
+                StatusCode::FORBIDDEN,
+            ),
+        ] {
+            let req = create_get_jwt_request("/decoder", &create_token(claims)).to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), expected);
+        }
+        Ok(())
+    }
+
+    ///Test that the extractors reject tokens without a required claim
+    #[actix_rt::test]
+    async fn test_required_claims() -> Result<(), Error> {
+        let oidc =
+            create_oidc()
+                .await
+                .with_required_claims(["/email_verified", "/tenant/id", "/sub"]);
+        let app = test::init_service(App::new().app_data(oidc.clone()).service(decoder)).await;
+
+        for (claims, expected) in [
+            (
+                json!({ "sub": "joe", "email_verified": false, "tenant": { "id": "acme" } }),
+                StatusCode::OK,
+            ),
+            (
+                json!({ "sub": "joe", "email_verified": null, "tenant": { "id": "acme" } }),
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                json!({ "sub": "joe", "email_verified": true }),
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                json!({ "email_verified": true, "tenant": { "id": "acme" } }),
+                StatusCode::FORBIDDEN,
+            ),
+        ] {
+            let req = create_get_jwt_request("/decoder", &create_token(claims)).to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), expected);
+        }
+        Ok(())
+    }
+
+    ///Test that required claims with an expected value reject other values
+    #[actix_rt::test]
+    async fn test_required_claim_values() -> Result<(), Error> {
+        let oidc = create_oidc().await.with_required_claims([
+            RequiredClaim::equals("email_verified", true),
+            RequiredClaim::present("tenant_id"),
+        ]);
+        let app = test::init_service(App::new().app_data(oidc.clone()).service(decoder)).await;
+
+        for (claims, expected) in [
+            (
+                json!({ "email_verified": true, "tenant_id": "acme" }),
+                StatusCode::OK,
+            ),
+            (
+                json!({ "email_verified": false, "tenant_id": "acme" }),
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                json!({ "email_verified": "true", "tenant_id": "acme" }),
+                StatusCode::FORBIDDEN,
+            ),
+            (json!({ "email_verified": true }), StatusCode::FORBIDDEN),
+        ] {
+            let req = create_get_jwt_request("/decoder", &create_token(claims)).to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), expected);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,220 @@
+use std::{sync::Arc, time::Duration};
+
+use biscuit::{errors::ValidationError, TemporalOptions, Validation};
+use futures_util::future::{ready, FutureExt, LocalBoxFuture};
+
+use crate::{DecodedInfo, OIDCValidationError, ValidationReport};
+
+/// A check on the claims of a token whose signature is validated, registered in the chain of
+/// [`Oidc::with_claims_validator`](crate::Oidc::with_claims_validator)
+///
+/// The validators run in the order they are registered, the first failure rejects the token.
+/// Implement it for the checks of the application, e.g. the organization of the token or a call
+/// to an entitlement service; the [`ExpiryValidator`], [`AudienceValidator`] and
+/// [`IssuerValidator`] are ready-made entries. They are not the `exp`, `iss` and `aud` checks of
+/// the middleware's validation options, which run separately.
+pub trait ClaimsValidator: Send + Sync {
+    /// Checks the claims of the validated token
+    fn validate<'a>(
+        &'a self,
+        info: &'a DecodedInfo,
+    ) -> LocalBoxFuture<'a, Result<(), OIDCValidationError>>;
+}
+
+/// Reports the failed check of a registered claim like the middleware does
+fn rejected(failure: Result<(), ValidationError>) -> Result<(), OIDCValidationError> {
+    failure.map_err(|failure| {
+        let mut report = ValidationReport::default();
+        report.push(failure);
+        OIDCValidationError::ClaimsRejected(report)
+    })
+}
+
+/// Requires the `exp` claim and rejects expired tokens
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExpiryValidator {
+    leeway: Duration,
+}
+
+impl ExpiryValidator {
+    /// Rejects the tokens from their expiry on
+    pub fn new() -> Self {
+        ExpiryValidator::default()
+    }
+
+    /// Accepts the tokens for the leeway after their expiry, for clock skew
+    pub fn with_leeway(mut self, leeway: Duration) -> Self {
+        self.leeway = leeway;
+        self
+    }
+}
+
+impl ClaimsValidator for ExpiryValidator {
+    fn validate<'a>(
+        &'a self,
+        info: &'a DecodedInfo,
+    ) -> LocalBoxFuture<'a, Result<(), OIDCValidationError>> {
+        let temporal = TemporalOptions {
+            epsilon: chrono::Duration::from_std(self.leeway).unwrap_or(chrono::Duration::MAX),
+            now: None,
+        };
+        let expiry = info
+            .payload
+            .registered
+            .validate_exp(Validation::Validate(temporal));
+        ready(rejected(expiry)).boxed_local()
+    }
+}
+
+/// Requires one of the accepted audiences in the `aud` claim
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AudienceValidator {
+    audiences: Vec<String>,
+}
+
+impl AudienceValidator {
+    /// Accepts the tokens of one of the audiences
+    pub fn new<I>(audiences: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        AudienceValidator {
+            audiences: audiences.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl ClaimsValidator for AudienceValidator {
+    fn validate<'a>(
+        &'a self,
+        info: &'a DecodedInfo,
+    ) -> LocalBoxFuture<'a, Result<(), OIDCValidationError>> {
+        let audience = match &info.payload.registered.audience {
+            Some(audience)
+                if self
+                    .audiences
+                    .iter()
+                    .any(|accepted| audience.contains(accepted)) =>
+            {
+                Ok(())
+            }
+            Some(audience) => Err(ValidationError::InvalidAudience(audience.clone())),
+            None => Err(ValidationError::MissingRequiredClaims(vec!["aud".into()])),
+        };
+        ready(rejected(audience)).boxed_local()
+    }
+}
+
+/// Requires the issuer in the `iss` claim
+///
+/// A trailing slash of the issuer or the claim is ignored, like in the discovery of the issuer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IssuerValidator {
+    issuer: String,
+}
+
+impl IssuerValidator {
+    /// Accepts the tokens of the issuer
+    pub fn new(issuer: impl Into<String>) -> Self {
+        IssuerValidator {
+            issuer: issuer.into(),
+        }
+    }
+}
+
+impl ClaimsValidator for IssuerValidator {
+    fn validate<'a>(
+        &'a self,
+        info: &'a DecodedInfo,
+    ) -> LocalBoxFuture<'a, Result<(), OIDCValidationError>> {
+        let issuer = match &info.payload.registered.issuer {
+            Some(issuer) if issuer.trim_end_matches('/') == self.issuer.trim_end_matches('/') => {
+                Ok(())
+            }
+            Some(issuer) => Err(ValidationError::InvalidIssuer(issuer.clone())),
+            None => Err(ValidationError::MissingRequiredClaims(vec!["iss".into()])),
+        };
+        ready(rejected(issuer)).boxed_local()
+    }
+}
+
+/// Runs the chain in order, up to the first failure
+pub(crate) async fn check(
+    validators: &[Arc<dyn ClaimsValidator>],
+    info: &DecodedInfo,
+) -> Result<(), OIDCValidationError> {
+    for validator in validators {
+        validator.validate(info).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use biscuit::errors::ValidationError;
+    use futures_util::future::{FutureExt, LocalBoxFuture};
+    use serde_json::{json, Value};
+
+    use super::{AudienceValidator, ClaimsValidator, ExpiryValidator, IssuerValidator};
+    use crate::{
+        tests::{create_oidc, create_token},
+        DecodedInfo, OIDCValidationError,
+    };
+
+    struct Organization(&'static str);
+
+    impl ClaimsValidator for Organization {
+        fn validate<'a>(
+            &'a self,
+            info: &'a DecodedInfo,
+        ) -> LocalBoxFuture<'a, Result<(), OIDCValidationError>> {
+            async move {
+                match info.payload.private.get("org_id").and_then(Value::as_str) {
+                    Some(org_id) if org_id == self.0 => Ok(()),
+                    _ => Err(OIDCValidationError::ClaimRuleFailed("org_id".to_string())),
+                }
+            }
+            .boxed_local()
+        }
+    }
+
+    ///Test that the chain runs the built-in and custom validators in order
+    #[actix_rt::test]
+    async fn test_claims_validator_chain() {
+        let oidc = create_oidc()
+            .await
+            .with_claims_validator(ExpiryValidator::new())
+            .with_claims_validator(IssuerValidator::new("http://0.0.0.0:9090/"))
+            .with_claims_validator(AudienceValidator::new(["api", "legacy-api"]))
+            .with_claims_validator(Organization("acme"));
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let claims = |exp: u64, aud: &str, org_id: &str| json!({ "iss": "http://0.0.0.0:9090", "aud": aud, "exp": exp, "org_id": org_id });
+
+        let token = create_token(claims(now + 300, "legacy-api", "acme"));
+        assert!(oidc.validate_token(&token).await.is_ok());
+
+        let token = create_token(claims(now - 300, "other", "other"));
+        assert!(matches!(
+            oidc.validate_token(&token).await,
+            Err(OIDCValidationError::ClaimsRejected(report))
+                if matches!(report.failures(), [ValidationError::Expired(_)])
+        ));
+        let token = create_token(claims(now + 300, "other", "other"));
+        assert!(matches!(
+            oidc.validate_token(&token).await,
+            Err(OIDCValidationError::ClaimsRejected(report))
+                if matches!(report.failures(), [ValidationError::InvalidAudience(_)])
+        ));
+        let token = create_token(claims(now + 300, "api", "other"));
+        assert!(matches!(
+            oidc.validate_token(&token).await,
+            Err(OIDCValidationError::ClaimRuleFailed(claim)) if claim == "org_id"
+        ));
+    }
+}
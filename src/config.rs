@@ -0,0 +1,300 @@
+//! Validation of the configuration when it is constructed, so misconfiguration fails at startup
+//! with a descriptive error instead of as 401s at runtime
+
+use std::str::FromStr;
+
+use actix_web::http::{header::HeaderName, Uri};
+use biscuit::Validation;
+
+use crate::{
+    middleware::InsecureTransport, webfinger, IssuerPattern, OIDCValidationError,
+    OidcBiscuitValidator, OidcConfig, TokenLookup,
+};
+
+fn invalid(message: String) -> OIDCValidationError {
+    OIDCValidationError::InvalidConfiguration(message)
+}
+
+/// Checks that the URL is an absolute http(s) URL without query
+fn check_url(kind: &str, url: &str) -> Result<(), OIDCValidationError> {
+    let uri = Uri::from_str(url)
+        .map_err(|e| invalid(format!("{} {:?} is not a URL: {}", kind, url, e)))?;
+    let scheme_ok = matches!(uri.scheme_str(), Some("https") | Some("http"));
+    if !scheme_ok || uri.host().is_none_or(str::is_empty) {
+        return Err(invalid(format!(
+            "{} {:?} must be an absolute http(s) URL",
+            kind, url
+        )));
+    }
+    if uri.query().is_some() {
+        return Err(invalid(format!("{} {:?} must not have a query", kind, url)));
+    }
+    Ok(())
+}
+
+fn check_not_empty<T>(kind: &str, items: &[T]) -> Result<(), OIDCValidationError> {
+    if items.is_empty() {
+        return Err(invalid(format!("at least one {} must be configured", kind)));
+    }
+    Ok(())
+}
+
+impl OidcConfig {
+    /// Checks the configuration without contacting the identity provider: the shape of the URLs
+    /// and that lists are not empty
+    ///
+    /// The constructors of [`crate::Oidc`] call it before the discovery.
+    pub fn validate(&self) -> Result<(), OIDCValidationError> {
+        match self {
+            OidcConfig::Issuer(issuer) => check_url("issuer", issuer),
+            OidcConfig::KeyUrl(key_url) => check_url("key URL", key_url),
+            OidcConfig::KeyUrls(key_urls) => {
+                check_not_empty("key URL", key_urls)?;
+                key_urls
+                    .iter()
+                    .try_for_each(|url| check_url("key URL", url))
+            }
+            OidcConfig::Jwks(jwks) => check_not_empty("key", &jwks.keys),
+            OidcConfig::JwksFile(path) if path.as_os_str().is_empty() => {
+                Err(invalid("JWKS file must not be empty".to_string()))
+            }
+            OidcConfig::JwksJson(json) if json.trim().is_empty() => {
+                Err(invalid("JWKS JSON must not be empty".to_string()))
+            }
+            OidcConfig::JwksFile(_) | OidcConfig::JwksJson(_) => Ok(()),
+            OidcConfig::WebFinger(identifier) => webfinger::resource_and_host(identifier)
+                .map(|_| ())
+                .map_err(|_| {
+                    invalid(format!(
+                        "WebFinger identifier {:?} must be an account or URL",
+                        identifier
+                    ))
+                }),
+            OidcConfig::Issuers(issuers) => {
+                check_not_empty("issuer", issuers)?;
+                issuers
+                    .iter()
+                    .try_for_each(|issuer| check_url("issuer", issuer))
+            }
+            OidcConfig::IssuerPatterns(patterns) => {
+                check_not_empty("issuer pattern", patterns)?;
+                patterns.iter().try_for_each(|pattern| match pattern {
+                    IssuerPattern::Wildcard(pattern) if pattern.trim().is_empty() => {
+                        Err(invalid("issuer pattern must not be empty".to_string()))
+                    }
+                    _ => Ok(()),
+                })
+            }
+            OidcConfig::Validator(_) | OidcConfig::Tenants(_) => Ok(()),
+        }
+    }
+}
+
+impl TokenLookup {
+    /// Checks that the header or cookie name is usable
+    pub(crate) fn validate(&self) -> Result<(), OIDCValidationError> {
+        match self {
+            TokenLookup::Header(name) => HeaderName::from_str(name)
+                .map(|_| ())
+                .map_err(|_| invalid(format!("token header {:?} is not a header name", name))),
+            TokenLookup::Cookie(name) if name.is_empty() => {
+                Err(invalid("token cookie name must not be empty".to_string()))
+            }
+            TokenLookup::Cookie(_) => Ok(()),
+        }
+    }
+}
+
+impl OidcBiscuitValidator {
+    /// Checks the configuration of the middleware: the audience and issuer that are validated,
+    /// the public paths and the combination of options
+    ///
+    /// The middleware calls it when the app starts and fails to start with the error, so a
+    /// misconfigured app doesn't serve requests.
+    pub fn validate(&self) -> Result<(), OIDCValidationError> {
+        if let Validation::Validate(audience) = &self.options.audience {
+            if audience.trim().is_empty() {
+                return Err(invalid("validated audience must not be empty".to_string()));
+            }
+        }
+        let leeway = match self.leeway {
+            Some(leeway) => chrono::Duration::from_std(leeway).unwrap_or(chrono::Duration::MAX),
+            None => self.options.temporal_options.epsilon,
+        };
+        if leeway < chrono::Duration::zero() {
+            return Err(invalid("leeway must not be negative".to_string()));
+        }
+        if let Validation::Validate(max_age) = self.options.issued_at {
+            if leeway >= max_age {
+                return Err(invalid(format!(
+                    "leeway of {}s must be shorter than the maximum token age of {}s",
+                    leeway.num_seconds(),
+                    max_age.num_seconds()
+                )));
+            }
+        }
+        if let Validation::Validate(issuer) = &self.options.issuer {
+            check_url("validated issuer", issuer)?;
+        }
+        let paths = self
+            .public_paths
+            .iter()
+            .map(|path| ("public path", path))
+            .chain(
+                self.expired_grace_paths
+                    .iter()
+                    .map(|(path, _)| ("expired grace path", path)),
+            )
+            .chain(
+                self.route_requirements
+                    .iter()
+                    .map(|(path, _)| ("route requirements path", path)),
+            );
+        for (kind, path) in paths {
+            let wildcard = path.find('*');
+            if !path.starts_with('/')
+                || wildcard.is_some_and(|at| !path.ends_with("/*") || at != path.len() - 1)
+            {
+                return Err(invalid(format!(
+                    "{} {:?} must start with / and may only end with /*",
+                    kind, path
+                )));
+            }
+        }
+        let route_scopes = self
+            .route_requirements
+            .iter()
+            .flat_map(|(_, route)| &route.required_scopes);
+        if let Some(scope) = self
+            .required_scopes
+            .iter()
+            .chain(route_scopes)
+            .find(|scope| scope.is_empty() || scope.contains(char::is_whitespace))
+        {
+            return Err(invalid(format!(
+                "required scope {:?} must be a single non-empty scope",
+                scope
+            )));
+        }
+        let route_audiences = self
+            .route_requirements
+            .iter()
+            .flat_map(|(_, route)| &route.audiences);
+        if self
+            .audiences
+            .iter()
+            .chain(route_audiences)
+            .any(|audience| audience.trim().is_empty())
+        {
+            return Err(invalid("accepted audience must not be empty".to_string()));
+        }
+        if self
+            .acr_values
+            .iter()
+            .chain(&self.required_amr)
+            .any(|value| value.trim().is_empty())
+        {
+            return Err(invalid(
+                "accepted acr and required amr must not be empty".to_string(),
+            ));
+        }
+        if let Some(pointer) = self
+            .role_claims
+            .iter()
+            .find(|pointer| !pointer.starts_with('/'))
+        {
+            return Err(invalid(format!(
+                "role claim {:?} must be a JSON pointer starting with /",
+                pointer
+            )));
+        }
+        if self.insecure_transport == InsecureTransport::Allow && !self.trusted_proxies.is_empty() {
+            return Err(invalid(
+                "trusted proxies are only used when secure transport is required".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use biscuit::{Validation, ValidationOptions};
+
+    use crate::{
+        IssuerPattern, OIDCValidationError, Oidc, OidcBiscuitValidator, OidcConfig, TokenLookup,
+    };
+
+    ///Test that malformed configurations fail before contacting the identity provider
+    #[actix_rt::test]
+    async fn test_oidc_config_validation() {
+        assert!(
+            OidcConfig::Issuer("https://idp.example.com/realms/a".into())
+                .validate()
+                .is_ok()
+        );
+        for config in [
+            OidcConfig::Issuer("idp.example.com".into()),
+            OidcConfig::Issuer("https://idp.example.com?realm=a".into()),
+            OidcConfig::KeyUrls(vec![]),
+            OidcConfig::Issuers(vec!["https://a.example.com".into(), "ftp://b".into()]),
+            OidcConfig::IssuerPatterns(vec![IssuerPattern::Wildcard("".into())]),
+            OidcConfig::WebFinger("joe".into()),
+        ] {
+            assert!(matches!(
+                config.validate(),
+                Err(OIDCValidationError::InvalidConfiguration(_))
+            ));
+        }
+
+        let result = Oidc::new_with_token_lookup(
+            OidcConfig::Issuer("https://idp.example.com".into()),
+            TokenLookup::Header("bad header".into()),
+        )
+        .await;
+        assert!(matches!(
+            result,
+            Err(OIDCValidationError::InvalidConfiguration(_))
+        ));
+    }
+
+    ///Test the validation of the middleware configuration
+    #[test]
+    fn test_middleware_config_validation() {
+        assert!(OidcBiscuitValidator::default()
+            .public_infrastructure_paths()
+            .public_path("/items/{id}")
+            .validate()
+            .is_ok());
+
+        let empty_audience = OidcBiscuitValidator {
+            options: ValidationOptions {
+                audience: Validation::Validate("".into()),
+                ..ValidationOptions::default()
+            },
+            ..OidcBiscuitValidator::default()
+        };
+        assert!(empty_audience.validate().is_err());
+        let leeway_exceeds_max_age = OidcBiscuitValidator {
+            options: ValidationOptions {
+                issued_at: Validation::Validate(chrono::Duration::minutes(5)),
+                ..ValidationOptions::default()
+            },
+            ..OidcBiscuitValidator::default()
+        }
+        .with_leeway(std::time::Duration::from_secs(600));
+        assert!(leeway_exceeds_max_age.validate().is_err());
+        assert!(OidcBiscuitValidator::default()
+            .accept_audience(" ")
+            .validate()
+            .is_err());
+        assert!(OidcBiscuitValidator::default()
+            .public_path("/items/*/details")
+            .validate()
+            .is_err());
+        assert!(OidcBiscuitValidator::default()
+            .trusted_proxy("10.0.0.1".parse().unwrap())
+            .validate()
+            .is_err());
+    }
+}
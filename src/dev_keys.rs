@@ -0,0 +1,136 @@
+//! Development keys: generated RSA key pairs that mint tokens the crate accepts, for local
+//! development and CI scripts
+
+use std::sync::Arc;
+
+use biscuit::{
+    jwa::{Algorithm, SignatureAlgorithm},
+    jwk::{AlgorithmParameters, CommonParameters, JWKSet, PublicKeyUse, RSAKeyParameters, JWK},
+    jws::{RegisteredHeader, Secret},
+    ClaimsSet, Empty, JWT,
+};
+use num_bigint::BigUint;
+use ring::{
+    rsa::PublicKeyComponents,
+    signature::{KeyPair, RsaKeyPair},
+};
+use rsa::pkcs1::EncodeRsaPrivateKey;
+use serde_json::Value;
+
+use crate::OIDCValidationError;
+
+/// An RSA key pair that signs RS256 tokens and publishes its public key as JWKS
+///
+/// Never use it in production, the private key is meant to be stored next to the code.
+///
+/// ```ignore
+/// let key = DevKeyPair::generate("dev")?;
+/// let oidc = Oidc::new(OidcConfig::Jwks(key.jwks())).await?;
+/// let token = key.mint(serde_json::from_value(json!({ "sub": "joe" }))?)?;
+/// ```
+pub struct DevKeyPair {
+    kid: String,
+    der: Vec<u8>,
+    key_pair: Arc<RsaKeyPair>,
+}
+
+impl DevKeyPair {
+    /// Size of the generated keys in bits
+    pub const KEY_SIZE: usize = 2048;
+
+    /// Generates a new key pair with the given `kid`
+    pub fn generate(kid: impl Into<String>) -> Result<Self, OIDCValidationError> {
+        let private_key = rsa::RsaPrivateKey::new(&mut rand_core::OsRng, Self::KEY_SIZE)
+            .map_err(|e| invalid(format!("cannot generate key: {}", e)))?;
+        let der = private_key
+            .to_pkcs1_der()
+            .map_err(|e| invalid(format!("cannot encode key: {}", e)))?;
+        Self::from_der(kid, der.as_bytes().to_vec())
+    }
+
+    /// Loads a PKCS#1 DER encoded private key, as written by [`Self::to_der`]
+    pub fn from_der(kid: impl Into<String>, der: Vec<u8>) -> Result<Self, OIDCValidationError> {
+        let key_pair = RsaKeyPair::from_der(&der)
+            .map_err(|e| invalid(format!("not a PKCS#1 DER encoded RSA key: {}", e)))?;
+        Ok(DevKeyPair {
+            kid: kid.into(),
+            der,
+            key_pair: Arc::new(key_pair),
+        })
+    }
+
+    /// The `kid` of the key
+    pub fn kid(&self) -> &str {
+        &self.kid
+    }
+
+    /// The PKCS#1 DER encoded private key
+    pub fn to_der(&self) -> &[u8] {
+        &self.der
+    }
+
+    /// The public key as JWKS, e.g. for [`OidcConfig::Jwks`](crate::OidcConfig::Jwks)
+    pub fn jwks(&self) -> JWKSet<Empty> {
+        let public_key = PublicKeyComponents::<Vec<_>>::from(self.key_pair.public_key());
+        JWKSet {
+            keys: vec![JWK {
+                common: CommonParameters {
+                    public_key_use: Some(PublicKeyUse::Signature),
+                    algorithm: Some(Algorithm::Signature(SignatureAlgorithm::RS256)),
+                    key_id: Some(self.kid.clone()),
+                    ..Default::default()
+                },
+                algorithm: AlgorithmParameters::RSA(RSAKeyParameters {
+                    n: BigUint::from_bytes_be(&public_key.n),
+                    e: BigUint::from_bytes_be(&public_key.e),
+                    ..Default::default()
+                }),
+                additional: Default::default(),
+            }],
+        }
+    }
+
+    /// Signs the claims as RS256 token with the `kid` of the key
+    pub fn mint(&self, claims: ClaimsSet<Value>) -> Result<String, OIDCValidationError> {
+        let header = RegisteredHeader {
+            algorithm: SignatureAlgorithm::RS256,
+            key_id: Some(self.kid.clone()),
+            ..Default::default()
+        };
+        let token = JWT::new_decoded(From::from(header), claims)
+            .encode(&Secret::RsaKeyPair(self.key_pair.clone()))?;
+        Ok(token.unwrap_encoded().to_string())
+    }
+}
+
+fn invalid(message: String) -> OIDCValidationError {
+    OIDCValidationError::InvalidConfiguration(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::DevKeyPair;
+    use crate::{Oidc, OidcConfig};
+
+    ///Test that minted tokens validate against the published keys
+    #[actix_rt::test]
+    async fn test_dev_key_pair() {
+        let key = DevKeyPair::generate("dev").unwrap();
+        let key = DevKeyPair::from_der("dev", key.to_der().to_vec()).unwrap();
+        let oidc = Oidc::new(OidcConfig::Jwks(key.jwks())).await.unwrap();
+
+        let token = key
+            .mint(serde_json::from_value(json!({ "sub": "joe" })).unwrap())
+            .unwrap();
+        let info = oidc.validate_token(&token).await.unwrap();
+        assert_eq!(info.payload.registered.subject.as_deref(), Some("joe"));
+
+        let other = DevKeyPair::generate("dev").unwrap();
+        let token = other
+            .mint(serde_json::from_value(json!({ "sub": "joe" })).unwrap())
+            .unwrap();
+        assert!(oidc.validate_token(&token).await.is_err());
+    }
+}
@@ -0,0 +1,667 @@
+use std::{
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use actix_web::{
+    http::{header, Uri},
+    HttpRequest,
+};
+use biscuit::{
+    digest,
+    jwa::SignatureAlgorithm,
+    jwk::{AlgorithmParameters, EllipticCurve, JWK},
+    jws, ClaimsSet, Compact, CompactJson, Empty, JWT,
+};
+use data_encoding::BASE64URL_NOPAD;
+use ring::{
+    rand::{SecureRandom, SystemRandom},
+    signature::{UnparsedPublicKey, ED25519},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{middleware::from_trusted_proxy, DecodedInfo, OIDCValidationError, ReplayGuard};
+
+/// Issues and verifies the server provided nonces of DPoP proofs
+/// ([RFC 9449 section 8](https://www.rfc-editor.org/rfc/rfc9449#section-8))
+///
+/// Implement this trait to share nonces between instances, e.g. in a distributed cache.
+pub trait NonceStore: Send + Sync {
+    /// Returns the nonce clients must use in their next proofs
+    fn issue(&self) -> String;
+
+    /// Returns whether the nonce of a proof is (still) accepted
+    fn verify(&self, nonce: &str) -> bool;
+
+    /// Drops the nonces that are no longer accepted, called every interval by
+    /// [`Oidc::spawn_nonce_cleanup`](crate::Oidc::spawn_nonce_cleanup)
+    fn cleanup(&self) {}
+}
+
+struct Nonces {
+    current: String,
+    previous: Option<String>,
+    issued_at: Instant,
+}
+
+/// Nonce store that rotates a random nonce every lifetime, the previous nonce stays accepted
+/// for one more lifetime so clients that just received it are not rejected
+pub struct InMemoryNonceStore {
+    lifetime: Duration,
+    nonces: Mutex<Nonces>,
+    random: SystemRandom,
+}
+
+impl InMemoryNonceStore {
+    /// Creates a store that rotates the nonce every lifetime
+    pub fn new(lifetime: Duration) -> Self {
+        let random = SystemRandom::new();
+        InMemoryNonceStore {
+            lifetime,
+            nonces: Mutex::new(Nonces {
+                current: random_nonce(&random),
+                previous: None,
+                issued_at: Instant::now(),
+            }),
+            random,
+        }
+    }
+
+    fn rotated(&self) -> std::sync::MutexGuard<'_, Nonces> {
+        let mut nonces = self.nonces.lock().unwrap();
+        let elapsed = nonces.issued_at.elapsed();
+        if elapsed >= self.lifetime {
+            let current = random_nonce(&self.random);
+            let previous = std::mem::replace(&mut nonces.current, current);
+            // after an idle period, the former nonce is outdated as well
+            nonces.previous = (elapsed < self.lifetime * 2).then_some(previous);
+            nonces.issued_at = Instant::now();
+        }
+        nonces
+    }
+}
+
+impl Default for InMemoryNonceStore {
+    fn default() -> Self {
+        InMemoryNonceStore::new(Duration::from_secs(300))
+    }
+}
+
+impl NonceStore for InMemoryNonceStore {
+    fn issue(&self) -> String {
+        self.rotated().current.clone()
+    }
+
+    fn verify(&self, nonce: &str) -> bool {
+        let nonces = self.rotated();
+        nonces.current == nonce || nonces.previous.as_deref() == Some(nonce)
+    }
+
+    fn cleanup(&self) {
+        drop(self.rotated());
+    }
+}
+
+fn random_nonce(random: &SystemRandom) -> String {
+    random_hex(random, 16)
+}
+
+/// Hex encoded random bytes, for nonces and references that must not be guessed
+pub(crate) fn random_hex(random: &SystemRandom, len: usize) -> String {
+    let mut bytes = vec![0u8; len];
+    random
+        .fill(&mut bytes)
+        .expect("system random number generator is not available");
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Checks the `nonce` claim of a DPoP proof, a missing or outdated nonce is rejected with a
+/// fresh nonce for the client to retry with
+pub(crate) fn check_nonce(
+    store: &dyn NonceStore,
+    proof: &ClaimsSet<Value>,
+) -> Result<(), OIDCValidationError> {
+    match proof.private.get("nonce").and_then(Value::as_str) {
+        Some(nonce) if store.verify(nonce) => Ok(()),
+        _ => Err(OIDCValidationError::UseDpopNonce(store.issue())),
+    }
+}
+
+/// Validation of the DPoP proofs of sender-constrained access tokens
+/// ([RFC 9449](https://www.rfc-editor.org/rfc/rfc9449)), see
+/// [`Oidc::with_dpop`](crate::Oidc::with_dpop)
+///
+/// A token bound to a key by its `cnf.jkt` claim is only accepted with a proof in the `DPoP`
+/// header that is signed by that key and made for the request: the `htm` and `htu` claims are the
+/// method and URL of the request, the `ath` claim is the hash of the token and the `iat` claim is
+/// recent. Every proof is accepted once, by its `jti`.
+///
+/// ```ignore
+/// let oidc = oidc.with_dpop(DpopValidation::new().with_max_age(Duration::from_secs(30)));
+/// ```
+#[derive(Clone)]
+pub struct DpopValidation {
+    max_age: Duration,
+    require_bound_tokens: bool,
+    seen: ReplayGuard,
+    trusted_proxies: Vec<IpAddr>,
+}
+
+impl Default for DpopValidation {
+    fn default() -> Self {
+        DpopValidation {
+            max_age: Duration::from_secs(60),
+            require_bound_tokens: false,
+            seen: ReplayGuard::new(),
+            trusted_proxies: Vec::new(),
+        }
+    }
+}
+
+impl DpopValidation {
+    /// Accepts proofs created within the last minute, tokens without `cnf.jkt` stay accepted as
+    /// bearer tokens
+    pub fn new() -> Self {
+        DpopValidation::default()
+    }
+
+    /// Accepts proofs of which the `iat` is off by no more than the max age, in both directions
+    /// for clock drift
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    /// Rejects tokens that are not bound to a key, e.g. once all clients use DPoP
+    pub fn require_bound_tokens(mut self) -> Self {
+        self.require_bound_tokens = true;
+        self
+    }
+
+    /// Trusts the forwarded scheme and host of requests from the given reverse proxy for the
+    /// `htu`, like [`OidcBiscuitValidator::trusted_proxy`](crate::OidcBiscuitValidator::trusted_proxy)
+    ///
+    /// The `htu` of other requests is checked against the `Host` header and the scheme of the
+    /// server.
+    pub fn trusted_proxy(mut self, proxy: IpAddr) -> Self {
+        self.trusted_proxies.push(proxy);
+        self
+    }
+
+    /// Checks the proof of the request for the validated token (as sent, without the scheme)
+    pub(crate) fn check(
+        &self,
+        request: &ProofRequest,
+        token: &str,
+        info: &DecodedInfo,
+        nonces: Option<&dyn NonceStore>,
+    ) -> Result<(), OIDCValidationError> {
+        let jkt = info
+            .payload
+            .private
+            .pointer("/cnf/jkt")
+            .and_then(Value::as_str);
+        let Some(jkt) = jkt else {
+            return match self.require_bound_tokens || request.dpop_scheme {
+                true => Err(invalid_proof("token is not DPoP-bound")),
+                false => Ok(()),
+            };
+        };
+        let proof = match request.proofs.as_slice() {
+            [proof] => proof,
+            [] => return Err(invalid_proof("proof is missing")),
+            _ => return Err(invalid_proof("more than one proof")),
+        };
+
+        let (key, claims) = verify_proof(proof)?;
+        if key.algorithm.thumbprint(&digest::SHA256).ok().as_deref() != Some(jkt) {
+            return Err(invalid_proof("proof key does not match cnf.jkt"));
+        }
+        let claim = |name: &str| claims.private.get(name).and_then(Value::as_str);
+        if claim("htm") != Some(request.method.as_str()) {
+            return Err(invalid_proof("htm does not match"));
+        }
+        let htu = claim("htu").and_then(normalized_htu);
+        if htu.is_none() || htu != normalized_htu(&request.uri) {
+            return Err(invalid_proof("htu does not match"));
+        }
+        let ath = BASE64URL_NOPAD
+            .encode(ring::digest::digest(&ring::digest::SHA256, token.as_bytes()).as_ref());
+        if claim("ath") != Some(ath.as_str()) {
+            return Err(invalid_proof("ath does not match"));
+        }
+        let iat = claims
+            .registered
+            .issued_at
+            .map(|iat| iat.timestamp())
+            .ok_or_else(|| invalid_proof("iat is missing"))?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as i64;
+        let max_age = self.max_age.as_secs() as i64;
+        if now.abs_diff(iat) > max_age as u64 {
+            return Err(invalid_proof("iat is outside the accepted window"));
+        }
+        if let Some(store) = nonces {
+            check_nonce(store, &claims)?;
+        }
+        let jti = claims
+            .registered
+            .id
+            .as_deref()
+            .ok_or_else(|| invalid_proof("jti is missing"))?;
+        self.seen
+            .record(&format!("{}:{}", jkt, jti), iat + max_age)
+            .map_err(|_| invalid_proof("proof is replayed"))
+    }
+}
+
+/// The parts of a request its DPoP proof is checked against, read before the token is validated
+pub(crate) struct ProofRequest {
+    proofs: Vec<String>,
+    dpop_scheme: bool,
+    method: String,
+    uri: String,
+}
+
+impl ProofRequest {
+    pub(crate) fn of(req: &HttpRequest, dpop: &DpopValidation) -> Self {
+        let uri = match from_trusted_proxy(req, &dpop.trusted_proxies) {
+            true => {
+                let info = req.connection_info();
+                format!("{}://{}{}", info.scheme(), info.host(), req.path())
+            }
+            false => {
+                let scheme = match req.app_config().secure() {
+                    true => "https",
+                    false => "http",
+                };
+                let host = req
+                    .headers()
+                    .get(header::HOST)
+                    .and_then(|host| host.to_str().ok())
+                    .or_else(|| req.uri().authority().map(|authority| authority.as_str()))
+                    .unwrap_or_else(|| req.app_config().host());
+                format!("{}://{}{}", scheme, host, req.path())
+            }
+        };
+        ProofRequest {
+            proofs: req
+                .headers()
+                .get_all("DPoP")
+                .filter_map(|proof| proof.to_str().ok())
+                .map(str::to_string)
+                .collect(),
+            dpop_scheme: req
+                .headers()
+                .get(header::AUTHORIZATION)
+                .and_then(|value| value.to_str().ok())
+                .is_some_and(|value| value.starts_with("DPoP ")),
+            method: req.method().to_string(),
+            uri,
+        }
+    }
+}
+
+/// The `htu` without query and fragment, with the scheme and host in lowercase and without the
+/// default port, as compared by RFC 9449, `None` when it is no http(s) URL
+fn normalized_htu(htu: &str) -> Option<String> {
+    let uri = htu.split(['?', '#']).next()?.parse::<Uri>().ok()?;
+    let scheme = uri.scheme_str()?.to_ascii_lowercase();
+    let host = uri.host()?.to_ascii_lowercase();
+    let port = match (scheme.as_str(), uri.port_u16()) {
+        ("https", Some(443)) | ("http", Some(80)) | (_, None) => String::new(),
+        ("https" | "http", Some(port)) => format!(":{}", port),
+        _ => return None,
+    };
+    let path = match uri.path() {
+        "" => "/",
+        path => path,
+    };
+    Some(format!("{}://{}{}{}", scheme, host, port, path))
+}
+
+#[derive(Serialize, Deserialize)]
+struct ProofHeader {
+    typ: Option<String>,
+    alg: String,
+    jwk: JWK<Empty>,
+}
+
+impl CompactJson for ProofHeader {}
+
+/// Verifies the signature of the proof with the public key in its header
+fn verify_proof(proof: &str) -> Result<(JWK<Empty>, ClaimsSet<Value>), OIDCValidationError> {
+    let compact = Compact::decode(proof);
+    let header: ProofHeader = compact
+        .part(0)
+        .map_err(|_| invalid_proof("proof is malformed"))?;
+    if !header
+        .typ
+        .as_deref()
+        .is_some_and(|typ| typ.eq_ignore_ascii_case("dpop+jwt"))
+    {
+        return Err(invalid_proof("typ is not dpop+jwt"));
+    }
+    let claims = match (&header.jwk.algorithm, header.alg.as_str()) {
+        (AlgorithmParameters::OctetKeyPair(okp), "EdDSA")
+            if okp.curve == EllipticCurve::Curve25519 && okp.d.is_none() =>
+        {
+            let signature: Vec<u8> = compact
+                .part(2)
+                .map_err(|_| invalid_proof("proof is malformed"))?;
+            let (signing_input, _) = proof.rsplit_once('.').unwrap_or_default();
+            UnparsedPublicKey::new(&ED25519, &okp.x)
+                .verify(signing_input.as_bytes(), &signature)
+                .map_err(|_| invalid_proof("signature is invalid"))?;
+            compact
+                .part(1)
+                .map_err(|_| invalid_proof("proof is malformed"))?
+        }
+        (key, alg) => {
+            let algorithm: SignatureAlgorithm = serde_json::from_value(Value::from(alg))
+                .map_err(|_| invalid_proof("alg is not supported"))?;
+            let secret = public_key(key, algorithm)
+                .ok_or_else(|| invalid_proof("alg does not suit the proof key"))?;
+            let token: jws::Compact<ClaimsSet<Value>, Empty> = JWT::new_encoded(proof);
+            let decoded = token
+                .decode(&secret, algorithm)
+                .map_err(|_| invalid_proof("signature is invalid"))?;
+            decoded
+                .payload()
+                .map_err(|_| invalid_proof("proof is malformed"))?
+                .clone()
+        }
+    };
+    Ok((header.jwk, claims))
+}
+
+/// The public key to verify the signature with, `None` for private keys, symmetric algorithms
+/// and algorithms of another key type
+fn public_key(key: &AlgorithmParameters, algorithm: SignatureAlgorithm) -> Option<jws::Secret> {
+    use SignatureAlgorithm::{ES256, ES384, PS256, PS384, PS512, RS256, RS384, RS512};
+
+    match (key, algorithm) {
+        (AlgorithmParameters::RSA(rsa), RS256 | RS384 | RS512 | PS256 | PS384 | PS512)
+            if rsa.d.is_none() =>
+        {
+            Some(rsa.jws_public_key_secret())
+        }
+        (AlgorithmParameters::EllipticCurve(ec), ES256)
+            if ec.curve == EllipticCurve::P256 && ec.d.is_none() =>
+        {
+            Some(ec.jws_public_key_secret())
+        }
+        (AlgorithmParameters::EllipticCurve(ec), ES384)
+            if ec.curve == EllipticCurve::P384 && ec.d.is_none() =>
+        {
+            Some(ec.jws_public_key_secret())
+        }
+        _ => None,
+    }
+}
+
+fn invalid_proof(reason: &str) -> OIDCValidationError {
+    OIDCValidationError::InvalidDpopProof(reason.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::Arc,
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    };
+
+    use actix_web::{dev::Service, get, http::StatusCode, test, App, Error, ResponseError};
+    use biscuit::{
+        jwa::SignatureAlgorithm,
+        jwk::{AlgorithmParameters, EllipticCurve, EllipticCurveKeyParameters, JWK},
+        jws, ClaimsSet, Empty,
+    };
+    use data_encoding::BASE64URL_NOPAD;
+    use ring::{
+        digest,
+        rand::SystemRandom,
+        signature::{self, EcdsaKeyPair, KeyPair},
+    };
+    use serde_json::{json, Value};
+
+    use super::{DpopValidation, InMemoryNonceStore, NonceStore};
+    use crate::{
+        tests::{create_get_jwt_request, create_jwt_token, create_oidc, create_token, sign_token},
+        AuthContext, DecodedInfo,
+    };
+
+    #[get("/decoder")]
+    async fn decoder(claims: DecodedInfo) -> String {
+        claims.jwt
+    }
+
+    #[get("/context")]
+    async fn context(claims: DecodedInfo, _context: AuthContext) -> String {
+        claims.jwt
+    }
+
+    fn proof(value: Value) -> ClaimsSet<Value> {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn test_nonce_rotation() {
+        let store = InMemoryNonceStore::new(Duration::from_millis(50));
+        let first = store.issue();
+        assert_eq!(first.len(), 32);
+        assert!(store.verify(&first));
+        assert!(!store.verify("guessed"));
+
+        actix_rt::time::sleep(Duration::from_millis(60)).await;
+        let second = store.issue();
+        assert_ne!(first, second);
+        assert!(store.verify(&first), "previous nonce is accepted");
+
+        actix_rt::time::sleep(Duration::from_millis(60)).await;
+        store.issue();
+        assert!(
+            !store.verify(&first),
+            "nonce is outdated after two rotations"
+        );
+
+        let third = store.issue();
+        actix_rt::time::sleep(Duration::from_millis(110)).await;
+        store.cleanup();
+        assert!(
+            !store.verify(&third),
+            "nonce is outdated after an idle period"
+        );
+    }
+
+    ///Test that the cleanup task ends with the nonce store
+    #[actix_rt::test]
+    async fn test_nonce_cleanup_task() {
+        let store = Arc::new(InMemoryNonceStore::new(Duration::from_millis(10)));
+        let oidc = create_oidc().await.with_dpop_nonces(store.clone());
+        let task = oidc.spawn_nonce_cleanup(Duration::from_millis(10));
+        let nonce = store.issue();
+        actix_rt::time::sleep(Duration::from_millis(50)).await;
+        {
+            // rotated by the task, without a call to the store
+            let nonces = store.nonces.lock().unwrap();
+            assert!(nonces.current != nonce && nonces.previous.as_ref() != Some(&nonce));
+        }
+        assert!(task.is_running());
+
+        drop((oidc, store));
+        actix_rt::time::sleep(Duration::from_millis(50)).await;
+        assert!(!task.is_running());
+    }
+
+    #[actix_rt::test]
+    async fn test_check_dpop_nonce() {
+        let store = Arc::new(InMemoryNonceStore::default());
+        let oidc = create_oidc().await.with_dpop_nonces(store.clone());
+
+        let nonce = store.issue();
+        assert!(oidc
+            .check_dpop_nonce(&proof(json!({ "htm": "GET", "nonce": nonce })))
+            .is_ok());
+
+        let err = oidc
+            .check_dpop_nonce(&proof(json!({ "htm": "GET" })))
+            .unwrap_err();
+        let response = err.error_response();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            response.headers().get("dpop-nonce").unwrap(),
+            nonce.as_str()
+        );
+        assert!(response
+            .headers()
+            .get("www-authenticate")
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .contains("use_dpop_nonce"));
+    }
+
+    ///Test that DPoP-bound tokens are only accepted with a fresh proof of their key for the request
+    #[actix_rt::test]
+    async fn test_dpop_proof() -> Result<(), Error> {
+        let rng = SystemRandom::new();
+        let signing = &signature::ECDSA_P256_SHA256_FIXED_SIGNING;
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(signing, &rng).unwrap();
+        let key_pair = EcdsaKeyPair::from_pkcs8(signing, pkcs8.as_ref(), &rng).unwrap();
+        let point = &key_pair.public_key().as_ref()[1..];
+        let (x, y) = point.split_at(point.len() / 2);
+        let key = JWK::<Empty> {
+            common: Default::default(),
+            algorithm: AlgorithmParameters::EllipticCurve(EllipticCurveKeyParameters {
+                curve: EllipticCurve::P256,
+                x: x.to_vec(),
+                y: y.to_vec(),
+                ..Default::default()
+            }),
+            additional: Default::default(),
+        };
+        let secret = jws::Secret::EcdsaKeyPair(Arc::new(key_pair));
+        let jkt = key.algorithm.thumbprint(&biscuit::digest::SHA256).unwrap();
+        let token = create_token(json!({ "sub": "admin", "cnf": { "jkt": jkt } }));
+        let ath =
+            BASE64URL_NOPAD.encode(digest::digest(&digest::SHA256, token.as_bytes()).as_ref());
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        let proof = |jti: &str, htm: &str, iat: i64, htu: &str| {
+            let header = jws::RegisteredHeader {
+                algorithm: SignatureAlgorithm::ES256,
+                media_type: Some("dpop+jwt".to_string()),
+                web_key: Some(key.clone()),
+                ..Default::default()
+            };
+            let claims = json!({ "jti": jti, "iat": iat, "htm": htm, "htu": htu, "ath": ath });
+            sign_token(header, claims, &secret)
+        };
+
+        let htu = "http://localhost:8080/decoder?page=2";
+
+        let oidc = create_oidc().await.with_dpop(DpopValidation::new());
+        let app = test::init_service(
+            App::new()
+                .app_data(oidc.clone())
+                .service(decoder)
+                .service(context),
+        )
+        .await;
+        let request = |path: &str, token: &str, proof: Option<String>| {
+            let req = test::TestRequest::get()
+                .uri(path)
+                .insert_header(("authorization", format!("DPoP {}", token)))
+                .insert_header(("x-forwarded-host", "evil.example.com"));
+            match proof {
+                Some(proof) => req.insert_header(("DPoP", proof)),
+                None => req,
+            }
+            .to_request()
+        };
+
+        let req = request("/decoder", &token, Some(proof("proof-1", "GET", now, htu)));
+        assert_eq!(app.call(req).await?.status(), StatusCode::OK);
+        // the proof is checked once for all extractors of the handler
+        let req = request(
+            "/context",
+            &token,
+            Some(proof(
+                "proof-5",
+                "GET",
+                now,
+                "HTTP://LOCALHOST:8080/context#top",
+            )),
+        );
+        assert_eq!(app.call(req).await?.status(), StatusCode::OK);
+        for (req, reason) in [
+            (
+                request("/decoder", &token, Some(proof("proof-1", "GET", now, htu))),
+                "proof is replayed",
+            ),
+            (
+                request("/decoder", &token, Some(proof("proof-2", "POST", now, htu))),
+                "htm does not match",
+            ),
+            (
+                request(
+                    "/decoder",
+                    &token,
+                    Some(proof("proof-3", "GET", now - 600, htu)),
+                ),
+                "iat is outside the accepted window",
+            ),
+            (request("/decoder", &token, None), "proof is missing"),
+            (
+                request(
+                    "/decoder",
+                    &create_jwt_token(),
+                    Some(proof("proof-4", "GET", now, htu)),
+                ),
+                "token is not DPoP-bound",
+            ),
+            (
+                // the forwarded host of a client is not trusted
+                request(
+                    "/decoder",
+                    &token,
+                    Some(proof(
+                        "proof-6",
+                        "GET",
+                        now,
+                        "http://evil.example.com/decoder",
+                    )),
+                ),
+                "htu does not match",
+            ),
+        ] {
+            let res = app.call(req).await?;
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+            let challenge = res
+                .headers()
+                .get("www-authenticate")
+                .unwrap()
+                .to_str()
+                .unwrap();
+            assert!(challenge.contains(reason), "{}", challenge);
+        }
+
+        // tokens without cnf.jkt stay accepted as bearer tokens
+        let req = create_get_jwt_request("/decoder", &create_jwt_token()).to_request();
+        assert_eq!(app.call(req).await?.status(), StatusCode::OK);
+        Ok(())
+    }
+
+    #[actix_rt::test]
+    async fn test_check_dpop_nonce_disabled() {
+        let oidc = create_oidc().await;
+        assert!(oidc.check_dpop_nonce(&proof(json!({}))).is_ok());
+    }
+}
@@ -1,9 +1,87 @@
+use crate::ValidationReport;
+use actix_web::body::BoxBody;
+use actix_web::http::header::{ContentType, WWW_AUTHENTICATE};
 use actix_web::http::StatusCode;
-use actix_web::ResponseError;
+use actix_web::{HttpResponse, ResponseError};
 use awc::error::{JsonPayloadError, SendRequestError};
 use biscuit::errors::Error as BiscuitError;
+use biscuit::errors::ValidationError;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+/// Stable, machine-readable codes of the validation failures, emitted in JSON error responses
+/// so clients can branch on them
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum ErrorCode {
+    /// No token is found on the request
+    TokenMissing,
+    /// The token is not in the expected format, e.g. without the `Bearer` prefix
+    TokenMalformed,
+    /// The token cannot be verified, e.g. due to its signature
+    TokenInvalid,
+    /// The token has expired
+    TokenExpired,
+    /// The token is not valid yet
+    TokenNotYetValid,
+    /// The token is issued for another audience
+    AudMismatch,
+    /// The token is issued by another issuer
+    IssMismatch,
+    /// The claims of the token are rejected for another reason
+    ClaimsInvalid,
+    /// The token does not have sufficient rights
+    InsufficientRights,
+    /// The user must authenticate again with a stronger method, e.g. with MFA
+    InsufficientUserAuthentication,
+    /// The DPoP proof must contain the nonce provided by the server
+    UseDpopNonce,
+    /// The DPoP proof is missing or invalid for the request or the token
+    InvalidDpopProof,
+    /// The identity provider cannot be reached or answers unexpectedly
+    ProviderUnavailable,
+    /// The token is sent over plaintext HTTP
+    InsecureTransport,
+    /// The token is bound to the fingerprint of another client
+    FingerprintMismatch,
+    /// The configuration is invalid, only returned at startup
+    InvalidConfiguration,
+    /// The request belongs to no known tenant
+    UnknownTenant,
+}
+
+impl ErrorCode {
+    /// The code as emitted in the responses, e.g. `token_expired`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::TokenMissing => "token_missing",
+            ErrorCode::TokenMalformed => "token_malformed",
+            ErrorCode::TokenInvalid => "token_invalid",
+            ErrorCode::TokenExpired => "token_expired",
+            ErrorCode::TokenNotYetValid => "token_not_yet_valid",
+            ErrorCode::AudMismatch => "aud_mismatch",
+            ErrorCode::IssMismatch => "iss_mismatch",
+            ErrorCode::ClaimsInvalid => "claims_invalid",
+            ErrorCode::InsufficientRights => "insufficient_rights",
+            ErrorCode::InsufficientUserAuthentication => "insufficient_user_authentication",
+            ErrorCode::UseDpopNonce => "use_dpop_nonce",
+            ErrorCode::InvalidDpopProof => "invalid_dpop_proof",
+            ErrorCode::ProviderUnavailable => "provider_unavailable",
+            ErrorCode::InsecureTransport => "insecure_transport",
+            ErrorCode::FingerprintMismatch => "fingerprint_mismatch",
+            ErrorCode::InvalidConfiguration => "invalid_configuration",
+            ErrorCode::UnknownTenant => "unknown_tenant",
+        }
+    }
+}
+
+impl std::fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 /// When a JWT token is received and validated, it may be faulty due to different reasons
 #[derive(Error, Debug)]
 pub enum OIDCValidationError {
@@ -11,7 +89,7 @@ pub enum OIDCValidationError {
     #[error("Bearer authentication token invalid: {0:?}")]
     InvalidBearerAuth(awc::error::HttpError),
 
-    ///The token validation fails due to cryptographic issues
+    ///The token is malformed, its signature does not verify or its key is unknown
     #[error("Crypto handling error: {0:?}")]
     CryptoError(biscuit::errors::Error),
 
@@ -19,6 +97,10 @@ pub enum OIDCValidationError {
     #[error("Token on bearer header is not found")]
     BearerNotComplete,
 
+    ///No token is found on the request
+    #[error("No token found or token is not authorized")]
+    TokenMissing,
+
     ///The validated token has been validated but is not valid for this situation.
     #[error("No token found or token is not authorized")]
     Unauthorized,
@@ -39,9 +121,194 @@ pub enum OIDCValidationError {
     #[error("Cannot fetch {0:?}")]
     ConnectivityError(SendRequestError),
 
+    ///The identity provider did not respond within the configured timeout
+    #[error("Request to the identity provider timed out")]
+    RequestTimeout,
+
+    ///The response of the identity provider exceeds the configured maximum size
+    #[error("Response of the identity provider is too large")]
+    ResponseTooLarge,
+
     ///Token does not have sufficient rights
     #[error("Token does not have sufficient rights")]
     InvalidAccess,
+
+    ///The claims of the token failed one or more checks, the report holds all failures
+    #[error("Token claims are rejected: {0}")]
+    ClaimsRejected(ValidationReport),
+
+    ///The claims of the token break a configured claim rule
+    #[error("Token does not meet the claim rule {0}")]
+    ClaimRuleFailed(String),
+
+    ///The claims of the valid token do not deserialize into the claims struct of the extractor,
+    ///e.g. a field is missing or has another type
+    #[error("Token claims do not match the expected shape: {0}")]
+    ClaimsMismatch(serde_json::Error),
+
+    ///The token misses a claim that is required for every token
+    #[error("Token misses the required claim {0}")]
+    MissingClaim(String),
+
+    ///The token misses a scope that the route requires
+    #[error("Token misses the required scope {0}")]
+    MissingScope(String),
+
+    ///The token misses a role that the route requires
+    #[error("Token misses the required role {0}")]
+    MissingRole(String),
+
+    ///The token is signed with an algorithm that is not allowed
+    #[error("Token is signed with the algorithm {0}, which is not allowed")]
+    AlgorithmNotAllowed(String),
+
+    ///The `typ` header of the token is not the required one, e.g. an ID token where an access
+    ///token is expected
+    #[error("Token has the type {0}, which is not accepted")]
+    InvalidTokenType(String),
+
+    ///The ID token does not meet the expectations of the login flow, e.g. another nonce
+    #[error("ID token rejected: {0}")]
+    IdTokenRejected(String),
+
+    ///The token is revoked before it expires, by its `jti` or `sub`
+    #[error("Token is revoked")]
+    TokenRevoked,
+
+    ///The one-time token is presented again, its `jti` was seen before
+    #[error("Token is already used")]
+    TokenReplayed,
+
+    ///The JWE wrapped token cannot be decrypted with the configured key
+    #[error("Token cannot be decrypted")]
+    DecryptionFailed,
+
+    ///The authentication of the token is too weak for the route, by its `acr` or `amr` claim,
+    ///holds the accepted `acr` values separated by spaces
+    #[error("Token authentication is insufficient for this resource")]
+    InsufficientUserAuthentication(String),
+
+    ///The token is issued for none of the accepted audiences, which are only logged
+    #[error("Token is not issued for one of the accepted audiences")]
+    InvalidAudience,
+
+    ///WebFinger did not resolve an issuer for the given user identifier
+    #[error("No issuer found for {0}")]
+    IssuerNotFound(String),
+
+    ///The openid-configuration of the issuer names another issuer, so its keys are not trusted
+    #[error("The openid-configuration of {0} belongs to another issuer")]
+    DiscoveredIssuerMismatch(String),
+
+    ///The discovery of the issuers did not finish in time
+    #[error("Discovery of the issuers timed out")]
+    DiscoveryTimeout,
+
+    ///The DPoP proof lacks the current server provided nonce, the client must retry with the given nonce
+    #[error("DPoP proof must contain the nonce provided by the server")]
+    UseDpopNonce(String),
+
+    ///The DPoP proof is missing or invalid for the request or the DPoP-bound token, holds the reason
+    #[error("DPoP proof rejected: {0}")]
+    InvalidDpopProof(String),
+
+    ///The UMA permission is missing, the client can obtain it with the permission ticket
+    #[error("UMA permission is missing")]
+    UmaTicket {
+        /// Realm of the challenge
+        realm: String,
+        /// Authorization server to request the token at
+        as_uri: String,
+        /// Permission ticket
+        ticket: String,
+    },
+
+    ///The token is sent over plaintext HTTP, which RFC 6750 forbids
+    #[error("Tokens must be sent over HTTPS")]
+    InsecureTransport,
+
+    ///The token is bound to the fingerprint of another client
+    #[error("Token is not issued to this client")]
+    FingerprintMismatch,
+
+    ///The token is bound to another client certificate than the one of the connection
+    #[error("Token is not bound to the client certificate")]
+    CertificateMismatch,
+
+    ///The request belongs to no known tenant, or the token is validated without its request
+    #[error("Request belongs to no known tenant")]
+    UnknownTenant,
+
+    ///The configuration is invalid, detected when it is constructed
+    #[error("Invalid configuration: {0}")]
+    InvalidConfiguration(String),
+
+    ///Calls to the identity provider are suspended after repeated failures
+    #[error("Identity provider is not available")]
+    CircuitOpen,
+}
+
+impl OIDCValidationError {
+    /// The report of all failed checks, when the claims of the token are rejected
+    pub fn report(&self) -> Option<&ValidationReport> {
+        match self {
+            OIDCValidationError::ClaimsRejected(report) => Some(report),
+            _ => None,
+        }
+    }
+
+    /// The machine-readable code of the failure
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            OIDCValidationError::TokenMissing => ErrorCode::TokenMissing,
+            OIDCValidationError::InvalidBearerAuth(_) | OIDCValidationError::BearerNotComplete => {
+                ErrorCode::TokenMalformed
+            }
+            OIDCValidationError::CryptoError(_)
+            | OIDCValidationError::Unauthorized
+            | OIDCValidationError::AlgorithmNotAllowed(_)
+            | OIDCValidationError::DecryptionFailed
+            | OIDCValidationError::TokenRevoked
+            | OIDCValidationError::CertificateMismatch
+            | OIDCValidationError::TokenReplayed
+            | OIDCValidationError::InvalidTokenType(_)
+            | OIDCValidationError::IdTokenRejected(_) => ErrorCode::TokenInvalid,
+            OIDCValidationError::ClaimsRejected(report) => match report.failures().first() {
+                Some(ValidationError::Expired(_)) => ErrorCode::TokenExpired,
+                Some(ValidationError::NotYetValid(_)) => ErrorCode::TokenNotYetValid,
+                Some(ValidationError::InvalidAudience(_)) => ErrorCode::AudMismatch,
+                Some(ValidationError::InvalidIssuer(_)) => ErrorCode::IssMismatch,
+                _ => ErrorCode::ClaimsInvalid,
+            },
+            OIDCValidationError::InvalidAudience => ErrorCode::AudMismatch,
+            OIDCValidationError::DiscoveredIssuerMismatch(_) => ErrorCode::IssMismatch,
+            OIDCValidationError::InsufficientUserAuthentication(_) => {
+                ErrorCode::InsufficientUserAuthentication
+            }
+            OIDCValidationError::ClaimRuleFailed(_)
+            | OIDCValidationError::MissingClaim(_)
+            | OIDCValidationError::ClaimsMismatch(_) => ErrorCode::ClaimsInvalid,
+            OIDCValidationError::InvalidAccess
+            | OIDCValidationError::MissingScope(_)
+            | OIDCValidationError::MissingRole(_)
+            | OIDCValidationError::UmaTicket { .. } => ErrorCode::InsufficientRights,
+            OIDCValidationError::UseDpopNonce(_) => ErrorCode::UseDpopNonce,
+            OIDCValidationError::InvalidDpopProof(_) => ErrorCode::InvalidDpopProof,
+            OIDCValidationError::FailedToParseJsonResponse(_)
+            | OIDCValidationError::FailedToLoadKeystore(_)
+            | OIDCValidationError::FailedToLoadDiscovery(_)
+            | OIDCValidationError::ConnectivityError(_)
+            | OIDCValidationError::RequestTimeout
+            | OIDCValidationError::ResponseTooLarge
+            | OIDCValidationError::IssuerNotFound(_)
+            | OIDCValidationError::DiscoveryTimeout
+            | OIDCValidationError::CircuitOpen => ErrorCode::ProviderUnavailable,
+            OIDCValidationError::InsecureTransport => ErrorCode::InsecureTransport,
+            OIDCValidationError::FingerprintMismatch => ErrorCode::FingerprintMismatch,
+            OIDCValidationError::InvalidConfiguration(_) => ErrorCode::InvalidConfiguration,
+            OIDCValidationError::UnknownTenant => ErrorCode::UnknownTenant,
+        }
+    }
 }
 
 impl From<awc::error::HttpError> for OIDCValidationError {
@@ -75,11 +342,131 @@ impl ResponseError for OIDCValidationError {
             OIDCValidationError::BearerNotComplete => StatusCode::BAD_REQUEST,
             OIDCValidationError::FailedToLoadKeystore(_) => StatusCode::INTERNAL_SERVER_ERROR,
             OIDCValidationError::FailedToLoadDiscovery(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            OIDCValidationError::TokenMissing => StatusCode::UNAUTHORIZED,
             OIDCValidationError::Unauthorized => StatusCode::UNAUTHORIZED,
             OIDCValidationError::FailedToParseJsonResponse(_) => StatusCode::INTERNAL_SERVER_ERROR,
             OIDCValidationError::ConnectivityError(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            OIDCValidationError::CryptoError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            OIDCValidationError::CryptoError(_) => StatusCode::UNAUTHORIZED,
+            OIDCValidationError::RequestTimeout => StatusCode::GATEWAY_TIMEOUT,
+            OIDCValidationError::ResponseTooLarge => StatusCode::BAD_GATEWAY,
             OIDCValidationError::InvalidAccess => StatusCode::FORBIDDEN,
+            // expired tokens and tokens of other issuers or audiences are invalid, not lacking rights
+            OIDCValidationError::ClaimsRejected(report) if report.token_invalid() => {
+                StatusCode::UNAUTHORIZED
+            }
+            OIDCValidationError::ClaimsRejected(_) => StatusCode::FORBIDDEN,
+            OIDCValidationError::ClaimRuleFailed(_) => StatusCode::FORBIDDEN,
+            OIDCValidationError::MissingClaim(_) => StatusCode::FORBIDDEN,
+            OIDCValidationError::ClaimsMismatch(_) => StatusCode::UNAUTHORIZED,
+            OIDCValidationError::MissingScope(_) => StatusCode::FORBIDDEN,
+            OIDCValidationError::MissingRole(_) => StatusCode::FORBIDDEN,
+            OIDCValidationError::InvalidAudience => StatusCode::UNAUTHORIZED,
+            OIDCValidationError::InsufficientUserAuthentication(_) => StatusCode::UNAUTHORIZED,
+            OIDCValidationError::AlgorithmNotAllowed(_) => StatusCode::UNAUTHORIZED,
+            OIDCValidationError::DecryptionFailed => StatusCode::UNAUTHORIZED,
+            OIDCValidationError::TokenRevoked => StatusCode::UNAUTHORIZED,
+            OIDCValidationError::TokenReplayed => StatusCode::UNAUTHORIZED,
+            OIDCValidationError::InvalidTokenType(_) => StatusCode::UNAUTHORIZED,
+            OIDCValidationError::IdTokenRejected(_) => StatusCode::UNAUTHORIZED,
+            OIDCValidationError::IssuerNotFound(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            OIDCValidationError::DiscoveryTimeout => StatusCode::INTERNAL_SERVER_ERROR,
+            OIDCValidationError::DiscoveredIssuerMismatch(_) => StatusCode::UNAUTHORIZED,
+            OIDCValidationError::UseDpopNonce(_) => StatusCode::UNAUTHORIZED,
+            OIDCValidationError::InvalidDpopProof(_) => StatusCode::UNAUTHORIZED,
+            OIDCValidationError::UmaTicket { .. } => StatusCode::UNAUTHORIZED,
+            OIDCValidationError::CircuitOpen => StatusCode::SERVICE_UNAVAILABLE,
+            OIDCValidationError::InsecureTransport => StatusCode::BAD_REQUEST,
+            OIDCValidationError::FingerprintMismatch => StatusCode::UNAUTHORIZED,
+            OIDCValidationError::CertificateMismatch => StatusCode::UNAUTHORIZED,
+            OIDCValidationError::InvalidConfiguration(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            OIDCValidationError::UnknownTenant => StatusCode::UNAUTHORIZED,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse<BoxBody> {
+        let mut res = HttpResponse::build(self.status_code());
+        res.insert_header(ContentType::plaintext());
+        match self {
+            OIDCValidationError::UseDpopNonce(nonce) => {
+                res.insert_header(("DPoP-Nonce", nonce.as_str()));
+                res.insert_header((
+                    WWW_AUTHENTICATE,
+                    r#"DPoP error="use_dpop_nonce", error_description="Resource server requires nonce in DPoP proof""#,
+                ));
+            }
+            OIDCValidationError::InvalidDpopProof(reason) => {
+                res.insert_header((
+                    WWW_AUTHENTICATE,
+                    format!(
+                        r#"DPoP error="invalid_dpop_proof", error_description="{}""#,
+                        challenge_value(reason)
+                    ),
+                ));
+            }
+            OIDCValidationError::InsufficientUserAuthentication(acr_values) => {
+                let mut challenge = r#"Bearer error="insufficient_user_authentication", error_description="A different authentication level is required""#.to_string();
+                if !acr_values.is_empty() {
+                    challenge.push_str(&format!(
+                        r#", acr_values="{}""#,
+                        challenge_value(acr_values)
+                    ));
+                }
+                res.insert_header((WWW_AUTHENTICATE, challenge));
+            }
+            OIDCValidationError::UmaTicket {
+                realm,
+                as_uri,
+                ticket,
+            } => {
+                res.insert_header((
+                    WWW_AUTHENTICATE,
+                    format!(
+                        r#"UMA realm="{}", as_uri="{}", ticket="{}""#,
+                        challenge_value(realm),
+                        challenge_value(as_uri),
+                        challenge_value(ticket)
+                    ),
+                ));
+            }
+            _ => {
+                if let Some(challenge) = self.bearer_challenge() {
+                    res.insert_header((WWW_AUTHENTICATE, challenge));
+                }
+            }
         }
+        res.body(self.to_string())
     }
-}
\ No newline at end of file
+}
+
+impl OIDCValidationError {
+    /// The `Bearer` challenge of the failure
+    /// ([RFC 6750 section 3](https://www.rfc-editor.org/rfc/rfc6750#section-3)), none for the
+    /// failures of the server
+    fn bearer_challenge(&self) -> Option<String> {
+        let error = match (self, self.status_code()) {
+            // a request without token gets no error code, the client just needs to authenticate
+            (OIDCValidationError::TokenMissing, _) => return Some("Bearer".to_string()),
+            (_, StatusCode::BAD_REQUEST) => "invalid_request",
+            (_, StatusCode::UNAUTHORIZED) => "invalid_token",
+            (_, StatusCode::FORBIDDEN) => "insufficient_scope",
+            _ => return None,
+        };
+        let mut challenge = format!(
+            r#"Bearer error="{}", error_description="{}""#,
+            error,
+            challenge_value(&self.to_string())
+        );
+        if let OIDCValidationError::MissingScope(scope) = self {
+            challenge.push_str(&format!(r#", scope="{}""#, challenge_value(scope)));
+        }
+        Some(challenge)
+    }
+}
+
+/// Keeps the characters allowed in the quoted values of a challenge, which exclude `"` and `\`
+fn challenge_value(value: &str) -> String {
+    value
+        .chars()
+        .filter(|c| matches!(c, ' '..='~') && *c != '"' && *c != '\\')
+        .collect()
+}
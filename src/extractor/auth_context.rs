@@ -0,0 +1,154 @@
+use std::{
+    collections::BTreeSet,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use actix_web::{dev::Payload, Error, FromRequest, HttpRequest};
+use futures::future::LocalBoxFuture;
+
+use crate::{
+    permissions::{token_roles, PermissionMap, DEFAULT_ROLE_CLAIMS},
+    DecodedInfo, Oidc, Scopes, TokenHash,
+};
+
+/// AuthContext aggregates what handlers and middlewares usually derive from the validated token:
+/// the claims, scopes, roles, mapped permissions, tenant, token hash and expiry
+#[derive(Debug, PartialEq, Clone)]
+pub struct AuthContext {
+    /// The validated token and its claims
+    pub token: DecodedInfo,
+    /// The `sub` claim
+    pub subject: Option<String>,
+    /// The scopes of the `scope` or `scp` claim
+    pub scopes: Scopes,
+    /// The roles of the `roles` claim and the Keycloak `realm_access.roles` claim
+    pub roles: BTreeSet<String>,
+    /// The scopes mapped with the [`PermissionMap`] of the Oidc
+    pub permissions: BTreeSet<String>,
+    /// The `tenant` claim, or the `tid` claim of Azure AD
+    pub tenant: Option<String>,
+    /// The SHA-256 of the token, e.g. to correlate logs without exposing the token
+    pub token_hash: TokenHash,
+    /// The `exp` claim as unix timestamp
+    pub expires_at: Option<i64>,
+}
+
+impl AuthContext {
+    /// Derives the context of the validated token, the scopes are mapped with the permission map
+    pub fn new(token: DecodedInfo, permission_map: &PermissionMap) -> Self {
+        let scopes = Scopes::from(&token);
+        let permissions = permission_map.permissions(scopes.iter().map(String::as_str));
+        let roles = token_roles(&token, DEFAULT_ROLE_CLAIMS);
+        let tenant = token
+            .claim::<String>("/tenant")
+            .or_else(|| token.claim("/tid"));
+        AuthContext {
+            subject: token.payload.registered.subject.clone(),
+            scopes,
+            roles,
+            permissions,
+            tenant,
+            token_hash: TokenHash::of(&token.jwt),
+            expires_at: token
+                .payload
+                .registered
+                .expiry
+                .as_ref()
+                .map(|expiry| expiry.timestamp()),
+            token,
+        }
+    }
+
+    /// Returns whether the token has the role
+    pub fn has_role(&self, role: &str) -> bool {
+        self.roles.contains(role)
+    }
+
+    /// Returns whether the token grants the (mapped) permission
+    pub fn has_permission(&self, permission: &str) -> bool {
+        self.permissions.contains(permission)
+    }
+
+    /// Time until the token expires, zero when it has expired and `None` without `exp` claim
+    pub fn expires_in(&self) -> Option<Duration> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as i64;
+        self.expires_at
+            .map(|expires_at| Duration::from_secs((expires_at - now).max(0) as u64))
+    }
+}
+
+impl FromRequest for AuthContext {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let permission_map = req
+            .app_data::<Oidc>()
+            .map(|oidc| oidc.permission_map.clone())
+            .unwrap_or_default();
+        let decoded_info = DecodedInfo::from_request(req, payload);
+        Box::pin(async move { Ok(AuthContext::new(decoded_info.await?, &permission_map)) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        tests::{create_get_jwt_request, create_oidc, create_token},
+        AuthContext, PermissionMap, TokenHash,
+    };
+    use actix_web::{get, test, App, Error};
+    use bytes::Bytes;
+    use serde_json::json;
+
+    #[get("/context")]
+    async fn auth_context(context: AuthContext) -> String {
+        format!(
+            "{} {:?} {:?} {:?} {}",
+            context.subject.as_deref().unwrap_or_default(),
+            context.roles,
+            context.permissions,
+            context.tenant,
+            context.token_hash == TokenHash::of(&context.token.jwt),
+        )
+    }
+
+    ///Test for aggregating the token in one context
+    #[actix_rt::test]
+    async fn test_auth_context() -> Result<(), Error> {
+        let oidc = create_oidc()
+            .await
+            .with_permission_map(PermissionMap::new().map("api.admin", "write"));
+        let app = test::init_service(App::new().app_data(oidc.clone()).service(auth_context)).await;
+
+        let token = create_token(json!({
+            "sub": "joe",
+            "scope": "api.admin openid",
+            "roles": ["editor"],
+            "realm_access": { "roles": ["offline_access"] },
+            "tid": "acme",
+        }));
+        let req = create_get_jwt_request("/context", &token).to_request();
+        let result: Bytes = test::call_and_read_body(&app, req).await;
+
+        assert_eq!(
+            result,
+            Bytes::from_static(
+                br#"joe {"editor", "offline_access"} {"openid", "write"} Some("acme") true"#
+            )
+        );
+        Ok(())
+    }
+
+    ///Test for the expiry of the context
+    #[actix_rt::test]
+    async fn test_auth_context_expiry() {
+        let expired = crate::testing::decoded_info(&json!({ "exp": 1602324610 }));
+        let context = AuthContext::new(expired, &PermissionMap::default());
+        assert_eq!(context.expires_at, Some(1602324610));
+        assert_eq!(context.expires_in(), Some(std::time::Duration::ZERO));
+    }
+}
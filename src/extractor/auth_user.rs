@@ -1,11 +1,10 @@
-
 use actix_web::{dev::Payload, Error, FromRequest, HttpRequest};
 use biscuit::ClaimsSet;
 use futures::future::LocalBoxFuture;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
-use crate::DecodedInfo;
+use crate::{response::rejection, DecodedInfo, OIDCValidationError};
 
 /// AuthenticatedUser with your given Claims struct will be extracted data to use in your functions.
 /// The struct may contain registered claims, these are validated according to
@@ -22,15 +21,15 @@ pub struct AuthenticatedUser<T> {
 
 impl<T: for<'de> Deserialize<'de>> AuthenticatedUser<T> {
     /// Gets the claims from the access token
-    /// This will return a complete claimset that contains all the claims found inside the token
-    /// as Serde Json Value.
-    fn get_claims(
-        claims_set: &ClaimsSet<Value>
-    ) -> T
-    {
-        let json_value = serde_json::to_value(claims_set).unwrap();
-        let authenticated_user: T = serde_json::from_value(json_value).unwrap();
-        authenticated_user
+    /// This will deserialize the complete claimset, with all the claims found inside the token,
+    /// into the given struct T.
+    ///
+    /// Fails with [`OIDCValidationError::ClaimsMismatch`] when the claims don't fit the struct,
+    /// e.g. when a field is missing.
+    fn get_claims(claims_set: &ClaimsSet<Value>) -> Result<T, OIDCValidationError> {
+        serde_json::to_value(claims_set)
+            .and_then(serde_json::from_value)
+            .map_err(OIDCValidationError::ClaimsMismatch)
     }
 }
 
@@ -44,28 +43,30 @@ impl<T: for<'de> Deserialize<'de>> FromRequest for AuthenticatedUser<T> {
         Box::pin(async move {
             let decoded_info = DecodedInfo::from_request(&req_local, &mut payload_local).await?;
 
-            let claims = AuthenticatedUser::<T>::get_claims(&decoded_info.payload);
+            let claims = AuthenticatedUser::<T>::get_claims(&decoded_info.payload)
+                .map_err(|e| rejection(&req_local, e))?;
             Ok(AuthenticatedUser {
                 jwt: decoded_info.jwt.clone(),
                 claims,
             })
-        }) 
+        })
     }
-
-
-
 }
 
-
 #[cfg(test)]
 mod tests {
-    
-    use crate::{tests::{create_get_jwt_request, create_jwt_token, create_oidc, create_post_jwt_request}, AuthenticatedUser};
-    use actix_web::{get, post, test, web::Json, App, Error};
+
+    use crate::{
+        tests::{
+            create_get_jwt_request, create_jwt_token, create_oidc, create_post_jwt_request,
+            create_token,
+        },
+        AuthenticatedUser, TokenLookup,
+    };
+    use actix_web::{cookie::Cookie, get, http::StatusCode, post, test, web::Json, App, Error};
     use bytes::Bytes;
     use serde::{Deserialize, Serialize};
-    
-
+    use serde_json::json;
 
     // Create a struct that will deserialize your claims.
     #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
@@ -79,8 +80,7 @@ mod tests {
     }
 
     #[derive(Serialize, Deserialize)]
-    pub struct SomePayload {
-    }
+    pub struct SomePayload {}
 
     #[get("/authenticated_user")]
     async fn authenticated_user(user: AuthenticatedUser<FoundClaims>) -> String {
@@ -88,19 +88,21 @@ mod tests {
     }
 
     #[post("/authenticated_user")]
-    async fn authenticated_user_post(user: AuthenticatedUser<FoundClaims>, _: Json<SomePayload>) -> String {
+    async fn authenticated_user_post(
+        user: AuthenticatedUser<FoundClaims>,
+        _: Json<SomePayload>,
+    ) -> String {
         format!("Welcome {}!", user.claims.name)
     }
-    
+
     #[get("/no_user")]
     async fn no_user() -> String {
-        format!("Welcome Anonymous!")
+        "Welcome Anonymous!".to_string()
     }
 
     ///Test for getting claims from a token using an extractor
     #[actix_rt::test]
     async fn test_extractor_auth_user() -> Result<(), Error> {
-
         let oidc = create_oidc().await;
 
         let app = test::init_service(
@@ -118,11 +120,10 @@ mod tests {
         assert_eq!(resp, Bytes::from_static(b"Welcome admin!"));
         Ok(())
     }
-    
+
     ///Test for getting claims from a token using an extractor in a post request with JSON payload
     #[actix_rt::test]
     async fn test_extractor_auth_user_should_not_break_payload() -> Result<(), Error> {
-
         let oidc = create_oidc().await;
 
         let app = test::init_service(
@@ -133,7 +134,9 @@ mod tests {
         )
         .await;
 
-        let req = create_post_jwt_request("/authenticated_user", &create_jwt_token(), "{}".as_bytes()).to_request();
+        let req =
+            create_post_jwt_request("/authenticated_user", &create_jwt_token(), "{}".as_bytes())
+                .to_request();
 
         let resp: Bytes = test::call_and_read_body(&app, req).await;
 
@@ -141,10 +144,31 @@ mod tests {
         Ok(())
     }
 
-    ///Test for calling a method without authentication as there is an extractor 
+    ///Test that claims which don't fit the claims struct are rejected instead of panicking
     #[actix_rt::test]
-    async fn test_no_user_with_extractor() -> Result<(), Error> {
+    async fn test_extractor_auth_user_claims_mismatch() -> Result<(), Error> {
+        let oidc = create_oidc().await;
 
+        let app = test::init_service(
+            App::new()
+                .app_data(oidc.clone())
+                .service(authenticated_user),
+        )
+        .await;
+
+        let token =
+            create_token(json!({ "iss": "http://0.0.0.0:9090", "sub": "admin", "aud": ["api"] }));
+        let req = create_get_jwt_request("/authenticated_user", &token).to_request();
+
+        let resp = test::call_service(&app, req).await;
+
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        Ok(())
+    }
+
+    ///Test for calling a method without authentication as there is an extractor
+    #[actix_rt::test]
+    async fn test_no_user_with_extractor() -> Result<(), Error> {
         let oidc = create_oidc().await;
 
         let app = test::init_service(
@@ -155,18 +179,22 @@ mod tests {
         )
         .await;
 
-        let req = test::TestRequest::get().uri("/authenticated_user").to_request();
+        let req = test::TestRequest::get()
+            .uri("/authenticated_user")
+            .to_request();
 
         let resp: Bytes = test::call_and_read_body(&app, req).await;
 
-        assert_eq!(resp, Bytes::from_static(b"No token found or token is not authorized"));
+        assert_eq!(
+            resp,
+            Bytes::from_static(b"No token found or token is not authorized")
+        );
         Ok(())
     }
-    
-    ///Test for calling a method without authentication as there is no extractor 
+
+    ///Test for calling a method without authentication as there is no extractor
     #[actix_rt::test]
     async fn test_no_extractor() -> Result<(), Error> {
-
         let oidc = create_oidc().await;
 
         let app = test::init_service(
@@ -185,10 +213,9 @@ mod tests {
         Ok(())
     }
 
-    ///Test for calling a method without authentication as there is no extractor 
+    ///Test for calling a method without authentication as there is no extractor
     #[actix_rt::test]
     async fn test_no_extractor_with_user() -> Result<(), Error> {
-
         let oidc = create_oidc().await;
 
         let app = test::init_service(
@@ -206,4 +233,29 @@ mod tests {
         assert_eq!(resp, Bytes::from_static(b"Welcome Anonymous!"));
         Ok(())
     }
+
+    ///Test for getting claims from a token in a named cookie
+    #[actix_rt::test]
+    async fn test_extractor_auth_user_cookie() -> Result<(), Error> {
+        let oidc = create_oidc()
+            .await
+            .with_token_lookup(TokenLookup::Cookie("access_token".into()));
+
+        let app = test::init_service(
+            App::new()
+                .app_data(oidc.clone())
+                .service(authenticated_user),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/authenticated_user")
+            .cookie(Cookie::new("access_token", create_jwt_token()))
+            .to_request();
+
+        let resp: Bytes = test::call_and_read_body(&app, req).await;
+
+        assert_eq!(resp, Bytes::from_static(b"Welcome admin!"));
+        Ok(())
+    }
 }
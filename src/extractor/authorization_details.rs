@@ -0,0 +1,206 @@
+use std::ops::Deref;
+
+use actix_web::{dev::Payload, Error, FromRequest, HttpRequest};
+use futures::future::LocalBoxFuture;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::{response::rejection, DecodedInfo, OIDCValidationError};
+
+/// A single entry of the [RFC 9396](https://www.rfc-editor.org/rfc/rfc9396) `authorization_details` claim
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct AuthorizationDetail {
+    /// The type of authorization, e.g. `payment_initiation`
+    #[serde(rename = "type")]
+    pub detail_type: String,
+    /// Locations of the resources, e.g. `https://example.com/payments`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub locations: Vec<String>,
+    /// Actions allowed on the resources, e.g. `initiate`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub actions: Vec<String>,
+    /// Kinds of data that are accessed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub datatypes: Vec<String>,
+    /// Identifier of a specific resource
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identifier: Option<String>,
+    /// Privileges, e.g. roles, on the resources
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub privileges: Vec<String>,
+    /// Fields specific for the type, e.g. `instructedAmount`
+    #[serde(flatten)]
+    pub fields: Map<String, Value>,
+}
+
+/// Requirement an authorization detail of the token must meet
+///
+/// A detail matches when it has the type, all of the actions and all of the locations.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct AuthorizationDetailRequirement {
+    /// The required type
+    pub detail_type: String,
+    /// The actions that must all be allowed
+    pub actions: Vec<String>,
+    /// The locations that must all be covered
+    pub locations: Vec<String>,
+}
+
+impl AuthorizationDetailRequirement {
+    /// Requires a detail of the given type
+    pub fn new(detail_type: impl Into<String>) -> Self {
+        AuthorizationDetailRequirement {
+            detail_type: detail_type.into(),
+            actions: vec![],
+            locations: vec![],
+        }
+    }
+
+    /// Requires the action to be allowed
+    pub fn action(mut self, action: impl Into<String>) -> Self {
+        self.actions.push(action.into());
+        self
+    }
+
+    /// Requires the location to be covered
+    pub fn location(mut self, location: impl Into<String>) -> Self {
+        self.locations.push(location.into());
+        self
+    }
+
+    /// Returns whether the detail meets the requirement
+    pub fn matches(&self, detail: &AuthorizationDetail) -> bool {
+        detail.detail_type == self.detail_type
+            && self
+                .actions
+                .iter()
+                .all(|action| detail.actions.contains(action))
+            && self
+                .locations
+                .iter()
+                .all(|location| detail.locations.contains(location))
+    }
+}
+
+/// AuthorizationDetails extracts the `authorization_details` claim of the validated token, for
+/// fine-grained consent models that don't fit plain scopes.
+///
+/// A token without the claim yields no details, a claim that cannot be parsed is rejected as unauthorized.
+#[derive(Debug, PartialEq, Clone, Default, Serialize, Deserialize)]
+pub struct AuthorizationDetails(pub Vec<AuthorizationDetail>);
+
+impl AuthorizationDetails {
+    /// Parses the claim of the token
+    pub fn from_decoded_info(info: &DecodedInfo) -> Result<Self, OIDCValidationError> {
+        match info.payload.private.get("authorization_details") {
+            Some(details) => serde_json::from_value(details.clone())
+                .map(AuthorizationDetails)
+                .map_err(|_| OIDCValidationError::Unauthorized),
+            None => Ok(AuthorizationDetails::default()),
+        }
+    }
+
+    /// Returns the first detail that meets the requirement
+    pub fn find(
+        &self,
+        requirement: &AuthorizationDetailRequirement,
+    ) -> Option<&AuthorizationDetail> {
+        self.0.iter().find(|detail| requirement.matches(detail))
+    }
+
+    /// Guards a handler: returns the first detail that meets the requirement, or rejects the
+    /// request as having insufficient rights
+    pub fn require(
+        &self,
+        requirement: &AuthorizationDetailRequirement,
+    ) -> Result<&AuthorizationDetail, OIDCValidationError> {
+        self.find(requirement)
+            .ok_or(OIDCValidationError::InvalidAccess)
+    }
+}
+
+impl Deref for AuthorizationDetails {
+    type Target = [AuthorizationDetail];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl FromRequest for AuthorizationDetails {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        let decoded_info = DecodedInfo::from_request(&req, payload);
+        Box::pin(async move {
+            AuthorizationDetails::from_decoded_info(&decoded_info.await?)
+                .map_err(|e| rejection(&req, e))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        tests::{create_get_jwt_request, create_oidc, create_token},
+        AuthorizationDetailRequirement, AuthorizationDetails,
+    };
+    use actix_web::{get, http::StatusCode, test, App, Error, HttpResponse};
+    use bytes::Bytes;
+    use serde_json::json;
+
+    #[get("/payments")]
+    async fn payments(details: AuthorizationDetails) -> Result<HttpResponse, Error> {
+        let requirement = AuthorizationDetailRequirement::new("payment_initiation")
+            .action("initiate")
+            .location("https://example.com/payments");
+        let detail = details.require(&requirement)?;
+        Ok(HttpResponse::Ok().body(detail.fields["instructedAmount"]["amount"].to_string()))
+    }
+
+    fn create_payment_token(actions: &[&str]) -> String {
+        create_token(json!({
+            "iss": "http://0.0.0.0:9090",
+            "authorization_details": [{
+                "type": "payment_initiation",
+                "actions": actions,
+                "locations": ["https://example.com/payments"],
+                "instructedAmount": { "currency": "EUR", "amount": "123.50" },
+            }],
+        }))
+    }
+
+    ///Test for a token with a matching authorization detail
+    #[actix_rt::test]
+    async fn test_extractor_authorization_details() -> Result<(), Error> {
+        let oidc = create_oidc().await;
+
+        let app = test::init_service(App::new().app_data(oidc.clone()).service(payments)).await;
+
+        let token = create_payment_token(&["initiate", "status"]);
+        let req = create_get_jwt_request("/payments", &token).to_request();
+
+        let result: Bytes = test::call_and_read_body(&app, req).await;
+
+        assert_eq!(result, Bytes::from_static(b"\"123.50\""));
+        Ok(())
+    }
+
+    ///Test for a token without the required action
+    #[actix_rt::test]
+    async fn test_extractor_authorization_details_missing_action() -> Result<(), Error> {
+        let oidc = create_oidc().await;
+
+        let app = test::init_service(App::new().app_data(oidc.clone()).service(payments)).await;
+
+        let token = create_payment_token(&["status"]);
+        let req = create_get_jwt_request("/payments", &token).to_request();
+
+        let response = test::call_service(&app, req).await;
+
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        Ok(())
+    }
+}
@@ -1,14 +1,21 @@
-use std::str::FromStr;
-
-use actix_web::{dev::Payload, http::header::HeaderName, Error, FromRequest, HttpRequest};
+use actix_web::{dev::Payload, Error, FromRequest, HttpMessage, HttpRequest};
 use biscuit::ClaimsSet;
-use futures_util::future::{ok, ready, Ready};
-use serde::{Deserialize, Serialize};
+use futures_util::future::{ready, LocalBoxFuture};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::Value;
 
-use crate::{OIDCValidationError, Oidc, TokenLookup};
+use crate::{
+    audit::{cover, RouteCoverage},
+    audit_sink::{audit, AuditDecision},
+    Oidc, ValidationOverride,
+};
 
 /// DecodedInfo with a decorated token will retrieve data for use in your functions
+///
+/// The token is validated once per request: the validated token is kept in the request
+/// extensions, the extractors running after the middleware or after a first extraction reuse it.
+/// The extractors of a same handler are resolved together, each validates the token when no
+/// middleware validated it before, the checks of the request like the DPoP proof run once.
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct DecodedInfo {
     /// The complete encoded token (without the Bearer part)
@@ -17,58 +24,126 @@ pub struct DecodedInfo {
     pub payload: ClaimsSet<Value>,
 }
 
+impl DecodedInfo {
+    /// Gets a single claim by its [JSON pointer](https://www.rfc-editor.org/rfc/rfc6901),
+    /// e.g. `/resource_access/api/roles`, deserialized to the given type.
+    ///
+    /// Returns `None` when the claim is not present or cannot be deserialized to `U`.
+    pub fn claim<U: DeserializeOwned>(&self, pointer: &str) -> Option<U> {
+        self.claim_value(pointer)
+            .and_then(|value| serde_json::from_value(value).ok())
+    }
+
+    /// Gets a single claim by its JSON pointer as Serde Json Value
+    pub fn claim_value(&self, pointer: &str) -> Option<Value> {
+        if let Some(value) = self.payload.private.pointer(pointer) {
+            return Some(value.clone());
+        }
+        // Registered claims are not part of the private claims, look them up in the complete set
+        serde_json::to_value(&self.payload.registered)
+            .ok()
+            .and_then(|registered| registered.pointer(pointer).cloned())
+    }
+}
+
 impl FromRequest for DecodedInfo {
     type Error = Error;
-    type Future = Ready<Result<Self, Self::Error>>;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
 
     fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        #[cfg(any(test, feature = "test-utils"))]
+        if let Some(info) = crate::testing::injected(req) {
+            return Box::pin(async move { Ok(info) });
+        }
+
+        cover(req, RouteCoverage::Extractor);
+        if let Some(info) = req.extensions().get::<DecodedInfo>() {
+            return Box::pin(ready(Ok(info.clone())));
+        }
         let oidc = req
             .app_data::<Oidc>()
-            .expect("Please configure the OIDC on your App");
+            .expect("Please configure the OIDC on your App")
+            .clone();
 
-        let prefix;
-
-        let authorization = match &oidc.token_lookup {
-            TokenLookup::Header(key) => {
-                prefix = "Bearer ";
-                match req.headers().get(HeaderName::from_str(key).unwrap()) {
-                    Some(value) => value.to_str().unwrap().to_string(),
-                    None => return ready(Err(OIDCValidationError::Unauthorized.into())),
-                }
+        let token = oidc
+            .extract_token(req)
+            .and_then(|token| Ok((oidc.tenant_issuer(req, &token)?, token)));
+        #[cfg(feature = "opentelemetry")]
+        let context = oidc
+            .trace_context
+            .then(|| crate::otel::validation_context(req));
+        let req = req.clone();
+        let validation = async move {
+            let validated = match token {
+                Ok((tenant, token)) => oidc
+                    .validate_tenant_token(tenant.as_deref(), &token)
+                    .await
+                    .map(|info| (token, info)),
+                Err(e) => Err(e),
+            };
+            // another extractor of the handler may have finished the checks of the request while
+            // this one awaited the validation, the DPoP proof must not be recorded twice
+            if let Some(info) = req.extensions().get::<DecodedInfo>() {
+                return Ok(info.clone());
             }
-            TokenLookup::Cookie(key) => {
-                prefix = "";
-                match req.cookie(key) {
-                    Some(value) => value.value().to_string(),
-                    None => return ready(Err(OIDCValidationError::Unauthorized.into())),
+            // behind the middleware, it decides on the validated tokens
+            let in_middleware =
+                req.extensions().get::<RouteCoverage>() == Some(&RouteCoverage::Middleware);
+            let validated = validated.and_then(|(token, info)| {
+                oidc.check_request(&req, &token, &info)?;
+                if let (false, Some(validation_override)) =
+                    (in_middleware, req.extensions().get::<ValidationOverride>())
+                {
+                    validation_override.check(&info.payload.registered)?;
+                }
+                Ok(info)
+            });
+            #[cfg(feature = "opentelemetry")]
+            crate::otel::record(&validated, oidc.trace_subjects);
+            match &validated {
+                Ok(info) if !in_middleware => {
+                    audit(&oidc, &req, AuditDecision::Allowed, Some(info), None)
                 }
+                Ok(_) => {}
+                Err(e) => audit(&oidc, &req, AuditDecision::Denied, None, Some(e)),
             }
+            let info = validated.map_err(|e| oidc.error_response(e))?;
+            req.extensions_mut().insert(info.clone());
+            Ok(info)
         };
-
-        match authorization.strip_prefix(prefix) {
-            Some(token) => match oidc.token_decoder.decode(&oidc.jwks, token) {
-                Ok(decoded_token) => match decoded_token.payload() {
-                    Ok(payload) => ok(DecodedInfo {
-                        jwt: token.to_string(),
-                        payload: payload.to_owned(),
-                    }),
-                    Err(_err) => ready(Err(OIDCValidationError::Unauthorized.into())),
-                },
-                Err(e) => ready(Err(e.into())),
-            },
-            _ => ready(Err(OIDCValidationError::BearerNotComplete.into())),
+        #[cfg(feature = "opentelemetry")]
+        if let Some(context) = context {
+            return Box::pin(opentelemetry::context::FutureExt::with_context(
+                validation, context,
+            ));
         }
+        Box::pin(validation)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::{
-        tests::{create_get_jwt_request, create_jwt_token, create_oidc},
-        DecodedInfo,
+        tests::{create_get_jwt_request, create_jwt_token, create_oidc, create_token},
+        ClaimsValidator, DecodedInfo, OIDCValidationError, OidcBiscuitValidator, Principal,
+        TokenLookup, ValidationOverride,
+    };
+    use actix_web::{
+        body::BoxBody,
+        dev::{Service, ServiceRequest},
+        get,
+        http::StatusCode,
+        middleware::{from_fn, Next},
+        test, web, App, Error, FromRequest, HttpMessage, HttpRequest,
     };
-    use actix_web::{dev::Service, get, http::StatusCode, test, App, Error};
+    use biscuit::Validation;
     use bytes::Bytes;
+    use futures_util::future::{ready, FutureExt, LocalBoxFuture};
+    use serde_json::json;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
 
     #[get("/decoder")]
     async fn decoder(claims: DecodedInfo) -> String {
@@ -92,6 +167,38 @@ mod tests {
         Ok(())
     }
 
+    #[get("/roles")]
+    async fn roles(claims: DecodedInfo) -> String {
+        let roles: Vec<String> = claims
+            .claim("/resource_access/api/roles")
+            .unwrap_or_default();
+        let subject: String = claims.claim("/sub").unwrap();
+        format!("{}: {}", subject, roles.join(","))
+    }
+
+    ///Test for reading nested claims by JSON pointer
+    #[actix_rt::test]
+    async fn test_extractor_decoder_claim_pointer() -> Result<(), Error> {
+        let oidc = create_oidc().await;
+
+        let app = test::init_service(App::new().app_data(oidc.clone()).service(roles)).await;
+
+        let token = create_token(json!({
+            "sub": "CgVhZG1pbhIFbG9jYWw",
+            "resource_access": { "api": { "roles": ["reader", "writer"] } }
+        }));
+
+        let req = create_get_jwt_request("/roles", &token).to_request();
+
+        let result: Bytes = test::call_and_read_body(&app, req).await;
+
+        assert_eq!(
+            result,
+            Bytes::from_static(b"CgVhZG1pbhIFbG9jYWw: reader,writer")
+        );
+        Ok(())
+    }
+
     ///Test for decoder entity extractor with bad token
     #[actix_rt::test]
     async fn test_extractor_decoder_bad_token() -> Result<(), Error> {
@@ -103,7 +210,179 @@ mod tests {
 
         let response = app.call(req).await.unwrap();
 
-        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let challenge = response.headers().get("www-authenticate").unwrap();
+        assert!(challenge
+            .to_str()
+            .unwrap()
+            .starts_with(r#"Bearer error="invalid_token", error_description="#));
+        Ok(())
+    }
+
+    ///Test for accepting the token as query parameter on a single scope
+    #[actix_rt::test]
+    async fn test_extractor_decoder_query_parameter() -> Result<(), Error> {
+        let oidc = create_oidc().await;
+
+        let app = test::init_service(
+            App::new().app_data(oidc.clone()).service(decoder).service(
+                web::scope("/downloads")
+                    .app_data(oidc.clone().with_query_parameter("access_token"))
+                    .service(decoder),
+            ),
+        )
+        .await;
+
+        let token = create_jwt_token();
+        for (uri, expected) in [
+            ("/downloads/decoder", StatusCode::OK),
+            ("/decoder", StatusCode::UNAUTHORIZED),
+        ] {
+            let req = test::TestRequest::get()
+                .uri(&format!("{}?access_token={}", uri, token))
+                .to_request();
+            assert_eq!(app.call(req).await?.status(), expected);
+        }
+        Ok(())
+    }
+
+    ///Test for reading the token from a custom header with another or without scheme
+    #[actix_rt::test]
+    async fn test_extractor_decoder_header_scheme() -> Result<(), Error> {
+        let token = create_jwt_token();
+        let header = TokenLookup::Header("x-auth-token".into());
+        for (oidc, value, expected) in [
+            (
+                create_oidc()
+                    .await
+                    .with_token_lookup(header.clone())
+                    .without_header_scheme(),
+                token.clone(),
+                StatusCode::OK,
+            ),
+            (
+                create_oidc()
+                    .await
+                    .with_token_lookup(header.clone())
+                    .with_header_scheme("JWT"),
+                format!("JWT {}", token),
+                StatusCode::OK,
+            ),
+            (
+                create_oidc().await.with_token_lookup(header.clone()),
+                token.clone(),
+                StatusCode::BAD_REQUEST,
+            ),
+        ] {
+            let app = test::init_service(App::new().app_data(oidc).service(decoder)).await;
+            let req = test::TestRequest::get()
+                .uri("/decoder")
+                .insert_header(("x-auth-token", value))
+                .to_request();
+            assert_eq!(app.call(req).await?.status(), expected);
+        }
+        Ok(())
+    }
+
+    ///Test for reading the token with a custom token extractor
+    #[actix_rt::test]
+    async fn test_extractor_decoder_token_extractor() -> Result<(), Error> {
+        let oidc = create_oidc()
+            .await
+            .with_token_extractor(|req: &HttpRequest| {
+                let payload = req.headers().get("x-payload")?.to_str().ok()?;
+                payload.strip_prefix("token=").map(str::to_string)
+            });
+
+        let app = test::init_service(App::new().app_data(oidc).service(decoder)).await;
+
+        let token = create_jwt_token();
+        let req = test::TestRequest::get()
+            .uri("/decoder")
+            .insert_header(("x-payload", format!("token={}", token)))
+            .to_request();
+        let result: Bytes = test::call_and_read_body(&app, req).await;
+        assert_eq!(result, Bytes::from(token.clone()));
+
+        let req = create_get_jwt_request("/decoder", &token).to_request();
+        assert_eq!(app.call(req).await?.status(), StatusCode::UNAUTHORIZED);
+        Ok(())
+    }
+
+    struct Counting(Arc<AtomicUsize>);
+
+    impl ClaimsValidator for Counting {
+        fn validate<'a>(
+            &'a self,
+            _info: &'a DecodedInfo,
+        ) -> LocalBoxFuture<'a, Result<(), OIDCValidationError>> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            ready(Ok(())).boxed_local()
+        }
+    }
+
+    #[get("/cached")]
+    async fn cached(req: HttpRequest) -> Result<String, Error> {
+        let claims = DecodedInfo::extract(&req).await?;
+        let principal = Principal::extract(&req).await?;
+        assert_eq!(principal.decoded_info(), Some(&claims));
+        Ok(claims.jwt)
+    }
+
+    ///Test that the token is validated once per request
+    #[actix_rt::test]
+    async fn test_extractor_decoder_cached() -> Result<(), Error> {
+        let validations = Arc::new(AtomicUsize::new(0));
+        let oidc = create_oidc()
+            .await
+            .with_claims_validator(Counting(validations.clone()));
+
+        let app = test::init_service(App::new().app_data(oidc.clone()).service(cached)).await;
+        let token = create_jwt_token();
+        let req = create_get_jwt_request("/cached", &token).to_request();
+        let result: Bytes = test::call_and_read_body(&app, req).await;
+        assert_eq!(result, Bytes::from(token.clone()));
+        assert_eq!(validations.swap(0, Ordering::SeqCst), 1);
+
+        let app = test::init_service(
+            App::new()
+                .app_data(oidc)
+                .wrap(OidcBiscuitValidator::default())
+                .service(cached),
+        )
+        .await;
+        let req = create_get_jwt_request("/cached", &token).to_request();
+        let result: Bytes = test::call_and_read_body(&app, req).await;
+        assert_eq!(result, Bytes::from(token));
+        assert_eq!(validations.load(Ordering::SeqCst), 1);
+        Ok(())
+    }
+
+    ///Test that the extractor checks the audience of an override inserted by a middleware
+    #[actix_rt::test]
+    async fn test_extractor_decoder_validation_override() -> Result<(), Error> {
+        let oidc = create_oidc().await;
+
+        let app = test::init_service(
+            App::new()
+                .app_data(oidc)
+                .wrap(from_fn(
+                    |req: ServiceRequest, next: Next<BoxBody>| async move {
+                        req.extensions_mut().insert(ValidationOverride {
+                            audience: Some(Validation::Validate("api".into())),
+                            ..ValidationOverride::default()
+                        });
+                        next.call(req).await
+                    },
+                ))
+                .service(decoder),
+        )
+        .await;
+        for (audience, status) in [("api", StatusCode::OK), ("other", StatusCode::UNAUTHORIZED)] {
+            let token = create_token(json!({ "aud": audience }));
+            let req = create_get_jwt_request("/decoder", &token).to_request();
+            assert_eq!(app.call(req).await?.status(), status);
+        }
         Ok(())
     }
 }
@@ -0,0 +1,154 @@
+use std::{any::Any, future::Future, rc::Rc, sync::Arc};
+
+use actix_web::{dev::Payload, Error, FromRequest, HttpMessage, HttpRequest};
+use futures_util::future::{FutureExt, LocalBoxFuture};
+use tokio::sync::OnceCell;
+
+use crate::{response::rejection, DecodedInfo, OIDCValidationError, Oidc};
+
+type EnrichmentFuture = LocalBoxFuture<'static, Result<Rc<dyn Any>, Error>>;
+
+/// The enrichment hook of the Oidc, see [`Oidc::with_enrichment`]
+#[derive(Clone)]
+pub(crate) struct Enrichment(Arc<dyn Fn(DecodedInfo) -> EnrichmentFuture + Send + Sync>);
+
+impl Enrichment {
+    pub(crate) fn new<U, F, Fut>(hook: F) -> Self
+    where
+        U: 'static,
+        F: Fn(DecodedInfo) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<U, Error>> + 'static,
+    {
+        Enrichment(Arc::new(move |info| {
+            hook(info)
+                .map(|principal| principal.map(|principal| Rc::new(principal) as Rc<dyn Any>))
+                .boxed_local()
+        }))
+    }
+}
+
+/// The principal of the request, enriched by the first extractor that gets to it, the others
+/// wait for it
+#[derive(Clone, Default)]
+struct Enriched(Rc<OnceCell<Rc<dyn Any>>>);
+
+/// EnrichedUser extracts the application-level principal of the validated token, e.g. the user
+/// record of the database, as returned by the enrichment hook of [`Oidc::with_enrichment`]
+///
+/// The hook runs once per request, also for several extractors of a same handler, the principal
+/// is kept in the request extensions for the other extractors and middlewares of the request. An
+/// error of the hook, e.g. for an unknown user, fails the request.
+#[derive(Debug, PartialEq, Clone)]
+pub struct EnrichedUser<U>(pub U);
+
+impl<U> EnrichedUser<U> {
+    /// Returns the principal
+    pub fn into_inner(self) -> U {
+        self.0
+    }
+}
+
+impl<U: Clone + 'static> FromRequest for EnrichedUser<U> {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        let info = DecodedInfo::from_request(&req, payload);
+        let Enriched(enriched) = req
+            .extensions_mut()
+            .get_or_insert_with(Enriched::default)
+            .clone();
+        Box::pin(async move {
+            let principal = enriched
+                .get_or_try_init(|| async {
+                    let info = info.await?;
+                    let oidc = req
+                        .app_data::<Oidc>()
+                        .expect("Please configure the OIDC on your App");
+                    let Some(Enrichment(hook)) = oidc.enrichment.clone() else {
+                        let err = OIDCValidationError::InvalidConfiguration(
+                            "no enrichment hook is configured".to_string(),
+                        );
+                        return Err(oidc.error_response(err));
+                    };
+                    hook(info).await
+                })
+                .await?;
+            match principal.downcast_ref::<U>() {
+                Some(principal) => Ok(EnrichedUser(principal.clone())),
+                None => {
+                    let err = OIDCValidationError::InvalidConfiguration(
+                        "the enrichment hook returns another type".to_string(),
+                    );
+                    Err(rejection(&req, err))
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use actix_web::{dev::Service, error, get, http::StatusCode, test, App, Error};
+    use bytes::Bytes;
+    use serde_json::json;
+
+    use crate::{
+        tests::{create_get_jwt_request, create_oidc, create_token},
+        EnrichedUser,
+    };
+
+    #[derive(Debug, PartialEq, Clone)]
+    struct Account {
+        id: u32,
+        name: String,
+    }
+
+    #[get("/account")]
+    async fn account(account: EnrichedUser<Account>, again: EnrichedUser<Account>) -> String {
+        assert_eq!(account, again);
+        format!("{} {}", account.0.id, account.0.name)
+    }
+
+    ///Test that the hook runs once per request, for both extractors of the handler, and its error
+    ///fails the request
+    #[actix_rt::test]
+    async fn test_extractor_enriched_user() -> Result<(), Error> {
+        let lookups = Arc::new(AtomicUsize::new(0));
+        let counter = lookups.clone();
+        let oidc = create_oidc().await.with_enrichment(move |info| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            async move {
+                // the other extractor of the handler runs while the hook waits
+                actix_rt::task::yield_now().await;
+                match info.payload.registered.subject.as_deref() {
+                    Some("admin") => Ok(Account {
+                        id: 7,
+                        name: "Admin".to_string(),
+                    }),
+                    _ => Err(error::ErrorForbidden("unknown user")),
+                }
+            }
+        });
+
+        let app = test::init_service(App::new().app_data(oidc.clone()).service(account)).await;
+
+        let req = create_get_jwt_request("/account", &create_token(json!({ "sub": "admin" })));
+        let result: Bytes = test::call_and_read_body(&app, req.to_request()).await;
+        assert_eq!(result, Bytes::from_static(b"7 Admin"));
+        assert_eq!(lookups.load(Ordering::SeqCst), 1);
+
+        let req = create_get_jwt_request("/account", &create_token(json!({ "sub": "eve" })));
+        assert_eq!(
+            app.call(req.to_request()).await?.status(),
+            StatusCode::FORBIDDEN
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,55 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use actix_web::{dev::Payload, Error, FromRequest, HttpRequest};
+use futures::future::LocalBoxFuture;
+
+use crate::DecodedInfo;
+
+/// ExpiryStatus extracts whether the validated token has expired, for routes that accept
+/// expired tokens within a grace window, e.g. token refresh and re-login endpoints.
+///
+/// See [`OidcBiscuitValidator::allow_expired_within`](crate::OidcBiscuitValidator::allow_expired_within).
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ExpiryStatus {
+    /// The token has no `exp` claim
+    NoExpiry,
+    /// The token expires in the given time
+    ExpiresIn(Duration),
+    /// The token expired the given time ago
+    Expired(Duration),
+}
+
+impl ExpiryStatus {
+    /// The expiry status of the token at the current time
+    pub fn of(info: &DecodedInfo) -> Self {
+        let expiry = match &info.payload.registered.expiry {
+            Some(expiry) => expiry.timestamp(),
+            None => return ExpiryStatus::NoExpiry,
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as i64;
+        match expiry - now {
+            remaining if remaining > 0 => {
+                ExpiryStatus::ExpiresIn(Duration::from_secs(remaining as u64))
+            }
+            remaining => ExpiryStatus::Expired(Duration::from_secs(remaining.unsigned_abs())),
+        }
+    }
+
+    /// Returns whether the token has expired
+    pub fn is_expired(&self) -> bool {
+        matches!(self, ExpiryStatus::Expired(_))
+    }
+}
+
+impl FromRequest for ExpiryStatus {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let decoded_info = DecodedInfo::from_request(req, payload);
+        Box::pin(async move { Ok(ExpiryStatus::of(&decoded_info.await?)) })
+    }
+}
@@ -0,0 +1,91 @@
+use actix_web::{dev::Payload, Error, FromRequest, HttpRequest};
+use futures::future::{ready, LocalBoxFuture};
+use serde::Deserialize;
+
+use crate::{AuthenticatedUser, OIDCValidationError, Oidc};
+
+/// MaybeAuthenticatedUser extracts the [`AuthenticatedUser`] when the request carries a token, for
+/// endpoints that serve anonymous and personalized responses.
+///
+/// A request without token yields `None`, an invalid token is still rejected. This differs from
+/// `Option<AuthenticatedUser<T>>`, which actix resolves to `None` on any failure.
+#[derive(Debug, PartialEq, Clone)]
+pub struct MaybeAuthenticatedUser<T>(pub Option<AuthenticatedUser<T>>);
+
+impl<T> MaybeAuthenticatedUser<T> {
+    /// Returns the authenticated user, `None` for anonymous requests
+    pub fn into_inner(self) -> Option<AuthenticatedUser<T>> {
+        self.0
+    }
+}
+
+impl<T: for<'de> Deserialize<'de> + 'static> FromRequest for MaybeAuthenticatedUser<T> {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        #[cfg(any(test, feature = "test-utils"))]
+        let injected = crate::testing::injected(req).is_some();
+        #[cfg(not(any(test, feature = "test-utils")))]
+        let injected = false;
+
+        let anonymous = !injected
+            && req.app_data::<Oidc>().is_some_and(|oidc| {
+                matches!(
+                    oidc.extract_token(req),
+                    Err(OIDCValidationError::TokenMissing)
+                )
+            });
+        if anonymous {
+            return Box::pin(ready(Ok(MaybeAuthenticatedUser(None))));
+        }
+        let user = AuthenticatedUser::<T>::from_request(req, payload);
+        Box::pin(async move { Ok(MaybeAuthenticatedUser(Some(user.await?))) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        tests::{create_get_jwt_request, create_jwt_token, create_oidc, create_token},
+        MaybeAuthenticatedUser,
+    };
+    use actix_web::{dev::Service, get, http::StatusCode, test, App, Error};
+    use bytes::Bytes;
+    use serde::Deserialize;
+    use serde_json::json;
+
+    #[derive(Debug, PartialEq, Clone, Deserialize)]
+    struct FoundClaims {
+        name: String,
+    }
+
+    #[get("/welcome")]
+    async fn welcome(user: MaybeAuthenticatedUser<FoundClaims>) -> String {
+        match user.into_inner() {
+            Some(user) => format!("Welcome {}!", user.claims.name),
+            None => "Welcome Anonymous!".to_string(),
+        }
+    }
+
+    ///Test for an endpoint serving anonymous and authenticated users
+    #[actix_rt::test]
+    async fn test_extractor_maybe_auth_user() -> Result<(), Error> {
+        let oidc = create_oidc().await.with_required_claims(["/name"]);
+
+        let app = test::init_service(App::new().app_data(oidc.clone()).service(welcome)).await;
+
+        let req = create_get_jwt_request("/welcome", &create_jwt_token()).to_request();
+        let result: Bytes = test::call_and_read_body(&app, req).await;
+        assert_eq!(result, Bytes::from_static(b"Welcome admin!"));
+
+        let req = test::TestRequest::get().uri("/welcome").to_request();
+        let result: Bytes = test::call_and_read_body(&app, req).await;
+        assert_eq!(result, Bytes::from_static(b"Welcome Anonymous!"));
+
+        let token = create_token(json!({ "sub": "CgVhZG1pbhIFbG9jYWw" }));
+        let req = create_get_jwt_request("/welcome", &token).to_request();
+        assert_eq!(app.call(req).await?.status(), StatusCode::FORBIDDEN);
+        Ok(())
+    }
+}
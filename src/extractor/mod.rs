@@ -1,2 +1,12 @@
+pub mod auth_context;
+pub mod auth_user;
+pub mod authorization_details;
 pub mod decoded_info;
-pub mod auth_user;
\ No newline at end of file
+pub mod enriched_user;
+pub mod expiry_status;
+pub mod maybe_auth_user;
+pub mod principal;
+pub mod scopes;
+pub mod subject;
+pub mod uma_permissions;
+pub mod user_info;
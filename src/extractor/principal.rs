@@ -0,0 +1,113 @@
+use actix_web::{dev::Payload, Error, FromRequest, HttpMessage, HttpRequest};
+use futures::future::{ready, LocalBoxFuture};
+
+use crate::{DecodedInfo, OIDCValidationError, Oidc};
+
+/// Principal extracts who sends the request: the validated token, or `Anonymous` for a request
+/// without token, so handlers branch on a single value
+///
+/// The middleware inserts it into the request extensions when it validates the token, and the
+/// `Anonymous` principal on requests without token when
+/// [`OidcBiscuitValidator::allow_anonymous`](crate::OidcBiscuitValidator::allow_anonymous) is
+/// set. Without the middleware, the token of the request is validated like for
+/// [`MaybeAuthenticatedUser`](crate::MaybeAuthenticatedUser), an invalid token is rejected.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Principal {
+    /// The request carries a valid token
+    Authenticated(Box<DecodedInfo>),
+    /// The request carries no token
+    Anonymous,
+}
+
+impl Principal {
+    /// Returns whether the request carries a valid token
+    pub fn is_authenticated(&self) -> bool {
+        matches!(self, Principal::Authenticated(_))
+    }
+
+    /// The validated token, `None` for anonymous requests
+    pub fn decoded_info(&self) -> Option<&DecodedInfo> {
+        match self {
+            Principal::Authenticated(info) => Some(info),
+            Principal::Anonymous => None,
+        }
+    }
+}
+
+impl FromRequest for Principal {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        if let Some(principal) = req.extensions().get::<Principal>() {
+            return Box::pin(ready(Ok(principal.clone())));
+        }
+
+        #[cfg(any(test, feature = "test-utils"))]
+        let injected = crate::testing::injected(req).is_some();
+        #[cfg(not(any(test, feature = "test-utils")))]
+        let injected = false;
+
+        let anonymous = !injected
+            && req.app_data::<Oidc>().is_some_and(|oidc| {
+                matches!(
+                    oidc.extract_token(req),
+                    Err(OIDCValidationError::TokenMissing)
+                )
+            });
+        if anonymous {
+            return Box::pin(ready(Ok(Principal::Anonymous)));
+        }
+        let info = DecodedInfo::from_request(req, payload);
+        Box::pin(async move { Ok(Principal::Authenticated(Box::new(info.await?))) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{dev::Service, get, test, App, Error};
+    use bytes::Bytes;
+    use serde_json::json;
+
+    use crate::{
+        tests::{create_get_jwt_request, create_oidc, create_token},
+        OidcBiscuitValidator, Principal,
+    };
+
+    #[get("/welcome")]
+    async fn welcome(principal: Principal) -> String {
+        match principal {
+            Principal::Authenticated(info) => {
+                format!("Welcome {}!", info.payload.registered.subject.unwrap())
+            }
+            Principal::Anonymous => "Welcome Anonymous!".to_string(),
+        }
+    }
+
+    ///Test that the middleware lets anonymous requests through with the anonymous principal
+    #[actix_rt::test]
+    async fn test_extractor_principal() -> Result<(), Error> {
+        let oidc = create_oidc().await;
+
+        let app = test::init_service(
+            App::new()
+                .app_data(oidc.clone())
+                .wrap(OidcBiscuitValidator::default().allow_anonymous())
+                .service(welcome),
+        )
+        .await;
+
+        let req = create_get_jwt_request("/welcome", &create_token(json!({ "sub": "admin" })))
+            .to_request();
+        let result: Bytes = test::call_and_read_body(&app, req).await;
+        assert_eq!(result, Bytes::from_static(b"Welcome admin!"));
+
+        let req = test::TestRequest::get().uri("/welcome").to_request();
+        let result: Bytes = test::call_and_read_body(&app, req).await;
+        assert_eq!(result, Bytes::from_static(b"Welcome Anonymous!"));
+
+        let req = create_get_jwt_request("/welcome", "bad_token").to_request();
+        assert!(app.call(req).await.is_err());
+        Ok(())
+    }
+}
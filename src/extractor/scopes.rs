@@ -0,0 +1,113 @@
+use std::{collections::HashSet, ops::Deref};
+
+use actix_web::{dev::Payload, Error, FromRequest, HttpRequest};
+use futures::future::LocalBoxFuture;
+use serde::{Deserialize, Serialize};
+
+use crate::{permissions::token_scopes, DecodedInfo};
+
+/// Scopes extracts the scopes of the validated token, read from the space separated `scope`
+/// claim or the `scp` array claim.
+///
+/// A token without scopes yields an empty set.
+#[derive(Debug, PartialEq, Eq, Clone, Default, Serialize, Deserialize)]
+pub struct Scopes(pub HashSet<String>);
+
+impl Scopes {
+    /// Returns whether the token has the scope
+    pub fn contains(&self, scope: &str) -> bool {
+        self.0.contains(scope)
+    }
+
+    /// Returns whether the token has all of the scopes
+    pub fn contains_all<'a>(&self, scopes: impl IntoIterator<Item = &'a str>) -> bool {
+        scopes.into_iter().all(|scope| self.contains(scope))
+    }
+
+    /// Returns whether the token has at least one of the scopes
+    pub fn contains_any<'a>(&self, scopes: impl IntoIterator<Item = &'a str>) -> bool {
+        scopes.into_iter().any(|scope| self.contains(scope))
+    }
+
+    /// Returns the scopes as owned set
+    pub fn into_inner(self) -> HashSet<String> {
+        self.0
+    }
+}
+
+impl From<&DecodedInfo> for Scopes {
+    fn from(info: &DecodedInfo) -> Self {
+        Scopes(token_scopes(info).into_iter().collect())
+    }
+}
+
+impl Deref for Scopes {
+    type Target = HashSet<String>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl FromRequest for Scopes {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let decoded_info = DecodedInfo::from_request(req, payload);
+        Box::pin(async move { Ok(Scopes::from(&decoded_info.await?)) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        tests::{create_get_jwt_request, create_oidc, create_token},
+        Scopes,
+    };
+    use actix_web::{get, test, App, Error};
+    use bytes::Bytes;
+    use serde_json::json;
+
+    #[get("/scopes")]
+    async fn scopes(scopes: Scopes) -> String {
+        format!(
+            "{} {} {}",
+            scopes.contains("api.read"),
+            scopes.contains_all(["api.read", "api.write"]),
+            scopes.contains_any(["api.write", "openid"])
+        )
+    }
+
+    ///Test for getting the scopes from the scope claim
+    #[actix_rt::test]
+    async fn test_extractor_scopes() -> Result<(), Error> {
+        let oidc = create_oidc().await;
+
+        let app = test::init_service(App::new().app_data(oidc.clone()).service(scopes)).await;
+
+        let token = create_token(json!({ "scope": "openid api.read" }));
+        let req = create_get_jwt_request("/scopes", &token).to_request();
+
+        let result: Bytes = test::call_and_read_body(&app, req).await;
+
+        assert_eq!(result, Bytes::from_static(b"true false true"));
+        Ok(())
+    }
+
+    ///Test for a token without scopes
+    #[actix_rt::test]
+    async fn test_extractor_scopes_missing() -> Result<(), Error> {
+        let oidc = create_oidc().await;
+
+        let app = test::init_service(App::new().app_data(oidc.clone()).service(scopes)).await;
+
+        let token = create_token(json!({ "iss": "http://0.0.0.0:9090" }));
+        let req = create_get_jwt_request("/scopes", &token).to_request();
+
+        let result: Bytes = test::call_and_read_body(&app, req).await;
+
+        assert_eq!(result, Bytes::from_static(b"false false false"));
+        Ok(())
+    }
+}
@@ -0,0 +1,123 @@
+use std::{fmt, ops::Deref};
+
+use actix_web::{dev::Payload, Error, FromRequest, HttpRequest};
+use futures::future::LocalBoxFuture;
+use serde::{Deserialize, Serialize};
+
+use crate::{response::rejection, DecodedInfo, OIDCValidationError};
+
+/// Subject extracts only the validated `sub` claim of the token, for handlers that just need
+/// the user id.
+///
+/// A token without a `sub` claim is rejected as unauthorized.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct Subject(pub String);
+
+impl Subject {
+    /// Returns the subject as string slice
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Returns the subject as owned string
+    pub fn into_inner(self) -> String {
+        self.0
+    }
+}
+
+impl Deref for Subject {
+    type Target = str;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl fmt::Display for Subject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl FromRequest for Subject {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        let decoded_info = DecodedInfo::from_request(&req, payload);
+        Box::pin(async move {
+            let decoded_info = decoded_info.await?;
+            match decoded_info.payload.registered.subject {
+                Some(subject) => Ok(Subject(subject)),
+                None => Err(rejection(&req, OIDCValidationError::Unauthorized)),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        tests::{create_get_jwt_request, create_jwt_token, create_oidc, create_token},
+        Subject, UnauthorizedBody,
+    };
+    use actix_web::{get, http::StatusCode, test, App, Error};
+    use bytes::Bytes;
+    use serde_json::json;
+
+    #[get("/subject")]
+    async fn subject(subject: Subject) -> String {
+        format!("Hello {}", subject)
+    }
+
+    ///Test for getting the subject from a token
+    #[actix_rt::test]
+    async fn test_extractor_subject() -> Result<(), Error> {
+        let oidc = create_oidc().await;
+
+        let app = test::init_service(App::new().app_data(oidc.clone()).service(subject)).await;
+
+        let req = create_get_jwt_request("/subject", &create_jwt_token()).to_request();
+
+        let result: Bytes = test::call_and_read_body(&app, req).await;
+
+        assert_eq!(result, Bytes::from_static(b"Hello CgVhZG1pbhIFbG9jYWw"));
+        Ok(())
+    }
+
+    ///Test for a token without subject
+    #[actix_rt::test]
+    async fn test_extractor_subject_missing() -> Result<(), Error> {
+        let oidc = create_oidc().await;
+
+        let app = test::init_service(App::new().app_data(oidc.clone()).service(subject)).await;
+
+        let token = create_token(json!({ "iss": "http://0.0.0.0:9090" }));
+        let req = create_get_jwt_request("/subject", &token).to_request();
+
+        let response = test::call_service(&app, req).await;
+
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        Ok(())
+    }
+
+    ///Test that a token without subject is answered with the configured unauthorized body
+    #[actix_rt::test]
+    async fn test_extractor_subject_missing_body() -> Result<(), Error> {
+        let oidc = create_oidc()
+            .await
+            .with_unauthorized_body(UnauthorizedBody::Text("login required".into()));
+
+        let app = test::init_service(App::new().app_data(oidc.clone()).service(subject)).await;
+
+        let token = create_token(json!({ "iss": "http://0.0.0.0:9090" }));
+        let req = create_get_jwt_request("/subject", &token).to_request();
+
+        let response = test::call_service(&app, req).await;
+
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(test::read_body(response).await, "login required");
+        Ok(())
+    }
+}
@@ -0,0 +1,144 @@
+use std::ops::Deref;
+
+use actix_web::{dev::Payload, Error, FromRequest, HttpRequest};
+use futures::future::LocalBoxFuture;
+use serde::{Deserialize, Serialize};
+
+use crate::{response::rejection, DecodedInfo, OIDCValidationError};
+
+/// A permission granted by a Keycloak UMA requesting party token (RPT)
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct UmaPermission {
+    /// Id of the resource
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rsid: Option<String>,
+    /// Name of the resource
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rsname: Option<String>,
+    /// Scopes granted on the resource
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub scopes: Vec<String>,
+}
+
+impl UmaPermission {
+    /// Returns whether the permission is for the resource, given by id or name
+    pub fn is_for(&self, resource: &str) -> bool {
+        self.rsid.as_deref() == Some(resource) || self.rsname.as_deref() == Some(resource)
+    }
+}
+
+#[derive(Deserialize)]
+struct Authorization {
+    #[serde(default)]
+    permissions: Vec<UmaPermission>,
+}
+
+/// UmaPermissions extracts the `authorization.permissions` claim of a Keycloak UMA requesting
+/// party token.
+///
+/// A token without the claim yields no permissions, use
+/// [`Oidc::require_uma_permission`](crate::Oidc::require_uma_permission) to answer with a
+/// permission ticket instead of a plain rejection.
+#[derive(Debug, PartialEq, Eq, Clone, Default, Serialize, Deserialize)]
+pub struct UmaPermissions(pub Vec<UmaPermission>);
+
+impl UmaPermissions {
+    /// Parses the claim of the token
+    pub fn from_decoded_info(info: &DecodedInfo) -> Result<Self, OIDCValidationError> {
+        match info.payload.private.get("authorization") {
+            Some(authorization) => serde_json::from_value::<Authorization>(authorization.clone())
+                .map(|authorization| UmaPermissions(authorization.permissions))
+                .map_err(|_| OIDCValidationError::Unauthorized),
+            None => Ok(UmaPermissions::default()),
+        }
+    }
+
+    /// Returns whether the scope is granted on the resource, given by id or name
+    pub fn has(&self, resource: &str, scope: &str) -> bool {
+        self.0.iter().any(|permission| {
+            permission.is_for(resource) && permission.scopes.iter().any(|granted| granted == scope)
+        })
+    }
+
+    /// Guards a handler: rejects the request as having insufficient rights when the scope is
+    /// not granted on the resource
+    pub fn require(&self, resource: &str, scope: &str) -> Result<(), OIDCValidationError> {
+        if self.has(resource, scope) {
+            Ok(())
+        } else {
+            Err(OIDCValidationError::InvalidAccess)
+        }
+    }
+}
+
+impl Deref for UmaPermissions {
+    type Target = [UmaPermission];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl FromRequest for UmaPermissions {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        let decoded_info = DecodedInfo::from_request(&req, payload);
+        Box::pin(async move {
+            UmaPermissions::from_decoded_info(&decoded_info.await?).map_err(|e| rejection(&req, e))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        tests::{create_get_jwt_request, create_oidc, create_token},
+        UmaPermissions,
+    };
+    use actix_web::{get, http::StatusCode, test, App, Error};
+    use bytes::Bytes;
+    use serde_json::json;
+
+    #[get("/reports")]
+    async fn reports(permissions: UmaPermissions) -> Result<String, Error> {
+        permissions.require("Reports", "view")?;
+        Ok(format!("{}", permissions.has("Reports", "delete")))
+    }
+
+    ///Test for a token with the required permission
+    #[actix_rt::test]
+    async fn test_extractor_uma_permissions() -> Result<(), Error> {
+        let oidc = create_oidc().await;
+
+        let app = test::init_service(App::new().app_data(oidc.clone()).service(reports)).await;
+
+        let token = create_token(json!({
+            "authorization": { "permissions": [{ "rsid": "1a2b", "rsname": "Reports", "scopes": ["view"] }] },
+        }));
+        let req = create_get_jwt_request("/reports", &token).to_request();
+
+        let result: Bytes = test::call_and_read_body(&app, req).await;
+
+        assert_eq!(result, Bytes::from_static(b"false"));
+        Ok(())
+    }
+
+    ///Test for a token without the authorization claim
+    #[actix_rt::test]
+    async fn test_extractor_uma_permissions_missing() -> Result<(), Error> {
+        let oidc = create_oidc().await;
+
+        let app = test::init_service(App::new().app_data(oidc.clone()).service(reports)).await;
+
+        let token = create_token(json!({ "iss": "http://0.0.0.0:9090" }));
+        let req = create_get_jwt_request("/reports", &token).to_request();
+
+        let response = test::call_service(&app, req).await;
+
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        Ok(())
+    }
+}
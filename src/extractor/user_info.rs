@@ -0,0 +1,107 @@
+use actix_web::{dev::Payload, Error, FromRequest, HttpRequest};
+use awc::error::JsonPayloadError;
+use futures_util::future::LocalBoxFuture;
+use serde::de::DeserializeOwned;
+
+use crate::{DecodedInfo, OIDCValidationError, Oidc};
+
+/// UserInfo extracts the claims of the `userinfo_endpoint` of the identity provider for the
+/// validated token, for access tokens that carry almost no profile claims
+///
+/// The endpoint is discovered with [`OidcConfig::Issuer`](crate::OidcConfig::Issuer) or
+/// configured with [`Oidc::with_userinfo_endpoint`]. The responses are cached by the hash of the
+/// token, see [`Oidc::with_userinfo_cache`].
+#[derive(Debug, PartialEq, Clone)]
+pub struct UserInfo<T>(pub T);
+
+impl<T> UserInfo<T> {
+    /// Returns the UserInfo claims
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T: DeserializeOwned + 'static> FromRequest for UserInfo<T> {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let oidc = req
+            .app_data::<Oidc>()
+            .expect("Please configure the OIDC on your App")
+            .clone();
+        let token = oidc.extract_token(req);
+        let info = DecodedInfo::from_request(req, payload);
+        Box::pin(async move {
+            let info = info.await?;
+            let Some(userinfo) = oidc.userinfo.clone() else {
+                let err = OIDCValidationError::InvalidConfiguration(
+                    "no userinfo_endpoint is configured".to_string(),
+                );
+                return Err(oidc.error_response(err));
+            };
+            let claims = match token {
+                Ok(token) => userinfo.fetch(&oidc, &token, &info).await,
+                Err(e) => Err(e),
+            };
+            claims
+                .and_then(|claims| {
+                    serde_json::from_value(claims).map_err(|e| {
+                        OIDCValidationError::FailedToParseJsonResponse(
+                            JsonPayloadError::Deserialize(e),
+                        )
+                    })
+                })
+                .map(UserInfo)
+                .map_err(|e| oidc.error_response(e))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{dev::Service, get, http::StatusCode, test, App, Error};
+    use bytes::Bytes;
+    use serde::Deserialize;
+    use serde_json::json;
+
+    use crate::{
+        tests::{create_get_jwt_request, create_token, start_test_idp},
+        Oidc, OidcConfig, UserInfo,
+    };
+
+    #[derive(Debug, PartialEq, Clone, Deserialize)]
+    struct Profile {
+        name: String,
+        email: String,
+    }
+
+    #[get("/profile")]
+    async fn profile(user_info: UserInfo<Profile>) -> String {
+        let profile = user_info.into_inner();
+        format!("{} <{}>", profile.name, profile.email)
+    }
+
+    ///Test that the userinfo of the discovered endpoint belongs to the subject of the token
+    #[actix_rt::test]
+    async fn test_extractor_user_info() -> Result<(), Error> {
+        let idp = start_test_idp().await;
+        let oidc = Oidc::new(OidcConfig::Issuer(idp.into())).await.unwrap();
+
+        let app = test::init_service(App::new().app_data(oidc.clone()).service(profile)).await;
+
+        let token = create_token(json!({ "sub": "admin" }));
+        for _ in 0..2 {
+            let req = create_get_jwt_request("/profile", &token).to_request();
+            let result: Bytes = test::call_and_read_body(&app, req).await;
+            assert_eq!(result, Bytes::from_static(b"Admin <admin@example.com>"));
+        }
+
+        for sub in ["disabled", "impostor"] {
+            let token = create_token(json!({ "sub": sub }));
+            let req = create_get_jwt_request("/profile", &token).to_request();
+            assert_eq!(app.call(req).await?.status(), StatusCode::UNAUTHORIZED);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,206 @@
+//! Binding of tokens to a client fingerprint, a mitigation for stolen tokens of browser clients
+//!
+//! At login the application stores a random secret in a secure cookie and puts the fingerprint
+//! of the client in a claim of the token. On every request the fingerprint is computed again,
+//! a token sent by another client than the one it was issued to is rejected.
+
+use std::borrow::Cow;
+
+use actix_web::{http::header, HttpRequest};
+use ring::{digest, rand::SystemRandom};
+
+use crate::{dpop::random_hex, DecodedInfo, OIDCValidationError};
+
+/// Computes the fingerprint of the client that sends a request
+///
+/// Implement this trait for other fingerprint schemes than [`UserAgentCookieFingerprint`].
+pub trait FingerprintScheme: Send + Sync {
+    /// Returns the fingerprint of the client, `None` when it cannot be computed (e.g. the
+    /// cookie is missing)
+    fn fingerprint(&self, req: &HttpRequest) -> Option<String>;
+}
+
+/// Fingerprint of the `User-Agent` header and a secret in a secure cookie: the hex encoded SHA-256
+/// hash of both, separated by a newline
+#[derive(Debug, Clone)]
+pub struct UserAgentCookieFingerprint {
+    cookie: Cow<'static, str>,
+}
+
+impl UserAgentCookieFingerprint {
+    /// Reads the secret from the given cookie
+    pub fn new(cookie: impl Into<Cow<'static, str>>) -> Self {
+        UserAgentCookieFingerprint {
+            cookie: cookie.into(),
+        }
+    }
+
+    /// Generates a random secret to set in the cookie at login
+    pub fn generate_secret() -> String {
+        random_hex(&SystemRandom::new(), 32)
+    }
+
+    /// Computes the fingerprint of the user agent and secret, to put in the token at login
+    pub fn fingerprint_of(user_agent: &str, secret: &str) -> String {
+        let hash = digest::digest(
+            &digest::SHA256,
+            format!("{}\n{}", user_agent, secret).as_bytes(),
+        );
+        hash.as_ref()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+}
+
+impl FingerprintScheme for UserAgentCookieFingerprint {
+    fn fingerprint(&self, req: &HttpRequest) -> Option<String> {
+        let secret = req.cookie(&self.cookie)?;
+        let user_agent = req
+            .headers()
+            .get(header::USER_AGENT)
+            .and_then(|user_agent| user_agent.to_str().ok())
+            .unwrap_or_default();
+        Some(Self::fingerprint_of(user_agent, secret.value()))
+    }
+}
+
+/// Binds the validated tokens to the fingerprint of the client, see [`crate::Oidc::with_fingerprint_binding`]
+pub struct FingerprintBinding {
+    scheme: Box<dyn FingerprintScheme>,
+    claim: Cow<'static, str>,
+}
+
+impl FingerprintBinding {
+    /// Binds with the fingerprint scheme, the token holds the fingerprint in the `fgp` claim
+    pub fn new(scheme: impl FingerprintScheme + 'static) -> Self {
+        FingerprintBinding {
+            scheme: Box::new(scheme),
+            claim: Cow::Borrowed("fgp"),
+        }
+    }
+
+    /// Reads the fingerprint of the token from another claim
+    pub fn with_claim(mut self, claim: impl Into<Cow<'static, str>>) -> Self {
+        self.claim = claim.into();
+        self
+    }
+
+    /// The fingerprint of the client that sends the request
+    pub(crate) fn fingerprint(&self, req: &HttpRequest) -> Option<String> {
+        self.scheme.fingerprint(req)
+    }
+
+    /// Checks the fingerprint of the token against the one of the client
+    pub(crate) fn check(
+        &self,
+        info: &DecodedInfo,
+        fingerprint: Option<&str>,
+    ) -> Result<(), OIDCValidationError> {
+        let expected = info.payload.private.get(self.claim.as_ref());
+        match (expected.and_then(|claim| claim.as_str()), fingerprint) {
+            (Some(expected), Some(fingerprint)) if constant_time_eq(expected, fingerprint) => {
+                Ok(())
+            }
+            _ => Err(OIDCValidationError::FingerprintMismatch),
+        }
+    }
+}
+
+/// Compares without an early return, so the time taken doesn't reveal the matching prefix
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{cookie::Cookie, get, http::StatusCode, test, App, Error};
+    use serde_json::json;
+
+    use super::{FingerprintBinding, UserAgentCookieFingerprint};
+    use crate::{
+        tests::{create_get_jwt_request, create_oidc, create_token},
+        DecodedInfo,
+    };
+
+    #[get("/decoder")]
+    async fn decoder(claims: DecodedInfo) -> String {
+        claims.jwt
+    }
+
+    ///Test that a token is only accepted from the client it is bound to
+    #[actix_rt::test]
+    async fn test_fingerprint_binding() -> Result<(), Error> {
+        let oidc = create_oidc()
+            .await
+            .with_fingerprint_binding(FingerprintBinding::new(UserAgentCookieFingerprint::new(
+                "__Secure-Fgp",
+            )));
+        let app = test::init_service(App::new().app_data(oidc.clone()).service(decoder)).await;
+
+        let secret = UserAgentCookieFingerprint::generate_secret();
+        let token = create_token(json!({
+            "iss": "http://0.0.0.0:9090",
+            "fgp": UserAgentCookieFingerprint::fingerprint_of("browser", &secret),
+        }));
+
+        let req = create_get_jwt_request("/decoder", &token)
+            .insert_header(("user-agent", "browser"))
+            .cookie(Cookie::new("__Secure-Fgp", secret.clone()))
+            .to_request();
+        let response = test::call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let req = create_get_jwt_request("/decoder", &token)
+            .insert_header(("user-agent", "curl"))
+            .cookie(Cookie::new("__Secure-Fgp", secret.clone()))
+            .to_request();
+        let response = test::call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let req = create_get_jwt_request("/decoder", &token)
+            .insert_header(("user-agent", "browser"))
+            .to_request();
+        let response = test::call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // the validation of custom middleware checks the binding as well
+        let req = create_get_jwt_request("/decoder", &token)
+            .insert_header(("user-agent", "curl"))
+            .cookie(Cookie::new("__Secure-Fgp", secret.clone()))
+            .to_http_request();
+        assert!(oidc.validate_request(&req).await.is_err());
+        let req = create_get_jwt_request("/decoder", &token)
+            .insert_header(("user-agent", "browser"))
+            .cookie(Cookie::new("__Secure-Fgp", secret))
+            .to_http_request();
+        assert!(oidc.validate_request(&req).await.is_ok());
+        Ok(())
+    }
+
+    ///Test that a token without fingerprint claim is rejected
+    #[actix_rt::test]
+    async fn test_fingerprint_binding_missing_claim() -> Result<(), Error> {
+        let oidc = create_oidc().await.with_fingerprint_binding(
+            FingerprintBinding::new(UserAgentCookieFingerprint::new("fgp")).with_claim("bound"),
+        );
+        let app = test::init_service(App::new().app_data(oidc.clone()).service(decoder)).await;
+
+        let secret = UserAgentCookieFingerprint::generate_secret();
+        let token = create_token(json!({
+            "iss": "http://0.0.0.0:9090",
+            "fgp": UserAgentCookieFingerprint::fingerprint_of("", &secret),
+        }));
+
+        let req = create_get_jwt_request("/decoder", &token)
+            .cookie(Cookie::new("fgp", secret))
+            .to_request();
+        let response = test::call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        Ok(())
+    }
+}
@@ -0,0 +1,95 @@
+use actix_web::guard::{Guard, GuardContext};
+use serde_json::Value;
+
+use crate::{ClaimRule, DecodedInfo};
+
+/// Routing guard on the claims of the token, e.g. to route `/api` differently per tenant
+///
+/// Guards run synchronously during routing, so the token is not validated by the guard: it
+/// matches on the claims the [`OidcBiscuitValidator`](crate::OidcBiscuitValidator) middleware
+/// validated. Wrap the middleware around the routes with the guards, a request without
+/// validated token never matches.
+///
+/// ```ignore
+/// App::new()
+///     .wrap(OidcBiscuitValidator::default())
+///     .route("/api", web::get().guard(ClaimGuard::eq("tenant", "acme")).to(acme))
+///     .route("/api", web::get().to(others))
+/// ```
+#[derive(Debug, PartialEq, Clone)]
+pub struct ClaimGuard(ClaimRule);
+
+impl ClaimGuard {
+    /// Matches tokens of which the claim has the value, the claim is a top-level claim name
+    /// or a JSON pointer like `/tenant/id`
+    pub fn eq(claim: &str, value: impl Into<Value>) -> Self {
+        ClaimGuard(ClaimRule::equals(pointer(claim), value.into()))
+    }
+
+    /// Matches tokens of which the claim is absent or has another value
+    pub fn ne(claim: &str, value: impl Into<Value>) -> Self {
+        ClaimGuard(ClaimRule::not_equals(pointer(claim), value.into()))
+    }
+
+    /// Matches tokens that meet the claim rule
+    pub fn rule(rule: ClaimRule) -> Self {
+        ClaimGuard(rule)
+    }
+}
+
+fn pointer(claim: &str) -> String {
+    match claim.starts_with('/') {
+        true => claim.to_string(),
+        false => format!("/{}", claim.replace('~', "~0").replace('/', "~1")),
+    }
+}
+
+impl Guard for ClaimGuard {
+    fn check(&self, ctx: &GuardContext<'_>) -> bool {
+        ctx.req_data()
+            .get::<DecodedInfo>()
+            .is_some_and(|info| self.0.holds(info))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        tests::{create_get_jwt_request, create_oidc, create_token},
+        ClaimGuard, OidcBiscuitValidator,
+    };
+    use actix_web::{test, web, App, Error, HttpResponse};
+    use bytes::Bytes;
+    use serde_json::json;
+
+    ///Test for routing on the claims of the token
+    #[actix_rt::test]
+    async fn test_claim_guard() -> Result<(), Error> {
+        let oidc = create_oidc().await;
+
+        let app = test::init_service(
+            App::new()
+                .app_data(oidc.clone())
+                .wrap(OidcBiscuitValidator::default())
+                .route(
+                    "/api",
+                    web::get()
+                        .guard(ClaimGuard::eq("tenant", "acme"))
+                        .to(|| async { HttpResponse::Ok().body("acme") }),
+                )
+                .route(
+                    "/api",
+                    web::get().to(|| async { HttpResponse::Ok().body("others") }),
+                ),
+        )
+        .await;
+
+        for (tenant, expected) in [("acme", "acme"), ("globex", "others")] {
+            let token = create_token(json!({ "tenant": tenant }));
+            let req = create_get_jwt_request("/api", &token).to_request();
+            let result: Bytes = test::call_and_read_body(&app, req).await;
+            assert_eq!(result, Bytes::from(expected));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,134 @@
+use std::{sync::Mutex, time::SystemTime};
+
+use crate::{OIDCValidationError, Oidc};
+
+/// The issuer the openid-configuration is discovered from, shared by the clones of the Oidc
+pub(crate) struct Discovery {
+    issuer: String,
+    discovered_at: Mutex<SystemTime>,
+}
+
+impl Discovery {
+    pub(crate) fn new(issuer: impl Into<String>) -> Self {
+        Discovery {
+            issuer: issuer.into(),
+            discovered_at: Mutex::new(SystemTime::now()),
+        }
+    }
+
+    /// When the openid-configuration was last fetched
+    pub(crate) fn discovered_at(&self) -> SystemTime {
+        *self.discovered_at.lock().unwrap()
+    }
+}
+
+/// A clonable handle to force the refresh of the keys and the discovery of an Oidc at runtime,
+/// e.g. from an admin endpoint after an emergency key rotation, instead of restarting
+///
+/// The refreshes apply to every clone of the Oidc the handle is taken from, see [`Oidc::handle`].
+#[derive(Clone)]
+pub struct OidcHandle {
+    oidc: Oidc,
+}
+
+impl OidcHandle {
+    /// Reloads the keys, see [`Oidc::refresh_keys`]
+    pub async fn refresh_keys(&self) -> Result<(), OIDCValidationError> {
+        self.oidc.refresh_keys().await
+    }
+
+    /// Fetches the openid-configuration again and reloads the keys from its `jwks_uri`, see
+    /// [`Oidc::refresh_discovery`]
+    pub async fn refresh_discovery(&self) -> Result<(), OIDCValidationError> {
+        self.oidc.refresh_discovery().await
+    }
+}
+
+impl Oidc {
+    /// A handle to refresh the keys and the discovery of this Oidc and its clones at runtime
+    pub fn handle(&self) -> OidcHandle {
+        OidcHandle { oidc: self.clone() }
+    }
+
+    /// Fetches the openid-configuration of the issuer again and reloads the keys, from the new
+    /// `jwks_uri` when it moved
+    ///
+    /// Has no effect when the Oidc is not created from an issuer. The openid-configuration must
+    /// still name the issuer. The other discovered endpoints, e.g. the `userinfo_endpoint`, are
+    /// kept.
+    pub async fn refresh_discovery(&self) -> Result<(), OIDCValidationError> {
+        let (Some(discovery), Some(key_store)) = (&self.discovery, &self.key_store) else {
+            return Ok(());
+        };
+        let document = self
+            .guarded(Oidc::discover(&discovery.issuer, &self.http_limits))
+            .await?
+            .ok_or(OIDCValidationError::CircuitOpen)?;
+        if !document.names_issuer(&discovery.issuer) {
+            return Err(OIDCValidationError::DiscoveredIssuerMismatch(
+                discovery.issuer.clone(),
+            ));
+        }
+        *discovery.discovered_at.lock().unwrap() = SystemTime::now();
+        key_store.move_endpoint(document.jwks_uri);
+        let refreshed = self.guarded(key_store.refresh(&self.http_limits)).await;
+        self.clear_validation_cache();
+        refreshed.map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use serde_json::json;
+
+    use super::Discovery;
+    use crate::{
+        tests::{create_jwk_set, create_oidc, create_token, start_test_idp},
+        OIDCValidationError, Oidc, OidcConfig,
+    };
+
+    ///Test that the handle refreshes the discovery and the keys of the Oidc
+    #[actix_rt::test]
+    async fn test_oidc_handle() {
+        let idp = start_test_idp().await;
+        let oidc = Oidc::new(OidcConfig::Issuer(idp.into())).await.unwrap();
+        let discovered_at = oidc.health().discovered_at;
+        let handle = oidc.handle();
+
+        assert!(handle.refresh_keys().await.is_ok());
+        assert!(handle.refresh_discovery().await.is_ok());
+        assert!(oidc.health().discovered_at > discovered_at);
+        assert_eq!(oidc.health().keys, 1);
+        assert_eq!(oidc.key_endpoints().len(), 1);
+    }
+
+    ///Test that a cached token of a removed key is rejected right after the key rotation
+    #[actix_rt::test]
+    async fn test_key_rotation_validation_cache() {
+        let oidc = create_oidc().await.with_validation_cache(16);
+        let exp = chrono::Utc::now().timestamp() + 300;
+        let token = create_token(json!({ "sub": "admin", "exp": exp }));
+        assert!(oidc.validate_token(&token).await.is_ok());
+
+        let mut rotated = create_jwk_set();
+        rotated.keys[0].common.key_id = Some("2024-06-01".to_string());
+        oidc.set_key_set(rotated);
+        assert!(oidc.validate_token(&token).await.is_err());
+    }
+
+    ///Test that the refresh rejects an openid-configuration of another issuer
+    #[actix_rt::test]
+    async fn test_refresh_discovery_issuer() {
+        let idp = start_test_idp().await;
+        let mut oidc = Oidc::new(OidcConfig::Issuer(idp.clone().into()))
+            .await
+            .unwrap();
+        oidc.discovery = Some(Arc::new(Discovery::new(format!("{}/impostor", idp))));
+        assert!(matches!(
+            oidc.refresh_discovery().await,
+            Err(OIDCValidationError::DiscoveredIssuerMismatch(_))
+        ));
+    }
+}
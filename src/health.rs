@@ -0,0 +1,123 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use actix_web::{web, HttpResponse, Resource};
+use serde::{Serialize, Serializer};
+
+use crate::{CircuitState, Oidc};
+
+/// Health of the connectivity to the identity provider, see [`Oidc::health`]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize)]
+pub struct OidcHealth {
+    /// Whether tokens can be validated: keys are loaded, or a custom validator is configured
+    pub ready: bool,
+    /// When the openid-configuration of the issuer was fetched, `None` without discovery
+    #[serde(serialize_with = "epoch_seconds")]
+    pub discovered_at: Option<SystemTime>,
+    /// Number of keys currently loaded
+    pub keys: usize,
+    /// When the keys were last fetched from one of the endpoints
+    #[serde(serialize_with = "epoch_seconds")]
+    pub keys_refreshed_at: Option<SystemTime>,
+    /// Error of the last refresh of the keys, `None` when it succeeded
+    pub last_refresh_error: Option<String>,
+    /// Whether the circuit breaker stops the calls to the identity provider
+    pub circuit_open: bool,
+}
+
+/// Serializes the times as seconds since epoch
+fn epoch_seconds<S: Serializer>(
+    time: &Option<SystemTime>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    time.map(|time| {
+        time.duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+    })
+    .serialize(serializer)
+}
+
+impl Oidc {
+    /// Health of the connectivity to the identity provider: the discovery, the freshness of the
+    /// keys and the error of their last refresh
+    pub fn health(&self) -> OidcHealth {
+        let keys = self.key_set().map(|jwks| jwks.keys.len());
+        let endpoints = self.key_endpoints();
+        OidcHealth {
+            ready: keys.is_none_or(|keys| keys > 0),
+            discovered_at: self
+                .discovery
+                .as_ref()
+                .map(|discovery| discovery.discovered_at()),
+            keys: keys.unwrap_or_default(),
+            keys_refreshed_at: endpoints
+                .iter()
+                .filter_map(|endpoint| endpoint.last_success)
+                .max(),
+            last_refresh_error: self
+                .key_store
+                .as_ref()
+                .and_then(|key_store| key_store.last_refresh_error()),
+            circuit_open: matches!(self.circuit_state(), Some(CircuitState::Open(_))),
+        }
+    }
+
+    /// A `GET` resource that serves the [`Oidc::health`] as JSON, with `503 Service Unavailable`
+    /// while not ready, e.g. as readiness probe on `/health/oidc`
+    ///
+    /// Register the path as public path of the middleware, the probe has no token.
+    pub fn health_resource(&self, path: &str) -> Resource {
+        let oidc = self.clone();
+        web::resource(path).route(web::get().to(move || {
+            let health = oidc.health();
+            async move {
+                match health.ready {
+                    true => HttpResponse::Ok().json(health),
+                    false => HttpResponse::ServiceUnavailable().json(health),
+                }
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test, App};
+    use biscuit::jwk::JWKSet;
+    use serde_json::Value;
+
+    use crate::{
+        tests::{create_oidc, start_test_idp},
+        Oidc, OidcConfig,
+    };
+
+    ///Test that the health is served as readiness probe
+    #[actix_rt::test]
+    async fn test_oidc_health() {
+        let oidc = create_oidc().await;
+        let health = oidc.health();
+        assert!(health.ready);
+        assert_eq!(health.keys, 1);
+        assert_eq!(health.last_refresh_error, None);
+
+        let app =
+            test::init_service(App::new().service(oidc.health_resource("/health/oidc"))).await;
+        let req = test::TestRequest::get().uri("/health/oidc").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body: Value = test::read_body_json(res).await;
+        assert_eq!(body["keys"], 1);
+        assert_eq!(body["discovered_at"], Value::Null);
+
+        oidc.set_key_set(JWKSet { keys: vec![] });
+        let app =
+            test::init_service(App::new().service(oidc.health_resource("/health/oidc"))).await;
+        let req = test::TestRequest::get().uri("/health/oidc").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let idp = start_test_idp().await;
+        let oidc = Oidc::new(OidcConfig::Issuer(idp.into())).await.unwrap();
+        assert!(oidc.health().discovered_at.is_some());
+    }
+}
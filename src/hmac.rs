@@ -0,0 +1,182 @@
+//! Shared-secret mode: tokens signed with HMAC secrets that are known to the issuer and the
+//! resource server, e.g. internal tokens between services
+
+use biscuit::{
+    jwa::SignatureAlgorithm,
+    jws::{RegisteredHeader, Secret},
+    ClaimsSet, Empty, JWT,
+};
+use futures_util::future::{ready, FutureExt, LocalBoxFuture};
+use serde_json::Value;
+
+use crate::{dangerous_peek_claims, DecodedInfo, OIDCValidationError, TokenValidator};
+
+/// A named shared secret
+struct NamedSecret {
+    kid: String,
+    secret: Vec<u8>,
+}
+
+/// Validates tokens signed with named HMAC secrets, selected by the `kid` header of the token
+///
+/// Secrets are rotated without downtime: sign with the new current secret while the previous
+/// secrets stay accepted until the tokens signed with them have expired.
+///
+/// ```ignore
+/// let validator = HmacValidator::new("2024-06", current_secret).with_previous("2024-01", previous_secret);
+/// let oidc = Oidc::new(OidcConfig::Validator(Arc::new(validator))).await?;
+/// ```
+pub struct HmacValidator {
+    secrets: Vec<NamedSecret>,
+    algorithm: SignatureAlgorithm,
+}
+
+impl HmacValidator {
+    /// Validates with the current secret, tokens without `kid` are validated with it as well
+    pub fn new(kid: impl Into<String>, secret: impl Into<Vec<u8>>) -> Self {
+        HmacValidator {
+            secrets: vec![NamedSecret {
+                kid: kid.into(),
+                secret: secret.into(),
+            }],
+            algorithm: SignatureAlgorithm::HS256,
+        }
+    }
+
+    /// Validates with the current secret read from the environment variable, e.g. as injected by
+    /// the secret store of the platform
+    ///
+    /// Fails when the variable is not set or empty.
+    pub fn from_env(kid: impl Into<String>, var: &str) -> Result<Self, OIDCValidationError> {
+        match std::env::var(var) {
+            Ok(secret) if !secret.is_empty() => Ok(HmacValidator::new(kid, secret)),
+            _ => Err(OIDCValidationError::InvalidConfiguration(format!(
+                "HMAC secret variable {} is not set",
+                var
+            ))),
+        }
+    }
+
+    /// Keeps accepting tokens signed with a previous secret
+    pub fn with_previous(mut self, kid: impl Into<String>, secret: impl Into<Vec<u8>>) -> Self {
+        self.secrets.push(NamedSecret {
+            kid: kid.into(),
+            secret: secret.into(),
+        });
+        self
+    }
+
+    /// Uses HS384 or HS512 instead of HS256
+    pub fn with_algorithm(mut self, algorithm: SignatureAlgorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// The `kid` of the current secret
+    pub fn current_kid(&self) -> &str {
+        &self.secrets[0].kid
+    }
+
+    /// Signs the claims with the current secret, e.g. for internal tokens
+    pub fn sign(&self, claims: ClaimsSet<Value>) -> Result<String, OIDCValidationError> {
+        let current = &self.secrets[0];
+        let header = RegisteredHeader {
+            algorithm: self.algorithm,
+            key_id: Some(current.kid.clone()),
+            ..Default::default()
+        };
+        let token = JWT::new_decoded(From::from(header), claims)
+            .encode(&Secret::Bytes(current.secret.clone()))?;
+        Ok(token.unwrap_encoded().to_string())
+    }
+
+    fn decode(&self, token: &str) -> Result<ClaimsSet<Value>, OIDCValidationError> {
+        let kid = dangerous_peek_claims(token)?.header.registered.key_id;
+        let secret = match kid {
+            Some(kid) => self.secrets.iter().find(|secret| secret.kid == kid),
+            None => self.secrets.first(),
+        }
+        .ok_or(OIDCValidationError::Unauthorized)?;
+        let token = JWT::<Value, Empty>::new_encoded(token)
+            .decode(&Secret::Bytes(secret.secret.clone()), self.algorithm)?;
+        Ok(token.payload()?.clone())
+    }
+}
+
+impl TokenValidator for HmacValidator {
+    fn validate<'a>(
+        &'a self,
+        token: &'a str,
+    ) -> LocalBoxFuture<'a, Result<DecodedInfo, OIDCValidationError>> {
+        let decoded = self.decode(token).map(|payload| DecodedInfo {
+            jwt: token.to_string(),
+            payload,
+        });
+        ready(decoded).boxed_local()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use biscuit::{ClaimsSet, RegisteredClaims};
+    use serde_json::{json, Value};
+
+    use super::HmacValidator;
+    use crate::TokenValidator;
+
+    fn claims() -> ClaimsSet<Value> {
+        ClaimsSet {
+            registered: RegisteredClaims {
+                subject: Some("service-a".into()),
+                ..Default::default()
+            },
+            private: json!({}),
+        }
+    }
+
+    ///Test that tokens of the current and previous secrets are accepted during a rotation
+    #[actix_rt::test]
+    async fn test_rotating_secrets() {
+        let previous = HmacValidator::new("2024-01", "previous secret");
+        let current = HmacValidator::new("2024-06", "current secret")
+            .with_previous("2024-01", "previous secret");
+        assert_eq!(current.current_kid(), "2024-06");
+
+        let old_token = previous.sign(claims()).unwrap();
+        let new_token = current.sign(claims()).unwrap();
+        for token in [&old_token, &new_token] {
+            let info = current.validate(token).await.unwrap();
+            assert_eq!(
+                info.payload.registered.subject.as_deref(),
+                Some("service-a")
+            );
+        }
+
+        // After the rotation, the previous secret is dropped
+        let rotated = HmacValidator::new("2024-06", "current secret");
+        assert!(rotated.validate(&old_token).await.is_err());
+        assert!(rotated.validate(&new_token).await.is_ok());
+    }
+
+    ///Test the secret from the environment
+    #[actix_rt::test]
+    async fn test_secret_from_env() {
+        std::env::set_var("TEST_HMAC_SECRET_FROM_ENV", "current secret");
+        let validator = HmacValidator::from_env("2024-06", "TEST_HMAC_SECRET_FROM_ENV").unwrap();
+        let token = HmacValidator::new("2024-06", "current secret")
+            .sign(claims())
+            .unwrap();
+        assert!(validator.validate(&token).await.is_ok());
+        assert!(HmacValidator::from_env("2024-06", "TEST_HMAC_SECRET_NOT_SET").is_err());
+    }
+
+    ///Test that a token with a known kid but another secret is rejected
+    #[actix_rt::test]
+    async fn test_forged_secret() {
+        let validator = HmacValidator::new("2024-06", "current secret");
+        let forged = HmacValidator::new("2024-06", "guessed secret")
+            .sign(claims())
+            .unwrap();
+        assert!(validator.validate(&forged).await.is_err());
+    }
+}
@@ -0,0 +1,425 @@
+use std::{cell::RefCell, future::Future, io, sync::Arc, time::Duration};
+
+use actix_web::{
+    http::{header, StatusCode},
+    web::Bytes,
+};
+use awc::{
+    error::{ConnectError, JsonPayloadError, PayloadError, SendRequestError},
+    ClientResponse, Connector,
+};
+use futures_core::Stream;
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::sync::Semaphore;
+
+use crate::OIDCValidationError;
+
+/// Limits of the calls to the identity provider (discovery, keys, WebFinger, introspection, UserInfo)
+///
+/// A slow or misbehaving endpoint fails with [`OIDCValidationError::RequestTimeout`] or
+/// [`OIDCValidationError::ResponseTooLarge`] instead of stalling request handling.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct HttpLimits {
+    /// Time before the complete response must be received
+    pub timeout: Duration,
+    /// Maximum size of the response body in bytes
+    pub max_response_size: usize,
+    /// Connection pooling and concurrency of the calls
+    pub pool: HttpPool,
+}
+
+impl Default for HttpLimits {
+    fn default() -> Self {
+        HttpLimits {
+            timeout: Duration::from_secs(5),
+            max_response_size: 2 * 1024 * 1024,
+            pool: HttpPool::default(),
+        }
+    }
+}
+
+/// Connection pooling of the calls to the identity provider
+///
+/// The connections are pooled per worker thread. A token storm, e.g. many tokens with an unknown
+/// key at once, can exhaust the connections of the identity provider, cap the concurrent calls
+/// with [`Self::with_max_concurrent_requests`] to prevent it.
+#[derive(Debug, Clone)]
+pub struct HttpPool {
+    /// Maximum number of connections per worker thread
+    pub max_connections: usize,
+    /// Time an idle connection is kept open for reuse
+    pub idle_timeout: Duration,
+    /// Time after which a connection is closed, also when it is in use
+    pub connection_lifetime: Duration,
+    /// Cap and permits of the concurrent calls, the permits are shared by all clones
+    concurrency: Option<(usize, Arc<Semaphore>)>,
+}
+
+impl HttpPool {
+    /// Caps the number of concurrent calls over all worker threads, further calls wait for a
+    /// call to finish
+    pub fn with_max_concurrent_requests(mut self, max: usize) -> Self {
+        self.concurrency = Some((max, Arc::new(Semaphore::new(max))));
+        self
+    }
+
+    /// The cap of the concurrent calls, when configured
+    pub fn max_concurrent_requests(&self) -> Option<usize> {
+        self.concurrency.as_ref().map(|(max, _)| *max)
+    }
+
+    fn connector_settings(&self) -> ConnectorSettings {
+        (
+            self.max_connections,
+            self.idle_timeout,
+            self.connection_lifetime,
+        )
+    }
+}
+
+impl Default for HttpPool {
+    fn default() -> Self {
+        HttpPool {
+            max_connections: 100,
+            idle_timeout: Duration::from_secs(15),
+            connection_lifetime: Duration::from_secs(75),
+            concurrency: None,
+        }
+    }
+}
+
+impl PartialEq for HttpPool {
+    fn eq(&self, other: &Self) -> bool {
+        let same_concurrency = match (&self.concurrency, &other.concurrency) {
+            (Some((_, a)), Some((_, b))) => Arc::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false,
+        };
+        self.connector_settings() == other.connector_settings() && same_concurrency
+    }
+}
+
+impl Eq for HttpPool {}
+
+/// Maximum connections, idle timeout and lifetime of the connections
+type ConnectorSettings = (usize, Duration, Duration);
+
+thread_local! {
+    /// The clients of this worker thread, by connector settings, so their connections are reused
+    static CLIENTS: RefCell<Vec<(ConnectorSettings, awc::Client)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// The client that pools the connections as configured
+pub(crate) fn client(limits: &HttpLimits) -> awc::Client {
+    let settings = limits.pool.connector_settings();
+    CLIENTS.with(|clients| {
+        let mut clients = clients.borrow_mut();
+        if let Some((_, client)) = clients.iter().find(|(key, _)| *key == settings) {
+            return client.clone();
+        }
+        let connector = Connector::new()
+            .limit(limits.pool.max_connections)
+            .conn_keep_alive(limits.pool.idle_timeout)
+            .conn_lifetime(limits.pool.connection_lifetime);
+        let client = awc::Client::builder().connector(connector).finish();
+        clients.push((settings, client.clone()));
+        client
+    })
+}
+
+/// Sends the request within the limits and parses the JSON response
+pub(crate) async fn get_json<T: DeserializeOwned>(
+    request: awc::ClientRequest,
+    limits: &HttpLimits,
+) -> Result<T, OIDCValidationError> {
+    let _permit = acquire(limits).await;
+    within_timeout(limits, read_json(request.send(), limits)).await
+}
+
+/// Sends the request within the limits and parses the JSON response, `None` when the identity
+/// provider answers `401 Unauthorized`, e.g. for a revoked token
+pub(crate) async fn get_json_authorized<T: DeserializeOwned>(
+    request: awc::ClientRequest,
+    limits: &HttpLimits,
+) -> Result<Option<T>, OIDCValidationError> {
+    let _permit = acquire(limits).await;
+    within_timeout(limits, async {
+        let mut res = send(request.send()).await?;
+        match res.status() {
+            StatusCode::UNAUTHORIZED => Ok(None),
+            _ => parse_json(&mut res, limits).await.map(Some),
+        }
+    })
+    .await
+}
+
+/// Posts the JSON body within the limits and parses the JSON response
+pub(crate) async fn post_json<B: Serialize, T: DeserializeOwned>(
+    request: awc::ClientRequest,
+    body: &B,
+    limits: &HttpLimits,
+) -> Result<T, OIDCValidationError> {
+    let _permit = acquire(limits).await;
+    within_timeout(limits, read_json(request.send_json(body), limits)).await
+}
+
+/// Posts the form within the limits and parses the JSON response
+pub(crate) async fn post_form<B: Serialize, T: DeserializeOwned>(
+    request: awc::ClientRequest,
+    form: &B,
+    limits: &HttpLimits,
+) -> Result<T, OIDCValidationError> {
+    let _permit = acquire(limits).await;
+    within_timeout(limits, read_json(request.send_form(form), limits)).await
+}
+
+/// Response of a conditional request, see [`get_cacheable_json`]
+pub(crate) struct Cacheable<T> {
+    /// The parsed body, `None` when the resource is not modified since the given ETag
+    pub(crate) body: Option<T>,
+    /// The ETag of the resource, for the next conditional request
+    pub(crate) etag: Option<String>,
+    /// The `max-age` of the `Cache-Control` header, `None` when absent or caching is not allowed
+    pub(crate) max_age: Option<Duration>,
+}
+
+/// Sends the request within the limits with `If-None-Match` when the ETag is known and parses
+/// the JSON response, unless it is `304 Not Modified`
+pub(crate) async fn get_cacheable_json<T: DeserializeOwned>(
+    mut request: awc::ClientRequest,
+    etag: Option<&str>,
+    limits: &HttpLimits,
+) -> Result<Cacheable<T>, OIDCValidationError> {
+    if let Some(etag) = etag {
+        request = request.insert_header((header::IF_NONE_MATCH, etag));
+    }
+    let _permit = acquire(limits).await;
+    within_timeout(limits, read_cacheable_json(request, etag, limits)).await
+}
+
+async fn read_cacheable_json<T: DeserializeOwned>(
+    request: awc::ClientRequest,
+    etag: Option<&str>,
+    limits: &HttpLimits,
+) -> Result<Cacheable<T>, OIDCValidationError> {
+    let mut res = send(request.send()).await?;
+    let header = |name| {
+        res.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+    };
+    let max_age = header(header::CACHE_CONTROL).and_then(|value| max_age(&value));
+    let not_modified = res.status() == StatusCode::NOT_MODIFIED;
+    let etag = header(header::ETAG).or_else(|| etag.filter(|_| not_modified).map(str::to_string));
+    let body = match not_modified {
+        true => None,
+        false => Some(parse_json(&mut res, limits).await?),
+    };
+    Ok(Cacheable {
+        body,
+        etag,
+        max_age,
+    })
+}
+
+/// The `max-age` of a `Cache-Control` header, `None` when the response must not be cached
+fn max_age(cache_control: &str) -> Option<Duration> {
+    let mut max_age = None;
+    for directive in cache_control.split(',').map(str::trim) {
+        match directive.split_once('=') {
+            Some((name, seconds)) if name.eq_ignore_ascii_case("max-age") => {
+                max_age = seconds
+                    .trim_matches('"')
+                    .parse()
+                    .ok()
+                    .map(Duration::from_secs);
+            }
+            None if directive.eq_ignore_ascii_case("no-store")
+                || directive.eq_ignore_ascii_case("no-cache") =>
+            {
+                return None;
+            }
+            _ => {}
+        }
+    }
+    max_age
+}
+
+/// Fails the call with [`OIDCValidationError::RequestTimeout`] when sending the request and
+/// reading the response body take longer than the timeout together
+async fn within_timeout<T>(
+    limits: &HttpLimits,
+    call: impl Future<Output = Result<T, OIDCValidationError>>,
+) -> Result<T, OIDCValidationError> {
+    actix_web::rt::time::timeout(limits.timeout, call)
+        .await
+        .map_err(|_| OIDCValidationError::RequestTimeout)?
+}
+
+/// Waits for a permit of the concurrent calls, when they are capped
+async fn acquire(limits: &HttpLimits) -> Option<tokio::sync::SemaphorePermit<'_>> {
+    match &limits.pool.concurrency {
+        // The semaphore is never closed
+        Some((_, concurrency)) => concurrency.acquire().await.ok(),
+        None => None,
+    }
+}
+
+async fn read_json<T, S>(
+    send_request: impl Future<Output = Result<ClientResponse<S>, SendRequestError>>,
+    limits: &HttpLimits,
+) -> Result<T, OIDCValidationError>
+where
+    T: DeserializeOwned,
+    S: Stream<Item = Result<Bytes, PayloadError>> + Unpin,
+{
+    let mut res = send(send_request).await?;
+    parse_json(&mut res, limits).await
+}
+
+async fn send<S>(
+    send_request: impl Future<Output = Result<ClientResponse<S>, SendRequestError>>,
+) -> Result<ClientResponse<S>, OIDCValidationError> {
+    send_request.await.map_err(|e| match e {
+        SendRequestError::Timeout | SendRequestError::Connect(ConnectError::Timeout) => {
+            OIDCValidationError::RequestTimeout
+        }
+        e => OIDCValidationError::ConnectivityError(e),
+    })
+}
+
+async fn parse_json<T, S>(
+    res: &mut ClientResponse<S>,
+    limits: &HttpLimits,
+) -> Result<T, OIDCValidationError>
+where
+    T: DeserializeOwned,
+    S: Stream<Item = Result<Bytes, PayloadError>> + Unpin,
+{
+    res.json::<T>()
+        .limit(limits.max_response_size)
+        .await
+        .map_err(|e| match e {
+            JsonPayloadError::Payload(PayloadError::Overflow) => {
+                OIDCValidationError::ResponseTooLarge
+            }
+            JsonPayloadError::Payload(PayloadError::Io(io))
+                if io.kind() == io::ErrorKind::TimedOut =>
+            {
+                OIDCValidationError::RequestTimeout
+            }
+            e => OIDCValidationError::FailedToParseJsonResponse(e),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        time::Duration,
+    };
+
+    use serde_json::Value;
+
+    use super::{client, get_json, max_age, HttpLimits, HttpPool};
+    use crate::{tests::start_test_idp, OIDCValidationError};
+
+    #[actix_rt::test]
+    async fn test_get_json_timeout() {
+        // Accepts connections but never answers
+        let silent = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/jwks", silent.local_addr().unwrap());
+        let limits = HttpLimits {
+            timeout: Duration::from_millis(200),
+            ..HttpLimits::default()
+        };
+
+        let result = get_json::<Value>(awc::Client::default().get(url), &limits).await;
+
+        assert!(matches!(result, Err(OIDCValidationError::RequestTimeout)));
+    }
+
+    #[actix_rt::test]
+    async fn test_get_json_timeout_reading_body() {
+        // Answers with the headers but never sends the complete body
+        let stalling = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/jwks", stalling.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let (mut stream, _) = stalling.accept().unwrap();
+            let _ = stream.read(&mut [0; 1024]);
+            let _ = stream.write_all(b"HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: 100\r\n\r\n{");
+            std::thread::sleep(Duration::from_secs(5));
+        });
+        let limits = HttpLimits {
+            timeout: Duration::from_millis(200),
+            ..HttpLimits::default()
+        };
+
+        let result = get_json::<Value>(awc::Client::default().get(url), &limits).await;
+
+        assert!(matches!(result, Err(OIDCValidationError::RequestTimeout)));
+    }
+
+    #[actix_rt::test]
+    async fn test_get_json_too_large() {
+        let idp = start_test_idp().await;
+        let limits = HttpLimits {
+            max_response_size: 16,
+            ..HttpLimits::default()
+        };
+
+        let client = awc::Client::default();
+        let result = get_json::<Value>(client.get(format!("{}/jwks", idp)), &limits).await;
+        assert!(matches!(result, Err(OIDCValidationError::ResponseTooLarge)));
+
+        let result =
+            get_json::<Value>(client.get(format!("{}/jwks", idp)), &HttpLimits::default()).await;
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_max_age() {
+        assert_eq!(
+            max_age("public, max-age=21446, must-revalidate"),
+            Some(Duration::from_secs(21446))
+        );
+        assert_eq!(max_age("max-age=60, no-cache"), None);
+        assert_eq!(max_age("public"), None);
+    }
+
+    #[actix_rt::test]
+    async fn test_get_json_max_concurrent_requests() {
+        let idp = start_test_idp().await;
+        let limits = HttpLimits {
+            pool: HttpPool::default().with_max_concurrent_requests(1),
+            ..HttpLimits::default()
+        };
+        assert_eq!(limits.pool.max_concurrent_requests(), Some(1));
+
+        let fetches = (0..3)
+            .map(|_| get_json::<Value>(client(&limits).get(format!("{}/jwks", idp)), &limits));
+        let results = futures::future::join_all(fetches).await;
+        assert!(results.iter().all(|result| result.is_ok()));
+        // the permit is given back
+        let (_, concurrency) = limits.pool.concurrency.as_ref().unwrap();
+        assert_eq!(concurrency.available_permits(), 1);
+    }
+
+    #[actix_rt::test]
+    async fn test_client_reused_per_pool_settings() {
+        let limits = HttpLimits::default();
+        let other = HttpLimits {
+            pool: HttpPool {
+                max_connections: 4,
+                ..HttpPool::default()
+            },
+            ..HttpLimits::default()
+        };
+        client(&limits);
+        client(&limits);
+        client(&other);
+        super::CLIENTS.with(|clients| assert_eq!(clients.borrow().len(), 2));
+    }
+}
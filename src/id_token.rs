@@ -0,0 +1,168 @@
+//! ID token validation for applications that run the OIDC login flow themselves
+//! ([OpenID Connect Core 3.1.3.7](https://openid.net/specs/openid-connect-core-1_0.html#IDTokenValidation))
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use biscuit::{SingleOrMultiple, Validation, ValidationOptions};
+
+use crate::{DecodedInfo, OIDCValidationError, ValidationReport};
+
+/// The expectations of the login flow for an ID token, see [`Oidc::validate_id_token`](crate::Oidc::validate_id_token)
+///
+/// ```ignore
+/// let expected = IdTokenValidation::new("my-client").with_nonce(nonce).with_max_age(Duration::from_secs(300));
+/// let info = oidc.validate_id_token(&id_token, &expected).await?;
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdTokenValidation {
+    client_id: String,
+    issuer: Option<String>,
+    nonce: Option<String>,
+    max_age: Option<Duration>,
+}
+
+impl IdTokenValidation {
+    /// Expects an ID token issued for the client, the `aud` must contain the client id
+    pub fn new(client_id: impl Into<String>) -> Self {
+        IdTokenValidation {
+            client_id: client_id.into(),
+            issuer: None,
+            nonce: None,
+            max_age: None,
+        }
+    }
+
+    /// Expects the `iss` of the identity provider the login was started with
+    pub fn with_issuer(mut self, issuer: impl Into<String>) -> Self {
+        self.issuer = Some(issuer.into());
+        self
+    }
+
+    /// Expects the `nonce` that was sent with the authentication request
+    pub fn with_nonce(mut self, nonce: impl Into<String>) -> Self {
+        self.nonce = Some(nonce.into());
+        self
+    }
+
+    /// Expects an authentication (`auth_time`) no longer ago than the `max_age` of the
+    /// authentication request
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Checks the registered claims, then the `azp`, `nonce` and `auth_time` claims
+    pub(crate) fn check(&self, info: &DecodedInfo) -> Result<(), OIDCValidationError> {
+        let options = ValidationOptions {
+            audience: Validation::Validate(self.client_id.as_str().into()),
+            issuer: match &self.issuer {
+                Some(issuer) => Validation::Validate(issuer.clone()),
+                None => Validation::Ignored,
+            },
+            ..ValidationOptions::default()
+        };
+        let report = ValidationReport::collect(&info.payload.registered, &options);
+        if !report.is_valid() {
+            return Err(OIDCValidationError::ClaimsRejected(report));
+        }
+
+        let claim = |name: &str| info.payload.private.get(name);
+        let azp = claim("azp").and_then(|azp| azp.as_str());
+        let multiple_audiences = matches!(
+            &info.payload.registered.audience,
+            Some(SingleOrMultiple::Multiple(audiences)) if audiences.len() > 1
+        );
+        match azp {
+            Some(azp) if azp != self.client_id => return Err(rejected("azp is another client")),
+            None if multiple_audiences => return Err(rejected("azp is missing")),
+            _ => {}
+        }
+
+        if let Some(nonce) = &self.nonce {
+            if claim("nonce").and_then(|nonce| nonce.as_str()) != Some(nonce.as_str()) {
+                return Err(rejected("nonce does not match"));
+            }
+        }
+
+        if let Some(max_age) = self.max_age {
+            let auth_time = claim("auth_time")
+                .and_then(|auth_time| auth_time.as_u64())
+                .ok_or_else(|| rejected("auth_time is missing"))?;
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            if now.saturating_sub(auth_time) > max_age.as_secs() {
+                return Err(rejected("auth_time exceeds max_age"));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn rejected(reason: &str) -> OIDCValidationError {
+    OIDCValidationError::IdTokenRejected(reason.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use serde_json::json;
+
+    use super::IdTokenValidation;
+    use crate::{
+        tests::{create_oidc, create_token},
+        OIDCValidationError,
+    };
+
+    ///Test the nonce, azp and max_age checks of ID tokens
+    #[actix_rt::test]
+    async fn test_validate_id_token() {
+        let oidc = create_oidc().await;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let expected = IdTokenValidation::new("app")
+            .with_nonce("n-0S6")
+            .with_max_age(Duration::from_secs(300));
+        let id_token = |claims: serde_json::Value| {
+            let mut token = json!({
+                "sub": "admin", "aud": ["app", "api"], "azp": "app", "nonce": "n-0S6",
+                "auth_time": now - 60, "exp": now + 300,
+            });
+            token
+                .as_object_mut()
+                .unwrap()
+                .extend(claims.as_object().unwrap().clone());
+            create_token(token)
+        };
+
+        let info = oidc
+            .validate_id_token(&id_token(json!({})), &expected)
+            .await
+            .unwrap();
+        assert_eq!(info.payload.registered.subject.as_deref(), Some("admin"));
+
+        for (claims, reason) in [
+            (json!({ "nonce": "replayed" }), "nonce does not match"),
+            (json!({ "azp": null }), "azp is missing"),
+            (json!({ "azp": "other" }), "azp is another client"),
+            (
+                json!({ "auth_time": now - 600 }),
+                "auth_time exceeds max_age",
+            ),
+        ] {
+            assert!(matches!(
+                oidc.validate_id_token(&id_token(claims), &expected).await,
+                Err(OIDCValidationError::IdTokenRejected(r)) if r == reason
+            ));
+        }
+        let token = id_token(json!({ "aud": "api" }));
+        assert!(matches!(
+            oidc.validate_id_token(&token, &expected).await,
+            Err(OIDCValidationError::ClaimsRejected(_))
+        ));
+    }
+}
@@ -0,0 +1,232 @@
+//! Opaque access tokens validated by the issuer with
+//! [RFC 7662 token introspection](https://www.rfc-editor.org/rfc/rfc7662)
+
+use std::time::Duration;
+
+use biscuit::ClaimsSet;
+use futures_util::future::{FutureExt, LocalBoxFuture};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    http::{self, HttpLimits},
+    response_cache::ResponseCache,
+    DecodedInfo, OIDCValidationError, Oidc, TokenValidator,
+};
+
+#[derive(Serialize)]
+struct IntrospectionRequest<'a> {
+    token: &'a str,
+    token_type_hint: &'static str,
+}
+
+#[derive(Deserialize)]
+struct IntrospectionResponse {
+    active: bool,
+    #[serde(flatten)]
+    claims: ClaimsSet<Value>,
+}
+
+/// Validates opaque access tokens by posting them to the `introspection_endpoint` of the issuer,
+/// authenticated with the client credentials of the resource server
+///
+/// The claims of an active token are the members of the introspection response, e.g. `sub`,
+/// `scope` and `exp`, the [`DecodedInfo::jwt`] holds the opaque token.
+///
+/// ```ignore
+/// let validator = IntrospectionValidator::discover("https://keycloak/realms/acme", "api", secret).await?;
+/// let oidc = Oidc::new(OidcConfig::Validator(Arc::new(validator))).await?;
+/// ```
+pub struct IntrospectionValidator {
+    endpoint: String,
+    client_id: String,
+    client_secret: String,
+    limits: HttpLimits,
+    /// The claims of the tokens, the tokens themselves are not kept
+    cache: Option<ResponseCache<ClaimsSet<Value>>>,
+}
+
+impl IntrospectionValidator {
+    /// Introspects the tokens at the endpoint with the credentials of the client
+    pub fn new(
+        endpoint: impl Into<String>,
+        client_id: impl Into<String>,
+        client_secret: impl Into<String>,
+    ) -> Self {
+        IntrospectionValidator {
+            endpoint: endpoint.into(),
+            client_id: client_id.into(),
+            client_secret: client_secret.into(),
+            limits: HttpLimits::default(),
+            cache: None,
+        }
+    }
+
+    /// Introspects the tokens at the `introspection_endpoint` of the openid-configuration of the
+    /// issuer
+    ///
+    /// Fails when the issuer does not advertise an introspection endpoint.
+    pub async fn discover(
+        issuer_url: &str,
+        client_id: impl Into<String>,
+        client_secret: impl Into<String>,
+    ) -> Result<Self, OIDCValidationError> {
+        IntrospectionValidator::discover_with_limits(
+            issuer_url,
+            client_id,
+            client_secret,
+            HttpLimits::default(),
+        )
+        .await
+    }
+
+    /// Introspects the tokens at the `introspection_endpoint` of the issuer like
+    /// [`Self::discover`], the discovery and the introspection requests are made within the limits
+    pub async fn discover_with_limits(
+        issuer_url: &str,
+        client_id: impl Into<String>,
+        client_secret: impl Into<String>,
+        limits: HttpLimits,
+    ) -> Result<Self, OIDCValidationError> {
+        let discovery_document = Oidc::discover(issuer_url, &limits).await?;
+        let endpoint = discovery_document.introspection_endpoint.ok_or_else(|| {
+            OIDCValidationError::InvalidConfiguration(format!(
+                "issuer {} has no introspection_endpoint",
+                issuer_url
+            ))
+        })?;
+        Ok(
+            IntrospectionValidator::new(endpoint, client_id, client_secret)
+                .with_http_limits(limits),
+        )
+    }
+
+    /// Limits the introspection requests, every request with a token is introspected
+    pub fn with_http_limits(mut self, limits: HttpLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Caches the responses by the hash of the token: active tokens for the TTL but never past
+    /// their `exp`, inactive tokens for the negative TTL
+    ///
+    /// A revoked token stays accepted until its cached response expires, keep the TTL short when
+    /// tokens are revoked before they expire. The cache keeps the 10 000 most recently used
+    /// active and the 1 000 most recently used inactive tokens.
+    pub fn with_cache(mut self, ttl: Duration, negative_ttl: Duration) -> Self {
+        self.cache = Some(ResponseCache::new(ttl, negative_ttl));
+        self
+    }
+
+    async fn introspect(&self, token: &str) -> Result<DecodedInfo, OIDCValidationError> {
+        let Some(cache) = &self.cache else {
+            return self.call_endpoint(token).await;
+        };
+        match cache.get(token) {
+            Some(Some(payload)) => {
+                return Ok(DecodedInfo {
+                    jwt: token.to_string(),
+                    payload,
+                })
+            }
+            Some(None) => return Err(OIDCValidationError::Unauthorized),
+            None => {}
+        }
+        match self.call_endpoint(token).await {
+            Ok(info) => {
+                let exp = info.payload.registered.expiry.map(|exp| exp.timestamp());
+                cache.insert(
+                    token,
+                    info.payload.clone(),
+                    exp.map(|exp| exp.max(0) as u64),
+                );
+                Ok(info)
+            }
+            Err(OIDCValidationError::Unauthorized) => {
+                cache.insert_negative(token);
+                Err(OIDCValidationError::Unauthorized)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    async fn call_endpoint(&self, token: &str) -> Result<DecodedInfo, OIDCValidationError> {
+        let request = http::client(&self.limits)
+            .post(&self.endpoint)
+            .basic_auth(&self.client_id, &self.client_secret);
+        let form = IntrospectionRequest {
+            token,
+            token_type_hint: "access_token",
+        };
+        let response: IntrospectionResponse = http::post_form(request, &form, &self.limits).await?;
+        if !response.active {
+            return Err(OIDCValidationError::Unauthorized);
+        }
+        Ok(DecodedInfo {
+            jwt: token.to_string(),
+            payload: response.claims,
+        })
+    }
+}
+
+impl TokenValidator for IntrospectionValidator {
+    fn validate<'a>(
+        &'a self,
+        token: &'a str,
+    ) -> LocalBoxFuture<'a, Result<DecodedInfo, OIDCValidationError>> {
+        self.introspect(token).boxed_local()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use super::IntrospectionValidator;
+    use crate::{tests::start_test_idp, OIDCValidationError, Oidc, OidcConfig, TokenValidator};
+
+    ///Test that active opaque tokens are accepted with the claims of the introspection response
+    #[actix_rt::test]
+    async fn test_introspection() {
+        let idp = start_test_idp().await;
+        let validator = IntrospectionValidator::discover(&idp, "api", "secret")
+            .await
+            .unwrap();
+        let oidc = Oidc::new(OidcConfig::Validator(Arc::new(validator)))
+            .await
+            .unwrap();
+
+        let info = oidc.validate_token("opaque-active").await.unwrap();
+        assert_eq!(info.jwt, "opaque-active");
+        assert_eq!(info.payload.registered.subject.as_deref(), Some("admin"));
+        assert_eq!(info.payload.private["scope"], "read write");
+        assert!(info.payload.private.get("active").is_none());
+        assert!(matches!(
+            oidc.validate_token("opaque-revoked").await,
+            Err(OIDCValidationError::Unauthorized)
+        ));
+
+        let validator = IntrospectionValidator::new(format!("{}/introspect", idp), "api", "wrong");
+        let oidc = Oidc::new(OidcConfig::Validator(Arc::new(validator)))
+            .await
+            .unwrap();
+        assert!(oidc.validate_token("opaque-active").await.is_err());
+    }
+
+    ///Test that introspection responses are cached by the hash of the token
+    #[actix_rt::test]
+    async fn test_introspection_cache() {
+        let idp = start_test_idp().await;
+        let validator = IntrospectionValidator::discover(&idp, "api", "secret")
+            .await
+            .unwrap()
+            .with_cache(Duration::from_secs(60), Duration::from_secs(5));
+
+        let info = validator.validate("opaque-active").await.unwrap();
+        assert!(validator.validate("opaque-revoked").await.is_err());
+        let cache = validator.cache.as_ref().unwrap();
+        assert_eq!(cache.get("opaque-active"), Some(Some(info.payload.clone())));
+        assert_eq!(cache.get("opaque-revoked"), Some(None));
+        assert_eq!(validator.validate("opaque-active").await.unwrap(), info);
+    }
+}
@@ -0,0 +1,556 @@
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use actix_web::http::Uri;
+use arc_swap::ArcSwapOption;
+use futures_util::future::{try_join_all, FutureExt, LocalBoxFuture};
+use tokio::sync::OnceCell;
+
+use crate::{
+    circuit_breaker::CircuitBreaker, keys::KeyStore, oidc::OidcDecoder, unverified::peek_claims,
+    validator::TokenValidator, AuthMetrics, DecodedInfo, HttpLimits, IssuerPolicy,
+    OIDCValidationError, Oidc,
+};
+
+/// Pattern of the issuers that are accepted
+#[derive(Debug, Clone)]
+pub enum IssuerPattern {
+    /// Issuer with `*` wildcards, each matches a single non empty DNS label or path segment of
+    /// letters, digits and `-`, e.g. `https://*.example.com` or `https://auth.example.com/realms/*`
+    Wildcard(Cow<'static, str>),
+    /// Regular expression that must match the complete issuer
+    #[cfg(feature = "regex")]
+    Regex(regex::Regex),
+}
+
+impl IssuerPattern {
+    /// Returns whether the issuer matches the pattern
+    ///
+    /// Only http(s) URLs without user info, query or fragment match, so the discovery URL built
+    /// from a matching issuer always points at the host the pattern allows.
+    pub fn matches(&self, issuer: &str) -> bool {
+        if !is_issuer_url(issuer) {
+            return false;
+        }
+        match self {
+            IssuerPattern::Wildcard(pattern) => wildcard_match(pattern, issuer),
+            #[cfg(feature = "regex")]
+            IssuerPattern::Regex(regex) => regex
+                .find(issuer)
+                .is_some_and(|found| found.start() == 0 && found.end() == issuer.len()),
+        }
+    }
+}
+
+fn is_issuer_url(issuer: &str) -> bool {
+    let Ok(uri) = issuer.parse::<Uri>() else {
+        return false;
+    };
+    matches!(uri.scheme_str(), Some("https") | Some("http"))
+        && uri.host().is_some_and(|host| !host.is_empty())
+        && uri.query().is_none()
+        && !issuer.contains(['#', '@'])
+}
+
+fn wildcard_match(pattern: &str, value: &str) -> bool {
+    let Some((prefix, rest)) = pattern.split_once('*') else {
+        return pattern == value;
+    };
+    let Some(value) = value.strip_prefix(prefix) else {
+        return false;
+    };
+    for (end, c) in value.char_indices() {
+        if !(c.is_ascii_alphanumeric() || c == '-') {
+            return false;
+        }
+        let end = end + c.len_utf8();
+        if wildcard_match(rest, &value[end..]) {
+            return true;
+        }
+    }
+    false
+}
+
+/// The key stores of the issuers discovered while serving requests, of the issuer patterns and
+/// of the tenants
+///
+/// An issuer is discovered once, concurrent requests of a new issuer wait for the same discovery.
+/// A failed discovery is not kept, the next request of the issuer discovers it again. At most
+/// `max_issuers` issuers are kept, the requests of further issuers are rejected.
+pub(crate) struct DiscoveredIssuers {
+    issuers: Mutex<HashMap<String, Arc<OnceCell<Arc<KeyStore>>>>>,
+    max_issuers: usize,
+    metrics: ArcSwapOption<AuthMetrics>,
+}
+
+impl DiscoveredIssuers {
+    pub(crate) fn new(max_issuers: usize) -> Self {
+        DiscoveredIssuers {
+            issuers: Mutex::default(),
+            max_issuers,
+            metrics: ArcSwapOption::empty(),
+        }
+    }
+
+    /// Counts the reloads of the keys of the issuers in the metrics, of the ones discovered so
+    /// far and of the ones discovered later
+    pub(crate) fn with_metrics(&self, metrics: Arc<AuthMetrics>) {
+        self.metrics.store(Some(metrics.clone()));
+        for (_, key_store) in self.key_stores() {
+            key_store.with_metrics(metrics.clone());
+        }
+    }
+
+    /// The keys of the issuer, discovered the first time it is seen, through the circuit breaker
+    /// when given
+    pub(crate) async fn key_store(
+        &self,
+        issuer: &str,
+        limits: &HttpLimits,
+        circuit_breaker: Option<&CircuitBreaker>,
+    ) -> Result<Arc<KeyStore>, OIDCValidationError> {
+        let discovery = {
+            let mut issuers = self.issuers.lock().unwrap();
+            match issuers.get(issuer) {
+                Some(discovery) => discovery.clone(),
+                None if issuers.len() >= self.max_issuers => {
+                    return Err(OIDCValidationError::Unauthorized)
+                }
+                None => issuers.entry(issuer.to_string()).or_default().clone(),
+            }
+        };
+        let key_store = discovery
+            .get_or_try_init(|| async {
+                let key_store = Oidc::discover_key_store(issuer, limits);
+                let key_store = match circuit_breaker {
+                    Some(circuit_breaker) => circuit_breaker.call(key_store).await,
+                    None => key_store.await,
+                }?;
+                if let Some(metrics) = self.metrics.load_full() {
+                    key_store.with_metrics(metrics);
+                }
+                Ok(Arc::new(key_store))
+            })
+            .await;
+        match key_store {
+            Ok(key_store) => Ok(key_store.clone()),
+            Err(e) => {
+                let mut issuers = self.issuers.lock().unwrap();
+                // a failed discovery does not hold a place, unless another request completed it
+                if issuers
+                    .get(issuer)
+                    .is_some_and(|discovery| discovery.get().is_none())
+                {
+                    issuers.remove(issuer);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// The current keys of the issuer, `None` when it is not discovered yet
+    pub(crate) fn get(&self, issuer: &str) -> Option<Arc<KeyStore>> {
+        let issuers = self.issuers.lock().unwrap();
+        issuers.get(issuer)?.get().cloned()
+    }
+
+    /// The discovered issuers and their key stores
+    pub(crate) fn key_stores(&self) -> Vec<(String, Arc<KeyStore>)> {
+        self.issuers
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(issuer, discovery)| Some((issuer.clone(), discovery.get()?.clone())))
+            .collect()
+    }
+}
+
+/// Validates tokens of every issuer that matches one of the patterns
+///
+/// The keys of an issuer are discovered with its openid-configuration the first time a token of
+/// that issuer is seen and cached from then on, the openid-configuration must name the issuer.
+/// At most [`Self::DEFAULT_MAX_ISSUERS`] issuers are kept, the tokens of further issuers are
+/// rejected, see [`Self::with_max_issuers`].
+pub struct IssuerPatternValidator {
+    patterns: Vec<IssuerPattern>,
+    discovered: DiscoveredIssuers,
+    policies: Vec<(IssuerPattern, IssuerPolicy)>,
+    decoder: OidcDecoder,
+    limits: HttpLimits,
+}
+
+impl IssuerPatternValidator {
+    /// The number of issuers of which the keys are kept by default
+    pub const DEFAULT_MAX_ISSUERS: usize = 100;
+
+    /// Creates a validator that accepts the issuers matching the patterns
+    pub fn new(patterns: Vec<IssuerPattern>) -> Self {
+        IssuerPatternValidator {
+            patterns,
+            discovered: DiscoveredIssuers::new(IssuerPatternValidator::DEFAULT_MAX_ISSUERS),
+            policies: Vec::new(),
+            decoder: OidcDecoder,
+            limits: HttpLimits::default(),
+        }
+    }
+
+    /// Holds the tokens of the issuers matching the pattern to the policy, e.g. all realms of a
+    /// partner
+    ///
+    /// The policy of the first matching pattern applies, in the order they are added.
+    pub fn with_policy(mut self, pattern: IssuerPattern, policy: IssuerPolicy) -> Self {
+        self.policies.push((pattern, policy));
+        self
+    }
+
+    /// Limits the calls that discover the keys of a newly seen issuer
+    pub fn with_http_limits(mut self, limits: HttpLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Keeps the keys of at most `max_issuers` issuers, instead of [`Self::DEFAULT_MAX_ISSUERS`]
+    ///
+    /// The issuers are read from tokens before they are verified, the limit keeps made up issuers
+    /// from filling the memory.
+    pub fn with_max_issuers(mut self, max_issuers: usize) -> Self {
+        self.discovered = DiscoveredIssuers::new(max_issuers);
+        self
+    }
+}
+
+impl TokenValidator for IssuerPatternValidator {
+    fn validate<'a>(
+        &'a self,
+        token: &'a str,
+    ) -> LocalBoxFuture<'a, Result<DecodedInfo, OIDCValidationError>> {
+        async move {
+            // The issuer selects the keys, the signature check below makes it trustworthy
+            let issuer = unverified_issuer(token)?;
+            if !self.patterns.iter().any(|pattern| pattern.matches(&issuer)) {
+                return Err(OIDCValidationError::Unauthorized);
+            }
+            let key_store = self
+                .discovered
+                .key_store(&issuer, &self.limits, None)
+                .await?;
+            let _ = key_store.refresh_for_unknown_kid(token, &self.limits).await;
+            let payload = self.decoder.decode(&key_store.current(), token)?;
+            let info = DecodedInfo {
+                jwt: token.to_string(),
+                payload,
+            };
+            let policy = self
+                .policies
+                .iter()
+                .find(|(pattern, _)| pattern.matches(&issuer))
+                .map(|(_, policy)| policy);
+            check_policy(policy, info)
+        }
+        .boxed_local()
+    }
+
+    fn set_auth_metrics(&self, metrics: Arc<AuthMetrics>) {
+        self.discovered.with_metrics(metrics);
+    }
+}
+
+/// Reads the issuer of a token, to select its keys before the signature is verified
+fn unverified_issuer(token: &str) -> Result<String, OIDCValidationError> {
+    peek_claims(token)
+        .and_then(|claims| claims.registered.issuer)
+        .ok_or(OIDCValidationError::Unauthorized)
+}
+
+/// Holds the validated token to the policy of its issuer, if any
+fn check_policy(
+    policy: Option<&IssuerPolicy>,
+    info: DecodedInfo,
+) -> Result<DecodedInfo, OIDCValidationError> {
+    match policy {
+        Some(policy) => policy.check(&info).map(|_| info),
+        None => Ok(info),
+    }
+}
+
+/// Validates tokens of a fixed set of issuers, each with its own keys
+pub struct MultiIssuerValidator {
+    key_stores: HashMap<String, Arc<KeyStore>>,
+    policies: HashMap<String, IssuerPolicy>,
+    decoder: OidcDecoder,
+    limits: HttpLimits,
+}
+
+impl MultiIssuerValidator {
+    /// Time allowed for the discovery of all issuers with [`OidcConfig::Issuers`](crate::OidcConfig::Issuers)
+    pub const DEFAULT_DISCOVERY_TIMEOUT: Duration = Duration::from_secs(30);
+
+    /// Discovers the keys of all issuers concurrently
+    ///
+    /// Fails when one of the issuers cannot be discovered or when the discovery of all issuers
+    /// together takes longer than the timeout.
+    pub async fn discover<I>(issuers: I, timeout: Duration) -> Result<Self, OIDCValidationError>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        MultiIssuerValidator::discover_with_limits(issuers, timeout, HttpLimits::default()).await
+    }
+
+    /// Discovers the keys of all issuers concurrently like [`Self::discover`], the calls to the
+    /// issuers are made within the limits, also the calls for the keys of unknown key ids later
+    pub async fn discover_with_limits<I>(
+        issuers: I,
+        timeout: Duration,
+        limits: HttpLimits,
+    ) -> Result<Self, OIDCValidationError>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        let limits_ref = &limits;
+        let discoveries = issuers.into_iter().map(|issuer| async move {
+            let key_store = Oidc::discover_key_store(issuer.as_ref(), limits_ref).await?;
+            Ok::<_, OIDCValidationError>((issuer.as_ref().to_string(), Arc::new(key_store)))
+        });
+        let key_stores = actix_web::rt::time::timeout(timeout, try_join_all(discoveries))
+            .await
+            .map_err(|_| OIDCValidationError::DiscoveryTimeout)??;
+        Ok(MultiIssuerValidator {
+            key_stores: key_stores.into_iter().collect(),
+            policies: HashMap::new(),
+            decoder: OidcDecoder,
+            limits,
+        })
+    }
+
+    /// Holds the tokens of the issuer to the policy
+    pub fn with_policy(mut self, issuer: impl Into<String>, policy: IssuerPolicy) -> Self {
+        self.policies.insert(issuer.into(), policy);
+        self
+    }
+
+    /// The issuers of which tokens are accepted
+    pub fn issuers(&self) -> impl Iterator<Item = &str> {
+        self.key_stores.keys().map(String::as_str)
+    }
+}
+
+impl TokenValidator for MultiIssuerValidator {
+    fn validate<'a>(
+        &'a self,
+        token: &'a str,
+    ) -> LocalBoxFuture<'a, Result<DecodedInfo, OIDCValidationError>> {
+        async move {
+            let issuer = unverified_issuer(token)?;
+            let key_store = self
+                .key_stores
+                .get(&issuer)
+                .ok_or(OIDCValidationError::Unauthorized)?;
+            let _ = key_store.refresh_for_unknown_kid(token, &self.limits).await;
+            let payload = self.decoder.decode(&key_store.current(), token)?;
+            let info = DecodedInfo {
+                jwt: token.to_string(),
+                payload,
+            };
+            check_policy(self.policies.get(&issuer), info)
+        }
+        .boxed_local()
+    }
+
+    fn set_auth_metrics(&self, metrics: Arc<AuthMetrics>) {
+        for key_store in self.key_stores.values() {
+            key_store.with_metrics(metrics.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpListener, sync::Arc, time::Duration};
+
+    use actix_web::{dev::Service, get, http::StatusCode, test, App, Error};
+    use serde_json::json;
+
+    use super::{unverified_issuer, IssuerPattern, IssuerPatternValidator, MultiIssuerValidator};
+    use crate::{
+        tests::{create_get_jwt_request, create_token, create_unverified_token, start_test_idp},
+        DecodedInfo, IssuerPolicy, Oidc, OidcConfig,
+    };
+
+    #[get("/decoder")]
+    async fn decoder(claims: DecodedInfo) -> String {
+        claims.payload.registered.issuer.unwrap_or_default()
+    }
+
+    ///Test that the issuer is read from tokens of which biscuit cannot represent the header
+    #[actix_rt::test]
+    async fn test_unverified_issuer_eddsa() {
+        let token = create_unverified_token("EdDSA", json!({ "iss": "https://idp/acme" }));
+        assert_eq!(unverified_issuer(&token).unwrap(), "https://idp/acme");
+        assert!(unverified_issuer("bad.token").is_err());
+    }
+
+    #[actix_rt::test]
+    async fn test_wildcard_matching() {
+        let pattern = IssuerPattern::Wildcard("https://auth.example.com/realms/*".into());
+        assert!(pattern.matches("https://auth.example.com/realms/acme"));
+        assert!(!pattern.matches("https://auth.example.com/realms/"));
+        assert!(!pattern.matches("https://auth.example.com/realms/acme/other"));
+        assert!(!pattern.matches("https://evil.example.com/realms/acme"));
+
+        let pattern = IssuerPattern::Wildcard("https://*.example.com".into());
+        assert!(pattern.matches("https://eu.example.com"));
+        assert!(!pattern.matches("https://eu.example.com.evil"));
+        for evil in [
+            "https://evil.com?.example.com",
+            "https://evil.com#.example.com",
+            "https://evil.com@eu.example.com",
+            "https://evil.com:443.example.com",
+            "https://evil.com/.example.com",
+        ] {
+            assert!(!pattern.matches(evil), "{}", evil);
+        }
+    }
+
+    #[cfg(feature = "regex")]
+    #[actix_rt::test]
+    async fn test_regex_matching() {
+        let pattern = IssuerPattern::Regex(
+            regex::Regex::new(r"https://auth\.example\.com/realms/[a-z]+").unwrap(),
+        );
+        assert!(pattern.matches("https://auth.example.com/realms/acme"));
+        assert!(!pattern.matches("https://auth.example.com/realms/acme/other"));
+    }
+
+    #[actix_rt::test]
+    async fn test_issuer_pattern_validator() -> Result<(), Error> {
+        let idp = start_test_idp().await;
+        let validator =
+            IssuerPatternValidator::new(vec![IssuerPattern::Wildcard("http://127.0.0.1:*".into())]);
+        let oidc = Oidc::new(OidcConfig::Validator(Arc::new(validator)))
+            .await
+            .unwrap();
+
+        let app = test::init_service(App::new().app_data(oidc.clone()).service(decoder)).await;
+
+        let token = create_token(json!({ "iss": idp, "sub": "admin" }));
+        let req = create_get_jwt_request("/decoder", &token).to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        assert_eq!(body, idp.as_bytes());
+
+        let token = create_token(json!({ "iss": "http://0.0.0.0:9090", "sub": "admin" }));
+        let req = create_get_jwt_request("/decoder", &token).to_request();
+        let response = app.call(req).await?;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        Ok(())
+    }
+
+    ///Test that the policy of the first pattern matching the discovered issuer applies
+    #[actix_rt::test]
+    async fn test_issuer_pattern_validator_policy() -> Result<(), Error> {
+        let idp = start_test_idp().await;
+        let validator =
+            IssuerPatternValidator::new(vec![IssuerPattern::Wildcard("http://127.0.0.1:*".into())])
+                .with_policy(
+                    IssuerPattern::Wildcard("http://127.0.0.1:*".into()),
+                    IssuerPolicy::default().audience("orders-api"),
+                )
+                .with_policy(
+                    IssuerPattern::Wildcard("http://*:*".into()),
+                    IssuerPolicy::default().audience("other-api"),
+                );
+        let oidc = Oidc::new(OidcConfig::Validator(Arc::new(validator)))
+            .await
+            .unwrap();
+
+        let app = test::init_service(App::new().app_data(oidc.clone()).service(decoder)).await;
+
+        for (audience, expected) in [
+            ("orders-api", StatusCode::OK),
+            ("other-api", StatusCode::UNAUTHORIZED),
+        ] {
+            let token = create_token(json!({ "iss": idp, "aud": audience }));
+            let req = create_get_jwt_request("/decoder", &token).to_request();
+            assert_eq!(app.call(req).await?.status(), expected, "{}", audience);
+        }
+        Ok(())
+    }
+
+    ///Test that issuers with another openid-configuration issuer and issuers over the limit are rejected
+    #[actix_rt::test]
+    async fn test_issuer_pattern_validator_rejections() -> Result<(), Error> {
+        let idp = start_test_idp().await;
+        let other = start_test_idp().await;
+        let validator = IssuerPatternValidator::new(vec![
+            IssuerPattern::Wildcard("http://127.0.0.1:*".into()),
+            IssuerPattern::Wildcard("http://127.0.0.1:*/impostor".into()),
+        ])
+        .with_max_issuers(1);
+        let oidc = Oidc::new(OidcConfig::Validator(Arc::new(validator)))
+            .await
+            .unwrap();
+
+        let app = test::init_service(App::new().app_data(oidc.clone()).service(decoder)).await;
+
+        for issuer in [format!("{}/impostor", idp), idp.clone(), other] {
+            let token = create_token(json!({ "iss": issuer, "sub": "admin" }));
+            let req = create_get_jwt_request("/decoder", &token).to_request();
+            let status = app.call(req).await?.status();
+            let expected = match issuer == idp {
+                true => StatusCode::OK,
+                false => StatusCode::UNAUTHORIZED,
+            };
+            assert_eq!(status, expected, "{}", issuer);
+        }
+        Ok(())
+    }
+
+    #[actix_rt::test]
+    async fn test_multi_issuer_discovery() -> Result<(), Error> {
+        let first = start_test_idp().await;
+        // issuers with a trailing slash, like Auth0, are matched on the exact `iss` claim
+        let second = format!("{}/", start_test_idp().await);
+        let oidc = Oidc::new(OidcConfig::Issuers(vec![
+            first.clone().into(),
+            second.clone().into(),
+        ]))
+        .await
+        .unwrap();
+
+        let app = test::init_service(App::new().app_data(oidc.clone()).service(decoder)).await;
+
+        for issuer in [&first, &second] {
+            let token = create_token(json!({ "iss": issuer, "sub": "admin" }));
+            let req = create_get_jwt_request("/decoder", &token).to_request();
+            let body = test::call_and_read_body(&app, req).await;
+            assert_eq!(body, issuer.as_bytes());
+        }
+
+        let token = create_token(json!({ "iss": "http://0.0.0.0:9090", "sub": "admin" }));
+        let req = create_get_jwt_request("/decoder", &token).to_request();
+        let response = app.call(req).await?;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        Ok(())
+    }
+
+    #[actix_rt::test]
+    async fn test_multi_issuer_discovery_timeout() {
+        let idp = start_test_idp().await;
+        // Accepts connections but never answers
+        let silent = TcpListener::bind("127.0.0.1:0").unwrap();
+        let silent_issuer = format!("http://{}", silent.local_addr().unwrap());
+
+        let result =
+            MultiIssuerValidator::discover([idp, silent_issuer], Duration::from_millis(500)).await;
+
+        assert!(matches!(
+            result.err(),
+            Some(crate::OIDCValidationError::DiscoveryTimeout)
+        ));
+    }
+}
@@ -0,0 +1,116 @@
+//! Encrypted tokens: JWS tokens nested in a JWE, e.g. ID tokens the identity provider encrypts
+//! for confidentiality
+
+use std::borrow::Cow;
+
+use biscuit::{
+    jwa::{ContentEncryptionAlgorithm, KeyManagementAlgorithm},
+    jwe,
+    jwk::JWK,
+    jws, ClaimsSet, Empty,
+};
+use serde_json::Value;
+
+use crate::OIDCValidationError;
+
+/// Decrypts JWE wrapped tokens before their nested JWS is validated
+///
+/// The key management algorithms of biscuit are supported: `dir`, `A128GCMKW` and `A256GCMKW`,
+/// with the symmetric (`oct`) key shared with the identity provider.
+///
+/// ```ignore
+/// let decryption = JweDecryption::new(key, KeyManagementAlgorithm::A256GCMKW, ContentEncryptionAlgorithm::A256GCM);
+/// let oidc = oidc.with_jwe_decryption(decryption);
+/// ```
+pub struct JweDecryption {
+    key: JWK<Empty>,
+    key_algorithm: KeyManagementAlgorithm,
+    content_algorithm: ContentEncryptionAlgorithm,
+}
+
+impl JweDecryption {
+    /// Decrypts tokens encrypted with exactly these algorithms, other tokens are rejected
+    pub fn new(
+        key: JWK<Empty>,
+        key_algorithm: KeyManagementAlgorithm,
+        content_algorithm: ContentEncryptionAlgorithm,
+    ) -> Self {
+        JweDecryption {
+            key,
+            key_algorithm,
+            content_algorithm,
+        }
+    }
+
+    /// The nested JWS of a JWE token (5 parts), other tokens are returned as they are
+    pub(crate) fn decrypt<'a>(&self, token: &'a str) -> Result<Cow<'a, str>, OIDCValidationError> {
+        if token.split('.').count() != 5 {
+            return Ok(Cow::Borrowed(token));
+        }
+        let encrypted: jwe::Compact<jws::Compact<ClaimsSet<Value>, Empty>, Empty> =
+            jwe::Compact::new_encrypted(token);
+        let nested = encrypted
+            .decrypt(&self.key, self.key_algorithm, self.content_algorithm)
+            .and_then(|decrypted| Ok(decrypted.payload()?.encoded()?.encode()))
+            .map_err(|_| OIDCValidationError::DecryptionFailed)?;
+        Ok(Cow::Owned(nested))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use biscuit::{
+        jwa::{ContentEncryptionAlgorithm, EncryptionOptions, KeyManagementAlgorithm},
+        jwe,
+        jwk::JWK,
+        Empty, JWE, JWT,
+    };
+    use serde_json::Value;
+
+    use super::JweDecryption;
+    use crate::{
+        tests::{create_jwt_token, create_oidc},
+        OIDCValidationError,
+    };
+
+    ///Test that the nested JWS is validated after decryption
+    #[actix_rt::test]
+    async fn test_jwe_decryption() {
+        let key = JWK::<Empty>::new_octet_key(&[7; 32], Default::default());
+        let encrypt = |key: &JWK<Empty>| {
+            let header = jwe::RegisteredHeader {
+                cek_algorithm: KeyManagementAlgorithm::A256GCMKW,
+                enc_algorithm: ContentEncryptionAlgorithm::A256GCM,
+                content_type: Some("JWT".to_string()),
+                ..Default::default()
+            };
+            let options = EncryptionOptions::AES_GCM { nonce: vec![1; 12] };
+            JWE::<Value, Empty, Empty>::new_decrypted(
+                From::from(header),
+                JWT::new_encoded(&create_jwt_token()),
+            )
+            .encrypt(key, &options)
+            .unwrap()
+            .unwrap_encrypted()
+            .to_string()
+        };
+        let oidc = create_oidc().await;
+        let token = encrypt(&key);
+        assert!(oidc.validate_token(&token).await.is_err());
+
+        let oidc = oidc.with_jwe_decryption(JweDecryption::new(
+            key,
+            KeyManagementAlgorithm::A256GCMKW,
+            ContentEncryptionAlgorithm::A256GCM,
+        ));
+        let info = oidc.validate_token(&token).await.unwrap();
+        assert_eq!(info.jwt, create_jwt_token());
+        assert!(oidc.validate_token(&create_jwt_token()).await.is_ok());
+
+        let other = encrypt(&JWK::new_octet_key(&[8; 32], Default::default()));
+        assert!(matches!(
+            oidc.validate_token(&other).await,
+            Err(OIDCValidationError::DecryptionFailed)
+        ));
+    }
+}
@@ -0,0 +1,444 @@
+use std::{
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+
+use actix_web::web;
+use arc_swap::{ArcSwap, ArcSwapOption};
+use biscuit::{jwk::JWKSet, Empty};
+use serde::{de::Error as _, Deserialize, Deserializer};
+use serde_json::Value;
+
+use crate::{unverified::RawHeader, AuthMetrics, HttpLimits, OIDCValidationError, Oidc};
+
+/// Health of one of the endpoints that publish the keys of the issuer
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct KeyEndpointHealth {
+    /// The JWKS uri
+    pub url: String,
+    /// Number of fetches that failed since the last successful one
+    pub consecutive_failures: u32,
+    /// Time of the last successful fetch
+    pub last_success: Option<SystemTime>,
+    /// Error of the last failed fetch
+    pub last_error: Option<String>,
+}
+
+impl KeyEndpointHealth {
+    fn new(url: String) -> Self {
+        KeyEndpointHealth {
+            url,
+            consecutive_failures: 0,
+            last_success: None,
+            last_error: None,
+        }
+    }
+}
+
+/// A JWKS as published by an identity provider
+///
+/// The `EdDSA` algorithm of OKP keys is dropped while parsing, biscuit cannot represent it and
+/// would reject the whole set. The algorithm follows from the `Ed25519` curve of the key.
+pub(crate) struct KeySetDocument(pub(crate) JWKSet<Empty>);
+
+impl<'de> Deserialize<'de> for KeySetDocument {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut jwks = Value::deserialize(deserializer)?;
+        if let Some(keys) = jwks.get_mut("keys").and_then(Value::as_array_mut) {
+            for key in keys.iter_mut().filter_map(Value::as_object_mut) {
+                if key.get("alg").and_then(Value::as_str) == Some("EdDSA") {
+                    key.remove("alg");
+                }
+            }
+        }
+        serde_json::from_value(jwks)
+            .map(KeySetDocument)
+            .map_err(D::Error::custom)
+    }
+}
+
+/// Holds the current key set and the endpoints it is (re)loaded from
+///
+/// Reading the current keys never takes a lock, a refresh swaps the new key set in atomically.
+///
+/// Endpoints are tried in the configured order (primary first), endpoints that failed before
+/// are tried after the healthy ones.
+pub(crate) struct KeyStore {
+    jwks: ArcSwap<JWKSet<Empty>>,
+    endpoints: Vec<Mutex<KeyEndpointHealth>>,
+    file: Option<PathBuf>,
+    last_unknown_kid_refresh: Mutex<Option<Instant>>,
+    cache: Mutex<CacheState>,
+    metrics: ArcSwapOption<AuthMetrics>,
+    last_refresh_error: Mutex<Option<String>>,
+}
+
+/// The HTTP caching headers of the current keys
+#[derive(Default)]
+struct CacheState {
+    /// The endpoint that served the current keys and their ETag
+    etag: Option<(String, String)>,
+    /// The `max-age` the current keys were served with
+    max_age: Option<Duration>,
+    /// The end of the `max-age` of the current keys
+    fresh_until: Option<Instant>,
+}
+
+impl KeyStore {
+    /// Minimum time between two refreshes for tokens signed with an unknown key
+    pub(crate) const UNKNOWN_KID_COOLDOWN: Duration = Duration::from_secs(30);
+
+    /// A store for a fixed key set that is never refreshed from an endpoint
+    pub(crate) fn new(jwks: JWKSet<Empty>) -> Self {
+        KeyStore::with_endpoints(jwks, vec![])
+    }
+
+    /// A store with the given keys that are refreshed from the endpoints
+    pub(crate) fn with_endpoints(jwks: JWKSet<Empty>, urls: Vec<String>) -> Self {
+        KeyStore {
+            jwks: ArcSwap::from_pointee(jwks),
+            endpoints: urls
+                .into_iter()
+                .map(|url| Mutex::new(KeyEndpointHealth::new(url)))
+                .collect(),
+            file: None,
+            last_unknown_kid_refresh: Mutex::default(),
+            cache: Mutex::default(),
+            metrics: ArcSwapOption::empty(),
+            last_refresh_error: Mutex::default(),
+        }
+    }
+
+    /// Counts the reloads of the keys in the metrics, instead of the ones set before
+    pub(crate) fn with_metrics(&self, metrics: Arc<AuthMetrics>) {
+        self.metrics.store(Some(metrics));
+    }
+
+    /// A store that loads its keys from a JWKS file, without any call to the identity provider
+    pub(crate) async fn from_file(path: PathBuf) -> Result<Self, OIDCValidationError> {
+        let store = KeyStore {
+            file: Some(path),
+            ..KeyStore::new(JWKSet { keys: vec![] })
+        };
+        store.reload_file().await?;
+        Ok(store)
+    }
+
+    /// A store that loads its keys from the first endpoint that is available
+    pub(crate) async fn fetch(
+        urls: Vec<String>,
+        limits: &HttpLimits,
+    ) -> Result<Self, OIDCValidationError> {
+        let store = KeyStore::with_endpoints(JWKSet { keys: vec![] }, urls);
+        store.refresh(limits).await?;
+        Ok(store)
+    }
+
+    /// The key set currently in use
+    pub(crate) fn current(&self) -> Arc<JWKSet<Empty>> {
+        self.jwks.load_full()
+    }
+
+    /// Replaces the key set in use
+    pub(crate) fn replace(&self, jwks: JWKSet<Empty>) {
+        self.jwks.store(Arc::new(jwks));
+    }
+
+    /// Whether the keys are read from a file instead of fetched from the identity provider
+    pub(crate) fn reads_file(&self) -> bool {
+        self.file.is_some()
+    }
+
+    /// Reads the keys from the file on the blocking thread pool, a file that cannot be read or
+    /// parsed keeps the current keys
+    async fn reload_file(&self) -> Result<(), OIDCValidationError> {
+        let Some(path) = self.file.clone() else {
+            return Ok(());
+        };
+        let json = web::block({
+            let path = path.clone();
+            move || fs::read_to_string(path)
+        })
+        .await
+        .map_err(|e| file_error(&path, e))?
+        .map_err(|e| file_error(&path, e))?;
+        self.load_file(&path, &json)
+    }
+
+    fn load_file(&self, path: &Path, json: &str) -> Result<(), OIDCValidationError> {
+        let KeySetDocument(jwks) = serde_json::from_str(json).map_err(|e| file_error(path, e))?;
+        self.replace(jwks);
+        Ok(())
+    }
+
+    /// Loads the keys of a discovered issuer from its new `jwks_uri`, the health and the caching
+    /// headers of the former endpoint are dropped
+    pub(crate) fn move_endpoint(&self, url: String) {
+        let [endpoint] = self.endpoints.as_slice() else {
+            return;
+        };
+        let mut endpoint = endpoint.lock().unwrap();
+        if endpoint.url != url {
+            *endpoint = KeyEndpointHealth::new(url);
+            *self.cache.lock().unwrap() = CacheState::default();
+        }
+    }
+
+    /// Health of the configured endpoints, in configured order
+    pub(crate) fn health(&self) -> Vec<KeyEndpointHealth> {
+        self.endpoints
+            .iter()
+            .map(|endpoint| endpoint.lock().unwrap().clone())
+            .collect()
+    }
+
+    /// Reloads the keys once when the token is signed with a `kid` that is not in the current set,
+    /// e.g. right after the issuer rotated its keys
+    ///
+    /// These refreshes are at least [`Self::UNKNOWN_KID_COOLDOWN`] apart, so tokens with made up
+    /// `kid`s cannot cause a storm of refreshes.
+    pub(crate) async fn refresh_for_unknown_kid(
+        &self,
+        token: &str,
+        limits: &HttpLimits,
+    ) -> Result<(), OIDCValidationError> {
+        if self.endpoints.is_empty() && self.file.is_none() {
+            return Ok(());
+        }
+        let kid = RawHeader::peek(token).and_then(|header| header.kid);
+        match kid {
+            Some(kid) if self.current().find(&kid).is_none() => {}
+            _ => return Ok(()),
+        }
+        {
+            let mut last_refresh = self.last_unknown_kid_refresh.lock().unwrap();
+            if last_refresh.is_some_and(|at| at.elapsed() < Self::UNKNOWN_KID_COOLDOWN) {
+                return Ok(());
+            }
+            *last_refresh = Some(Instant::now());
+        }
+        self.refresh(limits).await
+    }
+
+    /// Reloads the keys unless they are still fresh by the `max-age` they were served with
+    pub(crate) async fn refresh_if_stale(
+        &self,
+        limits: &HttpLimits,
+    ) -> Result<(), OIDCValidationError> {
+        let fresh_until = self.cache.lock().unwrap().fresh_until;
+        if fresh_until.is_some_and(|fresh_until| Instant::now() < fresh_until) {
+            return Ok(());
+        }
+        self.refresh(limits).await
+    }
+
+    /// Reloads the keys, failing over to the next endpoint when one is not available
+    ///
+    /// The endpoint that served the current keys is asked with their ETag, a `304 Not Modified`
+    /// keeps them.
+    pub(crate) async fn refresh(&self, limits: &HttpLimits) -> Result<(), OIDCValidationError> {
+        let refreshed = self.reload(limits).await;
+        if let Some(metrics) = &*self.metrics.load() {
+            metrics.record_refresh(&refreshed);
+        }
+        *self.last_refresh_error.lock().unwrap() =
+            refreshed.as_ref().err().map(ToString::to_string);
+        refreshed
+    }
+
+    /// Error of the last refresh, `None` when it succeeded
+    pub(crate) fn last_refresh_error(&self) -> Option<String> {
+        self.last_refresh_error.lock().unwrap().clone()
+    }
+
+    async fn reload(&self, limits: &HttpLimits) -> Result<(), OIDCValidationError> {
+        if self.file.is_some() {
+            return self.reload_file().await;
+        }
+        let mut order: Vec<(usize, u32, String)> = self
+            .endpoints
+            .iter()
+            .enumerate()
+            .map(|(index, endpoint)| {
+                let endpoint = endpoint.lock().unwrap();
+                (index, endpoint.consecutive_failures, endpoint.url.clone())
+            })
+            .collect();
+        // stable sort, so healthy endpoints keep their configured order
+        order.sort_by_key(|(_, failures, _)| *failures);
+
+        let mut last_error = None;
+        for (index, _, url) in order {
+            let etag = match &self.cache.lock().unwrap().etag {
+                Some((etag_url, etag)) if *etag_url == url => Some(etag.clone()),
+                _ => None,
+            };
+            match Oidc::fetch_jwks(&url, etag.as_deref(), limits).await {
+                Ok(response) => {
+                    let mut endpoint = self.endpoints[index].lock().unwrap();
+                    endpoint.consecutive_failures = 0;
+                    endpoint.last_success = Some(SystemTime::now());
+                    let mut cache = self.cache.lock().unwrap();
+                    // a 304 without caching headers keeps those of the stored keys
+                    let max_age = match response.body {
+                        Some(jwks) => {
+                            self.replace(jwks);
+                            response.max_age
+                        }
+                        None => response.max_age.or(cache.max_age),
+                    };
+                    *cache = CacheState {
+                        etag: response.etag.map(|etag| (url, etag)),
+                        max_age,
+                        fresh_until: max_age
+                            .and_then(|max_age| Instant::now().checked_add(max_age)),
+                    };
+                    return Ok(());
+                }
+                Err(e) => {
+                    let mut endpoint = self.endpoints[index].lock().unwrap();
+                    endpoint.consecutive_failures += 1;
+                    endpoint.last_error = Some(e.to_string());
+                    last_error = Some(e);
+                }
+            }
+        }
+        match last_error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+fn file_error(path: &Path, e: impl Display) -> OIDCValidationError {
+    OIDCValidationError::InvalidConfiguration(format!("JWKS file {:?}: {}", path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Instant};
+
+    use biscuit::jwk::JWKSet;
+
+    use super::KeyStore;
+    use crate::{
+        tests::{create_jwt_token, start_test_idp},
+        HttpLimits,
+    };
+
+    #[actix_rt::test]
+    async fn test_key_store_fails_over_to_mirror() {
+        let idp = start_test_idp().await;
+        let unavailable = "http://127.0.0.1:1/jwks".to_string();
+
+        let store = KeyStore::fetch(
+            vec![unavailable.clone(), format!("{}/jwks", idp)],
+            &HttpLimits::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(store.current().keys.len(), 1);
+
+        let health = store.health();
+        assert_eq!(health[0].url, unavailable);
+        assert_eq!(health[0].consecutive_failures, 1);
+        assert!(health[0].last_error.is_some());
+        assert_eq!(health[1].consecutive_failures, 0);
+        assert!(health[1].last_success.is_some());
+
+        // the failed primary is tried after the healthy mirror on the next refresh
+        store.refresh(&HttpLimits::default()).await.unwrap();
+        assert_eq!(store.health()[0].consecutive_failures, 1);
+    }
+
+    ///Test that the keys are reloaded with conditional requests and kept while fresh
+    #[actix_rt::test]
+    async fn test_key_store_caching_headers() {
+        let idp = start_test_idp().await;
+        let store = KeyStore::fetch(vec![format!("{}/cached-jwks", idp)], &HttpLimits::default())
+            .await
+            .unwrap();
+        assert_eq!(store.current().keys.len(), 1);
+        assert!(store.cache.lock().unwrap().etag.is_some());
+
+        // answered with 304 Not Modified
+        store.refresh(&HttpLimits::default()).await.unwrap();
+        assert_eq!(store.current().keys.len(), 1);
+
+        let last_success = store.health()[0].last_success;
+        store
+            .refresh_if_stale(&HttpLimits::default())
+            .await
+            .unwrap();
+        assert_eq!(store.health()[0].last_success, last_success);
+    }
+
+    ///Test that unknown kids refresh the keys at most once per cool-down
+    #[actix_rt::test]
+    async fn test_key_store_unknown_kid_cooldown() {
+        let idp = start_test_idp().await;
+        let store =
+            KeyStore::with_endpoints(JWKSet { keys: vec![] }, vec![format!("{}/jwks", idp)]);
+        *store.last_unknown_kid_refresh.lock().unwrap() = Some(Instant::now());
+
+        let token = create_jwt_token();
+        store
+            .refresh_for_unknown_kid(&token, &HttpLimits::default())
+            .await
+            .unwrap();
+        assert!(store.current().keys.is_empty());
+
+        *store.last_unknown_kid_refresh.lock().unwrap() =
+            Instant::now().checked_sub(KeyStore::UNKNOWN_KID_COOLDOWN);
+        store
+            .refresh_for_unknown_kid(&token, &HttpLimits::default())
+            .await
+            .unwrap();
+        assert_eq!(store.current().keys.len(), 1);
+    }
+
+    #[actix_rt::test]
+    async fn test_key_store_all_endpoints_down() {
+        let res = KeyStore::fetch(
+            vec![
+                "http://127.0.0.1:1/jwks".to_string(),
+                "http://127.0.0.1:2/jwks".to_string(),
+            ],
+            &HttpLimits::default(),
+        )
+        .await;
+        assert!(res.is_err());
+    }
+
+    ///Test that readers keep a consistent key set while the keys are swapped
+    #[actix_rt::test]
+    async fn test_key_store_swaps_keys() {
+        let idp = start_test_idp().await;
+        let store = KeyStore::fetch(vec![format!("{}/jwks", idp)], &HttpLimits::default())
+            .await
+            .unwrap();
+        let rotated = store.current();
+        let before = store.current();
+
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for _ in 0..1000 {
+                        assert!(store.current().keys.len() <= 1);
+                    }
+                });
+            }
+            for replaced in 0..1000 {
+                match replaced % 2 {
+                    0 => store.replace(JWKSet { keys: vec![] }),
+                    _ => store.replace((*rotated).clone()),
+                }
+            }
+        });
+        assert_eq!(before.keys.len(), 1);
+        assert_eq!(store.current().keys.len(), 1);
+    }
+}
@@ -35,7 +35,8 @@ async fn main() -> std::io::Result<()> {
     let biscuit_validator = OidcBiscuitValidator { options: ValidationOptions {
             issuer: Validation::Validate(authority),
             ..ValidationOptions::default()
-        }
+        },
+        ..OidcBiscuitValidator::default()
     };
 
     HttpServer::new(move || {
@@ -61,22 +62,156 @@ In addition to this API documentation, several other resources are available:
 */
 #![warn(missing_docs)]
 
+// lets the code generated by the macros refer to the crate in its own tests
+#[cfg(all(test, feature = "macros"))]
+extern crate self as actix_4_jwt_auth;
+
+mod audit;
+mod audit_sink;
+mod backend;
+mod background;
+mod builder;
+mod circuit_breaker;
+mod claim_rules;
+mod claims_validator;
+mod config;
+#[cfg(feature = "cli")]
+mod dev_keys;
+mod dpop;
 mod error;
 mod extractor;
+mod fingerprint;
+mod guard;
+mod handle;
+mod health;
+mod hmac;
+mod http;
+mod id_token;
+mod introspection;
+mod issuer;
+mod jwe;
+mod keys;
+mod metrics;
 mod middleware;
+mod mtls;
 mod oidc;
+#[cfg(feature = "utoipa")]
+mod openapi;
+#[cfg(feature = "opentelemetry")]
+mod otel;
+#[cfg(feature = "paseto")]
+mod paseto;
+mod permissions;
+mod policy;
+#[cfg(feature = "macros")]
+#[doc(hidden)]
+pub mod __private {
+    pub use crate::protect::*;
+}
+#[cfg(feature = "macros")]
+mod protect;
+mod reference;
+mod replay;
+mod report;
+mod response;
+mod response_cache;
+mod revocation;
+mod tenant;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod testing;
+mod token_extractor;
+mod token_hash;
+mod trace;
+mod uma;
+mod unverified;
+mod userinfo;
+mod validation_cache;
+mod validator;
+mod webfinger;
 
 #[doc(inline)]
 pub use ::biscuit;
+#[cfg(feature = "paseto")]
+pub use ::pasetors;
+#[cfg(feature = "utoipa")]
+pub use ::utoipa;
+#[cfg(feature = "macros")]
+pub use actix_4_jwt_auth_macros::protect;
 
-pub use error::OIDCValidationError;
-pub use extractor::{auth_user::AuthenticatedUser, decoded_info::DecodedInfo};
-pub use middleware::OidcBiscuitValidator;
+pub use audit::{AuditedRoute, RouteAudit, RouteCoverage};
+#[cfg(feature = "tracing")]
+pub use audit_sink::TracingAuditSink;
+pub use audit_sink::{AuditDecision, AuditRecord, AuditSink};
+pub use background::BackgroundTask;
+pub use builder::OidcBuilder;
+pub use circuit_breaker::{CircuitBreakerConfig, CircuitState, FallbackPolicy};
+pub use claim_rules::{ClaimOperator, ClaimRule, RequiredClaim};
+pub use claims_validator::{AudienceValidator, ClaimsValidator, ExpiryValidator, IssuerValidator};
+#[cfg(feature = "cli")]
+pub use dev_keys::DevKeyPair;
+pub use dpop::{DpopValidation, InMemoryNonceStore, NonceStore};
+pub use error::{ErrorCode, OIDCValidationError};
+pub use extractor::{
+    auth_context::AuthContext,
+    auth_user::AuthenticatedUser,
+    authorization_details::{
+        AuthorizationDetail, AuthorizationDetailRequirement, AuthorizationDetails,
+    },
+    decoded_info::DecodedInfo,
+    enriched_user::EnrichedUser,
+    expiry_status::ExpiryStatus,
+    maybe_auth_user::MaybeAuthenticatedUser,
+    principal::Principal,
+    scopes::Scopes,
+    subject::Subject,
+    uma_permissions::{UmaPermission, UmaPermissions},
+    user_info::UserInfo,
+};
+pub use fingerprint::{FingerprintBinding, FingerprintScheme, UserAgentCookieFingerprint};
+pub use guard::ClaimGuard;
+pub use handle::OidcHandle;
+pub use health::OidcHealth;
+pub use hmac::HmacValidator;
+pub use http::{HttpLimits, HttpPool};
+pub use id_token::IdTokenValidation;
+pub use introspection::IntrospectionValidator;
+pub use issuer::{IssuerPattern, IssuerPatternValidator, MultiIssuerValidator};
+pub use jwe::JweDecryption;
+pub use keys::KeyEndpointHealth;
+pub use metrics::{AuthMetrics, Histogram, HistogramSnapshot, TokenMetrics};
+pub use middleware::{
+    InsecureTransport, OidcBiscuitValidator, RouteRequirements, ValidationOverride,
+};
+pub use mtls::{CertificateBinding, ClientCertificate};
 pub use oidc::{Oidc, OidcConfig, TokenLookup};
+#[cfg(feature = "utoipa")]
+pub use openapi::OidcSecurity;
+#[cfg(feature = "paseto")]
+pub use paseto::{PasetoKey, PasetoValidator};
+pub use permissions::PermissionMap;
+pub use policy::IssuerPolicy;
+pub use reference::{InMemoryReferenceStore, ReferenceStore, ReferenceTokens};
+pub use replay::ReplayGuard;
+pub use report::ValidationReport;
+pub use response::{ErrorHandler, UnauthorizedBody};
+pub use revocation::{InMemoryRevocationStore, RevocationStore};
+pub use tenant::{ClaimTenants, HostTenants, PathTenants, TenantResolver};
+#[cfg(any(test, feature = "test-utils"))]
+pub use testing::{AuthenticatedTestRequest, TestRequestAuthExt};
+pub use token_extractor::{BearerHeader, CookieToken, QueryToken, TokenExtractor};
+pub use token_hash::TokenHash;
+pub use trace::SubjectRedaction;
+pub use uma::UmaTicketConfig;
+pub use unverified::{dangerous_peek_claims, UnverifiedToken};
+pub use validator::{JwksValidator, TokenValidator};
 
 #[cfg(test)]
 mod tests {
-    use actix_web::{cookie::Cookie, http::header, test};
+    use std::collections::HashMap;
+
+    use actix_web::{
+        cookie::Cookie, http::header, test, web, App, HttpRequest, HttpResponse, HttpServer,
+    };
     use biscuit::{
         jwa::{self, Algorithm, SignatureAlgorithm},
         jwk::{AlgorithmParameters, CommonParameters, JWKSet, RSAKeyParameters, JWK},
@@ -90,11 +225,11 @@ mod tests {
 
     use crate::{Oidc, OidcConfig, TokenLookup};
 
-    fn get_secret() -> Secret {
+    pub(crate) fn get_secret() -> Secret {
         Secret::rsa_keypair_from_file("private_key.der").unwrap()
     }
 
-    fn create_jwk_set() -> JWKSet<Empty> {
+    pub(crate) fn create_jwk_set() -> JWKSet<Empty> {
         let secret = get_secret();
         let public_key = match secret {
             Secret::RsaKeyPair(ring_pair) => {
@@ -124,6 +259,115 @@ mod tests {
         jwk_set
     }
 
+    /// Starts an identity provider on a random local port that publishes the discovery document
+    /// and the keys, returns its base url
+    pub(crate) async fn start_test_idp() -> String {
+        let server = HttpServer::new(move || {
+            App::new()
+                .route(
+                    "/.well-known/openid-configuration",
+                    web::get().to(|req: HttpRequest| async move {
+                        let base = format!("http://{}", req.connection_info().host());
+                        HttpResponse::Ok().json(json!({
+                            "issuer": base,
+                            "jwks_uri": format!("{}/jwks", base),
+                            "introspection_endpoint": format!("{}/introspect", base),
+                            "userinfo_endpoint": format!("{}/userinfo", base),
+                        }))
+                    }),
+                )
+                .route(
+                    "/impostor/.well-known/openid-configuration",
+                    web::get().to(|req: HttpRequest| async move {
+                        // claims to be the issuer at the root, not the one under /impostor
+                        let base = format!("http://{}", req.connection_info().host());
+                        HttpResponse::Ok().json(json!({
+                            "issuer": base,
+                            "jwks_uri": format!("{}/jwks", base),
+                        }))
+                    }),
+                )
+                .route(
+                    "/jwks",
+                    web::get().to(|| async { HttpResponse::Ok().json(create_jwk_set()) }),
+                )
+                .route(
+                    "/cached-jwks",
+                    web::get().to(|req: HttpRequest| async move {
+                        if req.headers().get("if-none-match").is_some_and(|etag| etag == "\"v1\"") {
+                            return HttpResponse::NotModified().finish();
+                        }
+                        HttpResponse::Ok()
+                            .insert_header(("etag", "\"v1\""))
+                            .insert_header(("cache-control", "public, max-age=300"))
+                            .json(create_jwk_set())
+                    }),
+                )
+                .route(
+                    "/authz/protection/permission",
+                    web::post().to(|req: HttpRequest, body: web::Json<Value>| async move {
+                        if req.headers().get("authorization").unwrap() != "Bearer pat" {
+                            return HttpResponse::Unauthorized().finish();
+                        }
+                        let resource_id = body[0]["resource_id"].as_str().unwrap_or_default();
+                        HttpResponse::Ok().json(json!({ "ticket": format!("ticket-{}", resource_id) }))
+                    }),
+                )
+                .route(
+                    "/introspect",
+                    web::post().to(|req: HttpRequest, form: web::Form<HashMap<String, String>>| async move {
+                        // api:secret
+                        if req.headers().get("authorization").unwrap() != "Basic YXBpOnNlY3JldA==" {
+                            return HttpResponse::Unauthorized().finish();
+                        }
+                        match form.get("token").map(String::as_str) {
+                            Some("opaque-active") => HttpResponse::Ok().json(json!({
+                                "active": true, "sub": "admin", "scope": "read write", "client_id": "app",
+                            })),
+                            _ => HttpResponse::Ok().json(json!({ "active": false })),
+                        }
+                    }),
+                )
+                .route(
+                    "/userinfo",
+                    web::get().to(|req: HttpRequest| async move {
+                        let token = req.headers().get("authorization").unwrap().to_str().unwrap();
+                        let claims = crate::dangerous_peek_claims(&token["Bearer ".len()..]).unwrap();
+                        let sub = match claims.payload.registered.subject.as_deref() {
+                            Some("disabled") => return HttpResponse::Unauthorized().finish(),
+                            Some("impostor") => "admin",
+                            sub => sub.unwrap_or_default(),
+                        };
+                        HttpResponse::Ok().json(json!({
+                            "sub": sub, "name": "Admin", "email": "admin@example.com",
+                        }))
+                    }),
+                )
+                .route(
+                    "/.well-known/webfinger",
+                    web::get().to(|req: HttpRequest| async move {
+                        let base = format!("http://{}", req.connection_info().host());
+                        if !req.query_string().contains("resource=acct%3Ajoe%40") {
+                            return HttpResponse::NotFound().json(json!({}));
+                        }
+                        HttpResponse::Ok().json(json!({
+                            "subject": "acct:joe@example.com",
+                            "links": [{
+                                "rel": "http://openid.net/specs/connect/1.0/issuer",
+                                "href": base,
+                            }],
+                        }))
+                    }),
+                )
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let address = server.addrs()[0];
+        actix_rt::spawn(server.run());
+        format!("http://{}", address)
+    }
+
     pub(crate) async fn create_oidc() -> Oidc {
         Oidc::new(OidcConfig::Jwks(create_jwk_set())).await.unwrap()
     }
@@ -135,33 +379,47 @@ mod tests {
     }
 
     pub(crate) fn create_token(tokenize: Value) -> String {
-        let signing_secret = get_secret();
-        let decoded_token = JWT::new_decoded(
-            From::from(RegisteredHeader {
-                algorithm: SignatureAlgorithm::RS256,
-                key_id: Some("2020-01-29".to_string()),
-                ..Default::default()
-            }),
-            ClaimsSet::<Value> {
-                registered: RegisteredClaims {
-                    issuer: None,
-                    subject: None,
-                    audience: None,
-                    not_before: None,
-                    expiry: None,
-                    id: None,
-                    issued_at: None,
-                },
-                private: tokenize,
+        let header = RegisteredHeader {
+            algorithm: SignatureAlgorithm::RS256,
+            key_id: Some("2020-01-29".to_string()),
+            ..Default::default()
+        };
+        sign_token(header, tokenize, &get_secret())
+    }
+
+    /// Signs the claims with the header and secret, for tokens that [`create_token`] can't make
+    pub(crate) fn sign_token(header: RegisteredHeader, claims: Value, secret: &Secret) -> String {
+        let claims = ClaimsSet::<Value> {
+            registered: RegisteredClaims {
+                issuer: None,
+                subject: None,
+                audience: None,
+                not_before: None,
+                expiry: None,
+                id: None,
+                issued_at: None,
             },
-        );
-        decoded_token
-            .encode(&signing_secret)
+            private: claims,
+        };
+        JWT::new_decoded(From::from(header), claims)
+            .encode(secret)
             .unwrap()
             .unwrap_encoded()
             .to_string()
     }
 
+    /// A token with the `alg` in its header and a made up signature, for the reads of the token
+    /// before its signature is verified
+    pub(crate) fn create_unverified_token(alg: &str, claims: Value) -> String {
+        let mut token = biscuit::Compact::new();
+        token
+            .push(&serde_json::to_vec(&json!({ "alg": alg })).unwrap())
+            .unwrap();
+        token.push(&serde_json::to_vec(&claims).unwrap()).unwrap();
+        token.push(&b"signature".to_vec()).unwrap();
+        token.encode()
+    }
+
     pub(crate) fn create_jwt_token() -> String {
         let claims = json!({
         "iss": "http://0.0.0.0:9090",
@@ -184,7 +442,11 @@ mod tests {
             ))
     }
 
-    pub(crate) fn create_post_jwt_request(url: &str, token: &str, payload: impl Into<Bytes>) -> test::TestRequest {
+    pub(crate) fn create_post_jwt_request(
+        url: &str,
+        token: &str,
+        payload: impl Into<Bytes>,
+    ) -> test::TestRequest {
         test::TestRequest::post()
             .uri(url)
             .insert_header(header::ContentType::json())
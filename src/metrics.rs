@@ -0,0 +1,446 @@
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use biscuit::ClaimsSet;
+use serde_json::Value;
+
+use crate::{AuditDecision, ErrorCode, OIDCValidationError};
+
+/// Histogram with fixed bucket bounds, in seconds unless created [`Histogram::with_unit`]
+#[derive(Debug)]
+pub struct Histogram {
+    bounds: Vec<u64>,
+    unit: Duration,
+    counts: Vec<AtomicU64>,
+    sum: AtomicU64,
+}
+
+/// Counts of a histogram at one moment, the buckets are cumulative as in Prometheus
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct HistogramSnapshot {
+    /// Upper bound in the unit of the histogram (`None` is +Inf) and the number of observations
+    /// up to it
+    pub buckets: Vec<(Option<u64>, u64)>,
+    /// Number of observations
+    pub count: u64,
+    /// Sum of the observations in the unit of the histogram
+    pub sum: u64,
+}
+
+impl Histogram {
+    /// Creates a histogram with the given upper bounds in seconds, in increasing order
+    pub fn new(bounds: Vec<u64>) -> Self {
+        Histogram::with_unit(bounds, Duration::from_secs(1))
+    }
+
+    /// Creates a histogram with the given upper bounds in the unit, e.g. microseconds for
+    /// latencies, in increasing order
+    pub fn with_unit(bounds: Vec<u64>, unit: Duration) -> Self {
+        Histogram {
+            counts: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            bounds,
+            unit: unit.max(Duration::from_nanos(1)),
+            sum: AtomicU64::new(0),
+        }
+    }
+
+    /// Records an observation
+    pub fn observe(&self, value: Duration) {
+        let units = (value.as_nanos() / self.unit.as_nanos()) as u64;
+        let bucket = self
+            .bounds
+            .iter()
+            .position(|bound| units <= *bound)
+            .unwrap_or(self.bounds.len());
+        self.counts[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(units, Ordering::Relaxed);
+    }
+
+    /// The unit of the bounds and the sum
+    pub fn unit(&self) -> Duration {
+        self.unit
+    }
+
+    /// The current counts
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        let mut cumulative = 0;
+        let buckets = self
+            .counts
+            .iter()
+            .enumerate()
+            .map(|(index, count)| {
+                cumulative += count.load(Ordering::Relaxed);
+                (self.bounds.get(index).copied(), cumulative)
+            })
+            .collect();
+        HistogramSnapshot {
+            buckets,
+            count: cumulative,
+            sum: self.sum.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Distribution of the remaining lifetime and the age of the validated tokens
+///
+/// Clients running with nearly expired tokens show up in the lowest buckets of the remaining
+/// lifetime, issuers with misconfigured lifetimes in the highest. Configure it with
+/// [`crate::Oidc::with_token_metrics`] and read or export the snapshots.
+#[derive(Debug)]
+pub struct TokenMetrics {
+    remaining_lifetime: Histogram,
+    age: Histogram,
+}
+
+impl TokenMetrics {
+    /// Bucket bounds in seconds, from 30 seconds to a day
+    pub const DEFAULT_BUCKETS: [u64; 9] = [30, 60, 300, 900, 1800, 3600, 7200, 21600, 86400];
+
+    /// Records the lifetime (`exp`) and age (`iat`) of a validated token, absent claims
+    /// are not recorded and an expired token is recorded as no remaining lifetime
+    pub fn record(&self, claims: &ClaimsSet<Value>) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as i64;
+        let seconds = |difference: i64| Duration::from_secs(difference.max(0) as u64);
+        if let Some(expiry) = &claims.registered.expiry {
+            self.remaining_lifetime
+                .observe(seconds(expiry.timestamp() - now));
+        }
+        if let Some(issued_at) = &claims.registered.issued_at {
+            self.age.observe(seconds(now - issued_at.timestamp()));
+        }
+    }
+
+    /// Remaining lifetime of the tokens at validation time
+    pub fn remaining_lifetime(&self) -> HistogramSnapshot {
+        self.remaining_lifetime.snapshot()
+    }
+
+    /// Age of the tokens at validation time
+    pub fn age(&self) -> HistogramSnapshot {
+        self.age.snapshot()
+    }
+}
+
+impl Default for TokenMetrics {
+    fn default() -> Self {
+        TokenMetrics {
+            remaining_lifetime: Histogram::new(Self::DEFAULT_BUCKETS.to_vec()),
+            age: Histogram::new(Self::DEFAULT_BUCKETS.to_vec()),
+        }
+    }
+}
+
+/// Counters of the authentication outcomes, the JWKS refreshes and the validation latency
+///
+/// Configure it with [`crate::Oidc::with_auth_metrics`] and serve
+/// [`AuthMetrics::render_prometheus`] from the `/metrics` endpoint of the application, next to
+/// its own metrics, to alert on spikes of rejected tokens.
+#[derive(Debug)]
+pub struct AuthMetrics {
+    successes: AtomicU64,
+    failures: Mutex<HashMap<ErrorCode, u64>>,
+    refreshes: AtomicU64,
+    failed_refreshes: AtomicU64,
+    latency: Histogram,
+}
+
+impl AuthMetrics {
+    /// Bucket bounds of the validation latency in microseconds, from 100µs to a second
+    pub const LATENCY_BUCKETS: [u64; 10] = [
+        100, 250, 500, 1_000, 2_500, 5_000, 10_000, 50_000, 250_000, 1_000_000,
+    ];
+
+    /// Records the decision on a request, anonymous requests are not counted
+    pub(crate) fn record_decision(
+        &self,
+        decision: AuditDecision,
+        failure: Option<&OIDCValidationError>,
+    ) {
+        match (decision, failure) {
+            (AuditDecision::Allowed, _) => {
+                self.successes.fetch_add(1, Ordering::Relaxed);
+            }
+            (AuditDecision::Denied, Some(e)) => {
+                *self.failures.lock().unwrap().entry(e.code()).or_default() += 1
+            }
+            _ => {}
+        }
+    }
+
+    /// Records the duration of a token validation
+    pub(crate) fn record_latency(&self, duration: Duration) {
+        self.latency.observe(duration);
+    }
+
+    /// Records a reload of the keys of the issuer
+    pub(crate) fn record_refresh<T>(&self, refreshed: &Result<T, OIDCValidationError>) {
+        let counter = match refreshed {
+            Ok(_) => &self.refreshes,
+            Err(_) => &self.failed_refreshes,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Number of allowed requests
+    pub fn successes(&self) -> u64 {
+        self.successes.load(Ordering::Relaxed)
+    }
+
+    /// Number of denied requests by the code of the failure
+    pub fn failures(&self) -> HashMap<ErrorCode, u64> {
+        self.failures.lock().unwrap().clone()
+    }
+
+    /// Number of reloads of the keys, successful and failed
+    pub fn jwks_refreshes(&self) -> (u64, u64) {
+        (
+            self.refreshes.load(Ordering::Relaxed),
+            self.failed_refreshes.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Duration of the validations in microseconds
+    pub fn validation_latency(&self) -> HistogramSnapshot {
+        self.latency.snapshot()
+    }
+
+    /// The metrics in the Prometheus text exposition format
+    pub fn render_prometheus(&self) -> String {
+        let mut out = String::new();
+        out.push_str("# HELP jwt_auth_validations_total Authentication decisions on the requests by outcome\n");
+        out.push_str("# TYPE jwt_auth_validations_total counter\n");
+        let _ = writeln!(
+            out,
+            "jwt_auth_validations_total{{outcome=\"success\"}} {}",
+            self.successes()
+        );
+        let mut failures: Vec<_> = self.failures().into_iter().collect();
+        failures.sort_by_key(|(code, _)| code.as_str());
+        for (code, count) in failures {
+            let _ = writeln!(
+                out,
+                "jwt_auth_validations_total{{outcome=\"failure\",reason=\"{}\"}} {}",
+                code.as_str(),
+                count
+            );
+        }
+
+        let (refreshes, failed_refreshes) = self.jwks_refreshes();
+        out.push_str("# HELP jwt_auth_jwks_refreshes_total Reloads of the keys by outcome\n");
+        out.push_str("# TYPE jwt_auth_jwks_refreshes_total counter\n");
+        let _ = writeln!(
+            out,
+            "jwt_auth_jwks_refreshes_total{{outcome=\"success\"}} {}",
+            refreshes
+        );
+        let _ = writeln!(
+            out,
+            "jwt_auth_jwks_refreshes_total{{outcome=\"failure\"}} {}",
+            failed_refreshes
+        );
+
+        let unit = self.latency.unit().as_secs_f64();
+        let latency = self.validation_latency();
+        out.push_str("# HELP jwt_auth_validation_duration_seconds Duration of the validations\n");
+        out.push_str("# TYPE jwt_auth_validation_duration_seconds histogram\n");
+        for (bound, count) in &latency.buckets {
+            let le = match bound {
+                Some(bound) => (*bound as f64 * unit).to_string(),
+                None => "+Inf".to_string(),
+            };
+            let _ = writeln!(
+                out,
+                "jwt_auth_validation_duration_seconds_bucket{{le=\"{}\"}} {}",
+                le, count
+            );
+        }
+        let _ = writeln!(
+            out,
+            "jwt_auth_validation_duration_seconds_sum {}",
+            latency.sum as f64 * unit
+        );
+        let _ = writeln!(
+            out,
+            "jwt_auth_validation_duration_seconds_count {}",
+            latency.count
+        );
+        out
+    }
+}
+
+impl Default for AuthMetrics {
+    fn default() -> Self {
+        AuthMetrics {
+            successes: AtomicU64::new(0),
+            failures: Mutex::default(),
+            refreshes: AtomicU64::new(0),
+            failed_refreshes: AtomicU64::new(0),
+            latency: Histogram::with_unit(Self::LATENCY_BUCKETS.to_vec(), Duration::from_micros(1)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::Arc,
+        time::{SystemTime, UNIX_EPOCH},
+    };
+
+    use actix_web::{dev::Service, get, test, web, App};
+    use biscuit::{jwa::SignatureAlgorithm, jws::RegisteredHeader};
+    use serde_json::json;
+
+    use super::{AuthMetrics, TokenMetrics};
+    use crate::{
+        tests::{
+            create_get_jwt_request, create_oidc, create_token, get_secret, sign_token,
+            start_test_idp,
+        },
+        DecodedInfo, ErrorCode, Oidc, OidcBiscuitValidator, OidcConfig, PathTenants,
+    };
+
+    #[get("/decoder")]
+    async fn decoder(claims: DecodedInfo) -> String {
+        claims.jwt
+    }
+
+    ///Test that the lifetime and age of validated tokens are recorded
+    #[actix_rt::test]
+    async fn test_token_metrics() {
+        let metrics = Arc::new(TokenMetrics::default());
+        let oidc = create_oidc().await.with_token_metrics(metrics.clone());
+        let app = test::init_service(App::new().app_data(oidc.clone()).service(decoder)).await;
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        for claims in [
+            json!({ "exp": now + 10, "iat": now - 3590 }),
+            json!({ "exp": now + 3000, "iat": now - 600 }),
+            json!({ "sub": "no times" }),
+        ] {
+            let req = create_get_jwt_request("/decoder", &create_token(claims)).to_request();
+            test::call_service(&app, req).await;
+        }
+
+        let lifetime = metrics.remaining_lifetime();
+        assert_eq!(lifetime.count, 2);
+        assert_eq!(lifetime.buckets[0], (Some(30), 1));
+        assert_eq!(lifetime.buckets[5], (Some(3600), 2));
+        assert_eq!(lifetime.buckets.last(), Some(&(None, 2)));
+
+        let age = metrics.age();
+        assert_eq!(age.count, 2);
+        assert_eq!(age.buckets[3], (Some(900), 1));
+        assert_eq!(age.buckets[5], (Some(3600), 2));
+    }
+
+    ///Test that the outcomes of the validations are counted and rendered for Prometheus
+    #[actix_rt::test]
+    async fn test_auth_metrics() {
+        let metrics = Arc::new(AuthMetrics::default());
+        let oidc = create_oidc().await.with_auth_metrics(metrics.clone());
+        let app = test::init_service(App::new().app_data(oidc.clone()).service(decoder)).await;
+
+        for token in [
+            create_token(json!({ "sub": "admin" })),
+            "bad.token".to_string(),
+        ] {
+            let req = create_get_jwt_request("/decoder", &token).to_request();
+            test::call_service(&app, req).await;
+        }
+        assert!(oidc.refresh_keys().await.is_ok());
+
+        assert_eq!(metrics.successes(), 1);
+        assert_eq!(metrics.failures().values().sum::<u64>(), 1);
+        assert_eq!(metrics.validation_latency().count, 2);
+        assert_eq!(metrics.jwks_refreshes(), (1, 0));
+
+        let code = metrics.failures().into_keys().next().unwrap();
+        let rendered = metrics.render_prometheus();
+        assert!(rendered.contains("jwt_auth_validations_total{outcome=\"success\"} 1\n"));
+        assert!(rendered.contains(&format!(
+            "jwt_auth_validations_total{{outcome=\"failure\",reason=\"{}\"}} 1\n",
+            code.as_str()
+        )));
+        assert!(rendered.contains("jwt_auth_jwks_refreshes_total{outcome=\"success\"} 1\n"));
+        assert!(rendered.contains("jwt_auth_validation_duration_seconds_bucket{le=\"+Inf\"} 2\n"));
+        assert!(rendered.contains("jwt_auth_validation_duration_seconds_count 2\n"));
+    }
+
+    ///Test that the requests are counted where they are decided, also without a token and when
+    ///the middleware denies them
+    #[actix_rt::test]
+    async fn test_auth_metrics_decisions() {
+        let metrics = Arc::new(AuthMetrics::default());
+        let oidc = create_oidc().await.with_auth_metrics(metrics.clone());
+        let app = test::init_service(
+            App::new().app_data(oidc.clone()).service(
+                web::scope("/admin")
+                    .wrap(OidcBiscuitValidator::default().require_scope("admin"))
+                    .service(decoder),
+            ),
+        )
+        .await;
+
+        for scope in ["read", "admin"] {
+            let token = create_token(json!({ "sub": "admin", "scope": scope }));
+            let req = create_get_jwt_request("/admin/decoder", &token).to_request();
+            test::call_service(&app, req).await;
+        }
+        let req = test::TestRequest::get().uri("/admin/decoder").to_request();
+        assert!(app.call(req).await.is_err());
+
+        assert_eq!(metrics.successes(), 1);
+        let failures = metrics.failures();
+        assert_eq!(failures.get(&ErrorCode::InsufficientRights), Some(&1));
+        assert_eq!(failures.get(&ErrorCode::TokenMissing), Some(&1));
+        assert_eq!(metrics.validation_latency().count, 2);
+    }
+
+    ///Test that the reloads of the keys of the tenants and of several issuers are counted, in the
+    ///metrics configured last
+    #[actix_rt::test]
+    async fn test_auth_metrics_key_stores() {
+        let idp = start_test_idp().await;
+        let metrics = Arc::new(AuthMetrics::default());
+        let tenants = PathTenants::new().tenant("acme", idp.clone());
+        let oidc = Oidc::new(OidcConfig::Tenants(Arc::new(tenants)))
+            .await
+            .unwrap()
+            .with_auth_metrics(Arc::new(AuthMetrics::default()))
+            .with_auth_metrics(metrics.clone());
+        let prefetch = oidc.spawn_tenant_prefetch(std::time::Duration::from_millis(10));
+        while prefetch.is_running() {
+            actix_rt::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert!(oidc.refresh_keys().await.is_ok());
+        assert_eq!(metrics.jwks_refreshes(), (1, 0));
+
+        let metrics = Arc::new(AuthMetrics::default());
+        let oidc = Oidc::new(OidcConfig::Issuers(vec![idp.clone().into()]))
+            .await
+            .unwrap()
+            .with_auth_metrics(metrics.clone());
+        let header = RegisteredHeader {
+            algorithm: SignatureAlgorithm::RS256,
+            key_id: Some("rotated".to_string()),
+            ..Default::default()
+        };
+        let token = sign_token(header, json!({ "iss": idp }), &get_secret());
+        assert!(oidc.validate_token(&token).await.is_err());
+        assert_eq!(metrics.jwks_refreshes(), (1, 0));
+    }
+}
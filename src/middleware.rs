@@ -1,22 +1,593 @@
 use std::{
+    borrow::Cow,
     future::{ready, Ready},
+    net::IpAddr,
     rc::Rc,
+    time::Duration,
 };
 
-use crate::{DecodedInfo, OIDCValidationError};
+use crate::{
+    audit::{cover, RouteCoverage},
+    audit_sink::{audit, AuditDecision},
+    permissions::{token_roles, token_scopes, DEFAULT_ROLE_CLAIMS},
+    response::rejection,
+    trace,
+    unverified::RawHeader,
+    DecodedInfo, OIDCValidationError, Oidc, Principal, ReplayGuard, ValidationReport,
+};
 use actix_web::{
     body::{BoxBody, EitherBody},
     dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
-    Error,
+    http::{
+        header::{self, HeaderName, HeaderValue},
+        Method,
+    },
+    Error, HttpMessage, HttpRequest, HttpResponse,
 };
-use biscuit::ValidationOptions;
+use biscuit::{RegisteredClaims, Validation, ValidationOptions};
 use futures_util::future::LocalBoxFuture;
 
 /// Middleware with standard biscuit validation
+///
+/// When the middleware wraps the whole `App`, every route requires a valid token (default deny).
+/// Routes that must stay reachable without a token are allowlisted with [`Self::public_paths`].
 #[derive(PartialEq, Clone, Default)]
 pub struct OidcBiscuitValidator {
     /// Biscuit validation options
     pub options: ValidationOptions,
+    /// Leeway of the `exp`, `nbf` and `iat` checks, replaces the epsilon of [`Self::options`]
+    ///
+    /// When not set, the leeway of the Oidc applies, see
+    /// [`Oidc::with_leeway`](crate::Oidc::with_leeway).
+    pub leeway: Option<Duration>,
+    /// Paths that are reachable without a token
+    ///
+    /// An entry matches the request path exactly or the pattern of the matched route,
+    /// e.g. `/items/{id}`. An entry ending with `/*` matches every path below it.
+    pub public_paths: Vec<Cow<'static, str>>,
+    /// Adds `X-Auth-Kid`, `X-Auth-Issuer` and `X-Auth-Source` headers to the responses of
+    /// authenticated requests, describing how the request was authenticated
+    ///
+    /// Meant for debug and staging environments, e.g. to verify gateway configurations.
+    pub debug_headers: bool,
+    /// Handling of requests that send a token over plaintext HTTP, which
+    /// [RFC 6750](https://www.rfc-editor.org/rfc/rfc6750#section-5.3) forbids
+    pub insecure_transport: InsecureTransport,
+    /// Reverse proxies that terminate TLS, their `Forwarded` and `X-Forwarded-Proto` headers
+    /// are trusted to tell the scheme of the original request
+    pub trusted_proxies: Vec<IpAddr>,
+    /// Paths or route patterns that accept expired tokens within the grace window, e.g. token
+    /// refresh and re-login endpoints
+    ///
+    /// The signature and the other claims are still validated, the handler reads the expiry
+    /// with the [`ExpiryStatus`](crate::ExpiryStatus) extractor.
+    pub expired_grace_paths: Vec<(Cow<'static, str>, Duration)>,
+    /// Scopes every token must have, the token is rejected as forbidden otherwise
+    ///
+    /// A scope matches the scopes of the token and the permissions they are mapped to with the
+    /// [`PermissionMap`](crate::PermissionMap) of the Oidc.
+    pub required_scopes: Vec<Cow<'static, str>>,
+    /// JSON pointers of the claims holding the roles, e.g. `/realm_access/roles` for Keycloak or
+    /// `/cognito:groups` for Cognito
+    ///
+    /// When empty, the roles are read from `/roles` and `/realm_access/roles`.
+    pub role_claims: Vec<Cow<'static, str>>,
+    /// Roles every token must have, the token is rejected as forbidden otherwise
+    pub required_roles: Vec<Cow<'static, str>>,
+    /// Accepted audiences, the `aud` claim must contain one of them, the token is rejected as
+    /// unauthorized otherwise
+    ///
+    /// Not checked when empty. Unlike the audience of [`Self::options`], it accepts several
+    /// audiences, e.g. during the migration to a new client.
+    pub audiences: Vec<Cow<'static, str>>,
+    /// Accepted authentication context classes, the `acr` claim must be one of them
+    ///
+    /// Not checked when empty. Tokens of a weaker authentication are rejected as unauthorized
+    /// with an `insufficient_user_authentication` challenge
+    /// ([RFC 9470](https://www.rfc-editor.org/rfc/rfc9470)), so the client can step up.
+    pub acr_values: Vec<Cow<'static, str>>,
+    /// Authentication methods every token must have in its `amr` claim, e.g. `mfa`, rejected
+    /// like [`Self::acr_values`] otherwise
+    pub required_amr: Vec<Cow<'static, str>>,
+    /// Rejects tokens presented before by their `jti`, for the scope of the routes of one-time
+    /// action tokens
+    ///
+    /// Checked last, so a token rejected for another reason is not used up.
+    pub replay_guard: Option<ReplayGuard>,
+    /// Requirements of single routes on top of the ones above, by path or route pattern like
+    /// [`Self::public_paths`], the first entry that covers the request applies
+    pub route_requirements: Vec<(Cow<'static, str>, RouteRequirements)>,
+    /// Requires a token on CORS preflight requests as well
+    ///
+    /// Browsers never send the `Authorization` header on the `OPTIONS` preflight, so preflights
+    /// (with `Origin` and `Access-Control-Request-Method` headers) pass through to the CORS
+    /// middleware or handler without a token by default.
+    pub authenticate_preflight: bool,
+    /// Lets requests without token through with the [`Principal::Anonymous`] principal in the
+    /// request extensions, a token that is sent is still validated
+    pub allow_anonymous: bool,
+}
+
+/// Requirements of a route on top of the ones of the shared middleware, see
+/// [`OidcBiscuitValidator::with_route_requirements`]
+///
+/// The middleware runs before the request is routed, so the requirements are keyed by the path
+/// or route pattern instead of the data of the route:
+///
+/// ```ignore
+/// let biscuit_validator = OidcBiscuitValidator::default()
+///     .with_route_requirements("/orders/{id}", RouteRequirements::new().require_scope("orders:write"))
+///     .with_route_requirements("/reports/*", RouteRequirements::new().accept_audience("reports-api"));
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct RouteRequirements {
+    /// Accepted audiences of the route, the `aud` claim must contain one of them as well
+    pub audiences: Vec<Cow<'static, str>>,
+    /// Scopes the route requires on top of [`OidcBiscuitValidator::required_scopes`]
+    pub required_scopes: Vec<Cow<'static, str>>,
+    /// Roles the route requires on top of [`OidcBiscuitValidator::required_roles`]
+    pub required_roles: Vec<Cow<'static, str>>,
+}
+
+impl RouteRequirements {
+    /// Requires nothing on top of the middleware
+    pub fn new() -> Self {
+        RouteRequirements::default()
+    }
+
+    /// Accepts tokens issued for the audience on the route, can be called for several audiences
+    pub fn accept_audience(mut self, audience: impl Into<Cow<'static, str>>) -> Self {
+        self.audiences.push(audience.into());
+        self
+    }
+
+    /// Rejects tokens without the scope on the route as forbidden
+    pub fn require_scope(mut self, scope: impl Into<Cow<'static, str>>) -> Self {
+        self.required_scopes.push(scope.into());
+        self
+    }
+
+    /// Rejects tokens without the role on the route as forbidden
+    pub fn require_role(mut self, role: impl Into<Cow<'static, str>>) -> Self {
+        self.required_roles.push(role.into());
+        self
+    }
+}
+
+/// Handling of requests that send a token over plaintext HTTP
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum InsecureTransport {
+    /// Tokens are accepted over plaintext HTTP
+    #[default]
+    Allow,
+    /// The request is rejected as a bad request
+    Reject,
+    /// The request is redirected to the same URL over HTTPS (308 Permanent Redirect)
+    Redirect,
+}
+
+impl OidcBiscuitValidator {
+    /// Conventional infrastructure endpoints, see [`Self::public_infrastructure_paths`]
+    pub const INFRASTRUCTURE_PATHS: [&'static str; 5] = [
+        "/healthz",
+        "/livez",
+        "/readyz",
+        "/metrics",
+        "/.well-known/*",
+    ];
+
+    /// Allowlists a path or route pattern that is reachable without a token
+    pub fn public_path(mut self, path: impl Into<Cow<'static, str>>) -> Self {
+        self.public_paths.push(path.into());
+        self
+    }
+
+    /// Allowlists several paths or route patterns that skip authentication entirely, e.g. read
+    /// from the configuration of the application
+    ///
+    /// ```ignore
+    /// OidcBiscuitValidator::default().with_public_paths(["/health", "/metrics", "/public/*"])
+    /// ```
+    pub fn with_public_paths<I>(mut self, paths: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<Cow<'static, str>>,
+    {
+        self.public_paths.extend(paths.into_iter().map(Into::into));
+        self
+    }
+
+    /// Allowlists the conventional infrastructure endpoints: health and readiness probes,
+    /// metrics and everything below `/.well-known/`
+    pub fn public_infrastructure_paths(mut self) -> Self {
+        self.public_paths
+            .extend(Self::INFRASTRUCTURE_PATHS.map(Cow::Borrowed));
+        self
+    }
+
+    /// Enables the debug response headers, see [`Self::debug_headers`]
+    pub fn with_debug_headers(mut self) -> Self {
+        self.debug_headers = true;
+        self
+    }
+
+    /// Rejects or redirects requests that send a token over plaintext HTTP
+    pub fn require_secure_transport(mut self, policy: InsecureTransport) -> Self {
+        self.insecure_transport = policy;
+        self
+    }
+
+    /// Accepts tokens on the path or route pattern that expired no longer than `grace` ago
+    pub fn allow_expired_within(
+        mut self,
+        path: impl Into<Cow<'static, str>>,
+        grace: Duration,
+    ) -> Self {
+        self.expired_grace_paths.push((path.into(), grace));
+        self
+    }
+
+    /// Rejects tokens without the scope as forbidden, e.g. on the scope of the routes that write
+    pub fn require_scope(mut self, scope: impl Into<Cow<'static, str>>) -> Self {
+        self.required_scopes.push(scope.into());
+        self
+    }
+
+    /// Reads the roles from the claim at the JSON pointer instead of `/roles` and
+    /// `/realm_access/roles`, can be called for several claims
+    pub fn with_role_claim(mut self, pointer: impl Into<Cow<'static, str>>) -> Self {
+        self.role_claims.push(pointer.into());
+        self
+    }
+
+    /// Rejects tokens without the role as forbidden, e.g. on the scope of the admin routes
+    pub fn require_role(mut self, role: impl Into<Cow<'static, str>>) -> Self {
+        self.required_roles.push(role.into());
+        self
+    }
+
+    /// Accepts `exp`, `nbf` and `iat` claims that are off by no more than the leeway, for clock
+    /// drift between the servers and the identity provider
+    ///
+    /// Takes precedence over the leeway of the Oidc, see [`Self::leeway`].
+    pub fn with_leeway(mut self, leeway: Duration) -> Self {
+        self.leeway = Some(leeway);
+        self
+    }
+
+    /// Accepts tokens issued for the audience, can be called for several audiences
+    pub fn accept_audience(mut self, audience: impl Into<Cow<'static, str>>) -> Self {
+        self.audiences.push(audience.into());
+        self
+    }
+
+    /// Accepts tokens of the authentication context class, can be called for several classes
+    pub fn accept_acr(mut self, acr: impl Into<Cow<'static, str>>) -> Self {
+        self.acr_values.push(acr.into());
+        self
+    }
+
+    /// Rejects tokens without the authentication method in their `amr` claim, e.g. `mfa` on the
+    /// scope of the routes that must only be reachable after MFA
+    pub fn require_amr(mut self, method: impl Into<Cow<'static, str>>) -> Self {
+        self.required_amr.push(method.into());
+        self
+    }
+
+    /// Accepts every token only once, see [`Self::replay_guard`]
+    pub fn reject_replays(mut self, guard: ReplayGuard) -> Self {
+        self.replay_guard = Some(guard);
+        self
+    }
+
+    /// Lets requests without token through as anonymous, see [`Self::allow_anonymous`]
+    pub fn allow_anonymous(mut self) -> Self {
+        self.allow_anonymous = true;
+        self
+    }
+
+    /// Requires a token on CORS preflight requests, see [`Self::authenticate_preflight`]
+    pub fn authenticate_preflight_requests(mut self) -> Self {
+        self.authenticate_preflight = true;
+        self
+    }
+
+    /// Adds requirements on the path or route pattern, see [`RouteRequirements`]
+    pub fn with_route_requirements(
+        mut self,
+        path: impl Into<Cow<'static, str>>,
+        requirements: RouteRequirements,
+    ) -> Self {
+        self.route_requirements.push((path.into(), requirements));
+        self
+    }
+
+    /// Trusts the forwarded scheme of requests from the given reverse proxy
+    pub fn trusted_proxy(mut self, proxy: IpAddr) -> Self {
+        self.trusted_proxies.push(proxy);
+        self
+    }
+}
+
+/// Overrides the validation options of the middleware for a single request
+///
+/// An earlier middleware inserts it into the request extensions, e.g. to accept expired tokens
+/// on a token refresh route, without a separate app scope. The extractors outside of the
+/// middleware check the audience and issuer of the override as well, they do not check the
+/// expiry of the token themselves.
+///
+/// ```ignore
+/// req.extensions_mut().insert(ValidationOverride {
+///     allow_expired: true,
+///     ..ValidationOverride::default()
+/// });
+/// ```
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct ValidationOverride {
+    /// Audience validation used instead of the configured one
+    pub audience: Option<Validation<String>>,
+    /// Issuer validation used instead of the configured one
+    pub issuer: Option<Validation<String>>,
+    /// Accept tokens of which the `exp` claim has passed
+    pub allow_expired: bool,
+    /// Accept tokens of which the `exp` claim has passed no longer than the given time ago,
+    /// ignored when `allow_expired` is set
+    pub expired_grace: Option<Duration>,
+}
+
+impl ValidationOverride {
+    /// Applies the override on the given validation options
+    pub fn apply(&self, mut options: ValidationOptions) -> ValidationOptions {
+        if let Some(audience) = &self.audience {
+            options.audience = audience.clone();
+        }
+        if let Some(issuer) = &self.issuer {
+            options.issuer = issuer.clone();
+        }
+        if self.allow_expired {
+            options.expiry = Validation::Ignored;
+        }
+        options
+    }
+
+    /// Checks the audience and issuer of the override, for the extractors outside of the
+    /// middleware
+    pub(crate) fn check(&self, claims: &RegisteredClaims) -> Result<(), OIDCValidationError> {
+        let options = ValidationOptions {
+            expiry: Validation::Ignored,
+            not_before: Validation::Ignored,
+            issued_at: Validation::Ignored,
+            ..ValidationOptions::default()
+        };
+        let report = ValidationReport::collect(claims, &self.apply(options));
+        match report.is_valid() {
+            true => Ok(()),
+            false => Err(OIDCValidationError::ClaimsRejected(report)),
+        }
+    }
+}
+
+/// Headers describing how the request was authenticated
+fn debug_headers(user: &DecodedInfo, source: Option<&str>) -> Vec<(HeaderName, HeaderValue)> {
+    let kid = RawHeader::peek(&user.jwt).and_then(|header| header.kid);
+    [
+        ("x-auth-kid", kid.as_deref()),
+        ("x-auth-issuer", user.payload.registered.issuer.as_deref()),
+        ("x-auth-source", source),
+    ]
+    .into_iter()
+    .filter_map(|(name, value)| {
+        let value = HeaderValue::from_str(value?).ok()?;
+        Some((HeaderName::from_static(name), value))
+    })
+    .collect()
+}
+
+/// Returns whether the request sends a token, complete or not
+fn carries_token(req: &ServiceRequest) -> bool {
+    match req.app_data::<Oidc>() {
+        Some(oidc) => !matches!(
+            oidc.extract_token(req.request()),
+            Err(OIDCValidationError::TokenMissing)
+        ),
+        None => req.headers().contains_key(header::AUTHORIZATION),
+    }
+}
+
+/// Returns whether the request is a CORS preflight, which browsers send without credentials
+fn is_preflight(req: &ServiceRequest) -> bool {
+    req.method() == Method::OPTIONS
+        && req.headers().contains_key(header::ORIGIN)
+        && req
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD)
+}
+
+/// Returns whether the request is sent by one of the proxies, of which the `Forwarded` and
+/// `X-Forwarded-*` headers are trusted
+pub(crate) fn from_trusted_proxy(req: &HttpRequest, trusted_proxies: &[IpAddr]) -> bool {
+    req.peer_addr()
+        .is_some_and(|peer| trusted_proxies.contains(&peer.ip()))
+}
+
+/// Returns whether the request arrived over HTTPS, the forwarded scheme is only trusted from
+/// the given proxies
+fn is_secure(req: &ServiceRequest, trusted_proxies: &[IpAddr]) -> bool {
+    if from_trusted_proxy(req.request(), trusted_proxies) {
+        req.connection_info().scheme() == "https"
+    } else {
+        req.app_config().secure()
+    }
+}
+
+/// The URL of the request over HTTPS
+///
+/// The host is taken from the `Host` header, forwarded hosts are not trusted to prevent open redirects.
+fn https_location(req: &ServiceRequest) -> String {
+    let host = req
+        .headers()
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .unwrap_or_else(|| req.app_config().host());
+    let path = req
+        .uri()
+        .path_and_query()
+        .map(|path| path.as_str())
+        .unwrap_or("/");
+    format!("https://{}{}", host, path)
+}
+
+/// Returns whether the request targets one of the allowlisted paths
+fn is_public(public_paths: &[Cow<'static, str>], req: &ServiceRequest) -> bool {
+    if public_paths.is_empty() {
+        return false;
+    }
+    let pattern = req.match_pattern();
+    public_paths
+        .iter()
+        .any(|public| route_matches(public, req.path(), pattern.as_deref()))
+}
+
+/// The grace window for expired tokens of the first entry that covers the request
+fn expired_grace(
+    expired_grace_paths: &[(Cow<'static, str>, Duration)],
+    req: &ServiceRequest,
+) -> Option<Duration> {
+    if expired_grace_paths.is_empty() {
+        return None;
+    }
+    let pattern = req.match_pattern();
+    expired_grace_paths
+        .iter()
+        .find(|(path, _)| route_matches(path, req.path(), pattern.as_deref()))
+        .map(|(_, grace)| *grace)
+}
+
+/// The requirements of the first entry that covers the request
+fn route_requirements<'a>(
+    route_requirements: &'a [(Cow<'static, str>, RouteRequirements)],
+    req: &ServiceRequest,
+) -> Option<&'a RouteRequirements> {
+    if route_requirements.is_empty() {
+        return None;
+    }
+    let pattern = req.match_pattern();
+    route_requirements
+        .iter()
+        .find(|(path, _)| route_matches(path, req.path(), pattern.as_deref()))
+        .map(|(_, requirements)| requirements)
+}
+
+/// The requirements of the middleware followed by the ones of the route
+fn with_route(
+    required: &Rc<Vec<Cow<'static, str>>>,
+    route: &[Cow<'static, str>],
+) -> Rc<Vec<Cow<'static, str>>> {
+    match route.is_empty() {
+        true => required.clone(),
+        false => Rc::new(required.iter().chain(route).cloned().collect()),
+    }
+}
+
+fn route_matches(entry: &str, path: &str, pattern: Option<&str>) -> bool {
+    path_matches(entry, path) || pattern.is_some_and(|pattern| path_matches(entry, pattern))
+}
+
+/// Returns whether the token is issued for none of the accepted audiences
+fn audience_mismatch(audiences: &[Cow<'static, str>], user: &DecodedInfo) -> bool {
+    if audiences.is_empty() {
+        return false;
+    }
+    match &user.payload.registered.audience {
+        Some(audience) => !audiences
+            .iter()
+            .any(|accepted| audience.contains(accepted.as_ref())),
+        None => true,
+    }
+}
+
+/// The first required scope that is neither a scope of the token nor a permission it maps to
+fn missing_scope<'a>(
+    required_scopes: &'a [Cow<'static, str>],
+    user: &DecodedInfo,
+    oidc: Option<&Oidc>,
+) -> Option<&'a str> {
+    if required_scopes.is_empty() {
+        return None;
+    }
+    let scopes = token_scopes(user);
+    let permissions = oidc.map(|oidc| oidc.permissions(user)).unwrap_or_default();
+    required_scopes
+        .iter()
+        .map(|scope| scope.as_ref())
+        .find(|scope| !scopes.iter().any(|s| s == scope) && !permissions.contains(*scope))
+}
+
+/// Returns whether the `acr` is not accepted or an `amr` is missing
+fn insufficient_authentication(
+    acr_values: &[Cow<'static, str>],
+    required_amr: &[Cow<'static, str>],
+    user: &DecodedInfo,
+) -> bool {
+    let acr = user.payload.private.get("acr").and_then(|acr| acr.as_str());
+    if !acr_values.is_empty() && !acr.is_some_and(|acr| acr_values.iter().any(|v| v == acr)) {
+        return true;
+    }
+    let amr = user
+        .payload
+        .private
+        .get("amr")
+        .and_then(|amr| amr.as_array());
+    required_amr.iter().any(|method| {
+        !amr.is_some_and(|amr| amr.iter().any(|m| m.as_str() == Some(method.as_ref())))
+    })
+}
+
+/// The first required role that the token does not have
+fn missing_role<'a>(
+    required_roles: &'a [Cow<'static, str>],
+    role_claims: &[Cow<'static, str>],
+    user: &DecodedInfo,
+) -> Option<&'a str> {
+    if required_roles.is_empty() {
+        return None;
+    }
+    let roles = match role_claims.is_empty() {
+        true => token_roles(user, DEFAULT_ROLE_CLAIMS),
+        false => token_roles(user, role_claims.iter().map(|claim| claim.as_ref())),
+    };
+    required_roles
+        .iter()
+        .map(|role| role.as_ref())
+        .find(|role| !roles.contains(*role))
+}
+
+/// Validates the expiry with the grace window added to the leeway of the validation options
+fn check_expiry_within(
+    report: &mut ValidationReport,
+    claims: &RegisteredClaims,
+    options: &ValidationOptions,
+    grace: Duration,
+) {
+    let mut temporal = options.temporal_options;
+    temporal.epsilon = chrono::Duration::from_std(grace)
+        .ok()
+        .and_then(|grace| temporal.epsilon.checked_add(&grace))
+        .unwrap_or(chrono::Duration::MAX);
+    if let Err(failure) = claims.validate_exp(Validation::Validate(temporal)) {
+        report.push(failure);
+    }
+}
+
+/// Returns whether the path or route pattern is covered by the allowlisted path, an entry
+/// ending with `/*` covers every path below it
+pub(crate) fn path_matches(public: &str, path: &str) -> bool {
+    match public.strip_suffix("/*") {
+        Some(prefix) => path
+            .strip_prefix(prefix)
+            .is_some_and(|rest| rest.starts_with('/')),
+        None => public == path,
+    }
 }
 
 impl<S, B> Transform<S, ServiceRequest> for OidcBiscuitValidator
@@ -32,9 +603,29 @@ where
     type Future = Ready<Result<Self::Transform, Self::InitError>>;
 
     fn new_transform(&self, service: S) -> Self::Future {
+        if let Err(e) = self.validate() {
+            trace::invalid_middleware(&e);
+            return ready(Err(()));
+        }
         ready(Ok(OidcBiscuitValidatorMiddleware {
             service: Rc::new(service),
             validation_options: self.options.clone(),
+            leeway: self.leeway,
+            public_paths: Rc::new(self.public_paths.clone()),
+            debug_headers: self.debug_headers,
+            insecure_transport: self.insecure_transport,
+            trusted_proxies: Rc::new(self.trusted_proxies.clone()),
+            expired_grace_paths: Rc::new(self.expired_grace_paths.clone()),
+            required_scopes: Rc::new(self.required_scopes.clone()),
+            role_claims: Rc::new(self.role_claims.clone()),
+            required_roles: Rc::new(self.required_roles.clone()),
+            audiences: Rc::new(self.audiences.clone()),
+            acr_values: Rc::new(self.acr_values.clone()),
+            required_amr: Rc::new(self.required_amr.clone()),
+            replay_guard: self.replay_guard.clone(),
+            route_requirements: Rc::new(self.route_requirements.clone()),
+            authenticate_preflight: self.authenticate_preflight,
+            allow_anonymous: self.allow_anonymous,
         }))
     }
 }
@@ -42,6 +633,22 @@ where
 pub struct OidcBiscuitValidatorMiddleware<S> {
     service: Rc<S>,
     validation_options: ValidationOptions,
+    leeway: Option<Duration>,
+    public_paths: Rc<Vec<Cow<'static, str>>>,
+    debug_headers: bool,
+    insecure_transport: InsecureTransport,
+    trusted_proxies: Rc<Vec<IpAddr>>,
+    expired_grace_paths: Rc<Vec<(Cow<'static, str>, Duration)>>,
+    required_scopes: Rc<Vec<Cow<'static, str>>>,
+    role_claims: Rc<Vec<Cow<'static, str>>>,
+    required_roles: Rc<Vec<Cow<'static, str>>>,
+    audiences: Rc<Vec<Cow<'static, str>>>,
+    acr_values: Rc<Vec<Cow<'static, str>>>,
+    required_amr: Rc<Vec<Cow<'static, str>>>,
+    replay_guard: Option<ReplayGuard>,
+    route_requirements: Rc<Vec<(Cow<'static, str>, RouteRequirements)>>,
+    authenticate_preflight: bool,
+    allow_anonymous: bool,
 }
 
 impl<S, B> Service<ServiceRequest> for OidcBiscuitValidatorMiddleware<S>
@@ -58,21 +665,153 @@ where
 
     fn call(&self, mut req: ServiceRequest) -> Self::Future {
         let svc = self.service.clone();
-        let validation_options = self.validation_options.clone();
+        let with_debug_headers = self.debug_headers;
+        let (mut validation_options, grace) = match req.extensions().get::<ValidationOverride>() {
+            Some(validation_override) => (
+                validation_override.apply(self.validation_options.clone()),
+                match validation_override.allow_expired {
+                    true => None,
+                    false => validation_override.expired_grace,
+                },
+            ),
+            None => (self.validation_options.clone(), None),
+        };
+        let oidc_leeway = req.app_data::<Oidc>().and_then(|oidc| oidc.leeway);
+        if let Some(leeway) = self.leeway.or(oidc_leeway) {
+            validation_options.temporal_options.epsilon =
+                chrono::Duration::from_std(leeway).unwrap_or(chrono::Duration::MAX);
+        }
+        let grace = grace.or_else(|| expired_grace(&self.expired_grace_paths, &req));
+        let expiry = grace.map(|grace| {
+            let expiry = validation_options.expiry;
+            validation_options.expiry = Validation::Ignored;
+            (expiry, grace)
+        });
 
+        if self.insecure_transport != InsecureTransport::Allow
+            && carries_token(&req)
+            && !is_secure(&req, &self.trusted_proxies)
+        {
+            let res = match self.insecure_transport {
+                InsecureTransport::Redirect => HttpResponse::PermanentRedirect()
+                    .insert_header((header::LOCATION, https_location(&req)))
+                    .finish(),
+                _ => rejection(req.request(), OIDCValidationError::InsecureTransport)
+                    .error_response(),
+            };
+            return Box::pin(ready(Ok(req.into_response(res).map_into_right_body())));
+        }
+
+        if !self.authenticate_preflight && is_preflight(&req) {
+            return Box::pin(async move {
+                let res = svc.call(req).await?;
+                Ok(res.map_into_left_body())
+            });
+        }
+
+        if is_public(&self.public_paths, &req) {
+            cover(&req, RouteCoverage::Public);
+            return Box::pin(async move {
+                let res = svc.call(req).await?;
+                Ok(res.map_into_left_body())
+            });
+        }
+
+        cover(&req, RouteCoverage::Middleware);
+        if self.allow_anonymous && !carries_token(&req) {
+            if let Some(oidc) = req.app_data::<Oidc>() {
+                audit(oidc, req.request(), AuditDecision::Anonymous, None, None);
+            }
+            req.extensions_mut().insert(Principal::Anonymous);
+            return Box::pin(async move {
+                let res = svc.call(req).await?;
+                Ok(res.map_into_left_body())
+            });
+        }
+        let route = route_requirements(&self.route_requirements, &req);
+        let route_audiences = route
+            .map(|route| route.audiences.clone())
+            .unwrap_or_default();
+        let (required_scopes, required_roles) = match route {
+            Some(route) => (
+                with_route(&self.required_scopes, &route.required_scopes),
+                with_route(&self.required_roles, &route.required_roles),
+            ),
+            None => (self.required_scopes.clone(), self.required_roles.clone()),
+        };
+        let role_claims = self.role_claims.clone();
+        let audiences = self.audiences.clone();
+        let acr_values = self.acr_values.clone();
+        let required_amr = self.required_amr.clone();
+        let replay_guard = self.replay_guard.clone();
         Box::pin(async move {
             let user = req.extract::<DecodedInfo>().await?.clone();
 
-            match user.payload.registered.validate(validation_options) {
-                Ok(()) => {
-                    let fut = svc.call(req);
-                    let res = fut.await?;
-                    Ok(res.map_into_left_body())
+            let mut report =
+                ValidationReport::collect(&user.payload.registered, &validation_options);
+            if let Some((Validation::Validate(()), grace)) = expiry {
+                check_expiry_within(
+                    &mut report,
+                    &user.payload.registered,
+                    &validation_options,
+                    grace,
+                );
+            }
+            let failure = match report.is_valid() {
+                true if audience_mismatch(&audiences, &user) => {
+                    trace::audience_mismatch(&audiences);
+                    Some(OIDCValidationError::InvalidAudience)
+                }
+                true if audience_mismatch(&route_audiences, &user) => {
+                    trace::audience_mismatch(&route_audiences);
+                    Some(OIDCValidationError::InvalidAudience)
                 }
-                Err(_err) => {
-                    let res: actix_web::Error = OIDCValidationError::InvalidAccess.into();
-                    Ok(req.error_response(res).map_into_right_body())
+                true if insufficient_authentication(&acr_values, &required_amr, &user) => Some(
+                    OIDCValidationError::InsufficientUserAuthentication(acr_values.join(" ")),
+                ),
+                true => missing_scope(&required_scopes, &user, req.app_data::<Oidc>())
+                    .map(|scope| OIDCValidationError::MissingScope(scope.to_string()))
+                    .or_else(|| {
+                        missing_role(&required_roles, &role_claims, &user)
+                            .map(|role| OIDCValidationError::MissingRole(role.to_string()))
+                    })
+                    .or_else(|| replay_guard.as_ref()?.check(&user).err()),
+                false => Some(OIDCValidationError::ClaimsRejected(report)),
+            };
+            if let Some(err) = failure {
+                let res = match req.app_data::<Oidc>() {
+                    Some(oidc) => {
+                        let decision = AuditDecision::Denied;
+                        audit(oidc, req.request(), decision, Some(&user), Some(&err));
+                        oidc.error_response(err)
+                    }
+                    None => err.into(),
+                };
+                Ok(req.error_response(res).map_into_right_body())
+            } else {
+                let headers = with_debug_headers.then(|| {
+                    let source = req.app_data::<Oidc>().map(|oidc| oidc.token_source());
+                    debug_headers(&user, source)
+                });
+                if let Some(oidc) = req.app_data::<Oidc>() {
+                    audit(
+                        oidc,
+                        req.request(),
+                        AuditDecision::Allowed,
+                        Some(&user),
+                        None,
+                    );
                 }
+                // guards of the routes decide on the validated claims, see ClaimGuard
+                req.extensions_mut()
+                    .insert(Principal::Authenticated(Box::new(user.clone())));
+                req.extensions_mut().insert(user);
+                let fut = svc.call(req);
+                let mut res = fut.await?;
+                for (name, value) in headers.into_iter().flatten() {
+                    res.headers_mut().insert(name, value);
+                }
+                Ok(res.map_into_left_body())
             }
         })
     }
@@ -87,18 +826,276 @@ mod tests {
             create_get_jwt_request_custom_header, create_jwt_token, create_oidc,
             create_oidc_with_token_lookup, create_token,
         },
-        DecodedInfo, OidcBiscuitValidator,
+        DecodedInfo, ExpiryStatus, InsecureTransport, OIDCValidationError, OidcBiscuitValidator,
+        PermissionMap, ReplayGuard, RouteRequirements, ValidationOverride,
+    };
+    use actix_web::{
+        body::{BoxBody, EitherBody},
+        dev::{fn_service, Service, ServiceRequest, ServiceResponse, Transform},
+        get,
+        http::{Method, StatusCode},
+        middleware::{from_fn, Next},
+        test, web, App, Error, HttpMessage, HttpResponse,
     };
-    use actix_web::{dev::Service, get, http::StatusCode, test, App, Error, HttpResponse};
     use biscuit::{Validation, ValidationOptions};
     use bytes::Bytes;
     use serde_json::json;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
     #[get("/decoder")]
     async fn decoder(claims: DecodedInfo) -> HttpResponse {
         HttpResponse::Ok().body(claims.jwt)
     }
 
+    #[get("/refresh")]
+    async fn refresh(claims: DecodedInfo) -> HttpResponse {
+        HttpResponse::Ok().body(claims.jwt)
+    }
+
+    #[get("/renew")]
+    async fn renew(expiry: ExpiryStatus) -> String {
+        format!("expired: {}", expiry.is_expired())
+    }
+
+    #[get("/status/{component}")]
+    async fn status() -> HttpResponse {
+        HttpResponse::Ok().body("up")
+    }
+
+    ///Test that tokens over plaintext HTTP are rejected, unless forwarded over HTTPS by a trusted proxy
+    #[actix_rt::test]
+    async fn test_biscuit_middleware_secure_transport_reject() -> Result<(), Error> {
+        let oidc = create_oidc().await;
+        let proxy = "10.0.0.1".parse().unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(oidc.clone())
+                .wrap(
+                    OidcBiscuitValidator::default()
+                        .require_secure_transport(InsecureTransport::Reject)
+                        .trusted_proxy(proxy),
+                )
+                .service(decoder),
+        )
+        .await;
+
+        let token = create_jwt_token();
+        let req = create_get_jwt_request("/decoder", &token)
+            .insert_header(("x-forwarded-proto", "https"))
+            .to_request();
+        let response = test::call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let req = create_get_jwt_request("/decoder", &token)
+            .insert_header(("x-forwarded-proto", "https"))
+            .peer_addr("10.0.0.1:4000".parse().unwrap())
+            .to_request();
+        let response = test::call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        Ok(())
+    }
+
+    ///Test that tokens over plaintext HTTP are redirected to HTTPS
+    #[actix_rt::test]
+    async fn test_biscuit_middleware_secure_transport_redirect() -> Result<(), Error> {
+        let oidc = create_oidc().await;
+
+        let app = test::init_service(
+            App::new()
+                .app_data(oidc.clone())
+                .wrap(
+                    OidcBiscuitValidator::default()
+                        .require_secure_transport(InsecureTransport::Redirect),
+                )
+                .service(decoder),
+        )
+        .await;
+
+        let req = create_get_jwt_request("/decoder?page=2", &create_jwt_token())
+            .insert_header(("host", "api.example.com"))
+            .insert_header(("x-forwarded-host", "evil.example.com"))
+            .to_request();
+        let response = test::call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(
+            response.headers().get("location").unwrap(),
+            "https://api.example.com/decoder?page=2"
+        );
+        Ok(())
+    }
+
+    ///Test that only allowlisted routes are reachable without a token
+    #[actix_rt::test]
+    async fn test_biscuit_middleware_default_deny_public_paths() -> Result<(), Error> {
+        let oidc = create_oidc().await;
+
+        let app = test::init_service(
+            App::new()
+                .app_data(oidc.clone())
+                .wrap(OidcBiscuitValidator::default().public_path("/status/{component}"))
+                .service(decoder)
+                .service(status),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/status/db").to_request();
+        let response = test::call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let req = test::TestRequest::get().uri("/decoder").to_request();
+        let response = app.call(req).await;
+        assert_eq!(
+            response.err().unwrap().as_response_error().status_code(),
+            StatusCode::UNAUTHORIZED
+        );
+
+        let req = test::TestRequest::get().uri("/not-registered").to_request();
+        let response = app.call(req).await;
+        assert_eq!(
+            response.err().unwrap().as_response_error().status_code(),
+            StatusCode::UNAUTHORIZED
+        );
+        Ok(())
+    }
+
+    ///Test that the excluded paths skip authentication, also for unregistered routes
+    #[actix_rt::test]
+    async fn test_biscuit_middleware_with_public_paths() -> Result<(), Error> {
+        let oidc = create_oidc().await;
+        let excluded = vec!["/health".to_string(), "/public/*".to_string()];
+
+        let app = test::init_service(
+            App::new()
+                .app_data(oidc.clone())
+                .wrap(OidcBiscuitValidator::default().with_public_paths(excluded))
+                .service(decoder),
+        )
+        .await;
+
+        for (path, expected) in [
+            ("/health", StatusCode::NOT_FOUND),
+            ("/public/css/site.css", StatusCode::NOT_FOUND),
+            ("/public", StatusCode::UNAUTHORIZED),
+            ("/healthz", StatusCode::UNAUTHORIZED),
+        ] {
+            let req = test::TestRequest::get().uri(path).to_request();
+            let response_status = match app.call(req).await {
+                Ok(response) => response.status(),
+                Err(e) => e.as_response_error().status_code(),
+            };
+            assert_eq!(response_status, expected, "{}", path);
+        }
+        Ok(())
+    }
+
+    ///Test that CORS preflights pass without a token unless they must be authenticated
+    #[actix_rt::test]
+    async fn test_biscuit_middleware_preflight() -> Result<(), Error> {
+        let oidc = create_oidc().await;
+        let preflight = || {
+            test::TestRequest::default()
+                .method(Method::OPTIONS)
+                .uri("/decoder")
+                .insert_header(("origin", "https://app.example.com"))
+                .insert_header(("access-control-request-method", "GET"))
+                .to_request()
+        };
+        let status_of = |response: Result<ServiceResponse<_>, Error>| match response {
+            Ok(response) => response.status(),
+            Err(e) => e.as_response_error().status_code(),
+        };
+
+        let app = test::init_service(
+            App::new()
+                .app_data(oidc.clone())
+                .wrap(OidcBiscuitValidator::default())
+                .service(decoder),
+        )
+        .await;
+        assert_eq!(
+            status_of(app.call(preflight()).await),
+            StatusCode::NOT_FOUND
+        );
+        let options = test::TestRequest::default()
+            .method(Method::OPTIONS)
+            .uri("/decoder")
+            .to_request();
+        assert_eq!(status_of(app.call(options).await), StatusCode::UNAUTHORIZED);
+
+        let app = test::init_service(
+            App::new()
+                .app_data(oidc.clone())
+                .wrap(OidcBiscuitValidator::default().authenticate_preflight_requests())
+                .service(decoder),
+        )
+        .await;
+        assert_eq!(
+            status_of(app.call(preflight()).await),
+            StatusCode::UNAUTHORIZED
+        );
+        Ok(())
+    }
+
+    ///Test that the debug headers describe how the request was authenticated
+    #[actix_rt::test]
+    async fn test_biscuit_middleware_debug_headers() -> Result<(), Error> {
+        let oidc =
+            create_oidc_with_token_lookup(TokenLookup::Cookie("x-cookie-token-key".into())).await;
+
+        let app = test::init_service(
+            App::new()
+                .app_data(oidc.clone())
+                .wrap(OidcBiscuitValidator::default().with_debug_headers())
+                .service(decoder),
+        )
+        .await;
+
+        let token = create_jwt_token();
+
+        let req = create_get_jwt_request_custom_cookie("/decoder", &token).to_request();
+        let response = test::call_service(&app, req).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let headers = response.headers();
+        assert_eq!(headers.get("x-auth-kid").unwrap(), "2020-01-29");
+        assert_eq!(headers.get("x-auth-issuer").unwrap(), "http://0.0.0.0:9090");
+        assert_eq!(headers.get("x-auth-source").unwrap(), "cookie");
+        Ok(())
+    }
+
+    ///Test that the infrastructure endpoints are reachable without a token
+    #[actix_rt::test]
+    async fn test_biscuit_middleware_public_infrastructure_paths() -> Result<(), Error> {
+        let oidc = create_oidc().await;
+
+        let app = test::init_service(
+            App::new()
+                .app_data(oidc.clone())
+                .wrap(OidcBiscuitValidator::default().public_infrastructure_paths())
+                .default_service(web::to(HttpResponse::Ok)),
+        )
+        .await;
+
+        for path in ["/healthz", "/metrics", "/.well-known/openid-configuration"] {
+            let req = test::TestRequest::get().uri(path).to_request();
+            let response = test::call_service(&app, req).await;
+            assert_eq!(response.status(), StatusCode::OK, "{}", path);
+        }
+
+        for path in ["/healthz/details", "/.well-known", "/.well-knownx/a"] {
+            let req = test::TestRequest::get().uri(path).to_request();
+            let response = app.call(req).await;
+            assert_eq!(
+                response.err().unwrap().as_response_error().status_code(),
+                StatusCode::UNAUTHORIZED,
+                "{}",
+                path
+            );
+        }
+        Ok(())
+    }
+
     ///Test in middleware for a valid issuer with overridden header key lookup
     #[actix_rt::test]
     async fn test_biscuit_middleware_issuer_valid_override_header_lookup() -> Result<(), Error> {
@@ -110,6 +1107,7 @@ mod tests {
                 issuer: Validation::Validate("http://0.0.0.0:9090".to_string()),
                 ..ValidationOptions::default()
             },
+            ..OidcBiscuitValidator::default()
         };
 
         let app = test::init_service(
@@ -141,6 +1139,7 @@ mod tests {
                 issuer: Validation::Validate("http://0.0.0.0:9090".to_string()),
                 ..ValidationOptions::default()
             },
+            ..OidcBiscuitValidator::default()
         };
 
         let app = test::init_service(
@@ -171,6 +1170,7 @@ mod tests {
                 issuer: Validation::Validate("http://0.0.0.0:9090".to_string()),
                 ..ValidationOptions::default()
             },
+            ..OidcBiscuitValidator::default()
         };
 
         let app = test::init_service(
@@ -201,6 +1201,7 @@ mod tests {
                 issuer: Validation::Validate("http://0.0.0.0:9091".to_string()),
                 ..ValidationOptions::default()
             },
+            ..OidcBiscuitValidator::default()
         };
 
         let app = test::init_service(
@@ -217,7 +1218,496 @@ mod tests {
 
         let response = app.call(req).await.unwrap();
 
-        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        Ok(())
+    }
+
+    ///Test that all failed checks are reported, not only the first one
+    #[actix_rt::test]
+    async fn test_biscuit_middleware_reports_all_failures() -> Result<(), Error> {
+        let oidc = create_oidc().await;
+
+        let biscuit_validator = OidcBiscuitValidator {
+            options: ValidationOptions {
+                audience: Validation::Validate("other-api".to_string()),
+                ..ValidationOptions::default()
+            },
+            ..OidcBiscuitValidator::default()
+        };
+
+        let app = test::init_service(
+            App::new()
+                .app_data(oidc.clone())
+                .wrap(biscuit_validator)
+                .service(decoder),
+        )
+        .await;
+        let claims = json!({
+          "iss": "http://0.0.0.0:9090",
+          "sub": "CgVhZG1pbhIFbG9jYWw",
+          "aud": ["cafienne-ui"],
+          "exp": 1602324610, //Saturday 10 October 2020 10:10:10 (e.g Expired)
+        });
+
+        let token = create_token(claims);
+
+        let req = create_get_jwt_request("/decoder", &token).to_request();
+
+        let response = app.call(req).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let report = response
+            .response()
+            .error()
+            .and_then(|err| err.as_error::<OIDCValidationError>())
+            .and_then(|err| err.report())
+            .unwrap();
+        assert_eq!(report.failures().len(), 2);
+        Ok(())
+    }
+
+    ///Test that an override inserted by an earlier middleware is honored
+    #[actix_rt::test]
+    async fn test_biscuit_middleware_validation_override() -> Result<(), Error> {
+        let oidc = create_oidc().await;
+
+        let app = test::init_service(
+            App::new()
+                .app_data(oidc.clone())
+                .wrap(OidcBiscuitValidator::default())
+                .wrap(from_fn(
+                    |req: ServiceRequest, next: Next<EitherBody<BoxBody>>| async move {
+                        if req.path() == "/refresh" {
+                            req.extensions_mut().insert(ValidationOverride {
+                                allow_expired: true,
+                                ..ValidationOverride::default()
+                            });
+                        }
+                        next.call(req).await
+                    },
+                ))
+                .service(decoder)
+                .service(refresh),
+        )
+        .await;
+        let claims = json!({
+          "iss": "http://0.0.0.0:9090",
+          "sub": "CgVhZG1pbhIFbG9jYWw",
+          "aud": ["cafienne-ui"],
+          "exp": 1602324610, //Saturday 10 October 2020 10:10:10 (e.g Expired)
+        });
+
+        let token = create_token(claims);
+
+        let req = create_get_jwt_request("/refresh", &token).to_request();
+        let response = app.call(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let req = create_get_jwt_request("/decoder", &token).to_request();
+        let response = app.call(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        Ok(())
+    }
+
+    ///Test that a route accepts tokens that expired within the grace window
+    #[actix_rt::test]
+    async fn test_biscuit_middleware_expired_grace() -> Result<(), Error> {
+        let oidc = create_oidc().await;
+
+        let app = test::init_service(
+            App::new()
+                .app_data(oidc.clone())
+                .wrap(
+                    OidcBiscuitValidator::default()
+                        .allow_expired_within("/renew", Duration::from_secs(300)),
+                )
+                .service(decoder)
+                .service(renew),
+        )
+        .await;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let token = |exp: u64| {
+            create_token(json!({
+              "iss": "http://0.0.0.0:9090",
+              "sub": "CgVhZG1pbhIFbG9jYWw",
+              "exp": exp,
+            }))
+        };
+
+        let req = create_get_jwt_request("/renew", &token(now - 60)).to_request();
+        let result: Bytes = test::call_and_read_body(&app, req).await;
+        assert_eq!(result, Bytes::from_static(b"expired: true"));
+
+        let req = create_get_jwt_request("/renew", &token(now - 600)).to_request();
+        let response = app.call(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let req = create_get_jwt_request("/decoder", &token(now - 60)).to_request();
+        let response = app.call(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        Ok(())
+    }
+
+    ///Test that the leeway accepts temporal claims that are off by clock drift
+    #[actix_rt::test]
+    async fn test_biscuit_middleware_leeway() -> Result<(), Error> {
+        let oidc = create_oidc().await;
+        let app = |validator: OidcBiscuitValidator| {
+            test::init_service(
+                App::new()
+                    .app_data(oidc.clone())
+                    .wrap(validator)
+                    .service(decoder),
+            )
+        };
+        let strict = app(OidcBiscuitValidator::default()).await;
+        let lenient =
+            app(OidcBiscuitValidator::default().with_leeway(Duration::from_secs(60))).await;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        for (claims, lenient_status) in [
+            (json!({ "exp": now - 30 }), StatusCode::OK),
+            (json!({ "nbf": now + 30 }), StatusCode::OK),
+            (json!({ "iat": now + 30 }), StatusCode::OK),
+            (json!({ "exp": now - 120 }), StatusCode::UNAUTHORIZED),
+        ] {
+            let req =
+                create_get_jwt_request("/decoder", &create_token(claims.clone())).to_request();
+            assert_eq!(strict.call(req).await?.status(), StatusCode::UNAUTHORIZED);
+            let req = create_get_jwt_request("/decoder", &create_token(claims)).to_request();
+            assert_eq!(lenient.call(req).await?.status(), lenient_status);
+        }
+        Ok(())
+    }
+
+    ///Test that a route rejects tokens without the required scope
+    #[actix_rt::test]
+    async fn test_biscuit_middleware_require_scope() -> Result<(), Error> {
+        let oidc = create_oidc()
+            .await
+            .with_permission_map(PermissionMap::new().map("api.admin", "orders:write"));
+
+        let app = test::init_service(
+            App::new().app_data(oidc.clone()).service(
+                web::scope("")
+                    .wrap(OidcBiscuitValidator::default().require_scope("orders:write"))
+                    .service(decoder),
+            ),
+        )
+        .await;
+
+        for (scope, expected) in [
+            ("openid orders:write", StatusCode::OK),
+            ("api.admin", StatusCode::OK),
+            ("openid orders:read", StatusCode::FORBIDDEN),
+        ] {
+            let token = create_token(json!({ "iss": "http://0.0.0.0:9090", "scope": scope }));
+            let req = create_get_jwt_request("/decoder", &token).to_request();
+            assert_eq!(app.call(req).await.unwrap().status(), expected);
+        }
+        Ok(())
+    }
+
+    ///Test the RFC 6750 challenges of missing and invalid tokens and of missing scopes
+    #[actix_rt::test]
+    async fn test_biscuit_middleware_bearer_challenges() -> Result<(), Error> {
+        let revocations = crate::InMemoryRevocationStore::default();
+        revocations.revoke_jti("revoked");
+        let oidc = create_oidc()
+            .await
+            .with_revocation_store(std::sync::Arc::new(revocations));
+
+        let app = test::init_service(
+            App::new().app_data(oidc.clone()).service(
+                web::scope("")
+                    .wrap(OidcBiscuitValidator::default().require_scope("orders:write"))
+                    .service(decoder),
+            ),
+        )
+        .await;
+
+        let revoked = json!({ "scope": "orders:write", "jti": "revoked" });
+        let expired = json!({ "scope": "orders:write", "exp": 1602324610 });
+        for (req, expected, challenge) in [
+            (
+                test::TestRequest::get().uri("/decoder"),
+                StatusCode::UNAUTHORIZED,
+                "Bearer",
+            ),
+            (
+                create_get_jwt_request("/decoder", &create_token(revoked)),
+                StatusCode::UNAUTHORIZED,
+                r#"Bearer error="invalid_token""#,
+            ),
+            (
+                create_get_jwt_request("/decoder", &create_token(expired)),
+                StatusCode::UNAUTHORIZED,
+                r#"Bearer error="invalid_token""#,
+            ),
+            (
+                create_get_jwt_request("/decoder", &create_token(json!({ "scope": "openid" }))),
+                StatusCode::FORBIDDEN,
+                r#"Bearer error="insufficient_scope""#,
+            ),
+        ] {
+            // the failures of the extractor are errors of the service
+            let res = match app.call(req.to_request()).await {
+                Ok(res) => res.into_parts().1.map_into_boxed_body(),
+                Err(e) => e.error_response(),
+            };
+            assert_eq!(res.status(), expected);
+            let header = res.headers().get("www-authenticate").unwrap();
+            assert!(header.to_str().unwrap().starts_with(challenge));
+            if expected == StatusCode::FORBIDDEN {
+                assert!(header
+                    .to_str()
+                    .unwrap()
+                    .ends_with(r#", scope="orders:write""#));
+            }
+        }
+        Ok(())
+    }
+
+    ///Test that the shared middleware enforces the requirements of each route
+    #[actix_rt::test]
+    async fn test_biscuit_middleware_route_requirements() -> Result<(), Error> {
+        let oidc = create_oidc().await;
+
+        let app = test::init_service(
+            App::new()
+                .app_data(oidc.clone())
+                .wrap(
+                    OidcBiscuitValidator::default()
+                        .require_scope("api")
+                        .with_route_requirements(
+                            "/status/{component}",
+                            RouteRequirements::new()
+                                .require_scope("status:read")
+                                .accept_audience("status-api"),
+                        ),
+                )
+                .service(decoder)
+                .service(status),
+        )
+        .await;
+
+        for (path, claims, expected) in [
+            ("/decoder", json!({ "scope": "api" }), StatusCode::OK),
+            (
+                "/status/db",
+                json!({ "scope": "api", "aud": "status-api" }),
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                "/status/db",
+                json!({ "scope": "api status:read" }),
+                StatusCode::UNAUTHORIZED,
+            ),
+            (
+                "/status/db",
+                json!({ "scope": "status:read", "aud": "status-api" }),
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                "/status/db",
+                json!({ "scope": "api status:read", "aud": "status-api" }),
+                StatusCode::OK,
+            ),
+        ] {
+            let req = create_get_jwt_request(path, &create_token(claims)).to_request();
+            assert_eq!(app.call(req).await?.status(), expected);
+        }
+        Ok(())
+    }
+
+    ///Test that a route rejects tokens without the required role in the configured claim
+    #[actix_rt::test]
+    async fn test_biscuit_middleware_require_role() -> Result<(), Error> {
+        let oidc = create_oidc().await;
+
+        let app = test::init_service(
+            App::new().app_data(oidc.clone()).service(
+                web::scope("")
+                    .wrap(
+                        OidcBiscuitValidator::default()
+                            .with_role_claim("/cognito:groups")
+                            .require_role("admin"),
+                    )
+                    .service(decoder),
+            ),
+        )
+        .await;
+
+        for (claims, expected) in [
+            (
+                json!({ "cognito:groups": ["users", "admin"] }),
+                StatusCode::OK,
+            ),
+            (
+                json!({ "cognito:groups": ["users"] }),
+                StatusCode::FORBIDDEN,
+            ),
+            (json!({ "roles": ["admin"] }), StatusCode::FORBIDDEN),
+        ] {
+            let req = create_get_jwt_request("/decoder", &create_token(claims)).to_request();
+            assert_eq!(app.call(req).await.unwrap().status(), expected);
+        }
+        Ok(())
+    }
+
+    ///Test that tokens must be issued for one of the accepted audiences
+    #[actix_rt::test]
+    async fn test_biscuit_middleware_accept_audience() -> Result<(), Error> {
+        let oidc = create_oidc().await;
+
+        let app = test::init_service(
+            App::new().app_data(oidc.clone()).service(
+                web::scope("")
+                    .wrap(
+                        OidcBiscuitValidator::default()
+                            .accept_audience("orders-api")
+                            .accept_audience("orders-api-v2"),
+                    )
+                    .service(decoder),
+            ),
+        )
+        .await;
+
+        for (claims, expected) in [
+            (json!({ "aud": "orders-api" }), StatusCode::OK),
+            (json!({ "aud": ["x", "orders-api-v2"] }), StatusCode::OK),
+            (json!({ "aud": "other-api" }), StatusCode::UNAUTHORIZED),
+            (json!({}), StatusCode::UNAUTHORIZED),
+        ] {
+            let req = create_get_jwt_request("/decoder", &create_token(claims)).to_request();
+            assert_eq!(app.call(req).await.unwrap().status(), expected);
+        }
+        // the accepted audiences are not disclosed to the client
+        let token = create_token(json!({ "aud": "other-api" }));
+        let res = app
+            .call(create_get_jwt_request("/decoder", &token).to_request())
+            .await?;
+        let challenge = res.headers().get("www-authenticate").unwrap();
+        assert!(!challenge.to_str().unwrap().contains("orders-api"));
+        let body = test::read_body(res).await;
+        assert!(!String::from_utf8_lossy(&body).contains("orders-api"));
+        Ok(())
+    }
+
+    ///Test that tokens of a too weak authentication are challenged to step up
+    #[actix_rt::test]
+    async fn test_biscuit_middleware_step_up() -> Result<(), Error> {
+        let oidc = create_oidc().await;
+
+        let app = test::init_service(
+            App::new().app_data(oidc.clone()).service(
+                web::scope("")
+                    .wrap(
+                        OidcBiscuitValidator::default()
+                            .accept_acr("urn:example:loa:2")
+                            .require_amr("mfa"),
+                    )
+                    .service(decoder),
+            ),
+        )
+        .await;
+
+        let strong = json!({ "acr": "urn:example:loa:2", "amr": ["pwd", "mfa"] });
+        let req = create_get_jwt_request("/decoder", &create_token(strong)).to_request();
+        assert_eq!(app.call(req).await?.status(), StatusCode::OK);
+
+        for claims in [
+            json!({ "acr": "urn:example:loa:1", "amr": ["pwd", "mfa"] }),
+            json!({ "acr": "urn:example:loa:2", "amr": ["pwd"] }),
+            json!({}),
+        ] {
+            let req = create_get_jwt_request("/decoder", &create_token(claims)).to_request();
+            let res = app.call(req).await?;
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+            let challenge = res
+                .headers()
+                .get("www-authenticate")
+                .unwrap()
+                .to_str()
+                .unwrap();
+            assert!(challenge.contains(r#"error="insufficient_user_authentication""#));
+            assert!(challenge.contains(r#"acr_values="urn:example:loa:2""#));
+        }
+        Ok(())
+    }
+
+    ///Test that one-time tokens are only accepted once, and not used up when rejected
+    #[actix_rt::test]
+    async fn test_biscuit_middleware_replay() -> Result<(), Error> {
+        let oidc = create_oidc().await;
+        let guard = ReplayGuard::new();
+
+        let app = test::init_service(
+            App::new().app_data(oidc.clone()).service(
+                web::scope("")
+                    .wrap(
+                        OidcBiscuitValidator::default()
+                            .require_scope("webhook")
+                            .reject_replays(guard.clone()),
+                    )
+                    .service(decoder),
+            ),
+        )
+        .await;
+
+        let exp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            + 300;
+        let token = create_token(json!({ "jti": "hook-1", "exp": exp, "scope": "webhook" }));
+        let unscoped = create_token(json!({ "jti": "hook-2", "exp": exp }));
+        let req = create_get_jwt_request("/decoder", &unscoped).to_request();
+        assert_eq!(app.call(req).await?.status(), StatusCode::FORBIDDEN);
+        assert!(guard.is_empty());
+
+        let req = create_get_jwt_request("/decoder", &token).to_request();
+        assert_eq!(app.call(req).await?.status(), StatusCode::OK);
+        let req = create_get_jwt_request("/decoder", &token).to_request();
+        assert_eq!(app.call(req).await?.status(), StatusCode::UNAUTHORIZED);
+        Ok(())
+    }
+
+    ///Test that rejected claims are answered with the error code
+    #[actix_rt::test]
+    async fn test_biscuit_middleware_json_error_code() -> Result<(), Error> {
+        let oidc = create_oidc().await.with_json_errors();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(oidc.clone())
+                .wrap(OidcBiscuitValidator::default())
+                .service(decoder),
+        )
+        .await;
+        let claims = json!({
+          "iss": "http://0.0.0.0:9090",
+          "exp": 1602324610, //Saturday 10 October 2020 10:10:10 (e.g Expired)
+        });
+
+        let token = create_token(claims);
+
+        let req = create_get_jwt_request("/decoder", &token).to_request();
+        let response = test::call_service(&app, req).await;
+
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["error"], "token_expired");
+        assert!(body["error_description"]
+            .as_str()
+            .unwrap()
+            .starts_with("Token claims are rejected: Token expired"));
         Ok(())
     }
 
@@ -275,7 +1765,7 @@ mod tests {
 
         let response = app.call(req).await.unwrap();
 
-        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
         Ok(())
     }
 
@@ -335,7 +1825,21 @@ mod tests {
 
         let response = app.call(req).await.unwrap();
 
-        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
         Ok(())
     }
+
+    ///Test that an invalid configuration fails to start the middleware
+    #[actix_rt::test]
+    async fn test_biscuit_middleware_invalid_configuration() {
+        let service = || {
+            fn_service(|req: ServiceRequest| async {
+                Ok::<_, Error>(req.into_response(HttpResponse::Ok().finish()))
+            })
+        };
+        let invalid = OidcBiscuitValidator::default().public_path("/items/*/details");
+        assert!(invalid.new_transform(service()).await.is_err());
+        let valid = OidcBiscuitValidator::default().public_path("/items/{id}");
+        assert!(valid.new_transform(service()).await.is_ok());
+    }
 }
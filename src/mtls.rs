@@ -0,0 +1,179 @@
+//! Certificate-bound access tokens of mutual TLS
+//! ([RFC 8705](https://www.rfc-editor.org/rfc/rfc8705#section-3)): the token carries the SHA-256
+//! thumbprint of the client certificate in its `cnf.x5t#S256` claim and is only accepted over a
+//! connection authenticated with that certificate.
+
+use std::{borrow::Cow, net::IpAddr};
+
+use actix_web::HttpRequest;
+use data_encoding::{BASE64, BASE64URL_NOPAD};
+use percent_encoding::percent_decode_str;
+use ring::digest;
+use serde_json::Value;
+
+use crate::{DecodedInfo, OIDCValidationError};
+
+/// The DER encoded client certificate of a TLS connection
+///
+/// Insert it into the connection data with `HttpServer::on_connect`, from the peer certificates
+/// of the TLS stream, when the server terminates TLS itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientCertificate(pub Vec<u8>);
+
+/// Binds the validated tokens to the client certificate of the connection, see
+/// [`Oidc::with_certificate_binding`](crate::Oidc::with_certificate_binding)
+///
+/// The certificate is read from the [`ClientCertificate`] of the connection or, behind a reverse
+/// proxy that terminates TLS, from a header the proxy forwards it in, as PEM (URL-encoded, e.g.
+/// nginx `$ssl_client_escaped_cert`) or base64 encoded DER.
+///
+/// ```ignore
+/// let binding = CertificateBinding::new().with_forwarded_header("x-client-cert", [proxy]);
+/// let oidc = oidc.with_certificate_binding(binding);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CertificateBinding {
+    forwarded_header: Option<(Cow<'static, str>, Vec<IpAddr>)>,
+    require_bound_tokens: bool,
+}
+
+impl CertificateBinding {
+    /// Reads the certificate from the connection, tokens without `cnf.x5t#S256` stay accepted
+    pub fn new() -> Self {
+        CertificateBinding::default()
+    }
+
+    /// Reads the certificate from the header on requests of the trusted reverse proxies, the
+    /// header of other requests is ignored
+    pub fn with_forwarded_header(
+        mut self,
+        name: impl Into<Cow<'static, str>>,
+        proxies: impl IntoIterator<Item = IpAddr>,
+    ) -> Self {
+        self.forwarded_header = Some((name.into(), proxies.into_iter().collect()));
+        self
+    }
+
+    /// Rejects tokens that are not bound to a certificate
+    pub fn require_bound_tokens(mut self) -> Self {
+        self.require_bound_tokens = true;
+        self
+    }
+
+    /// The `x5t#S256` thumbprint of the DER encoded certificate, as the issuer puts it in the
+    /// `cnf` claim
+    pub fn thumbprint(certificate: &[u8]) -> String {
+        BASE64URL_NOPAD.encode(digest::digest(&digest::SHA256, certificate).as_ref())
+    }
+
+    /// The DER encoded client certificate of the request, if any
+    pub(crate) fn certificate(&self, req: &HttpRequest) -> Option<Vec<u8>> {
+        if let Some(ClientCertificate(der)) = req.conn_data::<ClientCertificate>() {
+            return Some(der.clone());
+        }
+        let (name, proxies) = self.forwarded_header.as_ref()?;
+        let peer = req.peer_addr()?;
+        if !proxies.contains(&peer.ip()) {
+            return None;
+        }
+        let value = req.headers().get(name.as_ref())?.to_str().ok()?;
+        parse_forwarded(value)
+    }
+
+    /// Checks the `cnf.x5t#S256` claim of the token against the client certificate
+    pub(crate) fn check(
+        &self,
+        info: &DecodedInfo,
+        certificate: Option<&[u8]>,
+    ) -> Result<(), OIDCValidationError> {
+        let expected = info
+            .payload
+            .private
+            .pointer("/cnf/x5t#S256")
+            .and_then(Value::as_str);
+        match (expected, certificate) {
+            (None, _) if !self.require_bound_tokens => Ok(()),
+            (Some(expected), Some(certificate)) if expected == Self::thumbprint(certificate) => {
+                Ok(())
+            }
+            _ => Err(OIDCValidationError::CertificateMismatch),
+        }
+    }
+}
+
+/// Decodes a forwarded certificate: URL-encoded or plain PEM, or base64 encoded DER
+fn parse_forwarded(value: &str) -> Option<Vec<u8>> {
+    let value = percent_decode_str(value).decode_utf8().ok()?;
+    let base64: String = value
+        .lines()
+        .filter(|line| !line.starts_with("-----"))
+        .flat_map(|line| line.chars())
+        .filter(|c| !c.is_whitespace())
+        .collect();
+    BASE64.decode(base64.as_bytes()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+    use actix_web::{dev::Service, get, http::StatusCode, test, App, Error};
+    use data_encoding::BASE64;
+    use serde_json::json;
+
+    use super::CertificateBinding;
+    use crate::{
+        tests::{create_jwt_token, create_oidc, create_token},
+        DecodedInfo,
+    };
+
+    #[get("/decoder")]
+    async fn decoder(claims: DecodedInfo) -> String {
+        claims.jwt
+    }
+
+    ///Test that a certificate-bound token is only accepted with its certificate
+    #[actix_rt::test]
+    async fn test_certificate_binding() -> Result<(), Error> {
+        let proxy = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let certificate = b"DER encoded client certificate".to_vec();
+        let pem = format!(
+            "-----BEGIN CERTIFICATE-----\n{}\n-----END CERTIFICATE-----\n",
+            BASE64.encode(&certificate)
+        );
+        let escaped = pem.replace('\n', "%0A").replace('+', "%2B");
+        let oidc = create_oidc().await.with_certificate_binding(
+            CertificateBinding::new().with_forwarded_header("x-client-cert", [proxy]),
+        );
+        let app = test::init_service(App::new().app_data(oidc.clone()).service(decoder)).await;
+
+        let thumbprint = CertificateBinding::thumbprint(&certificate);
+        let token = create_token(json!({ "sub": "admin", "cnf": { "x5t#S256": thumbprint } }));
+        let request = |token: &str, peer: IpAddr, cert: Option<&str>| {
+            let req = test::TestRequest::get()
+                .uri("/decoder")
+                .peer_addr(SocketAddr::new(peer, 443))
+                .insert_header(("authorization", format!("Bearer {}", token)));
+            match cert {
+                Some(cert) => req.insert_header(("x-client-cert", cert)),
+                None => req,
+            }
+            .to_request()
+        };
+
+        let req = request(&token, proxy, Some(&escaped));
+        assert_eq!(app.call(req).await?.status(), StatusCode::OK);
+        let other = BASE64.encode(b"another certificate");
+        let client = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        for req in [
+            request(&token, proxy, Some(&other)),
+            request(&token, proxy, None),
+            request(&token, client, Some(&escaped)),
+        ] {
+            assert_eq!(app.call(req).await?.status(), StatusCode::UNAUTHORIZED);
+        }
+        let req = request(&create_jwt_token(), client, None);
+        assert_eq!(app.call(req).await?.status(), StatusCode::OK);
+        Ok(())
+    }
+}
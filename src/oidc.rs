@@ -1,17 +1,67 @@
-use biscuit::jwa::*;
+use actix_web::{dev::ServiceRequest, http::StatusCode, HttpRequest, ResponseError};
 use biscuit::jwk::JWKSet;
 use biscuit::*;
-use futures_util::TryFutureExt;
+use futures_util::future::join_all;
 use serde_derive::{Deserialize, Serialize};
 use serde_json::Value;
-use std::{borrow::Cow, format, sync::Arc};
+use std::{
+    borrow::Cow,
+    collections::BTreeSet,
+    format,
+    future::Future,
+    path::PathBuf,
+    sync::{Arc, Weak},
+    time::{Duration, Instant},
+};
 
+use crate::audit_sink::AuditSink;
+use crate::backend::{SelectedBackend, VerificationBackend};
+use crate::background::BackgroundTask;
+use crate::circuit_breaker::{CircuitBreaker, CircuitBreakerConfig, CircuitState, FallbackPolicy};
+use crate::claim_rules::{self, ClaimRule, RequiredClaim};
+use crate::claims_validator::{self, ClaimsValidator};
+use crate::dpop::{self, DpopValidation, NonceStore, ProofRequest};
 use crate::error::OIDCValidationError;
+use crate::extractor::enriched_user::Enrichment;
+use crate::fingerprint::FingerprintBinding;
+use crate::handle::Discovery;
+use crate::http::{self, HttpLimits};
+use crate::id_token::IdTokenValidation;
+use crate::issuer::{IssuerPattern, IssuerPatternValidator, MultiIssuerValidator};
+use crate::jwe::JweDecryption;
+use crate::keys::{KeyEndpointHealth, KeySetDocument, KeyStore};
+use crate::metrics::{AuthMetrics, TokenMetrics};
+use crate::mtls::CertificateBinding;
+use crate::permissions::{token_scopes, PermissionMap};
+use crate::reference::{ReferenceTokenValidator, ReferenceTokens};
+use crate::response::{self, ErrorHandler, UnauthorizedBody};
+use crate::revocation::{self, RevocationStore};
+use crate::tenant::{TenantResolver, Tenants, UnknownTenant};
+use crate::token_extractor::{self, CookieToken, TokenExtractor};
+use crate::trace::{self, SubjectRedaction};
+use crate::uma::{self, UmaTicketConfig};
+use crate::unverified::RawHeader;
+use crate::userinfo::UserInfoEndpoint;
+use crate::validation_cache::ValidationCache;
+use crate::validator::{JwksValidator, TokenValidator};
+use crate::webfinger;
+use crate::{DecodedInfo, UmaPermissions};
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
-struct OIDCDiscoveryDocument {
+pub(crate) struct OIDCDiscoveryDocument {
     issuer: String,
-    jwks_uri: String,
+    pub(crate) jwks_uri: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) introspection_endpoint: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) userinfo_endpoint: Option<String>,
+}
+
+impl OIDCDiscoveryDocument {
+    /// Returns whether the document is the one of the issuer, a trailing `/` is ignored
+    pub(crate) fn names_issuer(&self, issuer: &str) -> bool {
+        self.issuer.trim_end_matches('/') == issuer.trim_end_matches('/')
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -22,24 +72,125 @@ impl OidcDecoder {
         &self,
         jwks: &JWKSet<Empty>,
         token: &str,
-    ) -> Result<jws::Compact<ClaimsSet<Value>, Empty>, OIDCValidationError> {
-        let token: biscuit::jws::Compact<biscuit::ClaimsSet<Value>, Empty> =
-            JWT::new_encoded(token);
-        let decoded_token = token.decode_with_jwks(jwks, Some(SignatureAlgorithm::RS256))?;
-        Ok(decoded_token)
+    ) -> Result<ClaimsSet<Value>, OIDCValidationError> {
+        SelectedBackend.verify(jwks, token)
     }
 }
 
 /// The Oidc contains the core functionality and needs to be available in order to validate JWT
 #[derive(Clone)]
 pub struct Oidc {
-    //note that keys may expire based on Cache-Control: max-age=21446, must-revalidate header
-    /// Contains the JWK Keys that belong to the issuer
-    pub(crate) jwks: Arc<JWKSet<Empty>>,
-
-    /// Gets the claims from the access token
+    /// Validates the access token
     /// This will return a complete decoded token that contains all the claims found inside the token
-    pub(crate) token_decoder: OidcDecoder,
+    pub(crate) validator: Arc<dyn TokenValidator>,
+
+    /// The keys used by the default validator, absent when a custom validator is configured
+    pub(crate) key_store: Option<Arc<KeyStore>>,
+
+    /// Guards the calls to the identity provider made while serving requests
+    pub(crate) circuit_breaker: Option<Arc<CircuitBreaker>>,
+
+    /// Maps the scopes of the identity provider to the permissions of the application
+    pub(crate) permission_map: Arc<PermissionMap>,
+
+    /// Limits of the calls to the identity provider made while serving requests
+    pub(crate) http_limits: HttpLimits,
+
+    /// Server provided nonces that DPoP proofs must contain, when configured
+    pub(crate) dpop_nonces: Option<Arc<dyn NonceStore>>,
+
+    /// Requests UMA permission tickets when a permission is missing, when configured
+    pub(crate) uma_tickets: Option<Arc<UmaTicketConfig>>,
+
+    /// Body of the responses to unauthenticated requests, when configured
+    pub(crate) unauthorized_body: Option<Arc<UnauthorizedBody>>,
+
+    /// Answer failures with a JSON body holding the error code
+    pub(crate) json_errors: bool,
+
+    /// Renders the responses of all failures, when configured
+    pub(crate) error_handler: Option<Arc<dyn ErrorHandler>>,
+
+    /// Answer failures with RFC 7807 problem details, typed below this URI, when configured
+    pub(crate) problem_details: Option<Cow<'static, str>>,
+
+    /// Binds the tokens to the fingerprint of the client, when configured
+    pub(crate) fingerprint_binding: Option<Arc<FingerprintBinding>>,
+
+    /// Rules the claims of every validated token must meet
+    pub(crate) claim_rules: Arc<Vec<ClaimRule>>,
+
+    /// The claims every validated token must carry
+    pub(crate) required_claims: Arc<Vec<RequiredClaim>>,
+
+    /// Records the lifetime and age of the validated tokens, when configured
+    pub(crate) token_metrics: Option<Arc<TokenMetrics>>,
+
+    /// Counts the outcomes and the latency of the validations, when configured
+    pub(crate) auth_metrics: Option<Arc<AuthMetrics>>,
+
+    /// Query parameter read when the token lookup finds no token, disabled by default
+    pub(crate) query_parameter: Option<Cow<'static, str>>,
+
+    /// Authentication scheme in front of the token in the header, `None` for a bare token
+    pub(crate) header_scheme: Option<Cow<'static, str>>,
+
+    /// Source of the token used instead of the token lookup, when configured
+    pub(crate) token_extractor: Option<Arc<dyn TokenExtractor>>,
+
+    /// Resolves the tenant of the request and holds the keys of each tenant, when configured
+    pub(crate) tenants: Option<Arc<Tenants>>,
+
+    /// Signature algorithms of the accepted tokens, checked before the signature, all algorithms
+    /// of the validator when empty
+    pub(crate) allowed_algorithms: Arc<Vec<String>>,
+
+    /// Decrypts JWE wrapped tokens before validation, when configured
+    pub(crate) jwe_decryption: Option<Arc<JweDecryption>>,
+
+    /// Requires the `at+jwt` type of JWT access tokens (RFC 9068)
+    pub(crate) access_token_profile: bool,
+
+    /// Deny-list of tokens revoked before they expire, when configured
+    pub(crate) revocation_store: Option<Arc<dyn RevocationStore>>,
+
+    /// Validates the DPoP proofs of DPoP-bound tokens, when configured
+    pub(crate) dpop: Option<Arc<DpopValidation>>,
+
+    /// Binds the tokens to the client certificate of mutual TLS, when configured
+    pub(crate) certificate_binding: Option<Arc<CertificateBinding>>,
+
+    /// The UserInfo endpoint of the identity provider, when discovered or configured
+    pub(crate) userinfo: Option<Arc<UserInfoEndpoint>>,
+
+    /// Custom checks on the claims of the validated tokens, in order
+    pub(crate) claims_validators: Vec<Arc<dyn ClaimsValidator>>,
+
+    /// Leeway of the `exp`, `nbf` and `iat` checks of the middleware, when configured
+    pub(crate) leeway: Option<Duration>,
+
+    /// Looks up the principal of the [`EnrichedUser`](crate::EnrichedUser) extractor
+    pub(crate) enrichment: Option<Enrichment>,
+
+    /// The tokens of which the signature is verified, when enabled
+    pub(crate) validation_cache: Option<Arc<ValidationCache>>,
+
+    /// Receives the authentication decisions, when configured
+    pub(crate) audit_sink: Option<Arc<dyn AuditSink>>,
+
+    /// The issuer the openid-configuration is discovered from
+    pub(crate) discovery: Option<Arc<Discovery>>,
+
+    /// How the subjects are recorded in the spans of the `tracing` and `opentelemetry` features
+    pub(crate) trace_subjects: SubjectRedaction,
+
+    /// Validate the tokens in the trace context propagated by the caller
+    #[cfg(feature = "opentelemetry")]
+    pub(crate) trace_context: bool,
+
+    /// The key refresh started by [`OidcBuilder::key_refresh`](crate::OidcBuilder::key_refresh),
+    /// aborted when the last clone of the Oidc is dropped
+    pub(crate) key_refresh: Option<Arc<BackgroundTask>>,
 
     /// Use this to override token lookup location
     /// The default location is Header: Authorization
@@ -52,8 +203,24 @@ pub enum OidcConfig {
     Issuer(Cow<'static, str>),
     ///key url
     KeyUrl(Cow<'static, str>),
+    ///key urls of the same issuer (primary first, then mirrors) that are used with failover
+    KeyUrls(Vec<Cow<'static, str>>),
     ///jwks
     Jwks(JWKSet<Empty>),
+    ///file holding the jwks, read again by [`Oidc::refresh_keys`], for air-gapped deployments
+    JwksFile(PathBuf),
+    ///jwks as JSON, e.g. from an environment variable
+    JwksJson(Cow<'static, str>),
+    ///user identifier (e.g. an email address) of which the issuer is resolved with WebFinger
+    WebFinger(Cow<'static, str>),
+    ///several issuers, discovered concurrently at startup
+    Issuers(Vec<Cow<'static, str>>),
+    ///issuer patterns, the keys of each matching issuer are discovered on first use
+    IssuerPatterns(Vec<IssuerPattern>),
+    ///custom token validator
+    Validator(Arc<dyn TokenValidator>),
+    ///tenants, each request is validated with the keys of the issuer of its tenant, discovered on first use
+    Tenants(Arc<dyn TenantResolver>),
 }
 
 /// Override token lookup location
@@ -65,7 +232,22 @@ pub enum TokenLookup {
     Cookie(Cow<'static, str>),
 }
 
+impl TokenLookup {
+    /// Describes where the token is found, e.g. `header` or `cookie`
+    pub(crate) fn source(&self) -> &'static str {
+        match self {
+            TokenLookup::Header(_) => "header",
+            TokenLookup::Cookie(_) => "cookie",
+        }
+    }
+}
+
 impl Oidc {
+    /// The claims every access token must contain by RFC 9068, see
+    /// [`Oidc::with_access_token_profile`]
+    pub const ACCESS_TOKEN_CLAIMS: [&'static str; 7] =
+        ["/iss", "/exp", "/aud", "/sub", "/client_id", "/iat", "/jti"];
+
     /// Creates a new Oidc
     pub async fn new(config: OidcConfig) -> Result<Self, OIDCValidationError> {
         Self::new_with_token_lookup(
@@ -80,14 +262,109 @@ impl Oidc {
         config: OidcConfig,
         token_lookup: TokenLookup,
     ) -> Result<Self, OIDCValidationError> {
-        match config {
+        Oidc::new_with_limits(config, token_lookup, HttpLimits::default()).await
+    }
+
+    /// Creates a new Oidc of which the discovery and the first key fetch already keep to the limits
+    pub(crate) async fn new_with_limits(
+        config: OidcConfig,
+        token_lookup: TokenLookup,
+        limits: HttpLimits,
+    ) -> Result<Self, OIDCValidationError> {
+        config.validate()?;
+        token_lookup.validate()?;
+        let oidc = match config {
             OidcConfig::Issuer(issuer) => {
-                Oidc::new_from_issuer(issuer.as_ref(), token_lookup).await
+                Oidc::new_from_issuer(issuer.as_ref(), token_lookup, &limits).await
             }
             OidcConfig::KeyUrl(key_url) => {
-                Oidc::new_with_keys(key_url.as_ref(), token_lookup).await
+                Oidc::new_with_keys(vec![key_url.into_owned()], token_lookup, &limits).await
+            }
+            OidcConfig::KeyUrls(key_urls) => {
+                let key_urls = key_urls.into_iter().map(Cow::into_owned).collect();
+                Oidc::new_with_keys(key_urls, token_lookup, &limits).await
             }
             OidcConfig::Jwks(jwks) => Oidc::new_for_jwks(jwks, token_lookup),
+            OidcConfig::JwksFile(path) => Ok(Oidc::new_for_key_store(
+                Arc::new(KeyStore::from_file(path).await?),
+                token_lookup,
+            )),
+            OidcConfig::JwksJson(json) => {
+                let KeySetDocument(jwks) = serde_json::from_str(&json).map_err(|e| {
+                    OIDCValidationError::InvalidConfiguration(format!("invalid JWKS JSON: {}", e))
+                })?;
+                Oidc::new_for_jwks(jwks, token_lookup)
+            }
+            OidcConfig::WebFinger(identifier) => {
+                let issuer = webfinger::discover_issuer(identifier.as_ref(), &limits).await?;
+                Oidc::new_from_issuer(&issuer, token_lookup, &limits).await
+            }
+            OidcConfig::Issuers(issuers) => {
+                let validator = MultiIssuerValidator::discover_with_limits(
+                    issuers,
+                    MultiIssuerValidator::DEFAULT_DISCOVERY_TIMEOUT,
+                    limits.clone(),
+                )
+                .await?;
+                Ok(Oidc::new_for_validator(Arc::new(validator), token_lookup))
+            }
+            OidcConfig::IssuerPatterns(patterns) => Ok(Oidc::new_for_validator(
+                Arc::new(IssuerPatternValidator::new(patterns).with_http_limits(limits.clone())),
+                token_lookup,
+            )),
+            OidcConfig::Validator(validator) => {
+                Ok(Oidc::new_for_validator(validator, token_lookup))
+            }
+            OidcConfig::Tenants(resolver) => {
+                let mut oidc = Oidc::new_for_validator(Arc::new(UnknownTenant), token_lookup);
+                oidc.tenants = Some(Arc::new(Tenants::new(resolver)));
+                Ok(oidc)
+            }
+        };
+        Ok(oidc?.with_http_limits(limits))
+    }
+
+    /// Uses a validator that does not depend on a single key store
+    fn new_for_validator(validator: Arc<dyn TokenValidator>, token_lookup: TokenLookup) -> Self {
+        Oidc {
+            validator,
+            key_store: None,
+            circuit_breaker: None,
+            permission_map: Arc::default(),
+            http_limits: HttpLimits::default(),
+            dpop_nonces: None,
+            uma_tickets: None,
+            unauthorized_body: None,
+            json_errors: false,
+            error_handler: None,
+            problem_details: None,
+            fingerprint_binding: None,
+            claim_rules: Arc::default(),
+            required_claims: Arc::default(),
+            token_metrics: None,
+            auth_metrics: None,
+            query_parameter: None,
+            header_scheme: Some(Cow::Borrowed("Bearer")),
+            token_extractor: None,
+            tenants: None,
+            allowed_algorithms: Arc::default(),
+            jwe_decryption: None,
+            access_token_profile: false,
+            revocation_store: None,
+            dpop: None,
+            certificate_binding: None,
+            userinfo: None,
+            claims_validators: Vec::new(),
+            leeway: None,
+            enrichment: None,
+            validation_cache: None,
+            audit_sink: None,
+            discovery: None,
+            trace_subjects: SubjectRedaction::default(),
+            #[cfg(feature = "opentelemetry")]
+            trace_context: false,
+            key_refresh: None,
+            token_lookup,
         }
     }
 
@@ -95,23 +372,76 @@ impl Oidc {
     ///
     /// The given issuer_url will be extended with ./well-known/openid-configuration in order to
     /// fetch the configuration and use the jwks_uri property to retrieve the keys used for validation.actix_rt
+    /// The userinfo_endpoint property is used by the [`UserInfo`](crate::UserInfo) extractor. The
+    /// openid-configuration must name the issuer.
     async fn new_from_issuer(
         issuer_url: &str,
         token_lookup: TokenLookup,
+        limits: &HttpLimits,
     ) -> Result<Self, OIDCValidationError> {
-        let discovery_document =
-            Oidc::fetch_discovery(&format!("{}/.well-known/openid-configuration", issuer_url))
-                .await?;
-        Oidc::new_with_keys(&discovery_document.jwks_uri, token_lookup).await
+        let discovery_document = Oidc::discover_named(issuer_url, limits).await?;
+        let key_store = KeyStore::fetch(vec![discovery_document.jwks_uri], limits).await?;
+        let mut oidc = Oidc::new_for_key_store(Arc::new(key_store), token_lookup);
+        oidc.discovery = Some(Arc::new(Discovery::new(issuer_url)));
+        oidc.userinfo = discovery_document
+            .userinfo_endpoint
+            .map(|endpoint| Arc::new(UserInfoEndpoint::new(endpoint)));
+        Ok(oidc)
+    }
+
+    /// Fetches the keys of an issuer with its openid-configuration, which must name the issuer
+    pub(crate) async fn discover_key_store(
+        issuer_url: &str,
+        limits: &HttpLimits,
+    ) -> Result<KeyStore, OIDCValidationError> {
+        let discovery_document = Oidc::discover_named(issuer_url, limits).await?;
+        KeyStore::fetch(vec![discovery_document.jwks_uri], limits).await
+    }
+
+    /// Fetches the openid-configuration of an issuer, which must name the issuer
+    async fn discover_named(
+        issuer_url: &str,
+        limits: &HttpLimits,
+    ) -> Result<OIDCDiscoveryDocument, OIDCValidationError> {
+        let discovery_document = Oidc::discover(issuer_url, limits).await?;
+        if !discovery_document.names_issuer(issuer_url) {
+            return Err(OIDCValidationError::DiscoveredIssuerMismatch(
+                issuer_url.to_string(),
+            ));
+        }
+        Ok(discovery_document)
+    }
+
+    /// Fetches the openid-configuration of an issuer
+    pub(crate) async fn discover(
+        issuer_url: &str,
+        limits: &HttpLimits,
+    ) -> Result<OIDCDiscoveryDocument, OIDCValidationError> {
+        let uri = format!(
+            "{}/.well-known/openid-configuration",
+            issuer_url.trim_end_matches('/')
+        );
+        trace::call("discovery", &uri, Oidc::fetch_discovery(&uri, limits)).await
+    }
+
+    /// Resolves the issuer for a user identifier (e.g. `joe@example.com`) with
+    /// [RFC 7033 WebFinger](https://www.rfc-editor.org/rfc/rfc7033)
+    ///
+    /// The WebFinger endpoint of the host of the identifier is asked for the OpenID Connect issuer,
+    /// the result can be used with [`OidcConfig::Issuer`]. The call is made with the default
+    /// [`HttpLimits`], [`OidcConfig::WebFinger`] uses the configured ones.
+    pub async fn discover_issuer(identifier: &str) -> Result<String, OIDCValidationError> {
+        webfinger::discover_issuer(identifier, &HttpLimits::default()).await
     }
 
-    /// When you need the validator created with a specified key URL
+    /// When you need the validator created with specified key URLs
     async fn new_with_keys(
-        key_url: &str,
+        key_urls: Vec<String>,
         token_lookup: TokenLookup,
+        limits: &HttpLimits,
     ) -> Result<Self, OIDCValidationError> {
-        let jwks = Oidc::fetch_jwks(key_url).await?;
-        Oidc::new_for_jwks(jwks, token_lookup)
+        let key_store = KeyStore::fetch(key_urls, limits).await?;
+        Ok(Oidc::new_for_key_store(Arc::new(key_store), token_lookup))
     }
 
     /// Use your own JSWKSet directly
@@ -119,38 +449,1007 @@ impl Oidc {
         jwks: JWKSet<Empty>,
         token_lookup: TokenLookup,
     ) -> Result<Self, OIDCValidationError> {
-        Ok(Oidc {
-            jwks: Arc::new(jwks),
-            token_decoder: OidcDecoder,
+        Ok(Oidc::new_for_key_store(
+            Arc::new(KeyStore::new(jwks)),
             token_lookup,
+        ))
+    }
+
+    /// Uses the keys of the store, which are also refreshed and rotated by the Oidc
+    fn new_for_key_store(key_store: Arc<KeyStore>, token_lookup: TokenLookup) -> Self {
+        let validator = Arc::new(JwksValidator::from_store(key_store.clone()));
+        Oidc {
+            key_store: Some(key_store),
+            ..Oidc::new_for_validator(validator, token_lookup)
+        }
+    }
+
+    /// Guards the calls to the identity provider (key refresh, introspection, UserInfo) with a
+    /// circuit breaker
+    pub fn with_circuit_breaker(mut self, config: CircuitBreakerConfig) -> Self {
+        self.circuit_breaker = Some(Arc::new(CircuitBreaker::new(config)));
+        self
+    }
+
+    /// Maps the scopes issued by the identity provider to the permissions of the application,
+    /// applied before guards evaluate their requirements
+    pub fn with_permission_map(mut self, permission_map: PermissionMap) -> Self {
+        self.permission_map = Arc::new(permission_map);
+        self
+    }
+
+    /// The permissions granted by the scopes of the token, after the permission map is applied
+    pub fn permissions(&self, info: &DecodedInfo) -> BTreeSet<String> {
+        let scopes = token_scopes(info);
+        self.permission_map
+            .permissions(scopes.iter().map(String::as_str))
+    }
+
+    /// Reads the token from another location than the `Authorization` header, e.g. the
+    /// httpOnly cookie of a SPA
+    ///
+    /// Register an Oidc with another lookup on a scope to change the location for that scope only.
+    pub fn with_token_lookup(mut self, token_lookup: TokenLookup) -> Self {
+        self.token_lookup = token_lookup;
+        self
+    }
+
+    /// Finds the token with the extractor instead of the token lookup, e.g. for a token embedded
+    /// in a signed payload
+    ///
+    /// The extractor is the only source of the token, the header scheme and query parameter are
+    /// not used.
+    pub fn with_token_extractor(mut self, extractor: impl TokenExtractor + 'static) -> Self {
+        self.token_extractor = Some(Arc::new(extractor));
+        self
+    }
+
+    /// Expects another authentication scheme than `Bearer` in front of the token in the header,
+    /// e.g. `JWT` for `Authorization: JWT <token>`
+    pub fn with_header_scheme(mut self, scheme: impl Into<Cow<'static, str>>) -> Self {
+        self.header_scheme = Some(scheme.into());
+        self
+    }
+
+    /// Expects the bare token in the header, e.g. gateways that forward it as `X-Auth-Token: <token>`
+    pub fn without_header_scheme(mut self) -> Self {
+        self.header_scheme = None;
+        self
+    }
+
+    /// Accepts the token in the query parameter, e.g. `?access_token=...`, when the token lookup
+    /// finds no token
+    ///
+    /// Meant for file downloads and EventSource connections that cannot set headers. Tokens in URLs
+    /// end up in access logs and browser history, so register an Oidc with it on the scope of these
+    /// endpoints only.
+    pub fn with_query_parameter(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.query_parameter = Some(name.into());
+        self
+    }
+
+    /// Limits the time and response size of the calls to the identity provider made while serving
+    /// requests, e.g. key refresh, introspection and UserInfo
+    pub fn with_http_limits(mut self, limits: HttpLimits) -> Self {
+        self.http_limits = limits;
+        self
+    }
+
+    /// Binds the tokens to the fingerprint of the client, the extractors reject a token that
+    /// is sent by another client than the one it was issued to
+    pub fn with_fingerprint_binding(mut self, binding: FingerprintBinding) -> Self {
+        self.fingerprint_binding = Some(Arc::new(binding));
+        self
+    }
+
+    /// Fetches the claims of the [`UserInfo`](crate::UserInfo) extractor from the endpoint,
+    /// instead of the discovered `userinfo_endpoint`
+    pub fn with_userinfo_endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.userinfo = Some(Arc::new(UserInfoEndpoint::new(endpoint.into())));
+        self
+    }
+
+    /// Caches the UserInfo responses for the TTL but never past the `exp` of the token, and the
+    /// rejected tokens for the negative TTL, instead of a minute and 5 seconds
+    ///
+    /// The cache keeps the 10 000 most recently used responses and the 1 000 most recently
+    /// rejected tokens.
+    ///
+    /// Call it after the endpoint is configured, it has no effect without endpoint.
+    pub fn with_userinfo_cache(mut self, ttl: Duration, negative_ttl: Duration) -> Self {
+        self.userinfo = self.userinfo.map(|userinfo| {
+            let endpoint = userinfo.endpoint().to_string();
+            Arc::new(UserInfoEndpoint::with_cache(endpoint, ttl, negative_ttl))
+        });
+        self
+    }
+
+    /// Appends a check to the chain of claims validators, see [`ClaimsValidator`]
+    ///
+    /// The chain runs after the signature, the required claims and the claim rules are validated,
+    /// for the middleware and the extractors alike. Add the built-in [`ExpiryValidator`](crate::ExpiryValidator),
+    /// [`AudienceValidator`](crate::AudienceValidator) and [`IssuerValidator`](crate::IssuerValidator)
+    /// where they must run relative to the custom checks.
+    pub fn with_claims_validator(mut self, validator: impl ClaimsValidator + 'static) -> Self {
+        self.claims_validators.push(Arc::new(validator));
+        self
+    }
+
+    /// Accepts `exp`, `nbf` and `iat` claims that are off by no more than the leeway, for clock
+    /// drift with the identity provider
+    ///
+    /// The leeway is the one of every [`OidcBiscuitValidator`](crate::OidcBiscuitValidator) that
+    /// validates with this Oidc, unless the middleware is configured with its own
+    /// [`OidcBiscuitValidator::with_leeway`](crate::OidcBiscuitValidator::with_leeway). It does
+    /// not make the claims required.
+    pub fn with_leeway(mut self, leeway: Duration) -> Self {
+        self.leeway = Some(leeway);
+        self
+    }
+
+    /// Looks up the application-level principal of the validated token, e.g. the user record of
+    /// the database, for the [`EnrichedUser`](crate::EnrichedUser) extractor
+    ///
+    /// ```ignore
+    /// let oidc = oidc.with_enrichment(move |info: DecodedInfo| {
+    ///     let db = db.clone();
+    ///     async move { db.find_user(info.payload.registered.subject).await }
+    /// });
+    /// ```
+    pub fn with_enrichment<U, F, Fut>(mut self, hook: F) -> Self
+    where
+        U: 'static,
+        F: Fn(DecodedInfo) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<U, actix_web::Error>> + 'static,
+    {
+        self.enrichment = Some(Enrichment::new(hook));
+        self
+    }
+
+    /// Accepts certificate-bound tokens only over a connection authenticated with their client
+    /// certificate, see [`CertificateBinding`]
+    pub fn with_certificate_binding(mut self, binding: CertificateBinding) -> Self {
+        self.certificate_binding = Some(Arc::new(binding));
+        self
+    }
+
+    /// Adds rules the claims of every validated token must meet, e.g.
+    /// `claims.email_verified == true`
+    pub fn with_claim_rules(mut self, rules: impl IntoIterator<Item = ClaimRule>) -> Self {
+        Arc::make_mut(&mut self.claim_rules).extend(rules);
+        self
+    }
+
+    /// Requires the claims at the JSON pointers to be present and not null in every validated
+    /// token, e.g. `/email_verified` or `/tenant_id`, or to have an expected value, see
+    /// [`RequiredClaim`]
+    pub fn with_required_claims<I>(mut self, claims: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<RequiredClaim>,
+    {
+        Arc::make_mut(&mut self.required_claims).extend(claims.into_iter().map(Into::into));
+        self
+    }
+
+    /// Accepts only JWT access tokens as profiled by [RFC 9068](https://www.rfc-editor.org/rfc/rfc9068):
+    /// the `typ` header must be `at+jwt` and the [`Oidc::ACCESS_TOKEN_CLAIMS`] are required
+    ///
+    /// This keeps ID tokens, which are signed by the same keys, from being accepted as access
+    /// tokens.
+    pub fn with_access_token_profile(mut self) -> Self {
+        self.access_token_profile = true;
+        self.with_required_claims(Oidc::ACCESS_TOKEN_CLAIMS)
+    }
+
+    /// Rejects tokens without the `at+jwt` type when the access token profile is required
+    fn check_token_type(&self, token: &str) -> Result<(), OIDCValidationError> {
+        if !self.access_token_profile {
+            return Ok(());
+        }
+        let typ = RawHeader::peek(token).and_then(|header| header.typ);
+        match typ.as_deref().map(str::to_ascii_lowercase).as_deref() {
+            Some("at+jwt" | "application/at+jwt") => Ok(()),
+            _ => Err(OIDCValidationError::InvalidTokenType(
+                typ.unwrap_or_else(|| "none".to_string()),
+            )),
+        }
+    }
+
+    /// Accepts only tokens signed with one of the algorithms, e.g. RS256 and ES256
+    ///
+    /// The `alg` header is checked before the signature is verified, so tokens with unexpected
+    /// algorithms, like `none` or HMAC downgrade attempts, never reach the verification.
+    pub fn with_allowed_algorithms(
+        mut self,
+        algorithms: impl IntoIterator<Item = jwa::SignatureAlgorithm>,
+    ) -> Self {
+        Arc::make_mut(&mut self.allowed_algorithms).extend(algorithms.into_iter().map(|alg| {
+            serde_json::to_value(alg)
+                .ok()
+                .and_then(|alg| alg.as_str().map(str::to_string))
+                .unwrap_or_default()
+        }));
+        self
+    }
+
+    /// Accepts EdDSA (Ed25519) signed tokens next to the allowed algorithms, biscuit has no
+    /// [`jwa::SignatureAlgorithm`] for EdDSA
+    pub fn with_allowed_eddsa(mut self) -> Self {
+        Arc::make_mut(&mut self.allowed_algorithms).push("EdDSA".to_string());
+        self
+    }
+
+    /// Decrypts JWE wrapped tokens with the key before their nested JWS is validated, plain JWS
+    /// tokens are still accepted
+    ///
+    /// [`DecodedInfo::jwt`] holds the nested JWS of decrypted tokens.
+    pub fn with_jwe_decryption(mut self, decryption: JweDecryption) -> Self {
+        self.jwe_decryption = Some(Arc::new(decryption));
+        self
+    }
+
+    /// Rejects the tokens the store revoked by their `jti` or `sub`, e.g. of a disabled user
+    pub fn with_revocation_store(mut self, store: Arc<dyn RevocationStore>) -> Self {
+        self.revocation_store = Some(store);
+        self
+    }
+
+    /// Hands a record of every authentication decision to the sink, see [`AuditSink`]
+    pub fn with_audit_sink(mut self, sink: impl AuditSink + 'static) -> Self {
+        self.audit_sink = Some(Arc::new(sink));
+        self
+    }
+
+    /// Caches the claims of up to `capacity` tokens of which the signature is verified, by the
+    /// hash of the token until its `exp`, so repeated requests with the same token skip the
+    /// verification
+    ///
+    /// The least recently used token is dropped when the cache is full. The required claims, the
+    /// claim rules, the claims validators and the revocation store still check every request. The
+    /// cached tokens of a key that is removed from the key set are verified again, and
+    /// [`Self::refresh_keys`] and [`Self::refresh_discovery`] empty the cache, for the custom
+    /// validators of which the keys are not known.
+    pub fn with_validation_cache(mut self, capacity: usize) -> Self {
+        self.validation_cache = Some(Arc::new(ValidationCache::new(capacity)));
+        self
+    }
+
+    /// The nested JWS of a JWE wrapped token, when decryption is configured
+    fn decrypt<'a>(&self, token: &'a str) -> Result<Cow<'a, str>, OIDCValidationError> {
+        match &self.jwe_decryption {
+            Some(decryption) => decryption.decrypt(token),
+            None => Ok(Cow::Borrowed(token)),
+        }
+    }
+
+    /// Rejects JWS tokens of which the algorithm is not allowed, other tokens (e.g. references
+    /// or PASETO) are left to the validator
+    fn check_algorithm(&self, token: &str) -> Result<(), OIDCValidationError> {
+        if self.allowed_algorithms.is_empty() {
+            return Ok(());
+        }
+        let Some(header) = RawHeader::peek(token) else {
+            return Ok(());
+        };
+        match self.allowed_algorithms.contains(&header.alg) {
+            true => Ok(()),
+            false => Err(OIDCValidationError::AlgorithmNotAllowed(header.alg)),
+        }
+    }
+
+    /// Records the remaining lifetime and age of every validated token in the metrics
+    pub fn with_token_metrics(mut self, metrics: Arc<TokenMetrics>) -> Self {
+        self.token_metrics = Some(metrics);
+        self
+    }
+
+    /// Records the subjects of the tokens in the spans of the `tracing` feature and the
+    /// `enduser.id` of the `opentelemetry` feature as given, instead of their hash
+    pub fn with_trace_subjects(mut self, redaction: SubjectRedaction) -> Self {
+        self.trace_subjects = redaction;
+        self
+    }
+
+    /// Validates the tokens in the trace context propagated by the caller, e.g. with the
+    /// `traceparent` header, when no span is active yet
+    ///
+    /// The context is extracted with the global text map propagator of OpenTelemetry, the
+    /// validation runs in an `authenticate` span of the global tracer, child of the caller, so it
+    /// and the calls to the identity provider join the trace of the caller.
+    #[cfg(feature = "opentelemetry")]
+    pub fn with_trace_context_extraction(mut self) -> Self {
+        self.trace_context = true;
+        self
+    }
+
+    /// Counts the allowed and denied requests, the validation latency of their tokens and the
+    /// reloads of the keys in the metrics, see [`AuthMetrics`]
+    ///
+    /// The requests are counted where they are decided, like for the audit sink: a missing token
+    /// or a token lacking a scope of the middleware counts as denied. The reloads are counted for
+    /// the keys of the issuer, of every tenant and of the issuers of a
+    /// [`MultiIssuerValidator`](crate::MultiIssuerValidator) or an
+    /// [`IssuerPatternValidator`](crate::IssuerPatternValidator). Calling it again counts in the
+    /// new metrics instead.
+    pub fn with_auth_metrics(mut self, metrics: Arc<AuthMetrics>) -> Self {
+        if let Some(key_store) = &self.key_store {
+            key_store.with_metrics(metrics.clone());
+        }
+        if let Some(tenants) = &self.tenants {
+            tenants.with_metrics(metrics.clone());
+        }
+        self.validator.set_auth_metrics(metrics.clone());
+        self.auth_metrics = Some(metrics);
+        self
+    }
+
+    /// Accepts DPoP-bound tokens with the `DPoP` authorization scheme and validates their proofs,
+    /// see [`DpopValidation`]
+    pub fn with_dpop(mut self, validation: DpopValidation) -> Self {
+        self.dpop = Some(Arc::new(validation));
+        self
+    }
+
+    /// Checks the validated token against the request it is sent with: the DPoP proof, the
+    /// fingerprint of the client and the client certificate, as far as they are configured
+    pub(crate) fn check_request(
+        &self,
+        req: &HttpRequest,
+        token: &str,
+        info: &DecodedInfo,
+    ) -> Result<(), OIDCValidationError> {
+        if let Some(dpop) = &self.dpop {
+            let request = ProofRequest::of(req, dpop);
+            dpop.check(&request, token, info, self.dpop_nonces.as_deref())?;
+        }
+        if let Some(binding) = &self.fingerprint_binding {
+            binding.check(info, binding.fingerprint(req).as_deref())?;
+        }
+        if let Some(binding) = &self.certificate_binding {
+            binding.check(info, binding.certificate(req).as_deref())?;
+        }
+        Ok(())
+    }
+
+    /// Requires DPoP proofs to contain a nonce issued by the store
+    pub fn with_dpop_nonces(mut self, store: Arc<dyn NonceStore>) -> Self {
+        self.dpop_nonces = Some(store);
+        self
+    }
+
+    /// The nonce clients must use in their next DPoP proofs, to send in the `DPoP-Nonce`
+    /// response header
+    pub fn dpop_nonce(&self) -> Option<String> {
+        self.dpop_nonces.as_ref().map(|store| store.issue())
+    }
+
+    /// Checks the `nonce` claim of a DPoP proof when nonces are required
+    ///
+    /// A missing or outdated nonce fails with [`OIDCValidationError::UseDpopNonce`], of which the
+    /// response carries a fresh nonce in the `DPoP-Nonce` header.
+    pub fn check_dpop_nonce(&self, proof: &ClaimsSet<Value>) -> Result<(), OIDCValidationError> {
+        match &self.dpop_nonces {
+            Some(store) => dpop::check_nonce(store.as_ref(), proof),
+            None => Ok(()),
+        }
+    }
+
+    /// Accepts opaque references issued by [`ReferenceTokens::issue`] instead of the tokens
+    ///
+    /// The tokens stay server-side, the reference is resolved and the token it refers to is
+    /// validated like before.
+    pub fn with_reference_tokens(mut self, references: ReferenceTokens) -> Self {
+        self.validator = Arc::new(ReferenceTokenValidator {
+            references,
+            inner: self.validator,
+        });
+        self
+    }
+
+    /// Replaces the body of the responses to unauthenticated requests (401)
+    pub fn with_unauthorized_body(mut self, body: UnauthorizedBody) -> Self {
+        self.unauthorized_body = Some(Arc::new(body));
+        self
+    }
+
+    /// Answers failures with a JSON body holding the machine-readable error code, e.g.
+    /// `{"error": "token_expired", "error_description": "..."}`
+    ///
+    /// A configured [`UnauthorizedBody`] takes precedence for unauthenticated requests.
+    pub fn with_json_errors(mut self) -> Self {
+        self.json_errors = true;
+        self
+    }
+
+    /// Answers failures with `application/problem+json` bodies
+    /// ([RFC 7807](https://www.rfc-editor.org/rfc/rfc7807)) holding the `type`, `title`,
+    /// `status` and `detail` of the failure, and its machine-readable `code`
+    ///
+    /// The `type` is the error code appended to the base URI, e.g.
+    /// `https://errors.example.com/auth/token_expired`. A configured [`UnauthorizedBody`] takes
+    /// precedence for unauthenticated requests, the problem details over [`Self::with_json_errors`].
+    pub fn with_problem_details(mut self, type_base: impl Into<Cow<'static, str>>) -> Self {
+        self.problem_details = Some(type_base.into());
+        self
+    }
+
+    /// Renders the responses of all failures of the extractors and middleware with the handler,
+    /// which takes precedence over the other error response options, see [`ErrorHandler`]
+    pub fn with_error_handler(mut self, handler: impl ErrorHandler + 'static) -> Self {
+        self.error_handler = Some(Arc::new(handler));
+        self
+    }
+
+    /// Turns a failure of the extractors and middleware into the configured error response
+    pub(crate) fn error_response(&self, err: OIDCValidationError) -> actix_web::Error {
+        if let Some(handler) = &self.error_handler {
+            return response::handled(handler.as_ref(), err);
+        }
+        match (&self.unauthorized_body, &self.problem_details) {
+            (Some(body), _) if err.status_code() == StatusCode::UNAUTHORIZED => body.render(err),
+            (_, Some(type_base)) => response::problem_details(type_base, err),
+            _ if self.json_errors => response::json_error(err),
+            _ => err.into(),
+        }
+    }
+
+    /// Answers missing UMA permissions with a permission ticket, see [`Self::require_uma_permission`]
+    pub fn with_uma_tickets(mut self, config: UmaTicketConfig) -> Self {
+        self.uma_tickets = Some(Arc::new(config));
+        self
+    }
+
+    /// Guards a handler on a Keycloak UMA permission
+    ///
+    /// When the scope is not granted on the resource and UMA tickets are configured, a permission
+    /// ticket is requested and the request fails with [`OIDCValidationError::UmaTicket`], else
+    /// with [`OIDCValidationError::InvalidAccess`].
+    pub async fn require_uma_permission(
+        &self,
+        permissions: &UmaPermissions,
+        resource_id: &str,
+        scope: &str,
+    ) -> Result<(), OIDCValidationError> {
+        if permissions.has(resource_id, scope) {
+            return Ok(());
+        }
+        let Some(config) = &self.uma_tickets else {
+            return Err(OIDCValidationError::InvalidAccess);
+        };
+        let ticket = uma::request_ticket(config, resource_id, scope, &self.http_limits);
+        match self.guarded(ticket).await? {
+            Some(ticket) => Err(OIDCValidationError::UmaTicket {
+                realm: config.realm.clone(),
+                as_uri: config.as_uri.clone(),
+                ticket,
+            }),
+            None => Err(OIDCValidationError::InvalidAccess),
+        }
+    }
+
+    /// State of the circuit breaker, when configured
+    pub fn circuit_state(&self) -> Option<CircuitState> {
+        self.circuit_breaker
+            .as_ref()
+            .map(|circuit_breaker| circuit_breaker.state())
+    }
+
+    /// Runs a call to the identity provider through the circuit breaker, when configured
+    ///
+    /// Returns `Ok(None)` when the circuit is open and the fallback policy accepts cached data.
+    pub(crate) async fn guarded<T, F>(&self, call: F) -> Result<Option<T>, OIDCValidationError>
+    where
+        F: std::future::Future<Output = Result<T, OIDCValidationError>>,
+    {
+        guard(self.circuit_breaker.as_deref(), call).await
+    }
+
+    /// Refreshes the keys every interval in the background, see [`Oidc::refresh_keys`]
+    ///
+    /// The task ends by itself when the last clone of this Oidc is dropped. A failed refresh
+    /// keeps the current keys and is retried the next interval.
+    ///
+    /// When the keys are served with `Cache-Control: max-age`, they are only reloaded after it
+    /// passed, the interval is then how often this is checked. Reloads are conditional requests
+    /// with the `ETag` of the keys.
+    pub fn spawn_key_refresh(&self, interval: Duration) -> BackgroundTask {
+        let key_store = self.key_store.as_ref().map(Arc::downgrade);
+        let tenants = self.tenants.as_ref().map(Arc::downgrade);
+        let circuit_breaker = self.circuit_breaker.clone();
+        let limits = self.http_limits.clone();
+        BackgroundTask::spawn(interval, move || {
+            let key_store = key_store.as_ref().and_then(Weak::upgrade);
+            let tenants = tenants.as_ref().and_then(Weak::upgrade);
+            let circuit_breaker = circuit_breaker.clone();
+            let limits = limits.clone();
+            async move {
+                let key_stores = match (key_store, tenants) {
+                    (Some(key_store), _) => vec![key_store],
+                    (None, Some(tenants)) => tenants
+                        .key_stores()
+                        .into_iter()
+                        .map(|(_, store)| store)
+                        .collect(),
+                    (None, None) => return false,
+                };
+                for key_store in key_stores {
+                    let _ = guard_keys(
+                        circuit_breaker.as_deref(),
+                        &key_store,
+                        key_store.refresh_if_stale(&limits),
+                    )
+                    .await;
+                }
+                true
+            }
         })
     }
 
-    async fn fetch_discovery(uri: &str) -> Result<OIDCDiscoveryDocument, OIDCValidationError> {
-        let client = awc::Client::default();
-        client
-            .get(uri)
-            .send()
-            .await
-            .map(|mut res| {
-                res.json::<OIDCDiscoveryDocument>()
-                    .map_err(OIDCValidationError::FailedToParseJsonResponse)
-            })?
-            .await
+    /// Discovers the keys of the tenants known to the
+    /// [`TenantResolver::issuers`](crate::TenantResolver::issuers) in the background, so their
+    /// first requests do not wait for the discovery
+    ///
+    /// The tenants are discovered after the interval, a failed discovery is retried every
+    /// interval. The task ends by itself when every tenant is discovered or the last clone of
+    /// this Oidc is dropped.
+    pub fn spawn_tenant_prefetch(&self, interval: Duration) -> BackgroundTask {
+        let tenants = self.tenants.as_ref().map(Arc::downgrade);
+        let circuit_breaker = self.circuit_breaker.clone();
+        let limits = self.http_limits.clone();
+        BackgroundTask::spawn(interval, move || {
+            let tenants = tenants.as_ref().and_then(Weak::upgrade);
+            let circuit_breaker = circuit_breaker.clone();
+            let limits = limits.clone();
+            async move {
+                match tenants {
+                    Some(tenants) => tenants.prefetch(&limits, circuit_breaker.as_deref()).await,
+                    None => false,
+                }
+            }
+        })
+    }
+
+    /// Drops the DPoP nonces that are no longer accepted every interval in the background, see
+    /// [`NonceStore::cleanup`]
+    ///
+    /// The task ends by itself when the nonce store of [`Self::with_dpop_nonces`] is dropped.
+    pub fn spawn_nonce_cleanup(&self, interval: Duration) -> BackgroundTask {
+        let nonces = self.dpop_nonces.as_ref().map(Arc::downgrade);
+        BackgroundTask::spawn(interval, move || {
+            let nonces = nonces.as_ref().and_then(Weak::upgrade);
+            async move {
+                match nonces {
+                    Some(nonces) => {
+                        nonces.cleanup();
+                        true
+                    }
+                    None => false,
+                }
+            }
+        })
+    }
+
+    /// Reloads the keys of the issuer, failing over to the next key url when one is not available
+    ///
+    /// A JWKS file is read again, without the circuit breaker. Keys that were configured directly
+    /// are kept as they are, as well as the current keys when the circuit breaker is open and
+    /// accepts cached data.
+    ///
+    /// With tenants, the keys of every tenant seen so far are reloaded, a tenant of which the
+    /// identity provider is not available does not keep the others from being reloaded. The
+    /// validation cache is emptied, see [`Self::with_validation_cache`].
+    pub async fn refresh_keys(&self) -> Result<(), OIDCValidationError> {
+        let key_stores = match (&self.key_store, &self.tenants) {
+            (Some(key_store), _) => vec![key_store.clone()],
+            (None, Some(tenants)) => tenants
+                .key_stores()
+                .into_iter()
+                .map(|(_, store)| store)
+                .collect(),
+            (None, None) => vec![],
+        };
+        let mut result = Ok(());
+        for key_store in key_stores {
+            let circuit_breaker = self.circuit_breaker.as_deref();
+            let refresh = key_store.refresh(&self.http_limits);
+            if let Err(e) = guard_keys(circuit_breaker, &key_store, refresh).await {
+                result = Err(e);
+            }
+        }
+        self.clear_validation_cache();
+        result
+    }
+
+    /// Drops the cached validations after the keys are refreshed on purpose, the tokens of
+    /// custom validators have no known keys to check against
+    pub(crate) fn clear_validation_cache(&self) {
+        if let Some(cache) = &self.validation_cache {
+            cache.clear();
+        }
+    }
+
+    /// The issuers of the tenants of which the keys have been discovered so far
+    pub fn tenants(&self) -> Vec<String> {
+        self.tenants
+            .as_ref()
+            .map(|tenants| {
+                tenants
+                    .key_stores()
+                    .into_iter()
+                    .map(|(issuer, _)| issuer)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Replaces the keys used to validate tokens, e.g. keys configured as JSON after they were
+    /// rotated
+    ///
+    /// Has no effect when a custom validator is configured.
+    pub fn set_key_set(&self, jwks: JWKSet<Empty>) {
+        if let Some(key_store) = &self.key_store {
+            key_store.replace(jwks);
+        }
+        self.clear_validation_cache();
+    }
+
+    /// The keys currently used to validate tokens, `None` when a custom validator is configured
+    pub fn key_set(&self) -> Option<Arc<JWKSet<Empty>>> {
+        self.key_store.as_ref().map(|key_store| key_store.current())
+    }
+
+    /// Health of the endpoints the keys are loaded from
+    pub fn key_endpoints(&self) -> Vec<KeyEndpointHealth> {
+        self.key_store
+            .as_ref()
+            .map(|key_store| key_store.health())
+            .unwrap_or_default()
+    }
+
+    /// Validates the token found on the request and returns the decoded information
+    ///
+    /// This is the validation used by the extractors, use it when you write your own middleware
+    /// (e.g. rate limiting by subject) and need the validated claims.
+    pub async fn validate_request(
+        &self,
+        req: &HttpRequest,
+    ) -> Result<DecodedInfo, OIDCValidationError> {
+        let token = self.extract_token(req)?;
+        let tenant = self.tenant_issuer(req, &token)?;
+        let info = self
+            .validate_tenant_token(tenant.as_deref(), &token)
+            .await?;
+        self.check_request(req, &token, &info)?;
+        Ok(info)
+    }
+
+    /// Validates the token found on the request passed to a middleware
+    ///
+    /// See [`Oidc::validate_request`]
+    pub async fn validate_service_request(
+        &self,
+        req: &ServiceRequest,
+    ) -> Result<DecodedInfo, OIDCValidationError> {
+        self.validate_request(req.request()).await
+    }
+
+    /// Validates the given token (without the Bearer part)
+    ///
+    /// With tenants, tokens can only be validated with the request, see [`Oidc::validate_request`].
+    pub async fn validate_token(&self, token: &str) -> Result<DecodedInfo, OIDCValidationError> {
+        self.validate_tenant_token(None, token).await
+    }
+
+    /// The issuer of the tenant of the request, `None` when no tenants are configured
+    ///
+    /// Fails as unauthorized when the request belongs to no known tenant.
+    pub(crate) fn tenant_issuer(
+        &self,
+        req: &HttpRequest,
+        token: &str,
+    ) -> Result<Option<String>, OIDCValidationError> {
+        let Some(tenants) = &self.tenants else {
+            return Ok(None);
+        };
+        let claims = crate::unverified::peek_claims(&self.decrypt(token)?)
+            .ok_or(OIDCValidationError::Unauthorized)?;
+        match tenants.resolver.resolve(req, &claims) {
+            Some(issuer) => Ok(Some(issuer)),
+            None => Err(OIDCValidationError::UnknownTenant),
+        }
+    }
+
+    /// Validates the token with the keys of the tenant, when given
+    pub(crate) async fn validate_tenant_token(
+        &self,
+        tenant: Option<&str>,
+        token: &str,
+    ) -> Result<DecodedInfo, OIDCValidationError> {
+        let validation = trace::validation(
+            self.trace_subjects,
+            token,
+            self.check_tenant_token(tenant, token),
+        );
+        let Some(metrics) = &self.auth_metrics else {
+            return validation.await;
+        };
+        let started = Instant::now();
+        let validated = validation.await;
+        metrics.record_latency(started.elapsed());
+        validated
+    }
+
+    async fn check_tenant_token(
+        &self,
+        tenant: Option<&str>,
+        token: &str,
+    ) -> Result<DecodedInfo, OIDCValidationError> {
+        let info = match &self.validation_cache {
+            Some(cache) => match cache.get(tenant, token, self.current_keys(tenant).as_deref()) {
+                // the claims are cached without the token, it is taken from the request
+                Some(payload) => DecodedInfo {
+                    jwt: self.decrypt(token)?.into_owned(),
+                    payload,
+                },
+                None => {
+                    let info = self.verify_token(tenant, token).await?;
+                    cache.insert(tenant, token, &info);
+                    info
+                }
+            },
+            None => self.verify_token(tenant, token).await?,
+        };
+        if let Some(metrics) = &self.token_metrics {
+            metrics.record(&info.payload);
+        }
+        claim_rules::check_required(&self.required_claims, &info)?;
+        claim_rules::check(&self.claim_rules, &info)?;
+        claims_validator::check(&self.claims_validators, &info).await?;
+        if let Some(store) = &self.revocation_store {
+            revocation::check(store.as_ref(), &info).await?;
+        }
+        Ok(info)
+    }
+
+    /// The keys the tokens of the tenant are verified with, `None` for custom validators
+    fn current_keys(&self, tenant: Option<&str>) -> Option<Arc<JWKSet<Empty>>> {
+        match (tenant, &self.tenants, &self.key_store) {
+            (Some(issuer), Some(tenants), _) => tenants.current_keys(issuer),
+            (_, _, Some(key_store)) => Some(key_store.current()),
+            _ => None,
+        }
+    }
+
+    /// Decrypts the token and verifies its signature against the keys of its issuer
+    async fn verify_token(
+        &self,
+        tenant: Option<&str>,
+        token: &str,
+    ) -> Result<DecodedInfo, OIDCValidationError> {
+        let token = &*self.decrypt(token)?;
+        self.check_algorithm(token)?;
+        self.check_token_type(token)?;
+        if let Some(key_store) = &self.key_store {
+            // a failed refresh keeps the current keys, the token is validated against them
+            let refresh = key_store.refresh_for_unknown_kid(token, &self.http_limits);
+            let _ = guard_keys(self.circuit_breaker.as_deref(), key_store, refresh).await;
+        }
+        let info = match (tenant, &self.tenants) {
+            (Some(issuer), Some(tenants)) => {
+                tenants
+                    .validate(
+                        issuer,
+                        token,
+                        &self.http_limits,
+                        self.circuit_breaker.as_deref(),
+                    )
+                    .await?
+            }
+            _ => self.validator.validate(token).await?,
+        };
+        Ok(info)
+    }
+
+    /// Validates an ID token received by the login flow of the application, on top of the
+    /// validation of [`Oidc::validate_token`]
+    ///
+    /// The `aud`, `exp` (and `iss` when given) are validated, the `azp` must be the client when
+    /// present and is required with multiple audiences, and the `nonce` and `auth_time` are
+    /// checked against the authentication request.
+    pub async fn validate_id_token(
+        &self,
+        token: &str,
+        expected: &IdTokenValidation,
+    ) -> Result<DecodedInfo, OIDCValidationError> {
+        let info = self.validate_token(token).await?;
+        expected.check(&info)?;
+        Ok(info)
+    }
+
+    /// Validates many tokens concurrently against the shared keys, e.g. a batch of messages
+    /// that each carry their own token
+    ///
+    /// The results are in the order of the given tokens.
+    pub async fn validate_tokens<I>(
+        &self,
+        tokens: I,
+    ) -> Vec<Result<DecodedInfo, OIDCValidationError>>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        let validations = tokens
+            .into_iter()
+            .map(|token| async move { self.validate_token(token.as_ref()).await });
+        join_all(validations).await
+    }
+
+    /// Gets the token from the configured location on the request, or from the query parameter
+    /// when enabled
+    pub(crate) fn extract_token(&self, req: &HttpRequest) -> Result<String, OIDCValidationError> {
+        if let Some(extractor) = &self.token_extractor {
+            return extractor
+                .extract(req)
+                .filter(|token| !token.is_empty())
+                .ok_or(OIDCValidationError::TokenMissing);
+        }
+        let token = match &self.token_lookup {
+            TokenLookup::Header(name) if self.dpop.is_some() => {
+                token_extractor::header_token(req, name, Some("DPoP")).or_else(|_| {
+                    token_extractor::header_token(req, name, self.header_scheme.as_deref())
+                })
+            }
+            TokenLookup::Header(name) => {
+                token_extractor::header_token(req, name, self.header_scheme.as_deref())
+            }
+            TokenLookup::Cookie(name) => CookieToken::new(name.clone())
+                .extract(req)
+                .ok_or(OIDCValidationError::TokenMissing),
+        };
+        match (token, &self.query_parameter) {
+            (Err(OIDCValidationError::TokenMissing), Some(name)) => {
+                token_extractor::query_token(req, name).ok_or(OIDCValidationError::TokenMissing)
+            }
+            (token, _) => token,
+        }
+    }
+
+    /// Describes where the token is found, e.g. `header` or `cookie`
+    pub(crate) fn token_source(&self) -> &'static str {
+        match self.token_extractor {
+            Some(_) => "custom",
+            None => self.token_lookup.source(),
+        }
+    }
+
+    async fn fetch_discovery(
+        uri: &str,
+        limits: &HttpLimits,
+    ) -> Result<OIDCDiscoveryDocument, OIDCValidationError> {
+        http::get_json(http::client(limits).get(uri), limits).await
+    }
+
+    pub(crate) async fn fetch_jwks(
+        uri: &str,
+        etag: Option<&str>,
+        limits: &HttpLimits,
+    ) -> Result<http::Cacheable<JWKSet<Empty>>, OIDCValidationError> {
+        let fetch = http::get_cacheable_json(http::client(limits).get(uri), etag, limits);
+        let response: http::Cacheable<KeySetDocument> =
+            trace::call("jwks_fetch", uri, fetch).await?;
+        Ok(http::Cacheable {
+            body: response.body.map(|KeySetDocument(jwks)| jwks),
+            etag: response.etag,
+            max_age: response.max_age,
+        })
     }
+}
+
+/// Runs a reload of the keys through the circuit breaker, unless the keys are read from a file
+async fn guard_keys<F>(
+    circuit_breaker: Option<&CircuitBreaker>,
+    key_store: &KeyStore,
+    call: F,
+) -> Result<Option<()>, OIDCValidationError>
+where
+    F: std::future::Future<Output = Result<(), OIDCValidationError>>,
+{
+    guard(circuit_breaker.filter(|_| !key_store.reads_file()), call).await
+}
 
-    async fn fetch_jwks(uri: &str) -> Result<JWKSet<Empty>, OIDCValidationError> {
-        let client = awc::Client::default();
-        let req = client.get(uri);
-        let mut res = req.send().await?;
-        let jwk_set: JWKSet<Empty> = res.json::<JWKSet<Empty>>().await?;
-        Ok(jwk_set)
+/// Runs a call to the identity provider through the circuit breaker, when given
+///
+/// Returns `Ok(None)` when the circuit is open and the fallback policy accepts cached data.
+pub(crate) async fn guard<T, F>(
+    circuit_breaker: Option<&CircuitBreaker>,
+    call: F,
+) -> Result<Option<T>, OIDCValidationError>
+where
+    F: std::future::Future<Output = Result<T, OIDCValidationError>>,
+{
+    match circuit_breaker {
+        Some(circuit_breaker) => match circuit_breaker.call(call).await {
+            Err(OIDCValidationError::CircuitOpen)
+                if circuit_breaker.config.fallback == FallbackPolicy::AcceptCached =>
+            {
+                Ok(None)
+            }
+            result => result.map(Some),
+        },
+        None => call.await.map(Some),
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::tests::{
+        create_get_jwt_request, create_jwk_set, create_jwt_token, create_oidc, get_secret,
+        sign_token, start_test_idp,
+    };
+    use actix_web::{
+        body::MessageBody,
+        dev::ServiceResponse,
+        get,
+        http::{
+            header::{HeaderName, HeaderValue},
+            StatusCode,
+        },
+        middleware::{from_fn, Next},
+        test, App, Error,
+    };
+
+    /// A user written middleware exposing the subject as response header
+    async fn subject_header(
+        req: ServiceRequest,
+        next: Next<impl MessageBody>,
+    ) -> Result<ServiceResponse<impl MessageBody>, Error> {
+        let oidc = req.app_data::<Oidc>().unwrap().clone();
+        let decoded_info = oidc.validate_service_request(&req).await?;
+        let mut res = next.call(req).await?;
+        let subject = decoded_info.payload.registered.subject.unwrap_or_default();
+        res.headers_mut().insert(
+            HeaderName::from_static("x-subject"),
+            HeaderValue::from_str(&subject).unwrap(),
+        );
+        Ok(res)
+    }
+
+    #[get("/hello")]
+    async fn hello() -> &'static str {
+        "hello"
+    }
+
+    ///Test validation of the request from a user written middleware
+    #[actix_rt::test]
+    async fn test_validate_service_request_in_custom_middleware() -> Result<(), Error> {
+        let oidc = create_oidc().await;
+
+        let app = test::init_service(
+            App::new()
+                .app_data(oidc)
+                .wrap(from_fn(subject_header))
+                .service(hello),
+        )
+        .await;
+
+        let req = create_get_jwt_request("/hello", &create_jwt_token()).to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers().get("x-subject").unwrap(),
+            "CgVhZG1pbhIFbG9jYWw"
+        );
+
+        let req = test::TestRequest::get().uri("/hello").to_request();
+        let res = test::try_call_service(&app, req).await;
+        assert_eq!(
+            res.err().unwrap().as_response_error().status_code(),
+            StatusCode::UNAUTHORIZED
+        );
+        Ok(())
+    }
 
     #[actix_rt::test]
     async fn test_jwks_url() {
@@ -158,9 +1457,386 @@ mod tests {
         assert!(res.is_ok());
     }
 
+    #[actix_rt::test]
+    async fn test_key_urls_failover() {
+        let idp = start_test_idp().await;
+        let oidc = Oidc::new(OidcConfig::KeyUrls(vec![
+            "http://127.0.0.1:1/jwks".into(),
+            format!("{}/jwks", idp).into(),
+        ]))
+        .await
+        .unwrap();
+
+        let health = oidc.key_endpoints();
+        assert_eq!(health.len(), 2);
+        assert_eq!(health[0].consecutive_failures, 1);
+        assert!(oidc.refresh_keys().await.is_ok());
+        assert!(oidc.validate_token(&create_jwt_token()).await.is_ok());
+    }
+
+    fn create_oidc_with_unavailable_key_url(fallback: FallbackPolicy) -> Oidc {
+        let key_store = KeyStore::with_endpoints(
+            create_jwk_set(),
+            vec!["http://127.0.0.1:1/jwks".to_string()],
+        );
+        Oidc::new_for_key_store(
+            Arc::new(key_store),
+            TokenLookup::Header("authorization".into()),
+        )
+        .with_circuit_breaker(CircuitBreakerConfig {
+            failure_threshold: 1,
+            fallback,
+            ..CircuitBreakerConfig::default()
+        })
+    }
+
+    #[actix_rt::test]
+    async fn test_refresh_keys_circuit_breaker_accept_cached() {
+        let oidc = create_oidc_with_unavailable_key_url(FallbackPolicy::AcceptCached);
+
+        assert!(oidc.refresh_keys().await.is_err());
+        assert!(matches!(oidc.circuit_state(), Some(CircuitState::Open(_))));
+        // the cached keys stay in use while the circuit is open
+        assert!(oidc.refresh_keys().await.is_ok());
+        assert!(oidc.validate_token(&create_jwt_token()).await.is_ok());
+    }
+
+    #[actix_rt::test]
+    async fn test_refresh_keys_circuit_breaker_reject() {
+        let oidc = create_oidc_with_unavailable_key_url(FallbackPolicy::Reject);
+
+        assert!(oidc.refresh_keys().await.is_err());
+        assert!(matches!(
+            oidc.refresh_keys().await,
+            Err(OIDCValidationError::CircuitOpen)
+        ));
+    }
+
     #[actix_rt::test]
     async fn test_jwks_url_fail() {
         let res = Oidc::new(OidcConfig::Issuer("https://invalid.url".into())).await;
         assert!(res.is_err());
     }
+
+    ///Test that an issuer of which the openid-configuration names another issuer is rejected
+    #[actix_rt::test]
+    async fn test_issuer_discovery_mismatch() {
+        let idp = start_test_idp().await;
+        let res = Oidc::new(OidcConfig::Issuer(format!("{}/impostor", idp).into())).await;
+        assert!(matches!(
+            res,
+            Err(OIDCValidationError::DiscoveredIssuerMismatch(_))
+        ));
+    }
+
+    #[actix_rt::test]
+    async fn test_validate_tokens() {
+        let oidc = create_oidc().await;
+
+        let results = oidc
+            .validate_tokens([
+                create_jwt_token(),
+                "not-a-token".to_string(),
+                create_jwt_token(),
+            ])
+            .await;
+
+        assert_eq!(results.len(), 3);
+        assert!(results[0].is_ok());
+        assert!(results[1].is_err());
+        assert!(results[2].is_ok());
+    }
+
+    ///Test keys from a file that is read again on refresh, and keys from JSON
+    #[actix_rt::test]
+    async fn test_static_jwks() {
+        let path = std::env::temp_dir().join(format!("jwks-{}.json", std::process::id()));
+        let jwks = serde_json::to_string(&create_jwk_set()).unwrap();
+        std::fs::write(&path, &jwks).unwrap();
+
+        let oidc = Oidc::new(OidcConfig::JwksFile(path.clone())).await.unwrap();
+        assert!(oidc.validate_token(&create_jwt_token()).await.is_ok());
+        std::fs::write(&path, r#"{"keys": []}"#).unwrap();
+        oidc.refresh_keys().await.unwrap();
+        assert!(oidc.validate_token(&create_jwt_token()).await.is_err());
+        std::fs::remove_file(&path).unwrap();
+        assert!(oidc.refresh_keys().await.is_err());
+
+        // a missing file is no failure of the identity provider
+        std::fs::write(&path, &jwks).unwrap();
+        let oidc = Oidc::new(OidcConfig::JwksFile(path.clone()))
+            .await
+            .unwrap()
+            .with_circuit_breaker(CircuitBreakerConfig {
+                failure_threshold: 1,
+                ..CircuitBreakerConfig::default()
+            });
+        std::fs::remove_file(&path).unwrap();
+        assert!(oidc.refresh_keys().await.is_err());
+        assert!(oidc.refresh_keys().await.is_err());
+        assert!(matches!(
+            oidc.circuit_state(),
+            Some(CircuitState::Closed(_))
+        ));
+
+        let oidc = Oidc::new(OidcConfig::JwksJson(jwks.into())).await.unwrap();
+        assert!(oidc.validate_token(&create_jwt_token()).await.is_ok());
+        oidc.set_key_set(JWKSet { keys: vec![] });
+        assert!(oidc.validate_token(&create_jwt_token()).await.is_err());
+        assert!(Oidc::new(OidcConfig::JwksJson("{".into())).await.is_err());
+    }
+
+    ///Test that a token signed with an unknown key refreshes the keys
+    #[actix_rt::test]
+    async fn test_refresh_for_unknown_kid() {
+        let idp = start_test_idp().await;
+        let rotated =
+            KeyStore::with_endpoints(JWKSet { keys: vec![] }, vec![format!("{}/jwks", idp)]);
+        let oidc = Oidc::new_for_key_store(
+            Arc::new(rotated),
+            TokenLookup::Header("authorization".into()),
+        );
+        assert!(oidc.validate_token(&create_jwt_token()).await.is_ok());
+    }
+
+    ///Test that tokens with algorithms outside the allowlist are rejected before verification
+    #[actix_rt::test]
+    async fn test_allowed_algorithms() {
+        let oidc = create_oidc().await.with_allowed_algorithms([
+            jwa::SignatureAlgorithm::RS256,
+            jwa::SignatureAlgorithm::ES256,
+        ]);
+        assert!(oidc.validate_token(&create_jwt_token()).await.is_ok());
+
+        let unsigned = |algorithm, secret: &jws::Secret| {
+            let header = jws::RegisteredHeader {
+                algorithm,
+                ..Default::default()
+            };
+            sign_token(header, serde_json::json!({ "sub": "admin" }), secret)
+        };
+        for token in [
+            unsigned(jwa::SignatureAlgorithm::None, &jws::Secret::None),
+            unsigned(
+                jwa::SignatureAlgorithm::HS256,
+                &jws::Secret::Bytes(b"public key as secret".to_vec()),
+            ),
+        ] {
+            assert!(matches!(
+                oidc.validate_token(&token).await,
+                Err(OIDCValidationError::AlgorithmNotAllowed(_))
+            ));
+        }
+    }
+
+    ///Test ES256 and ES384 tokens signed with P-256 and P-384 keys of the JWKS
+    #[actix_rt::test]
+    async fn test_ec_keys() {
+        use ring::signature::{self, EcdsaKeyPair, KeyPair};
+
+        let rng = ring::rand::SystemRandom::new();
+        let mut keys = vec![];
+        let mut jwks = JWKSet { keys: vec![] };
+        for (kid, algorithm, signing, curve) in [
+            (
+                "p256",
+                jwa::SignatureAlgorithm::ES256,
+                &signature::ECDSA_P256_SHA256_FIXED_SIGNING,
+                jwk::EllipticCurve::P256,
+            ),
+            (
+                "p384",
+                jwa::SignatureAlgorithm::ES384,
+                &signature::ECDSA_P384_SHA384_FIXED_SIGNING,
+                jwk::EllipticCurve::P384,
+            ),
+        ] {
+            let pkcs8 = EcdsaKeyPair::generate_pkcs8(signing, &rng).unwrap();
+            let key_pair = EcdsaKeyPair::from_pkcs8(signing, pkcs8.as_ref(), &rng).unwrap();
+            // the uncompressed SEC1 point: 0x04 | x | y
+            let point = &key_pair.public_key().as_ref()[1..];
+            let (x, y) = point.split_at(point.len() / 2);
+            jwks.keys.push(jwk::JWK {
+                common: jwk::CommonParameters {
+                    key_id: Some(kid.to_string()),
+                    algorithm: Some(jwa::Algorithm::Signature(algorithm)),
+                    ..Default::default()
+                },
+                algorithm: jwk::AlgorithmParameters::EllipticCurve(
+                    jwk::EllipticCurveKeyParameters {
+                        curve,
+                        x: x.to_vec(),
+                        y: y.to_vec(),
+                        ..Default::default()
+                    },
+                ),
+                additional: Default::default(),
+            });
+            keys.push((
+                kid,
+                algorithm,
+                jws::Secret::EcdsaKeyPair(Arc::new(key_pair)),
+            ));
+        }
+        let oidc = Oidc::new(OidcConfig::Jwks(jwks)).await.unwrap();
+
+        let sign = |kid: &str, algorithm, secret: &jws::Secret| {
+            let header = jws::RegisteredHeader {
+                algorithm,
+                key_id: Some(kid.to_string()),
+                ..Default::default()
+            };
+            sign_token(header, serde_json::json!({ "sub": "admin" }), secret)
+        };
+        for (kid, algorithm, secret) in &keys {
+            let info = oidc
+                .validate_token(&sign(kid, *algorithm, secret))
+                .await
+                .unwrap();
+            assert_eq!(info.payload.registered.subject.as_deref(), Some("admin"));
+        }
+        // signed by the P-256 key while claiming the P-384 one
+        let (_, _, p256) = &keys[0];
+        let token = sign("p384", jwa::SignatureAlgorithm::ES384, p256);
+        assert!(oidc.validate_token(&token).await.is_err());
+    }
+
+    ///Test PS256 and PS384 tokens, the algorithm of the key takes precedence over the header
+    #[actix_rt::test]
+    async fn test_rsa_pss() {
+        let mut jwks = create_jwk_set();
+        let mut pss = jwks.keys[0].clone();
+        pss.common.key_id = Some("pss".to_string());
+        pss.common.algorithm = Some(jwa::Algorithm::Signature(jwa::SignatureAlgorithm::PS256));
+        let mut any = jwks.keys[0].clone();
+        any.common.key_id = Some("any".to_string());
+        any.common.algorithm = None;
+        jwks.keys.extend([pss, any]);
+        let oidc = Oidc::new(OidcConfig::Jwks(jwks)).await.unwrap();
+
+        let secret = get_secret();
+        let sign = |kid: &str, algorithm| {
+            let header = jws::RegisteredHeader {
+                algorithm,
+                key_id: Some(kid.to_string()),
+                ..Default::default()
+            };
+            sign_token(header, serde_json::json!({ "sub": "admin" }), &secret)
+        };
+        for (kid, algorithm) in [
+            ("pss", jwa::SignatureAlgorithm::PS256),
+            ("any", jwa::SignatureAlgorithm::PS384),
+        ] {
+            let token = sign(kid, algorithm);
+            assert!(oidc.validate_token(&token).await.is_ok(), "{:?}", algorithm);
+        }
+        for (kid, algorithm) in [
+            ("pss", jwa::SignatureAlgorithm::RS256),
+            ("2020-01-29", jwa::SignatureAlgorithm::PS256),
+        ] {
+            let token = sign(kid, algorithm);
+            assert!(
+                oidc.validate_token(&token).await.is_err(),
+                "{:?}",
+                algorithm
+            );
+        }
+    }
+
+    ///Test that the RFC 9068 profile rejects ID tokens and access tokens without its claims
+    #[actix_rt::test]
+    async fn test_access_token_profile() {
+        let oidc = create_oidc().await.with_access_token_profile();
+        let secret = get_secret();
+        let sign = |media_type: Option<&str>, claims: Value| {
+            let header = jws::RegisteredHeader {
+                algorithm: jwa::SignatureAlgorithm::RS256,
+                key_id: Some("2020-01-29".to_string()),
+                media_type: media_type.map(str::to_string),
+                ..Default::default()
+            };
+            sign_token(header, claims, &secret)
+        };
+        let claims = serde_json::json!({
+            "iss": "http://0.0.0.0:9090", "exp": 2000000000, "aud": "api", "sub": "admin",
+            "client_id": "app", "iat": 1700000000, "jti": "1",
+        });
+        assert!(oidc
+            .validate_token(&sign(Some("at+jwt"), claims.clone()))
+            .await
+            .is_ok());
+        assert!(matches!(
+            oidc.validate_token(&sign(Some("JWT"), claims.clone())).await,
+            Err(OIDCValidationError::InvalidTokenType(typ)) if typ == "JWT"
+        ));
+        let mut claims = claims;
+        claims.as_object_mut().unwrap().remove("client_id");
+        assert!(matches!(
+            oidc.validate_token(&sign(Some("application/at+jwt"), claims)).await,
+            Err(OIDCValidationError::MissingClaim(claim)) if claim == "/client_id"
+        ));
+    }
+
+    ///Test EdDSA tokens signed with an Ed25519 key of the JWKS
+    #[actix_rt::test]
+    async fn test_eddsa() {
+        use ring::signature::{Ed25519KeyPair, KeyPair};
+
+        let rng = ring::rand::SystemRandom::new();
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&rng).unwrap();
+        let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let okp = jwk::OctetKeyPairParameters {
+            curve: jwk::EllipticCurve::Curve25519,
+            x: key_pair.public_key().as_ref().to_vec(),
+            ..Default::default()
+        };
+        let mut key = serde_json::to_value(okp).unwrap();
+        key["kid"] = "ed".into();
+        key["alg"] = "EdDSA".into();
+        let jwks = serde_json::json!({ "keys": [key] }).to_string();
+        let oidc = Oidc::new(OidcConfig::JwksJson(jwks.into())).await.unwrap();
+
+        let sign = |kid: &str| {
+            let mut token = Compact::new();
+            let header = RawHeader {
+                alg: "EdDSA".to_string(),
+                kid: Some(kid.to_string()),
+                typ: None,
+            };
+            token.push(&header).unwrap();
+            let claims: ClaimsSet<Value> =
+                serde_json::from_value(serde_json::json!({ "sub": "admin" })).unwrap();
+            token.push(&claims).unwrap();
+            let signature = key_pair.sign(token.encode().as_bytes()).as_ref().to_vec();
+            token.push(&signature).unwrap();
+            token.encode()
+        };
+        let info = oidc.validate_token(&sign("ed")).await.unwrap();
+        assert_eq!(info.payload.registered.subject.as_deref(), Some("admin"));
+        assert!(oidc.validate_token(&sign("unknown")).await.is_err());
+        let tampered = sign("ed").replacen('.', ".e30", 1);
+        assert!(oidc.validate_token(&tampered).await.is_err());
+
+        let oidc = oidc.with_allowed_algorithms([jwa::SignatureAlgorithm::RS256]);
+        assert!(matches!(
+            oidc.validate_token(&sign("ed")).await,
+            Err(OIDCValidationError::AlgorithmNotAllowed(_))
+        ));
+        let oidc = oidc.with_allowed_eddsa();
+        assert!(oidc.validate_token(&sign("ed")).await.is_ok());
+    }
+
+    #[actix_rt::test]
+    async fn test_spawn_key_refresh_ends_with_oidc() {
+        let idp = start_test_idp().await;
+        let oidc = Oidc::new(OidcConfig::Issuer(idp.into())).await.unwrap();
+
+        let task = oidc.spawn_key_refresh(Duration::from_millis(20));
+        actix_web::rt::time::sleep(Duration::from_millis(60)).await;
+        assert!(task.is_running());
+
+        drop(oidc);
+        actix_web::rt::time::sleep(Duration::from_millis(100)).await;
+        assert!(!task.is_running());
+    }
 }
@@ -0,0 +1,248 @@
+//! OpenAPI documentation of the authentication with [utoipa](https://crates.io/crates/utoipa),
+//! available with the `utoipa` feature
+
+use std::{borrow::Cow, collections::BTreeMap};
+
+use utoipa::{
+    openapi::{
+        path::{Operation, PathItem},
+        security::{Http, HttpAuthScheme, OpenIdConnect, SecurityRequirement, SecurityScheme},
+        Components, OpenApi,
+    },
+    Modify,
+};
+
+use crate::{middleware::path_matches, OidcBiscuitValidator};
+
+/// Describes the authentication in the OpenAPI document: adds the security scheme, requires it
+/// on every operation and lists the scopes required per path
+///
+/// The scopes and public paths are taken from the middleware, see [`Self::with_validator`].
+/// Operations on the public paths are documented as reachable without a token.
+///
+/// ```ignore
+/// let security = OidcSecurity::new("oidc").with_validator(&biscuit_validator);
+/// let mut doc = ApiDoc::openapi();
+/// security.modify(&mut doc);
+/// ```
+#[derive(Debug, Clone)]
+pub struct OidcSecurity {
+    scheme_name: Cow<'static, str>,
+    openid_connect_url: Option<String>,
+    public_paths: Vec<Cow<'static, str>>,
+    required_scopes: Vec<Cow<'static, str>>,
+    route_scopes: Vec<(Cow<'static, str>, Vec<Cow<'static, str>>)>,
+    scopes: BTreeMap<String, Vec<String>>,
+}
+
+impl OidcSecurity {
+    /// Documents a bearer JWT security scheme with the given name
+    pub fn new(scheme_name: impl Into<Cow<'static, str>>) -> Self {
+        OidcSecurity {
+            scheme_name: scheme_name.into(),
+            openid_connect_url: None,
+            public_paths: Vec::new(),
+            required_scopes: Vec::new(),
+            route_scopes: Vec::new(),
+            scopes: BTreeMap::new(),
+        }
+    }
+
+    /// Documents an OpenID Connect security scheme with the discovery document of the issuer
+    /// instead, e.g. `https://idp.example.com/.well-known/openid-configuration`
+    pub fn with_openid_connect_url(mut self, url: impl Into<String>) -> Self {
+        self.openid_connect_url = Some(url.into());
+        self
+    }
+
+    /// Documents the requirements of the middleware: its public paths as reachable without a
+    /// token, and on the other paths its required scopes followed by the ones of the first
+    /// [`RouteRequirements`](crate::RouteRequirements) covering the path
+    pub fn with_validator(mut self, validator: &OidcBiscuitValidator) -> Self {
+        self.public_paths
+            .extend(validator.public_paths.iter().cloned());
+        self.required_scopes
+            .extend(validator.required_scopes.iter().cloned());
+        self.route_scopes.extend(
+            validator
+                .route_requirements
+                .iter()
+                .map(|(path, route)| (path.clone(), route.required_scopes.clone())),
+        );
+        self
+    }
+
+    /// Documents scopes that the handlers of the path check themselves, e.g. with
+    /// [`protect`](crate::protect), on top of the ones of the middleware
+    pub fn require_scopes<I: Into<String>>(
+        mut self,
+        path: impl Into<String>,
+        scopes: impl IntoIterator<Item = I>,
+    ) -> Self {
+        self.scopes
+            .entry(path.into())
+            .or_default()
+            .extend(scopes.into_iter().map(Into::into));
+        self
+    }
+
+    fn security_scheme(&self) -> SecurityScheme {
+        match &self.openid_connect_url {
+            Some(url) => SecurityScheme::OpenIdConnect(OpenIdConnect::new(url)),
+            None => {
+                let mut http = Http::new(HttpAuthScheme::Bearer);
+                http.bearer_format = Some("JWT".into());
+                SecurityScheme::Http(http)
+            }
+        }
+    }
+
+    fn requirement(&self, path: &str) -> SecurityRequirement {
+        if self
+            .public_paths
+            .iter()
+            .any(|public| path_matches(public, path))
+        {
+            // An empty requirement documents the anonymous access
+            return SecurityRequirement::default();
+        }
+        let route = self
+            .route_scopes
+            .iter()
+            .find(|(entry, _)| path_matches(entry, path))
+            .map(|(_, scopes)| scopes.as_slice())
+            .unwrap_or_default();
+        let middleware = self.required_scopes.iter().chain(route).map(|s| s.as_ref());
+        let handler = self
+            .scopes
+            .get(path)
+            .into_iter()
+            .flatten()
+            .map(String::as_str);
+        let mut scopes: Vec<String> = Vec::new();
+        for scope in middleware.chain(handler) {
+            if !scopes.iter().any(|s| s == scope) {
+                scopes.push(scope.to_string());
+            }
+        }
+        SecurityRequirement::new(self.scheme_name.to_string(), scopes)
+    }
+}
+
+/// The operations of the path item, for all HTTP methods
+fn operations_mut(item: &mut PathItem) -> impl Iterator<Item = &mut Operation> {
+    [
+        &mut item.get,
+        &mut item.put,
+        &mut item.post,
+        &mut item.delete,
+        &mut item.options,
+        &mut item.head,
+        &mut item.patch,
+        &mut item.trace,
+        &mut item.query,
+    ]
+    .into_iter()
+    .flatten()
+    .chain(item.additional_operations.values_mut())
+}
+
+impl Modify for OidcSecurity {
+    fn modify(&self, openapi: &mut OpenApi) {
+        openapi
+            .components
+            .get_or_insert_with(Components::new)
+            .add_security_scheme(self.scheme_name.to_string(), self.security_scheme());
+        openapi.security = Some(vec![SecurityRequirement::new(
+            self.scheme_name.to_string(),
+            Vec::<String>::new(),
+        )]);
+        for (path, item) in openapi.paths.paths.iter_mut() {
+            let requirement = self.requirement(path);
+            for operation in operations_mut(item) {
+                operation.security = Some(vec![requirement.clone()]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use utoipa::{
+        openapi::{
+            path::{HttpMethod, Operation, PathItem},
+            Info, OpenApi, OpenApiBuilder, PathsBuilder,
+        },
+        Modify,
+    };
+
+    use super::OidcSecurity;
+    use crate::{OidcBiscuitValidator, RouteRequirements};
+
+    fn document() -> OpenApi {
+        let paths = PathsBuilder::new()
+            .path("/reports", PathItem::new(HttpMethod::Get, Operation::new()))
+            .path(
+                "/items/{id}",
+                PathItem::new(HttpMethod::Post, Operation::new()),
+            )
+            .path("/healthz", PathItem::new(HttpMethod::Get, Operation::new()))
+            .build();
+        OpenApiBuilder::new()
+            .info(Info::new("api", "1.0.0"))
+            .paths(paths)
+            .build()
+    }
+
+    ///Test for documenting the bearer scheme with the scopes and public paths of the middleware
+    #[test]
+    fn test_bearer_security() {
+        let validator = OidcBiscuitValidator::default()
+            .public_infrastructure_paths()
+            .require_scope("api")
+            .with_route_requirements(
+                "/reports",
+                RouteRequirements::new().require_scope("reports.read"),
+            );
+        let security = OidcSecurity::new("oidc")
+            .with_validator(&validator)
+            .require_scopes("/items/{id}", ["items.write", "api"]);
+        let mut doc = document();
+        security.modify(&mut doc);
+
+        let doc: Value = serde_json::to_value(&doc).unwrap();
+        assert_eq!(
+            doc["components"]["securitySchemes"]["oidc"],
+            json!({ "type": "http", "scheme": "bearer", "bearerFormat": "JWT" })
+        );
+        assert_eq!(doc["security"], json!([{ "oidc": [] }]));
+        assert_eq!(
+            doc["paths"]["/reports"]["get"]["security"],
+            json!([{ "oidc": ["api", "reports.read"] }])
+        );
+        assert_eq!(
+            doc["paths"]["/items/{id}"]["post"]["security"],
+            json!([{ "oidc": ["api", "items.write"] }])
+        );
+        assert_eq!(doc["paths"]["/healthz"]["get"]["security"], json!([{}]));
+    }
+
+    ///Test for documenting the OpenID Connect scheme
+    #[test]
+    fn test_openid_connect_security() {
+        let security = OidcSecurity::new("oidc")
+            .with_openid_connect_url("http://0.0.0.0:9090/.well-known/openid-configuration");
+        let mut doc = document();
+        security.modify(&mut doc);
+
+        let doc: Value = serde_json::to_value(&doc).unwrap();
+        assert_eq!(
+            doc["components"]["securitySchemes"]["oidc"],
+            json!({
+                "type": "openIdConnect",
+                "openIdConnectUrl": "http://0.0.0.0:9090/.well-known/openid-configuration"
+            })
+        );
+    }
+}
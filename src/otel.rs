@@ -0,0 +1,218 @@
+use actix_web::{http::header::HeaderMap, HttpRequest};
+use opentelemetry::{
+    global,
+    propagation::{Extractor, TextMapPropagator},
+    trace::{TraceContextExt, Tracer},
+    Context, KeyValue,
+};
+
+use crate::{permissions::token_scopes, DecodedInfo, OIDCValidationError, SubjectRedaction};
+
+/// Reads the propagated trace context (e.g. `traceparent`) from the request headers
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|name| name.as_str()).collect()
+    }
+}
+
+/// The context the token of the request is validated in: the current one when a span is active,
+/// else an `authenticate` span of the global tracer, child of the trace context propagated by
+/// the caller
+///
+/// The propagated context itself is remote, the outcome of the validation cannot be recorded on
+/// it.
+pub(crate) fn validation_context(req: &HttpRequest) -> Context {
+    let current = Context::current();
+    if current.has_active_span() {
+        return current;
+    }
+    let parent =
+        global::get_text_map_propagator(|propagator| caller_context(propagator, &current, req));
+    let span = global::tracer(env!("CARGO_PKG_NAME")).start_with_context("authenticate", &parent);
+    parent.with_span(span)
+}
+
+/// The trace context propagated by the caller
+fn caller_context(
+    propagator: &dyn TextMapPropagator,
+    current: &Context,
+    req: &HttpRequest,
+) -> Context {
+    propagator.extract_with_context(current, &HeaderExtractor(req.headers()))
+}
+
+/// Records the outcome of the validation on the active span, following the OpenTelemetry
+/// semantic conventions: `enduser.id` and `enduser.scope` of a validated token, an
+/// `authentication failed` event with the `error.type` of a rejected token
+pub(crate) fn record(
+    validated: &Result<DecodedInfo, OIDCValidationError>,
+    redaction: SubjectRedaction,
+) {
+    let context = Context::current();
+    let span = context.span();
+    match validated {
+        Ok(info) => {
+            let subject = info.payload.registered.subject.as_deref();
+            if let Some(id) = subject.and_then(|subject| redaction.redact(subject)) {
+                span.set_attribute(KeyValue::new("enduser.id", id));
+            }
+            let scopes = token_scopes(info);
+            if !scopes.is_empty() {
+                span.set_attribute(KeyValue::new("enduser.scope", scopes.join(" ")));
+            }
+        }
+        Err(e) => span.add_event(
+            "authentication failed",
+            vec![KeyValue::new("error.type", e.code().as_str())],
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        borrow::Cow,
+        sync::{Arc, Mutex},
+        time::SystemTime,
+    };
+
+    use actix_web::test;
+    use opentelemetry::{
+        propagation::{text_map_propagator::FieldIter, Extractor, Injector, TextMapPropagator},
+        trace::{
+            Span, SpanContext, SpanId, Status, TraceContextExt, TraceFlags, TraceId, TraceState,
+        },
+        Context, KeyValue,
+    };
+    use serde_json::json;
+
+    use super::{caller_context, record, HeaderExtractor};
+    use crate::{
+        tests::{create_oidc, create_token},
+        OIDCValidationError, SubjectRedaction,
+    };
+
+    /// Extracts the `traceparent` header of the W3C trace context
+    #[derive(Debug)]
+    struct TraceParent;
+
+    impl TextMapPropagator for TraceParent {
+        fn inject_context(&self, _cx: &Context, _injector: &mut dyn Injector) {}
+
+        fn extract_with_context(&self, cx: &Context, extractor: &dyn Extractor) -> Context {
+            let Some(parent) = extractor.get("traceparent") else {
+                return cx.clone();
+            };
+            let parts: Vec<&str> = parent.split('-').collect();
+            let span_context = SpanContext::new(
+                TraceId::from_hex(parts[1]).unwrap(),
+                SpanId::from_hex(parts[2]).unwrap(),
+                TraceFlags::SAMPLED,
+                true,
+                TraceState::default(),
+            );
+            cx.with_remote_span_context(span_context)
+        }
+
+        fn fields(&self) -> FieldIter<'_> {
+            FieldIter::new(&[])
+        }
+    }
+
+    /// A span that keeps its attributes and events, as `name` or `event: attribute`
+    struct RecordingSpan {
+        span_context: SpanContext,
+        recorded: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Span for RecordingSpan {
+        fn add_event_with_timestamp<T>(&mut self, name: T, _: SystemTime, attributes: Vec<KeyValue>)
+        where
+            T: Into<Cow<'static, str>>,
+        {
+            let name = name.into();
+            for attribute in attributes {
+                let event = format!("{}: {}={}", name, attribute.key, attribute.value);
+                self.recorded.lock().unwrap().push(event);
+            }
+        }
+
+        fn span_context(&self) -> &SpanContext {
+            &self.span_context
+        }
+
+        fn is_recording(&self) -> bool {
+            true
+        }
+
+        fn set_attribute(&mut self, attribute: KeyValue) {
+            let attribute = format!("{}={}", attribute.key, attribute.value);
+            self.recorded.lock().unwrap().push(attribute);
+        }
+
+        fn set_status(&mut self, _status: Status) {}
+
+        fn update_name<T>(&mut self, _new_name: T)
+        where
+            T: Into<Cow<'static, str>>,
+        {
+        }
+
+        fn add_link(&mut self, _span_context: SpanContext, _attributes: Vec<KeyValue>) {}
+
+        fn end_with_timestamp(&mut self, _timestamp: SystemTime) {}
+    }
+
+    ///Test that the trace context of the caller is extracted from the headers
+    #[actix_rt::test]
+    async fn test_caller_context() {
+        let req = test::TestRequest::get()
+            .insert_header((
+                "traceparent",
+                "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            ))
+            .to_http_request();
+        assert_eq!(HeaderExtractor(req.headers()).keys(), vec!["traceparent"]);
+
+        let context = caller_context(&TraceParent, &Context::new(), &req);
+        assert_eq!(
+            context.span().span_context().trace_id(),
+            TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap()
+        );
+        assert!(context.span().span_context().is_remote());
+    }
+
+    ///Test that the outcome of the validation is recorded on the active span
+    #[actix_rt::test]
+    async fn test_record() {
+        let recorded = Arc::new(Mutex::new(vec![]));
+        let span = RecordingSpan {
+            span_context: SpanContext::empty_context(),
+            recorded: recorded.clone(),
+        };
+        let _active = Context::new().with_span(span).attach();
+
+        let oidc = create_oidc().await;
+        let token = create_token(json!({ "sub": "alice", "scope": "read write" }));
+        let validated = oidc.validate_token(&token).await;
+        record(&validated, SubjectRedaction::Plain);
+        record(
+            &Err(OIDCValidationError::TokenMissing),
+            SubjectRedaction::Plain,
+        );
+        assert_eq!(
+            *recorded.lock().unwrap(),
+            [
+                "enduser.id=alice",
+                "enduser.scope=read write",
+                "authentication failed: error.type=token_missing",
+            ]
+        );
+    }
+}
@@ -0,0 +1,207 @@
+use biscuit::ClaimsSet;
+use futures_util::future::{ready, FutureExt, LocalBoxFuture};
+use pasetors::{
+    claims::ClaimsValidationRules,
+    footer::Footer,
+    keys::{AsymmetricPublicKey, SymmetricKey},
+    local, public,
+    token::{TrustedToken, UntrustedToken},
+    version4::V4,
+    Local, Public,
+};
+use serde_json::{Map, Value};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use crate::{validator::TokenValidator, DecodedInfo, OIDCValidationError};
+
+/// Key of the PASETO v4 tokens, which also selects the purpose of the tokens that are accepted
+#[derive(Clone)]
+pub enum PasetoKey {
+    /// Verifies the signature of `v4.public.` tokens
+    Public(AsymmetricPublicKey<V4>),
+    /// Decrypts `v4.local.` tokens
+    Local(SymmetricKey<V4>),
+}
+
+/// Validates PASETO v4 tokens instead of JWTs, the extractors and middleware stay the same
+///
+/// The PASETO time claims (RFC 3339 strings) are converted to the numeric dates of the JWT
+/// claims, so `payload.registered.expiry` and the biscuit validation options work as usual.
+#[derive(Clone)]
+pub struct PasetoValidator {
+    key: PasetoKey,
+    footer: Option<Footer>,
+    implicit_assertion: Option<Vec<u8>>,
+    rules: ClaimsValidationRules,
+}
+
+impl PasetoValidator {
+    /// Accepts `v4.public.` tokens signed by the key
+    pub fn public(key: AsymmetricPublicKey<V4>) -> Self {
+        Self::new(PasetoKey::Public(key))
+    }
+
+    /// Accepts `v4.local.` tokens encrypted with the key
+    pub fn local(key: SymmetricKey<V4>) -> Self {
+        Self::new(PasetoKey::Local(key))
+    }
+
+    fn new(key: PasetoKey) -> Self {
+        PasetoValidator {
+            key,
+            footer: None,
+            implicit_assertion: None,
+            rules: ClaimsValidationRules::new(),
+        }
+    }
+
+    /// Requires the footer of the tokens to be equal to the given footer
+    ///
+    /// Without it, the footer of a token is validated but not compared.
+    pub fn with_footer(mut self, footer: Footer) -> Self {
+        self.footer = Some(footer);
+        self
+    }
+
+    /// Sets the implicit assertion the tokens are created with
+    pub fn with_implicit_assertion(mut self, implicit_assertion: impl Into<Vec<u8>>) -> Self {
+        self.implicit_assertion = Some(implicit_assertion.into());
+        self
+    }
+
+    /// Replaces the claim validation rules, by default `exp`, `nbf` and `iat` are validated
+    pub fn with_rules(mut self, rules: ClaimsValidationRules) -> Self {
+        self.rules = rules;
+        self
+    }
+
+    fn verify(&self, token: &str) -> Result<TrustedToken, pasetors::errors::Error> {
+        let footer = self.footer.as_ref();
+        let implicit_assertion = self.implicit_assertion.as_deref();
+        match &self.key {
+            PasetoKey::Public(key) => {
+                let token = UntrustedToken::<Public, V4>::try_from(token)?;
+                public::verify(key, &token, &self.rules, footer, implicit_assertion)
+            }
+            PasetoKey::Local(key) => {
+                let token = UntrustedToken::<Local, V4>::try_from(token)?;
+                local::decrypt(key, &token, &self.rules, footer, implicit_assertion)
+            }
+        }
+    }
+}
+
+/// Converts the PASETO claims to a JWT claims set
+fn claims_set(token: &TrustedToken) -> Result<ClaimsSet<Value>, OIDCValidationError> {
+    let mut claims: Map<String, Value> =
+        serde_json::from_str(token.payload()).map_err(|_| OIDCValidationError::Unauthorized)?;
+    for claim in ["exp", "nbf", "iat"] {
+        if let Some(Value::String(date)) = claims.get(claim) {
+            let timestamp = OffsetDateTime::parse(date, &Rfc3339)
+                .map_err(|_| OIDCValidationError::Unauthorized)?
+                .unix_timestamp();
+            claims.insert(claim.to_string(), timestamp.into());
+        }
+    }
+    serde_json::from_value(Value::Object(claims)).map_err(|_| OIDCValidationError::Unauthorized)
+}
+
+impl TokenValidator for PasetoValidator {
+    fn validate<'a>(
+        &'a self,
+        token: &'a str,
+    ) -> LocalBoxFuture<'a, Result<DecodedInfo, OIDCValidationError>> {
+        let decoded = self
+            .verify(token)
+            .map_err(|_| OIDCValidationError::Unauthorized)
+            .and_then(|trusted| claims_set(&trusted))
+            .map(|payload| DecodedInfo {
+                jwt: token.to_string(),
+                payload,
+            });
+        ready(decoded).boxed_local()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use actix_web::{get, http::StatusCode, test, App, Error};
+    use bytes::Bytes;
+    use pasetors::{
+        claims::Claims,
+        footer::Footer,
+        keys::{AsymmetricKeyPair, Generate, SymmetricKey},
+        local, public,
+        version4::V4,
+    };
+
+    use super::PasetoValidator;
+    use crate::{tests::create_get_jwt_request, DecodedInfo, Oidc, OidcConfig};
+
+    #[get("/decoder")]
+    async fn decoder(info: DecodedInfo) -> String {
+        format!(
+            "{} {}",
+            info.payload.registered.subject.unwrap_or_default(),
+            info.payload.registered.expiry.is_some()
+        )
+    }
+
+    fn create_claims() -> Claims {
+        let mut claims = Claims::new().unwrap();
+        claims.subject("service-a").unwrap();
+        claims
+    }
+
+    async fn oidc_for(validator: PasetoValidator) -> Oidc {
+        Oidc::new(OidcConfig::Validator(Arc::new(validator)))
+            .await
+            .unwrap()
+    }
+
+    ///Test for a public token through the extractor
+    #[actix_rt::test]
+    async fn test_paseto_public() -> Result<(), Error> {
+        let key_pair = AsymmetricKeyPair::<V4>::generate().unwrap();
+        let mut footer = Footer::new();
+        footer.add_additional("tenant", "acme").unwrap();
+        let token = public::sign(&key_pair.secret, &create_claims(), Some(&footer), None).unwrap();
+
+        let oidc = oidc_for(PasetoValidator::public(key_pair.public).with_footer(footer)).await;
+        let app = test::init_service(App::new().app_data(oidc.clone()).service(decoder)).await;
+
+        let req = create_get_jwt_request("/decoder", &token).to_request();
+        let result: Bytes = test::call_and_read_body(&app, req).await;
+
+        assert_eq!(result, Bytes::from_static(b"service-a true"));
+        Ok(())
+    }
+
+    ///Test that a local token is rejected by a public key and a wrong footer is rejected
+    #[actix_rt::test]
+    async fn test_paseto_purpose_and_footer() -> Result<(), Error> {
+        let key_pair = AsymmetricKeyPair::<V4>::generate().unwrap();
+        let symmetric = SymmetricKey::<V4>::generate().unwrap();
+        let mut footer = Footer::new();
+        footer.add_additional("tenant", "other").unwrap();
+        let mut expected = Footer::new();
+        expected.add_additional("tenant", "acme").unwrap();
+
+        let oidc = oidc_for(PasetoValidator::public(key_pair.public.clone())).await;
+        let local_token = local::encrypt(&symmetric, &create_claims(), None, None).unwrap();
+        assert!(oidc.validate_token(&local_token).await.is_err());
+
+        let oidc = oidc_for(PasetoValidator::local(symmetric)).await;
+        assert!(oidc.validate_token(&local_token).await.is_ok());
+
+        let oidc = oidc_for(PasetoValidator::public(key_pair.public).with_footer(expected)).await;
+        let app = test::init_service(App::new().app_data(oidc.clone()).service(decoder)).await;
+        let token = public::sign(&key_pair.secret, &create_claims(), Some(&footer), None).unwrap();
+        let req = create_get_jwt_request("/decoder", &token).to_request();
+        let response = test::call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        Ok(())
+    }
+}
@@ -0,0 +1,154 @@
+use std::collections::{BTreeSet, HashMap};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::DecodedInfo;
+
+/// Maps the scopes issued by the identity provider to the permission names used by the application
+///
+/// The mapping is many-to-many: a scope can grant several permissions and several scopes can
+/// grant the same permission. Scopes without a mapping are passed on as they are. The map
+/// deserializes from a table like `{ "api.read": ["read"], "api.admin": ["read", "write"] }`,
+/// so renaming a scope at the identity provider only changes configuration.
+#[derive(Debug, PartialEq, Eq, Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct PermissionMap {
+    mapping: HashMap<String, BTreeSet<String>>,
+}
+
+impl PermissionMap {
+    /// Creates an empty map, all scopes are passed on as they are
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Grants the permission to tokens with the given scope
+    pub fn map(mut self, scope: impl Into<String>, permission: impl Into<String>) -> Self {
+        self.mapping
+            .entry(scope.into())
+            .or_default()
+            .insert(permission.into());
+        self
+    }
+
+    /// Returns the permissions granted by the given scopes
+    pub fn permissions<'a>(&self, scopes: impl IntoIterator<Item = &'a str>) -> BTreeSet<String> {
+        scopes
+            .into_iter()
+            .flat_map(|scope| match self.mapping.get(scope) {
+                Some(permissions) => permissions.iter().cloned().collect::<Vec<_>>(),
+                None => vec![scope.to_string()],
+            })
+            .collect()
+    }
+}
+
+/// Reads the scopes of the token from the space separated `scope` claim or the `scp` array claim
+pub(crate) fn token_scopes(info: &DecodedInfo) -> Vec<String> {
+    match info
+        .payload
+        .private
+        .get("scope")
+        .or_else(|| info.payload.private.get("scp"))
+    {
+        Some(Value::String(scopes)) => scopes.split_whitespace().map(str::to_string).collect(),
+        Some(Value::Array(scopes)) => scopes
+            .iter()
+            .filter_map(Value::as_str)
+            .map(str::to_string)
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Claims holding the roles when no other claim is configured: `roles` and the Keycloak
+/// `realm_access.roles`
+pub(crate) const DEFAULT_ROLE_CLAIMS: [&str; 2] = ["/roles", "/realm_access/roles"];
+
+/// Reads the roles of the token from the array (or single string) claims at the JSON pointers
+pub(crate) fn token_roles<'a>(
+    info: &DecodedInfo,
+    pointers: impl IntoIterator<Item = &'a str>,
+) -> BTreeSet<String> {
+    pointers
+        .into_iter()
+        .filter_map(|pointer| info.claim_value(pointer))
+        .flat_map(|roles| match roles {
+            Value::String(role) => vec![role],
+            Value::Array(roles) => roles
+                .iter()
+                .filter_map(Value::as_str)
+                .map(str::to_string)
+                .collect(),
+            _ => Vec::new(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{token_scopes, PermissionMap};
+    use crate::{tests::create_oidc, DecodedInfo};
+    use serde_json::json;
+
+    #[test]
+    fn test_many_to_many_mapping() {
+        let map = PermissionMap::new()
+            .map("api.read", "read")
+            .map("api.admin", "read")
+            .map("api.admin", "write");
+
+        let permissions = map.permissions(["api.admin", "profile"]);
+
+        assert_eq!(
+            permissions.into_iter().collect::<Vec<_>>(),
+            vec!["profile", "read", "write"]
+        );
+        assert_eq!(map.permissions(["api.read"]).len(), 1);
+    }
+
+    #[test]
+    fn test_mapping_from_config() {
+        let map: PermissionMap =
+            serde_json::from_value(json!({ "api.read": ["read"], "api.admin": ["read", "write"] }))
+                .unwrap();
+
+        assert_eq!(
+            map,
+            PermissionMap::new()
+                .map("api.read", "read")
+                .map("api.admin", "read")
+                .map("api.admin", "write")
+        );
+    }
+
+    #[test]
+    fn test_token_scopes() {
+        let info = DecodedInfo {
+            jwt: String::new(),
+            payload: serde_json::from_value(json!({ "scope": "openid api.read" })).unwrap(),
+        };
+        assert_eq!(token_scopes(&info), vec!["openid", "api.read"]);
+
+        let info = DecodedInfo {
+            jwt: String::new(),
+            payload: serde_json::from_value(json!({ "scp": ["api.read"] })).unwrap(),
+        };
+        assert_eq!(token_scopes(&info), vec!["api.read"]);
+    }
+
+    #[actix_rt::test]
+    async fn test_oidc_permissions() {
+        let oidc = create_oidc()
+            .await
+            .with_permission_map(PermissionMap::new().map("api.admin", "write"));
+        let info = DecodedInfo {
+            jwt: String::new(),
+            payload: serde_json::from_value(json!({ "scope": "api.admin" })).unwrap(),
+        };
+
+        assert!(oidc.permissions(&info).contains("write"));
+        assert!(!oidc.permissions(&info).contains("api.admin"));
+    }
+}
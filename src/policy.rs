@@ -0,0 +1,189 @@
+use std::time::Duration;
+
+use biscuit::{
+    errors::ValidationError, jwa::SignatureAlgorithm, SingleOrMultiple, TemporalOptions,
+    Validation, ValidationOptions,
+};
+
+use crate::{dangerous_peek_claims, DecodedInfo, OIDCValidationError, ValidationReport};
+
+/// Restrictions for the tokens of an issuer, evaluated after the issuer is resolved and the
+/// signature is verified
+///
+/// In multi-issuer setups, e.g. a partner identity provider is limited to RS256 with a strict audience:
+///
+/// ```ignore
+/// let policy = IssuerPolicy::default()
+///     .allow_algorithm(SignatureAlgorithm::RS256)
+///     .audience("orders-api");
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct IssuerPolicy {
+    /// Accepted signature algorithms, all algorithms of the keys when empty
+    pub algorithms: Vec<SignatureAlgorithm>,
+    /// Accepted audiences, the token must be issued for one of them, not checked when empty
+    pub audiences: Vec<String>,
+    /// Leeway of the `exp` and `nbf` checks, not checked by the policy when `None`
+    ///
+    /// The checks of the policy come on top of those of the middleware, so a leeway longer than
+    /// the one of the middleware does not accept tokens the middleware rejects.
+    pub leeway: Option<Duration>,
+}
+
+impl IssuerPolicy {
+    /// Accepts tokens signed with the algorithm
+    pub fn allow_algorithm(mut self, algorithm: SignatureAlgorithm) -> Self {
+        self.algorithms.push(algorithm);
+        self
+    }
+
+    /// Accepts tokens issued for the audience
+    pub fn audience(mut self, audience: impl Into<String>) -> Self {
+        self.audiences.push(audience.into());
+        self
+    }
+
+    /// Checks the expiry and not before time with the leeway for clock drift
+    ///
+    /// The checks come on top of those of the middleware, which keeps its own leeway: behind the
+    /// middleware, a leeway longer than the one of the middleware accepts no more tokens.
+    pub fn with_leeway(mut self, leeway: Duration) -> Self {
+        self.leeway = Some(leeway);
+        self
+    }
+
+    /// Rejects the token when it doesn't meet the policy
+    ///
+    /// A disallowed algorithm is unauthorized, the claim checks fail with a report of all failures.
+    pub(crate) fn check(&self, info: &DecodedInfo) -> Result<(), OIDCValidationError> {
+        if !self.algorithms.is_empty() {
+            let algorithm = dangerous_peek_claims(&info.jwt)?
+                .header
+                .registered
+                .algorithm;
+            if !self.algorithms.contains(&algorithm) {
+                return Err(OIDCValidationError::Unauthorized);
+            }
+        }
+
+        let mut report = match self.leeway {
+            Some(leeway) => ValidationReport::collect(
+                &info.payload.registered,
+                &ValidationOptions {
+                    expiry: Validation::Validate(()),
+                    not_before: Validation::Validate(()),
+                    temporal_options: TemporalOptions {
+                        epsilon: chrono::Duration::from_std(leeway)
+                            .unwrap_or(chrono::Duration::MAX),
+                        now: None,
+                    },
+                    ..ValidationOptions::default()
+                },
+            ),
+            None => ValidationReport::default(),
+        };
+        if !self.audiences.is_empty() {
+            match &info.payload.registered.audience {
+                Some(audience) if self.accepts_audience(audience) => {}
+                Some(audience) => report.push(ValidationError::InvalidAudience(audience.clone())),
+                None => report.push(ValidationError::MissingRequiredClaims(vec!["aud".into()])),
+            }
+        }
+        if report.is_valid() {
+            Ok(())
+        } else {
+            Err(OIDCValidationError::ClaimsRejected(report))
+        }
+    }
+
+    fn accepts_audience(&self, audience: &SingleOrMultiple<String>) -> bool {
+        self.audiences
+            .iter()
+            .any(|accepted| audience.contains(accepted))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use actix_web::{dev::Service, get, http::StatusCode, test, App, Error};
+    use biscuit::jwa::SignatureAlgorithm;
+    use serde_json::json;
+
+    use super::IssuerPolicy;
+    use crate::{
+        tests::{create_get_jwt_request, create_token, start_test_idp},
+        DecodedInfo, MultiIssuerValidator, Oidc, OidcConfig,
+    };
+
+    #[get("/decoder")]
+    async fn decoder(claims: DecodedInfo) -> String {
+        claims.payload.registered.issuer.unwrap_or_default()
+    }
+
+    ///Test that each issuer is held to its own policy
+    #[actix_rt::test]
+    async fn test_issuer_policies() -> Result<(), Error> {
+        let internal = start_test_idp().await;
+        let partner = start_test_idp().await;
+        let strict = start_test_idp().await;
+        let validator = MultiIssuerValidator::discover(
+            [&internal, &partner, &strict],
+            MultiIssuerValidator::DEFAULT_DISCOVERY_TIMEOUT,
+        )
+        .await
+        .unwrap()
+        .with_policy(
+            &partner,
+            IssuerPolicy::default()
+                .allow_algorithm(SignatureAlgorithm::RS256)
+                .audience("orders-api"),
+        )
+        .with_policy(
+            &strict,
+            IssuerPolicy::default().allow_algorithm(SignatureAlgorithm::ES256),
+        );
+        let oidc = Oidc::new(OidcConfig::Validator(Arc::new(validator)))
+            .await
+            .unwrap();
+        let app = test::init_service(App::new().app_data(oidc.clone()).service(decoder)).await;
+
+        for (claims, expected) in [
+            (
+                json!({ "iss": internal, "aud": "other-api" }),
+                StatusCode::OK,
+            ),
+            (
+                json!({ "iss": partner, "aud": ["orders-api", "x"] }),
+                StatusCode::OK,
+            ),
+            (
+                json!({ "iss": partner, "aud": "other-api" }),
+                StatusCode::UNAUTHORIZED,
+            ),
+            (json!({ "iss": strict }), StatusCode::UNAUTHORIZED),
+        ] {
+            let req = create_get_jwt_request("/decoder", &create_token(claims)).to_request();
+            assert_eq!(app.call(req).await?.status(), expected);
+        }
+        Ok(())
+    }
+
+    ///Test the leeway of the expiry check of an issuer
+    #[actix_rt::test]
+    async fn test_issuer_policy_leeway() {
+        let policy = IssuerPolicy::default().with_leeway(Duration::from_secs(60));
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let info = |exp: u64| DecodedInfo {
+            jwt: String::new(),
+            payload: serde_json::from_value(json!({ "exp": exp })).unwrap(),
+        };
+
+        assert!(policy.check(&info(now - 30)).is_ok());
+        assert!(policy.check(&info(now - 120)).is_err());
+    }
+}
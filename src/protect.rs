@@ -0,0 +1,69 @@
+//! Support code of the `#[protect]` attribute macro, not a public API
+
+pub use actix_web::{Error, HttpRequest};
+
+use crate::{response::rejection, AuthContext, OIDCValidationError};
+
+/// Checks the scopes and roles a handler requires, a scope matches the scopes of the token and
+/// the permissions they are mapped to
+pub fn protect(
+    req: &HttpRequest,
+    context: &AuthContext,
+    scopes: &[&str],
+    roles: &[&str],
+) -> Result<(), Error> {
+    let missing_scope = scopes
+        .iter()
+        .find(|scope| !context.scopes.contains(scope) && !context.has_permission(scope))
+        .map(|scope| OIDCValidationError::MissingScope(scope.to_string()));
+    let missing_role = || {
+        roles
+            .iter()
+            .find(|role| !context.has_role(role))
+            .map(|role| OIDCValidationError::MissingRole(role.to_string()))
+    };
+    match missing_scope.or_else(missing_role) {
+        Some(err) => Err(rejection(req, err)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        protect,
+        tests::{create_get_jwt_request, create_oidc, create_token},
+    };
+    use actix_web::{dev::Service, get, http::StatusCode, test, App, Error};
+    use serde_json::json;
+
+    #[get("/items")]
+    #[protect(scopes("read:items"), roles("admin"))]
+    async fn items() -> String {
+        "items".to_string()
+    }
+
+    ///Test for protecting a handler with the attribute macro
+    #[actix_rt::test]
+    async fn test_protect() -> Result<(), Error> {
+        let oidc = create_oidc().await;
+
+        let app = test::init_service(App::new().app_data(oidc.clone()).service(items)).await;
+
+        for (claims, expected) in [
+            (
+                json!({ "scope": "read:items", "roles": ["admin"] }),
+                StatusCode::OK,
+            ),
+            (json!({ "scope": "read:items" }), StatusCode::FORBIDDEN),
+            (json!({ "roles": ["admin"] }), StatusCode::FORBIDDEN),
+        ] {
+            let req = create_get_jwt_request("/items", &create_token(claims)).to_request();
+            assert_eq!(app.call(req).await?.status(), expected);
+        }
+
+        let req = test::TestRequest::get().uri("/items").to_request();
+        assert_eq!(app.call(req).await?.status(), StatusCode::UNAUTHORIZED);
+        Ok(())
+    }
+}
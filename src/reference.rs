@@ -0,0 +1,159 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use futures_util::future::{ready, FutureExt, LocalBoxFuture};
+use ring::rand::SystemRandom;
+
+use crate::{
+    dpop::random_hex, validator::TokenValidator, AuthMetrics, DecodedInfo, OIDCValidationError,
+    TokenHash,
+};
+
+/// Keeps the tokens server-side, clients only hold an opaque reference to them
+///
+/// Implement this trait to store the tokens in e.g. a database or distributed cache.
+pub trait ReferenceStore: Send + Sync {
+    /// Stores the token under the reference
+    fn store<'a>(
+        &'a self,
+        reference: &'a str,
+        token: &'a str,
+    ) -> LocalBoxFuture<'a, Result<(), OIDCValidationError>>;
+
+    /// Returns the token stored under the reference, if any
+    fn resolve<'a>(
+        &'a self,
+        reference: &'a str,
+    ) -> LocalBoxFuture<'a, Result<Option<String>, OIDCValidationError>>;
+}
+
+/// Reference store that keeps the tokens in memory, by the [`TokenHash`] of their reference
+///
+/// A reference is a bearer credential itself, only the token it refers to is kept as is.
+#[derive(Default)]
+pub struct InMemoryReferenceStore {
+    tokens: Mutex<HashMap<TokenHash, String>>,
+}
+
+impl ReferenceStore for InMemoryReferenceStore {
+    fn store<'a>(
+        &'a self,
+        reference: &'a str,
+        token: &'a str,
+    ) -> LocalBoxFuture<'a, Result<(), OIDCValidationError>> {
+        self.tokens
+            .lock()
+            .unwrap()
+            .insert(TokenHash::of(reference), token.to_string());
+        ready(Ok(())).boxed_local()
+    }
+
+    fn resolve<'a>(
+        &'a self,
+        reference: &'a str,
+    ) -> LocalBoxFuture<'a, Result<Option<String>, OIDCValidationError>> {
+        let tokens = self.tokens.lock().unwrap();
+        ready(Ok(tokens.get(&TokenHash::of(reference)).cloned())).boxed_local()
+    }
+}
+
+/// Issues opaque references for tokens and resolves them again, for products that must not
+/// expose the contents of the tokens to clients
+///
+/// Configure it with [`Oidc::with_reference_tokens`](crate::Oidc::with_reference_tokens), the
+/// extractors then accept the references and validate the tokens they refer to.
+#[derive(Clone)]
+pub struct ReferenceTokens {
+    store: Arc<dyn ReferenceStore>,
+    random: SystemRandom,
+}
+
+impl ReferenceTokens {
+    /// Uses the store to keep the tokens
+    pub fn new(store: Arc<dyn ReferenceStore>) -> Self {
+        ReferenceTokens {
+            store,
+            random: SystemRandom::new(),
+        }
+    }
+
+    /// Stores the token and returns the random reference to hand out to the client
+    pub async fn issue(&self, token: &str) -> Result<String, OIDCValidationError> {
+        let reference = random_hex(&self.random, 32);
+        self.store.store(&reference, token).await?;
+        Ok(reference)
+    }
+}
+
+/// Resolves the reference and validates the stored token with the inner validator
+pub(crate) struct ReferenceTokenValidator {
+    pub(crate) references: ReferenceTokens,
+    pub(crate) inner: Arc<dyn TokenValidator>,
+}
+
+impl TokenValidator for ReferenceTokenValidator {
+    fn validate<'a>(
+        &'a self,
+        reference: &'a str,
+    ) -> LocalBoxFuture<'a, Result<DecodedInfo, OIDCValidationError>> {
+        async move {
+            let token = self
+                .references
+                .store
+                .resolve(reference)
+                .await?
+                .ok_or(OIDCValidationError::Unauthorized)?;
+            self.inner.validate(&token).await
+        }
+        .boxed_local()
+    }
+
+    fn set_auth_metrics(&self, metrics: Arc<AuthMetrics>) {
+        self.inner.set_auth_metrics(metrics);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use actix_web::{get, http::StatusCode, test, App, Error};
+    use bytes::Bytes;
+
+    use super::{InMemoryReferenceStore, ReferenceTokens};
+    use crate::{
+        tests::{create_get_jwt_request, create_jwt_token, create_oidc},
+        Subject,
+    };
+
+    #[get("/subject")]
+    async fn subject(subject: Subject) -> String {
+        subject.into_inner()
+    }
+
+    ///Test that the extractors accept the reference and not the token itself
+    #[actix_rt::test]
+    async fn test_reference_tokens() -> Result<(), Error> {
+        let references = ReferenceTokens::new(Arc::new(InMemoryReferenceStore::default()));
+        let oidc = create_oidc()
+            .await
+            .with_reference_tokens(references.clone());
+
+        let app = test::init_service(App::new().app_data(oidc.clone()).service(subject)).await;
+
+        let token = create_jwt_token();
+        let reference = references.issue(&token).await?;
+        assert_eq!(reference.len(), 64);
+
+        let req = create_get_jwt_request("/subject", &reference).to_request();
+        let result: Bytes = test::call_and_read_body(&app, req).await;
+        assert_eq!(result, Bytes::from_static(b"CgVhZG1pbhIFbG9jYWw"));
+
+        let req = create_get_jwt_request("/subject", &token).to_request();
+        let response = test::call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        Ok(())
+    }
+}
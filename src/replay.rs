@@ -0,0 +1,170 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{DecodedInfo, OIDCValidationError};
+
+/// Rejects a token the second time it is presented, for one-time action tokens such as
+/// password-reset or webhook-invocation tokens
+///
+/// The `jti` of every accepted token is recorded until the token expires, after which the token
+/// is rejected by its `exp` anyway. Tokens without `jti` or `exp` are rejected. Clones share the
+/// recorded values, configure it on the scope of the one-time routes with
+/// [`OidcBiscuitValidator::reject_replays`](crate::OidcBiscuitValidator::reject_replays) or call
+/// [`Self::check`] in the handler.
+#[derive(Clone, Default)]
+pub struct ReplayGuard {
+    seen: Arc<Mutex<Seen>>,
+}
+
+/// The expiry of the recorded token ids, expired ids are kept until the next sweep
+#[derive(Default)]
+struct Seen {
+    expiries: HashMap<String, i64>,
+    /// The number of ids at which the expired ids are dropped
+    sweep_at: usize,
+}
+
+impl ReplayGuard {
+    /// The number of ids recorded at least before the expired ids are dropped
+    const SWEEP_SIZE: usize = 1024;
+
+    /// Creates a guard that has seen no tokens yet
+    pub fn new() -> Self {
+        ReplayGuard::default()
+    }
+
+    /// Records the `jti` of the token, fails when it was seen before
+    pub fn check(&self, info: &DecodedInfo) -> Result<(), OIDCValidationError> {
+        let registered = &info.payload.registered;
+        let jti = registered
+            .id
+            .as_deref()
+            .ok_or_else(|| OIDCValidationError::MissingClaim("/jti".to_string()))?;
+        let exp = registered
+            .expiry
+            .map(|exp| exp.timestamp())
+            .ok_or_else(|| OIDCValidationError::MissingClaim("/exp".to_string()))?;
+        self.record(jti, exp)
+    }
+
+    /// Records the `jti` until the expiry (seconds since epoch), fails when it was seen before
+    pub(crate) fn record(&self, jti: &str, exp: i64) -> Result<(), OIDCValidationError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as i64;
+
+        let mut seen = self.seen.lock().unwrap();
+        // the ids are swept once their number doubled, so each record takes amortized constant time
+        if seen.expiries.len() >= seen.sweep_at {
+            seen.expiries.retain(|_, expiry| *expiry >= now);
+            seen.sweep_at = (seen.expiries.len() * 2).max(Self::SWEEP_SIZE);
+        }
+        match seen.expiries.get(jti) {
+            Some(expiry) if *expiry >= now => Err(OIDCValidationError::TokenReplayed),
+            _ => {
+                seen.expiries.insert(jti.to_string(), exp);
+                Ok(())
+            }
+        }
+    }
+
+    /// The number of recorded token ids that have not expired yet
+    pub fn len(&self) -> usize {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as i64;
+        let seen = self.seen.lock().unwrap();
+        seen.expiries
+            .values()
+            .filter(|expiry| **expiry >= now)
+            .count()
+    }
+
+    /// Whether no token ids are recorded
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Guards are equal when they share the recorded token ids
+impl PartialEq for ReplayGuard {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.seen, &other.seen)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use serde_json::json;
+
+    use super::ReplayGuard;
+    use crate::{
+        tests::{create_oidc, create_token},
+        OIDCValidationError,
+    };
+
+    ///Test that a one-time token is accepted once and evicted after it expired
+    #[actix_rt::test]
+    async fn test_replay_guard() {
+        let oidc = create_oidc().await;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let validate = |claims| {
+            let oidc = oidc.clone();
+            async move { oidc.validate_token(&create_token(claims)).await.unwrap() }
+        };
+        let guard = ReplayGuard::new();
+
+        let reset = validate(json!({ "jti": "reset-1", "exp": now + 300 })).await;
+        assert!(guard.check(&reset).is_ok());
+        assert!(matches!(
+            guard.clone().check(&reset),
+            Err(OIDCValidationError::TokenReplayed)
+        ));
+        let other = validate(json!({ "jti": "reset-2", "exp": now + 300 })).await;
+        assert!(guard.check(&other).is_ok());
+        assert!(matches!(
+            guard.check(&validate(json!({ "exp": now + 300 })).await),
+            Err(OIDCValidationError::MissingClaim(claim)) if claim == "/jti"
+        ));
+
+        // expired tokens are not counted, they are rejected by their exp
+        let expired = validate(json!({ "jti": "old", "exp": now - 10 })).await;
+        assert!(guard.check(&expired).is_ok());
+        assert!(guard.check(&expired).is_ok());
+        assert_eq!(guard.len(), 2);
+        assert!(guard.check(&other).is_err());
+    }
+
+    ///Test that the expired token ids are dropped in batches, not on every record
+    #[actix_rt::test]
+    async fn test_replay_guard_sweep() {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        let guard = ReplayGuard::new();
+        let recorded = || guard.seen.lock().unwrap().expiries.len();
+
+        for i in 0..ReplayGuard::SWEEP_SIZE - 1 {
+            guard.record(&format!("old-{}", i), now - 10).unwrap();
+        }
+        guard.record("live-1", now + 300).unwrap();
+        assert_eq!(recorded(), ReplayGuard::SWEEP_SIZE);
+        assert_eq!(guard.len(), 1);
+
+        // the next record at the sweep size drops the expired ids
+        guard.record("live-2", now + 300).unwrap();
+        assert_eq!(recorded(), 2);
+        assert!(guard.record("live-1", now + 300).is_err());
+    }
+}
@@ -0,0 +1,122 @@
+use std::fmt;
+
+use biscuit::{errors::ValidationError, RegisteredClaims, ValidationOptions};
+
+/// All checks a token failed, instead of only the first one
+///
+/// A token that is expired and issued for another audience reports both failures,
+/// so the consumer of the API can fix them at once.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ValidationReport {
+    failures: Vec<ValidationError>,
+}
+
+impl ValidationReport {
+    /// Runs every check of the validation options on the claims and collects the failures
+    pub fn collect(claims: &RegisteredClaims, options: &ValidationOptions) -> Self {
+        let options = options.clone();
+        let temporal = options.temporal_options;
+        let checks = [
+            claims.validate_claim_presence(options.claim_presence_options),
+            claims.validate_exp(options.expiry.map(|_| temporal)),
+            claims.validate_nbf(options.not_before.map(|_| temporal)),
+            claims.validate_iat(options.issued_at.map(|max_age| (max_age, temporal))),
+            claims.validate_iss(options.issuer),
+            claims.validate_aud(options.audience),
+        ];
+        ValidationReport {
+            failures: checks.into_iter().filter_map(Result::err).collect(),
+        }
+    }
+
+    /// The failed checks, in the order they are validated
+    pub fn failures(&self) -> &[ValidationError] {
+        &self.failures
+    }
+
+    /// Adds the failure of a check that is not part of the validation options
+    pub(crate) fn push(&mut self, failure: ValidationError) {
+        self.failures.push(failure);
+    }
+
+    /// Returns true when the token itself is invalid: expired, not yet valid, or of another
+    /// issuer or audience
+    pub(crate) fn token_invalid(&self) -> bool {
+        self.failures.iter().any(|failure| {
+            matches!(
+                failure,
+                ValidationError::Expired(_)
+                    | ValidationError::NotYetValid(_)
+                    | ValidationError::InvalidIssuer(_)
+                    | ValidationError::InvalidAudience(_)
+            )
+        })
+    }
+
+    /// Returns true when no check failed
+    pub fn is_valid(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, failure) in self.failures.iter().enumerate() {
+            if index > 0 {
+                f.write_str("; ")?;
+            }
+            write!(f, "{}", failure)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ValidationReport;
+    use biscuit::{errors::ValidationError, ClaimsSet, Validation, ValidationOptions};
+    use serde_json::{json, Value};
+
+    fn claims(value: Value) -> ClaimsSet<Value> {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_collects_all_failures() {
+        let claims = claims(json!({
+            "iss": "http://0.0.0.0:9090",
+            "aud": ["cafienne-ui"],
+            "exp": 1602324610,
+        }));
+        let options = ValidationOptions {
+            audience: Validation::Validate("other-api".to_string()),
+            ..ValidationOptions::default()
+        };
+
+        let report = ValidationReport::collect(&claims.registered, &options);
+
+        assert!(!report.is_valid());
+        assert_eq!(report.failures().len(), 2);
+        assert!(matches!(report.failures()[0], ValidationError::Expired(_)));
+        assert!(matches!(
+            report.failures()[1],
+            ValidationError::InvalidAudience(_)
+        ));
+        assert!(report
+            .to_string()
+            .contains("; Audience of token is invalid"));
+    }
+
+    #[test]
+    fn test_valid_claims() {
+        let claims = claims(json!({
+            "iss": "http://0.0.0.0:9090",
+            "exp": 2147483647,
+        }));
+
+        let report = ValidationReport::collect(&claims.registered, &ValidationOptions::default());
+
+        assert!(report.is_valid());
+        assert_eq!(report.to_string(), "");
+    }
+}
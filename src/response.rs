@@ -0,0 +1,257 @@
+use std::borrow::Cow;
+
+use actix_web::{
+    error::InternalError,
+    http::header::{self, ContentType},
+    HttpRequest, HttpResponse, HttpResponseBuilder, ResponseError,
+};
+use serde_json::{json, Value};
+
+use crate::{OIDCValidationError, Oidc};
+
+/// Body of the responses to unauthenticated requests (401), replacing the plain text sentence
+#[derive(Debug, PartialEq, Clone)]
+pub enum UnauthorizedBody {
+    /// Static plain text
+    Text(Cow<'static, str>),
+    /// Static JSON value
+    Json(Value),
+    /// Plain text in which `{reason}` is replaced by the reason of the failure
+    Template(Cow<'static, str>),
+}
+
+/// Renders the responses of all failures of the extractors and middleware, e.g. to match the
+/// error envelope of the application, see [`Oidc::with_error_handler`](crate::Oidc::with_error_handler)
+///
+/// The [`OIDCValidationError::code`] tells the category of the failure, e.g.
+/// [`ErrorCode::TokenMissing`](crate::ErrorCode::TokenMissing) or
+/// [`ErrorCode::TokenExpired`](crate::ErrorCode::TokenExpired). It is implemented for closures:
+///
+/// ```ignore
+/// let oidc = oidc.with_error_handler(|err: &OIDCValidationError| {
+///     HttpResponse::build(err.status_code()).json(json!({ "errors": [{ "code": err.code() }] }))
+/// });
+/// ```
+pub trait ErrorHandler: Send + Sync {
+    /// Renders the response of the failure, status and headers included
+    fn handle(&self, err: &OIDCValidationError) -> HttpResponse;
+}
+
+impl<F> ErrorHandler for F
+where
+    F: Fn(&OIDCValidationError) -> HttpResponse + Send + Sync,
+{
+    fn handle(&self, err: &OIDCValidationError) -> HttpResponse {
+        self(err)
+    }
+}
+
+/// Answers the error with the response of the handler
+pub(crate) fn handled(handler: &dyn ErrorHandler, err: OIDCValidationError) -> actix_web::Error {
+    let res = handler.handle(&err);
+    InternalError::from_response(err, res).into()
+}
+
+/// Answers the error with the response configured on the [`Oidc`] of the app, if any
+pub(crate) fn rejection(req: &HttpRequest, err: OIDCValidationError) -> actix_web::Error {
+    match req.app_data::<Oidc>() {
+        Some(oidc) => oidc.error_response(err),
+        None => err.into(),
+    }
+}
+
+/// Starts a response with the status and headers, like `WWW-Authenticate`, of the error response
+fn builder(err: &OIDCValidationError) -> HttpResponseBuilder {
+    let mut res = HttpResponse::build(err.status_code());
+    for (name, value) in err.error_response().headers() {
+        if name != header::CONTENT_TYPE {
+            res.append_header((name.clone(), value.clone()));
+        }
+    }
+    res
+}
+
+/// Answers the error with a JSON body holding its machine-readable code
+pub(crate) fn json_error(err: OIDCValidationError) -> actix_web::Error {
+    let res = builder(&err).json(json!({
+        "error": err.code(),
+        "error_description": err.to_string(),
+    }));
+    InternalError::from_response(err, res).into()
+}
+
+/// Answers the error with RFC 7807 problem details, the type is the code below the base URI
+pub(crate) fn problem_details(type_base: &str, err: OIDCValidationError) -> actix_web::Error {
+    let status = err.status_code();
+    let res = builder(&err).content_type("application/problem+json").body(
+        json!({
+            "type": format!("{}{}", type_base, err.code()),
+            "title": status.canonical_reason().unwrap_or_default(),
+            "status": status.as_u16(),
+            "detail": err.to_string(),
+            "code": err.code(),
+        })
+        .to_string(),
+    );
+    InternalError::from_response(err, res).into()
+}
+
+impl UnauthorizedBody {
+    /// Renders the response for the error
+    pub(crate) fn render(&self, err: OIDCValidationError) -> actix_web::Error {
+        let mut res = builder(&err);
+        let res = match self {
+            UnauthorizedBody::Text(text) => res
+                .insert_header(ContentType::plaintext())
+                .body(text.to_string()),
+            UnauthorizedBody::Json(value) => res.json(value),
+            UnauthorizedBody::Template(template) => res
+                .insert_header(ContentType::plaintext())
+                .body(template.replace("{reason}", &err.to_string())),
+        };
+        InternalError::from_response(err, res).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{get, http::StatusCode, test, App, Error, HttpResponse};
+    use bytes::Bytes;
+    use serde_json::{json, Value};
+
+    use super::UnauthorizedBody;
+    use crate::{
+        tests::{create_get_jwt_request, create_oidc},
+        DecodedInfo, ErrorCode, OIDCValidationError,
+    };
+
+    #[get("/decoder")]
+    async fn decoder(claims: DecodedInfo) -> String {
+        claims.jwt
+    }
+
+    ///Test for a JSON body on unauthenticated requests
+    #[actix_rt::test]
+    async fn test_unauthorized_json_body() -> Result<(), Error> {
+        let oidc = create_oidc()
+            .await
+            .with_unauthorized_body(UnauthorizedBody::Json(json!({ "error": "login required" })));
+
+        let app = test::init_service(App::new().app_data(oidc.clone()).service(decoder)).await;
+
+        let req = test::TestRequest::get().uri("/decoder").to_request();
+        let response = test::call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            response.headers().get("content-type").unwrap(),
+            "application/json"
+        );
+        let body = test::read_body(response).await;
+        assert_eq!(body, Bytes::from_static(br#"{"error":"login required"}"#));
+        Ok(())
+    }
+
+    ///Test for JSON error bodies with the error code
+    #[actix_rt::test]
+    async fn test_json_errors() -> Result<(), Error> {
+        let oidc = create_oidc().await.with_json_errors();
+
+        let app = test::init_service(App::new().app_data(oidc.clone()).service(decoder)).await;
+
+        let req = test::TestRequest::get().uri("/decoder").to_request();
+        let response = test::call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body["error"], "token_missing");
+        Ok(())
+    }
+
+    ///Test for RFC 7807 problem details of the failures
+    #[actix_rt::test]
+    async fn test_problem_details() -> Result<(), Error> {
+        let oidc = create_oidc()
+            .await
+            .with_problem_details("https://errors.example.com/auth/");
+
+        let app = test::init_service(App::new().app_data(oidc.clone()).service(decoder)).await;
+
+        let req = test::TestRequest::get().uri("/decoder").to_request();
+        let response = test::call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            response.headers().get("content-type").unwrap(),
+            "application/problem+json"
+        );
+        assert_eq!(
+            response.headers().get("www-authenticate").unwrap(),
+            "Bearer"
+        );
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(
+            body,
+            json!({
+                "type": "https://errors.example.com/auth/token_missing",
+                "title": "Unauthorized",
+                "status": 401,
+                "detail": "No token found or token is not authorized",
+                "code": "token_missing",
+            })
+        );
+        Ok(())
+    }
+
+    ///Test that the error handler renders the failures of each category
+    #[actix_rt::test]
+    async fn test_error_handler() -> Result<(), Error> {
+        let oidc = create_oidc().await.with_json_errors().with_error_handler(
+            |err: &OIDCValidationError| {
+                let status = match err.code() {
+                    ErrorCode::TokenMissing => StatusCode::UNAUTHORIZED,
+                    _ => StatusCode::FORBIDDEN,
+                };
+                HttpResponse::build(status)
+                    .insert_header(("x-error-code", err.code().as_str()))
+                    .json(json!({ "errors": [{ "code": err.code(), "message": err.to_string() }] }))
+            },
+        );
+
+        let app = test::init_service(App::new().app_data(oidc.clone()).service(decoder)).await;
+
+        let req = test::TestRequest::get().uri("/decoder").to_request();
+        let response = test::call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            response.headers().get("x-error-code").unwrap(),
+            "token_missing"
+        );
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body["errors"][0]["code"], "token_missing");
+
+        let req = create_get_jwt_request("/decoder", "bad_token").to_request();
+        let response = test::call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(
+            response.headers().get("x-error-code").unwrap(),
+            "token_invalid"
+        );
+        Ok(())
+    }
+
+    ///Test for a templated body with the reason of the failure
+    #[actix_rt::test]
+    async fn test_unauthorized_template_body() -> Result<(), Error> {
+        let oidc = create_oidc()
+            .await
+            .with_unauthorized_body(UnauthorizedBody::Template("Denied: {reason}".into()));
+
+        let app = test::init_service(App::new().app_data(oidc.clone()).service(decoder)).await;
+
+        let req = test::TestRequest::get().uri("/decoder").to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        assert_eq!(
+            body,
+            Bytes::from_static(b"Denied: No token found or token is not authorized")
+        );
+        Ok(())
+    }
+}
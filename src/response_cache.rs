@@ -0,0 +1,239 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::TokenHash;
+
+/// Caches the responses of the identity provider per token (introspection, UserInfo), so not
+/// every request makes a round-trip to it
+///
+/// Positive responses are kept for the TTL, but never past the `exp` of the token. Negative
+/// responses (inactive or unknown tokens) are kept for the negative TTL, which should be short so
+/// tokens that became valid are not rejected for long.
+///
+/// At most [`Self::CAPACITY`] positive and [`Self::NEGATIVE_CAPACITY`] negative responses are
+/// kept, the least recently used response is dropped for a new one, so made up tokens only
+/// displace other negative responses. Expired responses are dropped when they are looked up or
+/// evicted.
+pub(crate) struct ResponseCache<T> {
+    ttl: Duration,
+    negative_ttl: Duration,
+    positive: Mutex<Entries<T>>,
+    negative: Mutex<Entries<()>>,
+}
+
+/// Responses by the hash of the token, with their order of use
+struct Entries<T> {
+    capacity: usize,
+    responses: HashMap<TokenHash, Entry<T>>,
+    /// The tokens by their last use, least recent first
+    recent: BTreeMap<u64, TokenHash>,
+    uses: u64,
+}
+
+struct Entry<T> {
+    expires: Instant,
+    used: u64,
+    response: T,
+}
+
+impl<T: Clone> Entries<T> {
+    fn new(capacity: usize) -> Self {
+        Entries {
+            capacity,
+            responses: HashMap::new(),
+            recent: BTreeMap::new(),
+            uses: 0,
+        }
+    }
+
+    /// The response of the token, marked as most recently used, expired responses are dropped
+    fn get(&mut self, token: &TokenHash) -> Option<T> {
+        self.uses += 1;
+        let uses = self.uses;
+        let entry = self.responses.get_mut(token)?;
+        if entry.expires <= Instant::now() {
+            self.remove(token);
+            return None;
+        }
+        self.recent.remove(&entry.used);
+        entry.used = uses;
+        self.recent.insert(uses, *token);
+        Some(entry.response.clone())
+    }
+
+    fn insert(&mut self, token: TokenHash, ttl: Duration, response: T) {
+        self.remove(&token);
+        if ttl.is_zero() || self.capacity == 0 {
+            return;
+        }
+        while self.responses.len() >= self.capacity {
+            let Some((_, least_recent)) = self.recent.pop_first() else {
+                break;
+            };
+            self.responses.remove(&least_recent);
+        }
+        self.uses += 1;
+        let used = self.uses;
+        self.recent.insert(used, token);
+        self.responses.insert(
+            token,
+            Entry {
+                expires: Instant::now() + ttl,
+                used,
+                response,
+            },
+        );
+    }
+
+    fn remove(&mut self, token: &TokenHash) {
+        if let Some(entry) = self.responses.remove(token) {
+            self.recent.remove(&entry.used);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.responses.clear();
+        self.recent.clear();
+    }
+}
+
+impl<T: Clone> ResponseCache<T> {
+    /// The number of positive responses kept at most
+    pub(crate) const CAPACITY: usize = 10_000;
+
+    /// The number of negative responses kept at most
+    pub(crate) const NEGATIVE_CAPACITY: usize = 1_000;
+
+    pub(crate) fn new(ttl: Duration, negative_ttl: Duration) -> Self {
+        ResponseCache::with_capacity(ttl, negative_ttl, Self::CAPACITY, Self::NEGATIVE_CAPACITY)
+    }
+
+    pub(crate) fn with_capacity(
+        ttl: Duration,
+        negative_ttl: Duration,
+        capacity: usize,
+        negative_capacity: usize,
+    ) -> Self {
+        ResponseCache {
+            ttl,
+            negative_ttl,
+            positive: Mutex::new(Entries::new(capacity)),
+            negative: Mutex::new(Entries::new(negative_capacity)),
+        }
+    }
+
+    /// The cached response of the token, `Some(None)` when it is cached as negative
+    pub(crate) fn get(&self, token: &str) -> Option<Option<T>> {
+        self.get_hashed(&TokenHash::of(token))
+    }
+
+    /// The cached response by the hash of the token, see [`Self::get`]
+    pub(crate) fn get_hashed(&self, token: &TokenHash) -> Option<Option<T>> {
+        if let Some(response) = self.positive.lock().unwrap().get(token) {
+            return Some(Some(response));
+        }
+        self.negative.lock().unwrap().get(token).map(|()| None)
+    }
+
+    /// Caches the positive response of the token that expires at `exp` (seconds since epoch)
+    pub(crate) fn insert(&self, token: &str, response: T, exp: Option<u64>) {
+        self.insert_hashed(TokenHash::of(token), response, exp)
+    }
+
+    /// Caches the positive response by the hash of the token, see [`Self::insert`]
+    pub(crate) fn insert_hashed(&self, token: TokenHash, response: T, exp: Option<u64>) {
+        let ttl = match exp {
+            Some(exp) => {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+                self.ttl.min(Duration::from_secs(exp.saturating_sub(now)))
+            }
+            None => self.ttl,
+        };
+        self.negative.lock().unwrap().remove(&token);
+        self.positive.lock().unwrap().insert(token, ttl, response);
+    }
+
+    /// Caches that the token is inactive or unknown
+    pub(crate) fn insert_negative(&self, token: &str) {
+        let token = TokenHash::of(token);
+        self.positive.lock().unwrap().remove(&token);
+        self.negative
+            .lock()
+            .unwrap()
+            .insert(token, self.negative_ttl, ());
+    }
+
+    /// Drops the response of the token, positive or negative
+    pub(crate) fn remove(&self, token: &TokenHash) {
+        self.positive.lock().unwrap().remove(token);
+        self.negative.lock().unwrap().remove(token);
+    }
+
+    /// Drops all responses
+    pub(crate) fn clear(&self) {
+        self.positive.lock().unwrap().clear();
+        self.negative.lock().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use super::ResponseCache;
+
+    #[test]
+    fn test_response_cache() {
+        let cache = ResponseCache::new(Duration::from_secs(60), Duration::from_secs(5));
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        cache.insert("active", "admin", Some(now + 300));
+        cache.insert_negative("inactive");
+        assert_eq!(cache.get("active"), Some(Some("admin")));
+        assert_eq!(cache.get("inactive"), Some(None));
+        assert_eq!(cache.get("unknown"), None);
+
+        // never cached past the exp of the token
+        cache.insert("expired", "admin", Some(now - 1));
+        assert_eq!(cache.get("expired"), None);
+
+        let cache = ResponseCache::new(Duration::from_secs(60), Duration::ZERO);
+        cache.insert_negative("inactive");
+        assert_eq!(cache.get("inactive"), None::<Option<&str>>);
+    }
+
+    #[test]
+    fn test_response_cache_capacity() {
+        let cache =
+            ResponseCache::with_capacity(Duration::from_secs(60), Duration::from_secs(5), 2, 1);
+        cache.insert("alice", "admin", None);
+        cache.insert("bob", "user", None);
+        assert_eq!(cache.get("alice"), Some(Some("admin")));
+        cache.insert("carol", "user", None);
+        // the least recently used response is dropped
+        assert_eq!(cache.get("bob"), None);
+        assert_eq!(cache.get("alice"), Some(Some("admin")));
+        assert_eq!(cache.get("carol"), Some(Some("user")));
+
+        // negative responses only displace each other
+        cache.insert_negative("random-1");
+        cache.insert_negative("random-2");
+        assert_eq!(cache.get("random-1"), None);
+        assert_eq!(cache.get("random-2"), Some(None));
+        assert_eq!(cache.get("alice"), Some(Some("admin")));
+        assert_eq!(cache.get("carol"), Some(Some("user")));
+
+        // a token that became active is no longer negative
+        cache.insert("random-2", "user", None);
+        assert_eq!(cache.get("random-2"), Some(Some("user")));
+    }
+}
@@ -0,0 +1,120 @@
+use std::{collections::HashSet, sync::RwLock};
+
+use futures_util::future::{ready, FutureExt, LocalBoxFuture};
+
+use crate::{DecodedInfo, OIDCValidationError};
+
+/// Deny-list of tokens that are rejected before they expire, consulted after the signature and
+/// claims of every token are validated
+///
+/// Implement this trait to share the deny-list between instances, e.g. in a database or
+/// distributed cache, and configure it with
+/// [`Oidc::with_revocation_store`](crate::Oidc::with_revocation_store).
+pub trait RevocationStore: Send + Sync {
+    /// Returns whether the token is revoked, by its `jti` or its `sub`
+    fn is_revoked<'a>(
+        &'a self,
+        jti: Option<&'a str>,
+        sub: Option<&'a str>,
+    ) -> LocalBoxFuture<'a, Result<bool, OIDCValidationError>>;
+}
+
+/// Revocation store that keeps the revoked token ids and subjects in memory
+///
+/// Entries are added and removed at runtime, e.g. by an admin endpoint, and are kept until they
+/// are removed.
+#[derive(Default)]
+pub struct InMemoryRevocationStore {
+    jtis: RwLock<HashSet<String>>,
+    subjects: RwLock<HashSet<String>>,
+}
+
+impl InMemoryRevocationStore {
+    /// Rejects the token with the `jti`
+    pub fn revoke_jti(&self, jti: impl Into<String>) {
+        self.jtis.write().unwrap().insert(jti.into());
+    }
+
+    /// Accepts the token with the `jti` again, returns whether it was revoked
+    pub fn remove_jti(&self, jti: &str) -> bool {
+        self.jtis.write().unwrap().remove(jti)
+    }
+
+    /// Rejects all tokens of the subject, e.g. after the user is disabled
+    pub fn revoke_subject(&self, sub: impl Into<String>) {
+        self.subjects.write().unwrap().insert(sub.into());
+    }
+
+    /// Accepts the tokens of the subject again, returns whether it was revoked
+    pub fn remove_subject(&self, sub: &str) -> bool {
+        self.subjects.write().unwrap().remove(sub)
+    }
+}
+
+impl RevocationStore for InMemoryRevocationStore {
+    fn is_revoked<'a>(
+        &'a self,
+        jti: Option<&'a str>,
+        sub: Option<&'a str>,
+    ) -> LocalBoxFuture<'a, Result<bool, OIDCValidationError>> {
+        let revoked = jti.is_some_and(|jti| self.jtis.read().unwrap().contains(jti))
+            || sub.is_some_and(|sub| self.subjects.read().unwrap().contains(sub));
+        ready(Ok(revoked)).boxed_local()
+    }
+}
+
+/// Fails with [`OIDCValidationError::TokenRevoked`] when the store revoked the token
+pub(crate) async fn check(
+    store: &dyn RevocationStore,
+    info: &DecodedInfo,
+) -> Result<(), OIDCValidationError> {
+    let registered = &info.payload.registered;
+    match store
+        .is_revoked(registered.id.as_deref(), registered.subject.as_deref())
+        .await?
+    {
+        true => Err(OIDCValidationError::TokenRevoked),
+        false => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use serde_json::json;
+
+    use super::InMemoryRevocationStore;
+    use crate::{
+        tests::{create_oidc, create_token},
+        OIDCValidationError,
+    };
+
+    ///Test that tokens are rejected by their jti or subject until the entry is removed
+    #[actix_rt::test]
+    async fn test_revocation_store() {
+        let store = Arc::new(InMemoryRevocationStore::default());
+        let oidc = create_oidc().await.with_revocation_store(store.clone());
+        let token = create_token(json!({ "sub": "joe", "jti": "token-1" }));
+        let other = create_token(json!({ "sub": "ann", "jti": "token-2" }));
+        assert!(oidc.validate_token(&token).await.is_ok());
+
+        store.revoke_jti("token-1");
+        assert!(matches!(
+            oidc.validate_token(&token).await,
+            Err(OIDCValidationError::TokenRevoked)
+        ));
+        assert!(oidc.validate_token(&other).await.is_ok());
+        assert!(store.remove_jti("token-1"));
+        assert!(oidc.validate_token(&token).await.is_ok());
+
+        store.revoke_subject("ann");
+        assert!(matches!(
+            oidc.validate_token(&other).await,
+            Err(OIDCValidationError::TokenRevoked)
+        ));
+        assert!(store.remove_subject("ann"));
+        assert!(!store.remove_subject("ann"));
+        assert!(oidc.validate_token(&other).await.is_ok());
+    }
+}
@@ -0,0 +1,388 @@
+use std::{collections::HashMap, sync::Arc};
+
+use actix_web::{http::header, HttpRequest};
+use biscuit::{jwk::JWKSet, ClaimsSet, Empty};
+use futures_util::future::{ready, FutureExt, LocalBoxFuture};
+use serde_json::Value;
+
+use crate::{
+    circuit_breaker::CircuitBreaker,
+    issuer::DiscoveredIssuers,
+    keys::KeyStore,
+    oidc::{guard, OidcDecoder},
+    validator::TokenValidator,
+    AuthMetrics, DecodedInfo, HttpLimits, IssuerPatternValidator, OIDCValidationError,
+};
+
+/// Maps a request to the issuer of its tenant, for multi-tenant deployments where each tenant
+/// has its own identity provider, e.g. a Keycloak realm per tenant
+///
+/// The built-in resolvers are [`HostTenants`], [`PathTenants`] and [`ClaimTenants`]. Implement it,
+/// or pass a closure, for other mappings and configure it with
+/// [`OidcConfig::Tenants`](crate::OidcConfig::Tenants).
+pub trait TenantResolver: Send + Sync {
+    /// Returns the issuer of the tenant, `None` when the request belongs to no known tenant
+    ///
+    /// The claims are NOT verified yet, the token is only accepted when it is signed by the keys
+    /// of the returned issuer and its `iss` claim equals it.
+    fn resolve(&self, req: &HttpRequest, claims: &ClaimsSet<Value>) -> Option<String>;
+
+    /// The issuers of the known tenants, of which the keys are discovered ahead of their first
+    /// request by [`Oidc::spawn_tenant_prefetch`](crate::Oidc::spawn_tenant_prefetch)
+    fn issuers(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+impl<F> TenantResolver for F
+where
+    F: Fn(&HttpRequest, &ClaimsSet<Value>) -> Option<String> + Send + Sync,
+{
+    fn resolve(&self, req: &HttpRequest, claims: &ClaimsSet<Value>) -> Option<String> {
+        self(req, claims)
+    }
+}
+
+/// Resolves the tenant by the `Host` header of the request, without port
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HostTenants(pub HashMap<String, String>);
+
+impl HostTenants {
+    /// Creates a resolver without tenants
+    pub fn new() -> Self {
+        HostTenants::default()
+    }
+
+    /// Maps the host, e.g. `acme.example.com`, to the issuer of the tenant
+    pub fn tenant(mut self, host: impl Into<String>, issuer: impl Into<String>) -> Self {
+        self.0.insert(host.into(), issuer.into());
+        self
+    }
+}
+
+impl TenantResolver for HostTenants {
+    fn resolve(&self, req: &HttpRequest, _claims: &ClaimsSet<Value>) -> Option<String> {
+        let host = req.headers().get(header::HOST)?.to_str().ok()?;
+        let host = match host.rsplit_once(':') {
+            Some((host, port)) if port.bytes().all(|b| b.is_ascii_digit()) => host,
+            _ => host,
+        };
+        self.0.get(host).cloned()
+    }
+
+    fn issuers(&self) -> Vec<String> {
+        self.0.values().cloned().collect()
+    }
+}
+
+/// Resolves the tenant by the first segment of the request path, e.g. `acme` for `/acme/items`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PathTenants(pub HashMap<String, String>);
+
+impl PathTenants {
+    /// Creates a resolver without tenants
+    pub fn new() -> Self {
+        PathTenants::default()
+    }
+
+    /// Maps the first path segment to the issuer of the tenant
+    pub fn tenant(mut self, segment: impl Into<String>, issuer: impl Into<String>) -> Self {
+        self.0.insert(segment.into(), issuer.into());
+        self
+    }
+}
+
+impl TenantResolver for PathTenants {
+    fn resolve(&self, req: &HttpRequest, _claims: &ClaimsSet<Value>) -> Option<String> {
+        let segment = req.path().trim_start_matches('/').split('/').next()?;
+        self.0.get(segment).cloned()
+    }
+
+    fn issuers(&self) -> Vec<String> {
+        self.0.values().cloned().collect()
+    }
+}
+
+/// Resolves the tenant by a claim of the token, e.g. `/tenant` or `/tid` of Azure AD
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClaimTenants {
+    pointer: String,
+    tenants: HashMap<String, String>,
+}
+
+impl ClaimTenants {
+    /// Creates a resolver on the claim at the JSON pointer, without tenants
+    pub fn new(pointer: impl Into<String>) -> Self {
+        ClaimTenants {
+            pointer: pointer.into(),
+            tenants: HashMap::new(),
+        }
+    }
+
+    /// Maps the value of the claim to the issuer of the tenant
+    pub fn tenant(mut self, value: impl Into<String>, issuer: impl Into<String>) -> Self {
+        self.tenants.insert(value.into(), issuer.into());
+        self
+    }
+}
+
+impl TenantResolver for ClaimTenants {
+    fn resolve(&self, _req: &HttpRequest, claims: &ClaimsSet<Value>) -> Option<String> {
+        let value = claims.private.pointer(&self.pointer)?.as_str()?;
+        self.tenants.get(value).cloned()
+    }
+
+    fn issuers(&self) -> Vec<String> {
+        self.tenants.values().cloned().collect()
+    }
+}
+
+/// The resolver and the keys of the tenants seen so far, each tenant has its own key store
+pub(crate) struct Tenants {
+    pub(crate) resolver: Arc<dyn TenantResolver>,
+    discovered: DiscoveredIssuers,
+    decoder: OidcDecoder,
+}
+
+impl Tenants {
+    pub(crate) fn new(resolver: Arc<dyn TenantResolver>) -> Self {
+        Tenants {
+            resolver,
+            discovered: DiscoveredIssuers::new(IssuerPatternValidator::DEFAULT_MAX_ISSUERS),
+            decoder: OidcDecoder,
+        }
+    }
+
+    /// Counts the reloads of the keys of the tenants in the metrics
+    pub(crate) fn with_metrics(&self, metrics: Arc<AuthMetrics>) {
+        self.discovered.with_metrics(metrics);
+    }
+
+    /// Validates the token with the keys of the tenant, it must be issued by the tenant
+    ///
+    /// The keys are discovered the first time a token of the tenant is seen, the calls to the
+    /// identity provider go through the circuit breaker when given.
+    pub(crate) async fn validate(
+        &self,
+        issuer: &str,
+        token: &str,
+        limits: &HttpLimits,
+        circuit_breaker: Option<&CircuitBreaker>,
+    ) -> Result<DecodedInfo, OIDCValidationError> {
+        let key_store = self
+            .discovered
+            .key_store(issuer, limits, circuit_breaker)
+            .await?;
+        // a failed refresh keeps the current keys, the token is validated against them
+        let _ = guard(
+            circuit_breaker,
+            key_store.refresh_for_unknown_kid(token, limits),
+        )
+        .await;
+        let payload = self.decoder.decode(&key_store.current(), token)?;
+        if payload.registered.issuer.as_deref() != Some(issuer) {
+            return Err(OIDCValidationError::Unauthorized);
+        }
+        Ok(DecodedInfo {
+            jwt: token.to_string(),
+            payload,
+        })
+    }
+
+    /// The current keys of the tenant, `None` when they are not discovered yet
+    pub(crate) fn current_keys(&self, issuer: &str) -> Option<Arc<JWKSet<Empty>>> {
+        self.discovered
+            .get(issuer)
+            .map(|key_store| key_store.current())
+    }
+
+    /// The tenants seen so far and their key stores
+    pub(crate) fn key_stores(&self) -> Vec<(String, Arc<KeyStore>)> {
+        self.discovered.key_stores()
+    }
+
+    /// Discovers the keys of the known tenants that are not discovered yet, returns whether
+    /// some could not be discovered
+    pub(crate) async fn prefetch(
+        &self,
+        limits: &HttpLimits,
+        circuit_breaker: Option<&CircuitBreaker>,
+    ) -> bool {
+        let mut pending = false;
+        for issuer in self.resolver.issuers() {
+            if self.discovered.get(&issuer).is_none() {
+                let discovered = self.discovered.key_store(&issuer, limits, circuit_breaker);
+                pending |= discovered.await.is_err();
+            }
+        }
+        pending
+    }
+}
+
+/// The validator of an Oidc with tenants, tokens are only validated with the tenant of the request
+pub(crate) struct UnknownTenant;
+
+impl TokenValidator for UnknownTenant {
+    fn validate<'a>(
+        &'a self,
+        _token: &'a str,
+    ) -> LocalBoxFuture<'a, Result<DecodedInfo, OIDCValidationError>> {
+        ready(Err(OIDCValidationError::UnknownTenant)).boxed_local()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpListener, sync::Arc, time::Duration};
+
+    use actix_web::{dev::Service, get, http::StatusCode, test, App, Error};
+    use serde_json::json;
+
+    use super::{ClaimTenants, HostTenants, PathTenants, TenantResolver, Tenants};
+    use crate::{
+        tests::{create_get_jwt_request, create_token, create_unverified_token, start_test_idp},
+        CircuitBreakerConfig, DecodedInfo, HttpLimits, OIDCValidationError, Oidc, OidcConfig,
+    };
+
+    #[get("/{tenant}/items")]
+    async fn items(claims: DecodedInfo) -> String {
+        claims.payload.registered.issuer.unwrap_or_default()
+    }
+
+    ///Test the built-in tenant resolvers
+    #[actix_rt::test]
+    async fn test_tenant_resolvers() {
+        let req = test::TestRequest::get()
+            .uri("/acme/items")
+            .insert_header(("host", "globex.example.com:8080"))
+            .to_http_request();
+        let claims = serde_json::from_value(json!({ "tenant": "initech" })).unwrap();
+
+        let resolve = |resolver: &dyn TenantResolver| resolver.resolve(&req, &claims);
+        let host = HostTenants::new().tenant("globex.example.com", "https://idp/globex");
+        assert_eq!(resolve(&host).as_deref(), Some("https://idp/globex"));
+        assert_eq!(host.issuers(), vec!["https://idp/globex"]);
+        let path = PathTenants::new().tenant("acme", "https://idp/acme");
+        assert_eq!(resolve(&path).as_deref(), Some("https://idp/acme"));
+        let claim = ClaimTenants::new("/tenant").tenant("initech", "https://idp/initech");
+        assert_eq!(resolve(&claim).as_deref(), Some("https://idp/initech"));
+        assert_eq!(resolve(&PathTenants::new().tenant("globex", "x")), None);
+    }
+
+    ///Test that the tenant is resolved from the claims of tokens of which biscuit cannot represent
+    ///the header
+    #[actix_rt::test]
+    async fn test_tenant_issuer_eddsa() {
+        let resolver = ClaimTenants::new("/tenant").tenant("initech", "https://idp/initech");
+        let oidc = Oidc::new(OidcConfig::Tenants(Arc::new(resolver)))
+            .await
+            .unwrap();
+
+        let token = create_unverified_token("EdDSA", json!({ "tenant": "initech" }));
+        let req = create_get_jwt_request("/items", &token).to_http_request();
+        assert_eq!(
+            oidc.tenant_issuer(&req, &token).unwrap().as_deref(),
+            Some("https://idp/initech")
+        );
+    }
+
+    ///Test that a token is only accepted by its own tenant
+    #[actix_rt::test]
+    async fn test_tenant_validation() -> Result<(), Error> {
+        let acme = start_test_idp().await;
+        let globex = start_test_idp().await;
+        let resolver = PathTenants::new()
+            .tenant("acme", acme.clone())
+            .tenant("globex", globex.clone());
+        let oidc = Oidc::new(OidcConfig::Tenants(Arc::new(resolver)))
+            .await
+            .unwrap();
+
+        let app = test::init_service(App::new().app_data(oidc.clone()).service(items)).await;
+
+        let token = create_token(json!({ "iss": acme, "sub": "admin" }));
+        let req = create_get_jwt_request("/acme/items", &token).to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        assert_eq!(body, acme.as_bytes());
+
+        for path in ["/globex/items", "/initech/items"] {
+            let req = create_get_jwt_request(path, &token).to_request();
+            assert_eq!(app.call(req).await?.status(), StatusCode::UNAUTHORIZED);
+        }
+        assert_eq!(oidc.tenants().len(), 2);
+
+        let req = create_get_jwt_request("/initech/items", &token).to_http_request();
+        assert!(matches!(
+            oidc.validate_request(&req).await,
+            Err(OIDCValidationError::UnknownTenant)
+        ));
+        Ok(())
+    }
+
+    ///Test that a new tenant is discovered once by concurrent requests
+    #[actix_rt::test]
+    async fn test_tenant_single_discovery() {
+        let acme = start_test_idp().await;
+        let tenants = Tenants::new(Arc::new(PathTenants::new().tenant("acme", acme.clone())));
+        let token = create_token(json!({ "iss": acme, "sub": "admin" }));
+        let limits = HttpLimits::default();
+
+        let validations = (0..3).map(|_| tenants.discovered.key_store(&acme, &limits, None));
+        let key_stores = futures::future::try_join_all(validations).await.unwrap();
+        assert!(key_stores
+            .iter()
+            .all(|key_store| Arc::ptr_eq(key_store, &key_stores[0])));
+        assert!(tenants.validate(&acme, &token, &limits, None).await.is_ok());
+    }
+
+    ///Test that the known tenants are discovered ahead of their first request
+    #[actix_rt::test]
+    async fn test_tenant_prefetch() {
+        let acme = start_test_idp().await;
+        let oidc = Oidc::new(OidcConfig::Tenants(Arc::new(
+            PathTenants::new().tenant("acme", acme.clone()),
+        )))
+        .await
+        .unwrap();
+        assert!(oidc.tenants().is_empty());
+
+        let prefetch = oidc.spawn_tenant_prefetch(Duration::from_millis(10));
+        for _ in 0..100 {
+            if !prefetch.is_running() {
+                break;
+            }
+            actix_rt::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(!prefetch.is_running());
+        assert_eq!(oidc.tenants(), vec![acme]);
+    }
+
+    ///Test that the discovery of the tenants goes through the circuit breaker
+    #[actix_rt::test]
+    async fn test_tenant_circuit_breaker() {
+        // Refuses the connections
+        let down = format!("http://{}", {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap()
+        });
+        let oidc = Oidc::new(OidcConfig::Tenants(Arc::new(
+            PathTenants::new().tenant("acme", down.clone()),
+        )))
+        .await
+        .unwrap()
+        .with_circuit_breaker(CircuitBreakerConfig {
+            failure_threshold: 1,
+            ..CircuitBreakerConfig::default()
+        });
+        let token = create_token(json!({ "iss": down, "sub": "admin" }));
+
+        let req = create_get_jwt_request("/acme/items", &token).to_http_request();
+        // the failed discovery opens the circuit
+        assert!(oidc.validate_request(&req).await.is_err());
+        assert!(matches!(
+            oidc.validate_request(&req).await,
+            Err(OIDCValidationError::CircuitOpen)
+        ));
+        assert!(oidc.tenants().is_empty());
+    }
+}
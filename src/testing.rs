@@ -0,0 +1,238 @@
+//! Helpers for the unit tests of handlers, available with the `test-utils` feature
+//!
+//! A pre-built identity is inserted into the extensions of a test request, the extractors
+//! ([`DecodedInfo`], [`AuthenticatedUser`], [`crate::Subject`], ...) then return it without
+//! looking for a token, so no keys or identity provider are needed:
+//!
+//! ```ignore
+//! let req = test::TestRequest::get().uri("/me").to_request();
+//! testing::inject_user(&req, &AuthenticatedUser { jwt: String::new(), claims: my_claims });
+//! let response = test::call_service(&app, req).await;
+//! ```
+//!
+//! [`TestRequestAuthExt`] does the same while building the request, as its last step:
+//!
+//! ```ignore
+//! let req = test::TestRequest::get().uri("/me").authenticated_as(&my_claims).to_request();
+//! ```
+//!
+//! The identity only lives in the extensions of the built request, nothing a client sends can
+//! select it, so compiling the helpers in does not open up real requests.
+//!
+//! The [`crate::OidcBiscuitValidator`] middleware still requires a real token, leave it out of
+//! the test app.
+
+use actix_http::Request;
+use actix_web::{dev::ServiceRequest, http::header, test::TestRequest, HttpMessage, HttpRequest};
+use serde::Serialize;
+
+use crate::{AuthenticatedUser, DecodedInfo};
+
+/// The identity inserted by the helpers, honored by the [`DecodedInfo`] extractor
+#[derive(Debug, Clone)]
+pub(crate) struct InjectedIdentity(pub(crate) DecodedInfo);
+
+/// The identity injected into the request, if any
+pub(crate) fn injected(req: &HttpRequest) -> Option<DecodedInfo> {
+    req.extensions()
+        .get::<InjectedIdentity>()
+        .map(|identity| identity.0.clone())
+}
+
+/// Authentication for actix' [`TestRequest`], e.g. `TestRequest::get().bearer(&token)`
+pub trait TestRequestAuthExt {
+    /// Sends the token in the `Authorization: Bearer` header
+    fn bearer(self, token: &str) -> Self;
+
+    /// Authenticates the request with the given claims, without a token
+    ///
+    /// The identity is inserted into the extensions when the request is built, so call it after
+    /// the other settings of the request. Panics when the claims don't serialize to a JSON object.
+    fn authenticated_as<T: Serialize>(self, claims: &T) -> AuthenticatedTestRequest;
+}
+
+impl TestRequestAuthExt for TestRequest {
+    fn bearer(self, token: &str) -> Self {
+        self.insert_header((header::AUTHORIZATION, format!("Bearer {}", token)))
+    }
+
+    fn authenticated_as<T: Serialize>(self, claims: &T) -> AuthenticatedTestRequest {
+        AuthenticatedTestRequest {
+            req: self,
+            identity: decoded_info(claims),
+        }
+    }
+}
+
+/// A [`TestRequest`] with the identity of [`TestRequestAuthExt::authenticated_as`], built like
+/// the test request
+pub struct AuthenticatedTestRequest {
+    req: TestRequest,
+    identity: DecodedInfo,
+}
+
+impl AuthenticatedTestRequest {
+    /// Builds the request for `test::call_service`
+    pub fn to_request(self) -> Request {
+        let req = self.req.to_request();
+        inject_decoded_info(&req, self.identity);
+        req
+    }
+
+    /// Builds the `HttpRequest`, e.g. to call an extractor directly
+    pub fn to_http_request(self) -> HttpRequest {
+        let req = self.req.to_http_request();
+        inject_decoded_info(&req, self.identity);
+        req
+    }
+
+    /// Builds the `ServiceRequest`, e.g. to call a middleware directly
+    pub fn to_srv_request(self) -> ServiceRequest {
+        let req = self.req.to_srv_request();
+        inject_decoded_info(&req, self.identity);
+        req
+    }
+}
+
+/// Builds the [`DecodedInfo`] of a token with the given claims and an empty `jwt`
+///
+/// Panics when the claims don't serialize to a JSON object.
+pub fn decoded_info<T: Serialize>(claims: &T) -> DecodedInfo {
+    let claims = serde_json::to_value(claims).expect("Claims must serialize to JSON");
+    DecodedInfo {
+        jwt: String::new(),
+        payload: serde_json::from_value(claims).expect("Claims must be a JSON object"),
+    }
+}
+
+/// Inserts the decoded token into the extensions of the request
+pub fn inject_decoded_info<M: HttpMessage>(req: &M, info: DecodedInfo) {
+    req.extensions_mut().insert(InjectedIdentity(info));
+}
+
+/// Inserts the user into the extensions of the request, the [`AuthenticatedUser`] extractor
+/// returns it with its claims deserialized to the type of the handler
+pub fn inject_user<M: HttpMessage, T: Serialize>(req: &M, user: &AuthenticatedUser<T>) {
+    let mut info = decoded_info(&user.claims);
+    info.jwt = user.jwt.clone();
+    inject_decoded_info(req, info);
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{get, http::StatusCode, test, App, Error};
+    use bytes::Bytes;
+    use serde::{Deserialize, Serialize};
+    use serde_json::json;
+
+    use super::{decoded_info, inject_decoded_info, inject_user, TestRequestAuthExt};
+    use crate::{
+        tests::{create_jwt_token, create_oidc},
+        AuthenticatedUser, DecodedInfo, Subject,
+    };
+
+    #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+    struct Claims {
+        sub: String,
+        name: String,
+    }
+
+    #[get("/user")]
+    async fn user(user: AuthenticatedUser<Claims>) -> String {
+        format!("Welcome {}!", user.claims.name)
+    }
+
+    #[get("/subject")]
+    async fn subject(subject: Subject, info: DecodedInfo) -> String {
+        format!("{} {}", subject, info.jwt)
+    }
+
+    ///Test for injecting a user without Oidc or token
+    #[actix_rt::test]
+    async fn test_inject_user() -> Result<(), Error> {
+        let app = test::init_service(App::new().service(user)).await;
+
+        let req = test::TestRequest::get().uri("/user").to_request();
+        let claims = Claims {
+            sub: "joe".into(),
+            name: "Joe".into(),
+        };
+        inject_user(
+            &req,
+            &AuthenticatedUser {
+                jwt: String::new(),
+                claims,
+            },
+        );
+
+        let result: Bytes = test::call_and_read_body(&app, req).await;
+        assert_eq!(result, Bytes::from_static(b"Welcome Joe!"));
+        Ok(())
+    }
+
+    ///Test for injecting a decoded token
+    #[actix_rt::test]
+    async fn test_inject_decoded_info() -> Result<(), Error> {
+        let app = test::init_service(App::new().service(subject)).await;
+
+        let req = test::TestRequest::get().uri("/subject").to_request();
+        let mut info = decoded_info(&json!({ "sub": "joe" }));
+        info.jwt = "token".into();
+        inject_decoded_info(&req, info);
+
+        let result: Bytes = test::call_and_read_body(&app, req).await;
+        assert_eq!(result, Bytes::from_static(b"joe token"));
+        Ok(())
+    }
+
+    ///Test for authenticating a test request with claims
+    #[actix_rt::test]
+    async fn test_authenticated_as() -> Result<(), Error> {
+        let app = test::init_service(App::new().service(user)).await;
+
+        let req = test::TestRequest::get()
+            .uri("/user")
+            .authenticated_as(&json!({ "sub": "joe", "name": "Joe" }))
+            .to_request();
+
+        let result: Bytes = test::call_and_read_body(&app, req).await;
+        assert_eq!(result, Bytes::from_static(b"Welcome Joe!"));
+        Ok(())
+    }
+
+    ///Test that an identity header sent by a client authenticates nobody
+    #[actix_rt::test]
+    async fn test_identity_header_ignored() -> Result<(), Error> {
+        let oidc = create_oidc().await;
+        let app = test::init_service(App::new().app_data(oidc.clone()).service(user)).await;
+
+        let req = test::TestRequest::get()
+            .uri("/user")
+            .insert_header(("x-actix-4-jwt-auth-test-identity", "joe"))
+            .to_request();
+
+        let response = test::call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        Ok(())
+    }
+
+    ///Test for sending a bearer token with a test request
+    #[actix_rt::test]
+    async fn test_bearer() -> Result<(), Error> {
+        let oidc = create_oidc().await;
+        let app = test::init_service(App::new().app_data(oidc.clone()).service(subject)).await;
+
+        let token = create_jwt_token();
+        let req = test::TestRequest::get()
+            .uri("/subject")
+            .bearer(&token)
+            .to_request();
+
+        let result: Bytes = test::call_and_read_body(&app, req).await;
+        assert_eq!(
+            result,
+            Bytes::from(format!("CgVhZG1pbhIFbG9jYWw {}", token))
+        );
+        Ok(())
+    }
+}
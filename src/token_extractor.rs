@@ -0,0 +1,168 @@
+use std::{borrow::Cow, collections::HashMap};
+
+use actix_web::{web, HttpRequest};
+
+use crate::OIDCValidationError;
+
+/// Finds the token on the request, for the extractors and the middleware
+///
+/// The built-in sources are [`BearerHeader`], [`CookieToken`] and [`QueryToken`]. Implement it,
+/// or pass a closure, for other sources, e.g. a token embedded in a signed payload, and configure
+/// it with [`Oidc::with_token_extractor`](crate::Oidc::with_token_extractor).
+pub trait TokenExtractor: Send + Sync {
+    /// Returns the token (without scheme), `None` when the request carries no token
+    fn extract(&self, req: &HttpRequest) -> Option<String>;
+}
+
+impl<F> TokenExtractor for F
+where
+    F: Fn(&HttpRequest) -> Option<String> + Send + Sync,
+{
+    fn extract(&self, req: &HttpRequest) -> Option<String> {
+        self(req)
+    }
+}
+
+/// The token in a header, by default `Authorization: Bearer <token>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BearerHeader {
+    name: Cow<'static, str>,
+    scheme: Option<Cow<'static, str>>,
+}
+
+impl BearerHeader {
+    /// The token in the `Authorization` header with the `Bearer` scheme
+    pub fn authorization() -> Self {
+        BearerHeader::new(actix_web::http::header::AUTHORIZATION.as_str())
+    }
+
+    /// The token in the named header with the `Bearer` scheme
+    pub fn new(name: impl Into<Cow<'static, str>>) -> Self {
+        BearerHeader {
+            name: name.into(),
+            scheme: Some(Cow::Borrowed("Bearer")),
+        }
+    }
+
+    /// Expects another scheme in front of the token, e.g. `JWT`
+    pub fn with_scheme(mut self, scheme: impl Into<Cow<'static, str>>) -> Self {
+        self.scheme = Some(scheme.into());
+        self
+    }
+
+    /// Expects the bare token in the header
+    pub fn without_scheme(mut self) -> Self {
+        self.scheme = None;
+        self
+    }
+}
+
+impl TokenExtractor for BearerHeader {
+    fn extract(&self, req: &HttpRequest) -> Option<String> {
+        header_token(req, &self.name, self.scheme.as_deref()).ok()
+    }
+}
+
+/// The token as value of the named cookie
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CookieToken(pub Cow<'static, str>);
+
+impl CookieToken {
+    /// The token in the named cookie
+    pub fn new(name: impl Into<Cow<'static, str>>) -> Self {
+        CookieToken(name.into())
+    }
+}
+
+impl TokenExtractor for CookieToken {
+    fn extract(&self, req: &HttpRequest) -> Option<String> {
+        req.cookie(&self.0).map(|cookie| cookie.value().to_string())
+    }
+}
+
+/// The token as value of the named query parameter, e.g. `?access_token=...`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryToken(pub Cow<'static, str>);
+
+impl QueryToken {
+    /// The token in the named query parameter
+    pub fn new(name: impl Into<Cow<'static, str>>) -> Self {
+        QueryToken(name.into())
+    }
+}
+
+impl TokenExtractor for QueryToken {
+    fn extract(&self, req: &HttpRequest) -> Option<String> {
+        query_token(req, &self.0)
+    }
+}
+
+/// Reads the token from the header, the value must start with the scheme when given
+pub(crate) fn header_token(
+    req: &HttpRequest,
+    name: &str,
+    scheme: Option<&str>,
+) -> Result<String, OIDCValidationError> {
+    let value = match req.headers().get(name) {
+        Some(value) => value
+            .to_str()
+            .map_err(|_| OIDCValidationError::BearerNotComplete)?,
+        None => return Err(OIDCValidationError::TokenMissing),
+    };
+    let token = match scheme {
+        Some(scheme) => value
+            .strip_prefix(scheme)
+            .and_then(|token| token.strip_prefix(' ')),
+        None => Some(value),
+    };
+    token
+        .map(str::to_string)
+        .ok_or(OIDCValidationError::BearerNotComplete)
+}
+
+/// Reads the non-empty token from the query parameter
+pub(crate) fn query_token(req: &HttpRequest, name: &str) -> Option<String> {
+    web::Query::<HashMap<String, String>>::from_query(req.query_string())
+        .ok()
+        .and_then(|mut query| query.remove(name))
+        .filter(|token| !token.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BearerHeader, CookieToken, QueryToken, TokenExtractor};
+    use actix_web::{cookie::Cookie, test::TestRequest, HttpRequest};
+
+    ///Test the built-in token sources
+    #[actix_rt::test]
+    async fn test_token_extractors() {
+        let req = TestRequest::get()
+            .uri("/file?access_token=from-query")
+            .insert_header(("authorization", "Bearer from-header"))
+            .insert_header(("x-auth-token", "bare"))
+            .cookie(Cookie::new("access_token", "from-cookie"))
+            .to_http_request();
+
+        let token = |extractor: &dyn TokenExtractor| extractor.extract(&req);
+        assert_eq!(
+            token(&BearerHeader::authorization()).as_deref(),
+            Some("from-header")
+        );
+        assert_eq!(
+            token(&BearerHeader::new("x-auth-token").without_scheme()).as_deref(),
+            Some("bare")
+        );
+        assert_eq!(token(&BearerHeader::new("x-auth-token")), None);
+        assert_eq!(
+            token(&CookieToken::new("access_token")).as_deref(),
+            Some("from-cookie")
+        );
+        assert_eq!(
+            token(&QueryToken::new("access_token")).as_deref(),
+            Some("from-query")
+        );
+        assert_eq!(token(&QueryToken::new("token")), None);
+        let custom = |req: &HttpRequest| Some(req.path().trim_start_matches('/').to_string());
+        assert_eq!(token(&custom).as_deref(), Some("file"));
+    }
+}
@@ -0,0 +1,82 @@
+use std::fmt;
+
+use ring::digest;
+
+/// SHA-256 hash of a token, the key of the validation cache, of the introspection and UserInfo
+/// caches including their negative entries, and of the in-memory reference store
+///
+/// The raw token is not used as key, so memory dumps and debug output of the cache state don't
+/// expose usable bearer tokens. The [`ReplayGuard`](crate::ReplayGuard) records `jti`s, not
+/// tokens. Implementations of custom caches and stores should key by it as well.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TokenHash([u8; 32]);
+
+impl TokenHash {
+    /// Hashes the token (without the Bearer part)
+    pub fn of(token: &str) -> Self {
+        let mut hash = [0u8; 32];
+        hash.copy_from_slice(digest::digest(&digest::SHA256, token.as_bytes()).as_ref());
+        TokenHash(hash)
+    }
+
+    /// Hashes the token presented to the tenant, so the same token has another hash per tenant
+    pub(crate) fn of_tenant(tenant: Option<&str>, token: &str) -> Self {
+        let Some(tenant) = tenant else {
+            return TokenHash::of(token);
+        };
+        let mut context = digest::Context::new(&digest::SHA256);
+        context.update(tenant.as_bytes());
+        // neither issuers nor tokens contain a NUL, the tenant cannot run into the token
+        context.update(&[0]);
+        context.update(token.as_bytes());
+        let mut hash = [0u8; 32];
+        hash.copy_from_slice(context.finish().as_ref());
+        TokenHash(hash)
+    }
+
+    /// The bytes of the hash
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl fmt::Display for TokenHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|byte| write!(f, "{:02x}", byte))
+    }
+}
+
+impl fmt::Debug for TokenHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "TokenHash({})", self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TokenHash;
+    use crate::tests::create_jwt_token;
+
+    #[test]
+    fn test_token_hash() {
+        let token = create_jwt_token();
+        let hash = TokenHash::of(&token);
+
+        assert_eq!(hash, TokenHash::of(&token));
+        assert_ne!(hash, TokenHash::of("other"));
+        assert_eq!(TokenHash::of_tenant(None, &token), hash);
+        assert_ne!(TokenHash::of_tenant(Some("https://idp/acme"), &token), hash);
+        assert_ne!(
+            TokenHash::of_tenant(Some("https://idp/acme"), &token),
+            TokenHash::of_tenant(Some("https://idp/globex"), &token)
+        );
+        assert_eq!(
+            TokenHash::of("").to_string(),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        // the token cannot be read from the debug output of a cache
+        let debug = format!("{:?}", hash);
+        assert!(!debug.contains(&token));
+        assert!(!debug.contains(token.split('.').nth(1).unwrap()));
+    }
+}
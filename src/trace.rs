@@ -0,0 +1,161 @@
+use std::{borrow::Cow, future::Future};
+
+use ring::digest;
+
+use crate::{DecodedInfo, OIDCValidationError};
+
+/// How the `sub` of the validated tokens is recorded in the spans of the `tracing` feature and
+/// the `enduser.id` of the `opentelemetry` feature, see
+/// [`Oidc::with_trace_subjects`](crate::Oidc::with_trace_subjects)
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum SubjectRedaction {
+    /// The subject as is
+    Plain,
+    /// The first 16 hex digits of the SHA-256 hash of the subject, the requests of a user can be
+    /// correlated without recording the user id
+    #[default]
+    Hashed,
+    /// The subject is not recorded
+    Omitted,
+}
+
+impl SubjectRedaction {
+    /// The subject to record, `None` when omitted
+    pub fn redact(&self, subject: &str) -> Option<String> {
+        match self {
+            SubjectRedaction::Plain => Some(subject.to_string()),
+            SubjectRedaction::Hashed => {
+                let hash = digest::digest(&digest::SHA256, subject.as_bytes());
+                Some(data_encoding::HEXLOWER.encode(&hash.as_ref()[..8]))
+            }
+            SubjectRedaction::Omitted => None,
+        }
+    }
+}
+
+/// Runs the validation of the token in a `validate_token` span with the `kid`, `iss`, `sub` and
+/// the code of the failure
+#[cfg(feature = "tracing")]
+pub(crate) async fn validation<F>(
+    redaction: SubjectRedaction,
+    token: &str,
+    validation: F,
+) -> Result<DecodedInfo, OIDCValidationError>
+where
+    F: Future<Output = Result<DecodedInfo, OIDCValidationError>>,
+{
+    use tracing::{field, Instrument};
+
+    let kid = crate::unverified::RawHeader::peek(token).and_then(|header| header.kid);
+    let span = tracing::debug_span!(
+        "validate_token",
+        kid = kid.as_deref(),
+        iss = field::Empty,
+        sub = field::Empty,
+        error = field::Empty,
+    );
+    let validated = validation.instrument(span.clone()).await;
+    match &validated {
+        Ok(info) => {
+            let registered = &info.payload.registered;
+            span.record("iss", registered.issuer.as_deref());
+            let subject = registered.subject.as_deref();
+            span.record("sub", subject.and_then(|subject| redaction.redact(subject)));
+            tracing::debug!(parent: &span, "token validated");
+        }
+        Err(e) => {
+            // the issuer of a rejected token is not verified, but tells where the token is from
+            let issuer =
+                crate::unverified::peek_claims(token).and_then(|claims| claims.registered.issuer);
+            span.record("iss", issuer.as_deref());
+            span.record("error", e.code().as_str());
+            tracing::info!(parent: &span, error = %e, "token rejected");
+        }
+    }
+    validated
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) async fn validation<F>(
+    _redaction: SubjectRedaction,
+    _token: &str,
+    validation: F,
+) -> Result<DecodedInfo, OIDCValidationError>
+where
+    F: Future<Output = Result<DecodedInfo, OIDCValidationError>>,
+{
+    validation.await
+}
+
+/// Runs a call to the identity provider, e.g. the discovery or a JWKS fetch, in a span with the
+/// URL, a failure is emitted as warning
+#[cfg(feature = "tracing")]
+pub(crate) async fn call<T, F>(
+    name: &'static str,
+    url: &str,
+    call: F,
+) -> Result<T, OIDCValidationError>
+where
+    F: Future<Output = Result<T, OIDCValidationError>>,
+{
+    use tracing::Instrument;
+
+    let span = tracing::info_span!("idp_call", call = name, url);
+    let called = call.instrument(span.clone()).await;
+    match &called {
+        Ok(_) => tracing::debug!(parent: &span, "{} succeeded", name),
+        Err(e) => tracing::warn!(parent: &span, error = %e, "{} failed", name),
+    }
+    called
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) async fn call<T, F>(
+    _name: &'static str,
+    _url: &str,
+    call: F,
+) -> Result<T, OIDCValidationError>
+where
+    F: Future<Output = Result<T, OIDCValidationError>>,
+{
+    call.await
+}
+
+/// Reports the invalid configuration of a middleware that fails to start, actix only sees that
+/// the service could not be created
+#[cfg(feature = "tracing")]
+pub(crate) fn invalid_middleware(e: &OIDCValidationError) {
+    tracing::error!(error = %e, "invalid OidcBiscuitValidator configuration");
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn invalid_middleware(e: &OIDCValidationError) {
+    log::error!("Invalid OidcBiscuitValidator configuration: {}", e);
+}
+
+/// Reports the accepted audiences of a token issued for another audience, they are kept out of
+/// the response
+#[cfg(feature = "tracing")]
+pub(crate) fn audience_mismatch(audiences: &[Cow<'static, str>]) {
+    tracing::info!(accepted = %audiences.join(", "), "token is not issued for an accepted audience");
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn audience_mismatch(_: &[Cow<'static, str>]) {}
+
+#[cfg(test)]
+mod tests {
+    use super::SubjectRedaction;
+
+    #[test]
+    fn test_subject_redaction() {
+        assert_eq!(
+            SubjectRedaction::Plain.redact("admin"),
+            Some("admin".to_string())
+        );
+        let hashed = SubjectRedaction::Hashed.redact("admin").unwrap();
+        assert_eq!(hashed.len(), 16);
+        assert_ne!(hashed, SubjectRedaction::Hashed.redact("other").unwrap());
+        assert_eq!(SubjectRedaction::Omitted.redact("admin"), None);
+    }
+}
@@ -0,0 +1,135 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    http::{self, HttpLimits},
+    OIDCValidationError,
+};
+
+/// Configuration to request UMA permission tickets from the Keycloak protection API
+///
+/// When a requesting party token lacks a permission, a ticket is requested and returned in the
+/// `WWW-Authenticate: UMA` challenge, so the client can obtain a token with the permission.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct UmaTicketConfig {
+    /// The permission endpoint, e.g. `https://keycloak/realms/acme/authz/protection/permission`
+    pub permission_endpoint: String,
+    /// The authorization server the client requests the token at, e.g. `https://keycloak/realms/acme`
+    pub as_uri: String,
+    /// The realm of the challenge
+    pub realm: String,
+    /// Protection API token (PAT) of the resource server
+    pub protection_token: String,
+}
+
+#[derive(Serialize)]
+struct PermissionRequest<'a> {
+    resource_id: &'a str,
+    resource_scopes: [&'a str; 1],
+}
+
+#[derive(Deserialize)]
+struct PermissionTicket {
+    ticket: String,
+}
+
+/// Requests a permission ticket for the scope on the resource
+pub(crate) async fn request_ticket(
+    config: &UmaTicketConfig,
+    resource_id: &str,
+    scope: &str,
+    limits: &HttpLimits,
+) -> Result<String, OIDCValidationError> {
+    let request = http::client(limits)
+        .post(&config.permission_endpoint)
+        .bearer_auth(&config.protection_token);
+    let body = [PermissionRequest {
+        resource_id,
+        resource_scopes: [scope],
+    }];
+    let ticket: PermissionTicket = http::post_json(request, &body, limits).await?;
+    Ok(ticket.ticket)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, ResponseError};
+    use serde_json::json;
+
+    use super::UmaTicketConfig;
+    use crate::{
+        tests::{create_oidc, create_token, start_test_idp},
+        OIDCValidationError, UmaPermissions,
+    };
+
+    async fn uma_permissions(claims: serde_json::Value) -> UmaPermissions {
+        let oidc = create_oidc().await;
+        let info = oidc.validate_token(&create_token(claims)).await.unwrap();
+        UmaPermissions::from_decoded_info(&info).unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn test_require_uma_permission_ticket() {
+        let idp = start_test_idp().await;
+        let oidc = create_oidc().await.with_uma_tickets(UmaTicketConfig {
+            permission_endpoint: format!("{}/authz/protection/permission", idp),
+            as_uri: idp.clone(),
+            realm: "acme".to_string(),
+            protection_token: "pat".to_string(),
+        });
+        let permissions = uma_permissions(json!({ "iss": "http://0.0.0.0:9090" })).await;
+
+        let err = oidc
+            .require_uma_permission(&permissions, "1a2b", "view")
+            .await
+            .unwrap_err();
+
+        assert!(
+            matches!(err, OIDCValidationError::UmaTicket { ref ticket, .. } if ticket == "ticket-1a2b")
+        );
+        let response = err.error_response();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            response.headers().get("www-authenticate").unwrap(),
+            format!(
+                r#"UMA realm="acme", as_uri="{}", ticket="ticket-1a2b""#,
+                idp
+            )
+            .as_str()
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_uma_challenge_escaped() {
+        let err = OIDCValidationError::UmaTicket {
+            realm: r#"acme", error="x"#.to_string(),
+            as_uri: "https://idp\\".to_string(),
+            ticket: "ticket-1a2b\r\n".to_string(),
+        };
+        assert_eq!(
+            err.error_response()
+                .headers()
+                .get("www-authenticate")
+                .unwrap(),
+            r#"UMA realm="acme, error=x", as_uri="https://idp", ticket="ticket-1a2b""#
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_require_uma_permission() {
+        let oidc = create_oidc().await;
+        let permissions = uma_permissions(json!({
+            "authorization": { "permissions": [{ "rsid": "1a2b", "scopes": ["view"] }] },
+        }))
+        .await;
+
+        assert!(oidc
+            .require_uma_permission(&permissions, "1a2b", "view")
+            .await
+            .is_ok());
+        assert!(matches!(
+            oidc.require_uma_permission(&permissions, "1a2b", "delete")
+                .await,
+            Err(OIDCValidationError::InvalidAccess)
+        ));
+    }
+}
@@ -0,0 +1,82 @@
+use biscuit::{jws, ClaimsSet, CompactJson, Empty, JWT};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::OIDCValidationError;
+
+/// Header and claims of a token that have been read without verifying the signature
+///
+/// Nothing in here can be trusted, use it for routing or logging only.
+#[derive(Debug, PartialEq, Clone)]
+pub struct UnverifiedToken {
+    /// The JOSE header of the token, e.g. to read the `kid` or `alg`
+    pub header: jws::Header<Empty>,
+    /// The claims of the token, e.g. to read the `iss`
+    pub payload: ClaimsSet<Value>,
+}
+
+/// Decodes the header and claims of a token WITHOUT verifying its signature or any claim
+///
+/// This is meant for decisions that have to be made before validation, like selecting the
+/// provider by the `iss` claim, or for logging the `kid` when validation failed.
+/// Never use the result to authorize a request, use the extractors for that.
+pub fn dangerous_peek_claims(token: &str) -> Result<UnverifiedToken, OIDCValidationError> {
+    let token: jws::Compact<ClaimsSet<Value>, Empty> = JWT::new_encoded(token);
+    Ok(UnverifiedToken {
+        header: token.unverified_header()?,
+        payload: token.unverified_payload()?,
+    })
+}
+
+/// The `alg` and `kid` of a JOSE header, also for algorithms biscuit cannot represent like `EdDSA`
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct RawHeader {
+    pub(crate) alg: String,
+    pub(crate) kid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) typ: Option<String>,
+}
+
+impl CompactJson for RawHeader {}
+
+impl RawHeader {
+    /// Reads the header of a compact JWS token WITHOUT verifying it, `None` for other tokens
+    pub(crate) fn peek(token: &str) -> Option<Self> {
+        let token = biscuit::Compact::decode(token);
+        match token.len() {
+            3 => token.part(0).ok(),
+            _ => None,
+        }
+    }
+}
+
+/// Reads the claims of a compact JWS token WITHOUT verifying it, also when biscuit cannot
+/// represent its header like for `EdDSA`, `None` for other tokens
+pub(crate) fn peek_claims(token: &str) -> Option<ClaimsSet<Value>> {
+    RawHeader::peek(token)?;
+    biscuit::Compact::decode(token).part(1).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::dangerous_peek_claims;
+    use crate::tests::create_jwt_token;
+
+    #[test]
+    fn test_dangerous_peek_claims() {
+        let token = dangerous_peek_claims(&create_jwt_token()).unwrap();
+        assert_eq!(
+            token.header.registered.key_id.as_deref(),
+            Some("2020-01-29")
+        );
+        assert_eq!(
+            token.payload.registered.issuer.as_deref(),
+            Some("http://0.0.0.0:9090")
+        );
+    }
+
+    #[test]
+    fn test_dangerous_peek_claims_bad_token() {
+        assert!(dangerous_peek_claims("bad_token").is_err());
+    }
+}
@@ -0,0 +1,77 @@
+use std::time::Duration;
+
+use serde_json::Value;
+
+use crate::{
+    http::{self, HttpLimits},
+    response_cache::ResponseCache,
+    DecodedInfo, OIDCValidationError, Oidc,
+};
+
+/// The `userinfo_endpoint` of the identity provider and the responses cached per token
+pub(crate) struct UserInfoEndpoint {
+    endpoint: String,
+    cache: ResponseCache<Value>,
+}
+
+impl UserInfoEndpoint {
+    /// Caches the responses for a minute, unauthorized tokens for 5 seconds
+    pub(crate) fn new(endpoint: String) -> Self {
+        UserInfoEndpoint::with_cache(endpoint, Duration::from_secs(60), Duration::from_secs(5))
+    }
+
+    pub(crate) fn with_cache(endpoint: String, ttl: Duration, negative_ttl: Duration) -> Self {
+        UserInfoEndpoint {
+            endpoint,
+            cache: ResponseCache::new(ttl, negative_ttl),
+        }
+    }
+
+    pub(crate) fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    /// The UserInfo claims of the validated token, from the cache or the endpoint
+    ///
+    /// The `sub` of the response must be the one of the token
+    /// ([OpenID Connect Core 5.3.2](https://openid.net/specs/openid-connect-core-1_0.html#UserInfoResponse)).
+    pub(crate) async fn fetch(
+        &self,
+        oidc: &Oidc,
+        token: &str,
+        info: &DecodedInfo,
+    ) -> Result<Value, OIDCValidationError> {
+        let claims = match self.cache.get(token) {
+            Some(claims) => claims,
+            None => {
+                let claims = oidc
+                    .guarded(self.call_endpoint(token, &oidc.http_limits))
+                    .await?
+                    .ok_or(OIDCValidationError::CircuitOpen)?;
+                match &claims {
+                    Some(claims) => {
+                        let exp = info.payload.registered.expiry.map(|exp| exp.timestamp());
+                        self.cache
+                            .insert(token, claims.clone(), exp.map(|exp| exp.max(0) as u64));
+                    }
+                    None => self.cache.insert_negative(token),
+                }
+                claims
+            }
+        };
+        let claims = claims.ok_or(OIDCValidationError::Unauthorized)?;
+        match claims.get("sub").and_then(Value::as_str) {
+            Some(sub) if info.payload.registered.subject.as_deref() == Some(sub) => Ok(claims),
+            _ => Err(OIDCValidationError::Unauthorized),
+        }
+    }
+
+    async fn call_endpoint(
+        &self,
+        token: &str,
+        limits: &HttpLimits,
+    ) -> Result<Option<Value>, OIDCValidationError> {
+        let request = http::client(limits).get(&self.endpoint).bearer_auth(token);
+        http::get_json_authorized(request, limits).await
+    }
+}
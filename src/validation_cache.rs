@@ -0,0 +1,194 @@
+use std::time::Duration;
+
+use biscuit::{jwk::JWKSet, ClaimsSet, Empty};
+use serde_json::Value;
+
+use crate::{response_cache::ResponseCache, unverified::RawHeader, DecodedInfo, TokenHash};
+
+/// Caches the tokens of which the signature is verified per token, so repeated requests with the
+/// same token skip the signature verification, see
+/// [`Oidc::with_validation_cache`](crate::Oidc::with_validation_cache)
+///
+/// The tokens are kept until their `exp`, tokens without `exp` are not cached. The cache holds at
+/// most `capacity` tokens, the least recently used token is dropped for a new one. A token is
+/// cached per tenant, it is verified against the keys of each tenant it is presented to. A cached
+/// token is dropped as soon as its `kid` is no longer one of the keys, e.g. after an emergency key
+/// rotation. Only the claims are kept, not the token itself.
+pub(crate) struct ValidationCache {
+    /// The verified tokens by the hash of the tenant and the token
+    tokens: ResponseCache<Verified>,
+}
+
+#[derive(Clone)]
+struct Verified {
+    kid: Option<String>,
+    claims: ClaimsSet<Value>,
+}
+
+impl ValidationCache {
+    pub(crate) fn new(capacity: usize) -> Self {
+        ValidationCache {
+            // the tokens are kept until their exp, tokens without exp are not inserted
+            tokens: ResponseCache::with_capacity(Duration::MAX, Duration::ZERO, capacity, 0),
+        }
+    }
+
+    /// The claims of the token cached for the tenant, marked as most recently used
+    ///
+    /// The claims are only returned while the key of the token is in the keys it is verified
+    /// with, when known.
+    pub(crate) fn get(
+        &self,
+        tenant: Option<&str>,
+        token: &str,
+        keys: Option<&JWKSet<Empty>>,
+    ) -> Option<ClaimsSet<Value>> {
+        let hash = TokenHash::of_tenant(tenant, token);
+        let verified = self.tokens.get_hashed(&hash).flatten()?;
+        let key_removed = keys.is_some_and(|keys| match &verified.kid {
+            Some(kid) => keys.find(kid).is_none(),
+            None => true,
+        });
+        if key_removed {
+            self.tokens.remove(&hash);
+            return None;
+        }
+        Some(verified.claims)
+    }
+
+    /// Drops all tokens, they are verified again on their next use
+    pub(crate) fn clear(&self) {
+        self.tokens.clear();
+    }
+
+    /// Caches the token verified for the tenant until its `exp`
+    pub(crate) fn insert(&self, tenant: Option<&str>, token: &str, info: &DecodedInfo) {
+        let Some(exp) = info.payload.registered.expiry else {
+            return;
+        };
+        let verified = Verified {
+            kid: RawHeader::peek(&info.jwt).and_then(|header| header.kid),
+            claims: info.payload.clone(),
+        };
+        self.tokens.insert_hashed(
+            TokenHash::of_tenant(tenant, token),
+            verified,
+            Some(exp.timestamp().max(0) as u64),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::Arc,
+        time::{SystemTime, UNIX_EPOCH},
+    };
+
+    use actix_web::{get, http::StatusCode, test, App};
+    use serde_json::json;
+
+    use super::ValidationCache;
+    use crate::{
+        tests::{
+            create_get_jwt_request, create_jwk_set, create_oidc, create_token, start_test_idp,
+        },
+        DecodedInfo, Oidc, OidcConfig, PathTenants,
+    };
+
+    #[get("/{tenant}/items")]
+    async fn items(claims: DecodedInfo) -> String {
+        claims.payload.registered.issuer.unwrap_or_default()
+    }
+
+    ///Test that the cache drops the least recently used and the expired tokens
+    #[actix_rt::test]
+    async fn test_validation_cache() {
+        let oidc = create_oidc().await;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let mut tokens = vec![];
+        for sub in ["alice", "bob", "carol"] {
+            let token = create_token(json!({ "sub": sub, "exp": now + 300 }));
+            let info: DecodedInfo = oidc.validate_token(&token).await.unwrap();
+            tokens.push((token, info));
+        }
+        let cache = ValidationCache::new(2);
+        cache.insert(None, &tokens[0].0, &tokens[0].1);
+        cache.insert(None, &tokens[1].0, &tokens[1].1);
+        assert_eq!(
+            cache.get(None, &tokens[0].0, None),
+            Some(tokens[0].1.payload.clone())
+        );
+
+        cache.insert(None, &tokens[2].0, &tokens[2].1);
+        assert_eq!(cache.get(None, &tokens[1].0, None), None);
+        assert_eq!(
+            cache.get(None, &tokens[0].0, None),
+            Some(tokens[0].1.payload.clone())
+        );
+        assert_eq!(
+            cache.get(None, &tokens[2].0, None),
+            Some(tokens[2].1.payload.clone())
+        );
+        assert_eq!(
+            cache.get(Some("https://idp/acme"), &tokens[2].0, None),
+            None
+        );
+
+        // a token is only taken from the cache while its key is in the keys
+        assert!(cache
+            .get(None, &tokens[2].0, Some(&create_jwk_set()))
+            .is_some());
+        let mut rotated = create_jwk_set();
+        rotated.keys[0].common.key_id = Some("2024-06-01".to_string());
+        assert_eq!(cache.get(None, &tokens[2].0, Some(&rotated)), None);
+        assert_eq!(cache.get(None, &tokens[2].0, None), None);
+
+        // tokens without exp or past their exp are never cached
+        let token = create_token(json!({ "sub": "dave" }));
+        let info = oidc.validate_token(&token).await.unwrap();
+        cache.insert(None, &token, &info);
+        assert_eq!(cache.get(None, &token, None), None);
+
+        // a cached token is taken from the request, it is not kept in the cache
+        let oidc = oidc.with_validation_cache(16);
+        let first = oidc.validate_token(&tokens[0].0).await.unwrap();
+        assert_eq!(oidc.validate_token(&tokens[0].0).await.unwrap(), first);
+        assert_eq!(first, tokens[0].1);
+    }
+
+    ///Test that a token cached for one tenant is still rejected by another tenant
+    #[actix_rt::test]
+    async fn test_validation_cache_tenants() {
+        let acme = start_test_idp().await;
+        let globex = start_test_idp().await;
+        let resolver = PathTenants::new()
+            .tenant("acme", acme.clone())
+            .tenant("globex", globex.clone());
+        let oidc = Oidc::new(OidcConfig::Tenants(Arc::new(resolver)))
+            .await
+            .unwrap()
+            .with_validation_cache(16);
+
+        let app = test::init_service(App::new().app_data(oidc.clone()).service(items)).await;
+
+        let exp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            + 300;
+        let token = create_token(json!({ "iss": acme, "sub": "admin", "exp": exp }));
+        for _ in 0..2 {
+            let req = create_get_jwt_request("/acme/items", &token).to_request();
+            assert_eq!(test::call_and_read_body(&app, req).await, acme.as_bytes());
+        }
+        let req = create_get_jwt_request("/globex/items", &token).to_request();
+        assert_eq!(
+            test::call_service(&app, req).await.status(),
+            StatusCode::UNAUTHORIZED
+        );
+    }
+}
@@ -0,0 +1,138 @@
+use std::sync::Arc;
+
+use biscuit::{jwk::JWKSet, Empty};
+use futures_util::future::{ready, FutureExt, LocalBoxFuture};
+
+use crate::{keys::KeyStore, oidc::OidcDecoder, AuthMetrics, DecodedInfo, OIDCValidationError};
+
+/// Validates a raw token and turns it into the [`DecodedInfo`] that is handed to the extractors
+/// and the middleware.
+///
+/// The default implementation is the [`JwksValidator`] that verifies the signature against the
+/// keys of the issuer. Implement this trait when tokens must be validated in another way,
+/// e.g. by calling a sidecar, and configure it with [`crate::OidcConfig::Validator`].
+pub trait TokenValidator: Send + Sync {
+    /// Validates the token (without the Bearer part) and returns the decoded information
+    fn validate<'a>(
+        &'a self,
+        token: &'a str,
+    ) -> LocalBoxFuture<'a, Result<DecodedInfo, OIDCValidationError>>;
+
+    /// Counts the reloads of the keys of the validator in the metrics, called by
+    /// [`Oidc::with_auth_metrics`](crate::Oidc::with_auth_metrics), validators without keys
+    /// ignore it
+    fn set_auth_metrics(&self, _metrics: Arc<AuthMetrics>) {}
+}
+
+/// Validates the signature of the token with the JSON Web Key Set of the issuer
+#[derive(Clone)]
+pub struct JwksValidator {
+    //note that keys may expire based on Cache-Control: max-age=21446, must-revalidate header
+    keys: Arc<KeyStore>,
+    decoder: OidcDecoder,
+}
+
+impl JwksValidator {
+    /// Creates a validator for the given key set
+    pub fn new(jwks: JWKSet<Empty>) -> Self {
+        Self::from_store(Arc::new(KeyStore::new(jwks)))
+    }
+
+    pub(crate) fn from_store(keys: Arc<KeyStore>) -> Self {
+        JwksValidator {
+            keys,
+            decoder: OidcDecoder,
+        }
+    }
+}
+
+impl TokenValidator for JwksValidator {
+    fn validate<'a>(
+        &'a self,
+        token: &'a str,
+    ) -> LocalBoxFuture<'a, Result<DecodedInfo, OIDCValidationError>> {
+        let decoded = self
+            .decoder
+            .decode(&self.keys.current(), token)
+            .map(|payload| DecodedInfo {
+                jwt: token.to_string(),
+                payload,
+            });
+        ready(decoded).boxed_local()
+    }
+
+    fn set_auth_metrics(&self, metrics: Arc<AuthMetrics>) {
+        self.keys.with_metrics(metrics);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use actix_web::{get, http::StatusCode, test, App, Error};
+    use biscuit::{ClaimsSet, RegisteredClaims};
+    use bytes::Bytes;
+    use futures_util::future::{ready, FutureExt, LocalBoxFuture};
+    use serde_json::json;
+
+    use super::TokenValidator;
+    use crate::{
+        tests::create_get_jwt_request, AuthenticatedUser, DecodedInfo, OIDCValidationError, Oidc,
+        OidcConfig,
+    };
+
+    /// Accepts a single well known token, as a sidecar would do
+    struct SidecarValidator;
+
+    impl TokenValidator for SidecarValidator {
+        fn validate<'a>(
+            &'a self,
+            token: &'a str,
+        ) -> LocalBoxFuture<'a, Result<DecodedInfo, OIDCValidationError>> {
+            let result = match token {
+                "let-me-in" => Ok(DecodedInfo {
+                    jwt: token.to_string(),
+                    payload: ClaimsSet {
+                        registered: RegisteredClaims {
+                            subject: Some("sidecar-user".to_string()),
+                            ..Default::default()
+                        },
+                        private: json!({}),
+                    },
+                }),
+                _ => Err(OIDCValidationError::Unauthorized),
+            };
+            ready(result).boxed_local()
+        }
+    }
+
+    #[derive(serde::Deserialize)]
+    struct Subject {
+        sub: String,
+    }
+
+    #[get("/subject")]
+    async fn subject(user: AuthenticatedUser<Subject>) -> String {
+        user.claims.sub
+    }
+
+    ///Test the extractor with a custom token validator
+    #[actix_rt::test]
+    async fn test_custom_token_validator() -> Result<(), Error> {
+        let oidc = Oidc::new(OidcConfig::Validator(Arc::new(SidecarValidator)))
+            .await
+            .unwrap();
+
+        let app = test::init_service(App::new().app_data(oidc).service(subject)).await;
+
+        let req = create_get_jwt_request("/subject", "let-me-in").to_request();
+        let result: Bytes = test::call_and_read_body(&app, req).await;
+        assert_eq!(result, Bytes::from_static(b"sidecar-user"));
+
+        let req = create_get_jwt_request("/subject", "let-me-out").to_request();
+        let response = test::call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        Ok(())
+    }
+}
@@ -0,0 +1,126 @@
+use serde_derive::Deserialize;
+
+use crate::http::{self, HttpLimits};
+use crate::OIDCValidationError;
+
+/// Link relation of the OpenID Connect issuer in a WebFinger response
+const ISSUER_REL: &str = "http://openid.net/specs/connect/1.0/issuer";
+
+#[derive(Deserialize, Debug)]
+struct WebFingerLink {
+    rel: String,
+    href: Option<String>,
+}
+
+/// JSON Resource Descriptor returned by the WebFinger endpoint
+#[derive(Deserialize, Debug)]
+struct WebFingerDocument {
+    #[serde(default)]
+    links: Vec<WebFingerLink>,
+}
+
+/// Normalizes a user identifier to a WebFinger resource and returns it with its host
+///
+/// `user@example.com` becomes `acct:user@example.com`, URLs are used as they are.
+pub(crate) fn resource_and_host(identifier: &str) -> Result<(String, String), OIDCValidationError> {
+    let not_found = || OIDCValidationError::IssuerNotFound(identifier.to_string());
+    if let Some(rest) = identifier
+        .strip_prefix("https://")
+        .or_else(|| identifier.strip_prefix("http://"))
+    {
+        let host = rest.split(['/', '?', '#']).next().unwrap_or_default();
+        let host = host.rsplit('@').next().unwrap_or_default();
+        if host.is_empty() {
+            return Err(not_found());
+        }
+        return Ok((identifier.to_string(), host.to_string()));
+    }
+    let account = identifier.strip_prefix("acct:").unwrap_or(identifier);
+    match account.rsplit_once('@') {
+        Some((user, host)) if !user.is_empty() && !host.is_empty() => {
+            Ok((format!("acct:{}", account), host.to_string()))
+        }
+        _ => Err(not_found()),
+    }
+}
+
+/// Resolves the issuer for a user identifier (e.g. an email address) with
+/// [RFC 7033 WebFinger](https://www.rfc-editor.org/rfc/rfc7033) on the host of the identifier
+pub(crate) async fn discover_issuer(
+    identifier: &str,
+    limits: &HttpLimits,
+) -> Result<String, OIDCValidationError> {
+    let (_, host) = resource_and_host(identifier)?;
+    discover_issuer_at(&format!("https://{}", host), identifier, limits).await
+}
+
+/// Resolves the issuer with the WebFinger endpoint of the given base url
+pub(crate) async fn discover_issuer_at(
+    base_url: &str,
+    identifier: &str,
+    limits: &HttpLimits,
+) -> Result<String, OIDCValidationError> {
+    let (resource, _) = resource_and_host(identifier)?;
+    let request = http::client(limits)
+        .get(format!("{}/.well-known/webfinger", base_url))
+        .query(&[("resource", resource.as_str()), ("rel", ISSUER_REL)])
+        .map_err(|_| OIDCValidationError::IssuerNotFound(identifier.to_string()))?;
+    let document: WebFingerDocument = http::get_json(request, limits).await?;
+    document
+        .links
+        .into_iter()
+        .find(|link| link.rel == ISSUER_REL)
+        .and_then(|link| link.href)
+        .ok_or_else(|| OIDCValidationError::IssuerNotFound(identifier.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{discover_issuer_at, resource_and_host};
+    use crate::{tests::start_test_idp, HttpLimits};
+
+    #[test]
+    fn test_resource_and_host() {
+        assert_eq!(
+            resource_and_host("joe@example.com").unwrap(),
+            (
+                "acct:joe@example.com".to_string(),
+                "example.com".to_string()
+            )
+        );
+        assert_eq!(
+            resource_and_host("acct:joe@example.com").unwrap(),
+            (
+                "acct:joe@example.com".to_string(),
+                "example.com".to_string()
+            )
+        );
+        assert_eq!(
+            resource_and_host("https://example.com/joe").unwrap(),
+            (
+                "https://example.com/joe".to_string(),
+                "example.com".to_string()
+            )
+        );
+        assert!(resource_and_host("joe").is_err());
+    }
+
+    #[actix_rt::test]
+    async fn test_discover_issuer() {
+        let idp = start_test_idp().await;
+        let issuer = discover_issuer_at(&idp, "joe@example.com", &HttpLimits::default())
+            .await
+            .unwrap();
+        assert_eq!(issuer, idp);
+    }
+
+    #[actix_rt::test]
+    async fn test_discover_issuer_unknown_account() {
+        let idp = start_test_idp().await;
+        assert!(
+            discover_issuer_at(&idp, "nobody@example.com", &HttpLimits::default())
+                .await
+                .is_err()
+        );
+    }
+}